@@ -1,4 +1,4 @@
-use wisp_lexer::Span;
+use wisp_lexer::{FileId, Span};
 
 /// Unique identifier for AST nodes
 pub type NodeId = u32;
@@ -14,6 +14,8 @@ pub struct SourceFile {
 pub struct ImportedModule {
     /// The import declaration that brought this module in
     pub import: ImportDecl,
+    /// The module's source file
+    pub file: FileId,
     /// Items defined in this module (not including imports)
     pub items: Vec<Item>,
     /// Imports declared within this module (for scope resolution)
@@ -43,6 +45,115 @@ pub enum Item {
     Enum(EnumDef),
     Trait(TraitDef),
     Impl(ImplBlock),
+    /// An item marked `@cfg(...)`, kept only when its predicate holds for the target
+    Cfg(CfgItem),
+}
+
+/// `@cfg(predicate) item`
+#[derive(Debug, Clone)]
+pub struct CfgItem {
+    pub predicate: CfgPredicate,
+    pub item: Box<Item>,
+    pub span: Span,
+}
+
+/// Condition of a `@cfg` attribute
+#[derive(Debug, Clone)]
+pub enum CfgPredicate {
+    /// `target_os = "linux"`, `target_arch = "x86_64"` or `target_family = "unix"`
+    KeyValue(Ident, String),
+    /// `not(p)`
+    Not(Box<CfgPredicate>),
+    /// `all(p, q, ...)`: true when every predicate holds
+    All(Vec<CfgPredicate>),
+    /// `any(p, q, ...)`: true when some predicate holds
+    Any(Vec<CfgPredicate>),
+}
+
+impl CfgPredicate {
+    /// Keys that can appear in `key = "value"` predicates
+    pub const KEYS: [&'static str; 3] = ["target_os", "target_arch", "target_family"];
+
+    pub fn eval(&self, target: &TargetCfg) -> bool {
+        match self {
+            CfgPredicate::KeyValue(key, value) => match key.name.as_str() {
+                "target_os" => target.os == *value,
+                "target_arch" => target.arch == *value,
+                "target_family" => target.family == *value,
+                _ => false,
+            },
+            CfgPredicate::Not(p) => !p.eval(target),
+            CfgPredicate::All(ps) => ps.iter().all(|p| p.eval(target)),
+            CfgPredicate::Any(ps) => ps.iter().any(|p| p.eval(target)),
+        }
+    }
+
+    pub fn pretty_print(&self) -> String {
+        let list = |ps: &[CfgPredicate]| ps.iter().map(|p| p.pretty_print()).collect::<Vec<_>>().join(", ");
+        match self {
+            CfgPredicate::KeyValue(key, value) => format!("{} = \"{}\"", key.name, value),
+            CfgPredicate::Not(p) => format!("not({})", p.pretty_print()),
+            CfgPredicate::All(ps) => format!("all({})", list(ps)),
+            CfgPredicate::Any(ps) => format!("any({})", list(ps)),
+        }
+    }
+}
+
+/// The target properties `@cfg` predicates are checked against
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetCfg {
+    /// `linux`, `macos`, `windows`, ...
+    pub os: String,
+    /// `x86_64`, `aarch64`, `riscv64`, ...
+    pub arch: String,
+    /// `unix` or `windows`
+    pub family: String,
+}
+
+impl TargetCfg {
+    /// The machine the compiler is running on
+    pub fn host() -> Self {
+        Self {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            family: std::env::consts::FAMILY.to_string(),
+        }
+    }
+
+    /// Properties of a target triple such as `aarch64-unknown-linux-gnu`
+    pub fn from_triple(triple: &str) -> Self {
+        let mut parts = triple.split('-');
+        let arch = match parts.next().unwrap_or_default() {
+            "arm64" => "aarch64",
+            arch if arch.starts_with("riscv64") => "riscv64",
+            arch => arch,
+        };
+        let rest: Vec<_> = parts.collect();
+        let os = if rest.contains(&"linux") {
+            "linux"
+        } else if rest.iter().any(|p| p.starts_with("darwin") || p.starts_with("macos")) {
+            "macos"
+        } else if rest.contains(&"windows") {
+            "windows"
+        } else if let Some(os) = rest.iter().find(|p| p.ends_with("bsd")) {
+            os
+        } else {
+            "unknown"
+        };
+        let family = if os == "windows" { "windows" } else { "unix" };
+        Self { os: os.to_string(), arch: arch.to_string(), family: family.to_string() }
+    }
+}
+
+/// Drop items whose `@cfg` predicate doesn't hold for `target` and unwrap the rest
+pub fn configure_items(items: Vec<Item>, target: &TargetCfg) -> Vec<Item> {
+    items.into_iter()
+        .filter_map(|item| match item {
+            Item::Cfg(c) if c.predicate.eval(target) => configure_items(vec![*c.item], target).pop(),
+            Item::Cfg(_) => None,
+            item => Some(item),
+        })
+        .collect()
 }
 
 /// Import path type
@@ -141,6 +252,8 @@ pub struct FnDef {
     pub params: Vec<Param>,
     pub return_type: Option<TypeExpr>,
     pub body: Option<Block>,  // None for trait method signatures
+    /// Marked `@test`: run by `wisp test`
+    pub is_test: bool,
     pub span: Span,
 }
 
@@ -160,6 +273,9 @@ pub struct StructDef {
     pub name: Ident,
     pub type_params: Vec<GenericParam>,  // Generic type parameters
     pub fields: Vec<StructField>,
+    /// Marked `@defaults`: record literals may leave out fields, which are
+    /// filled in from `Type::defaults()`
+    pub has_defaults: bool,
     pub span: Span,
 }
 
@@ -178,6 +294,8 @@ pub struct EnumDef {
     pub name: Ident,
     pub type_params: Vec<GenericParam>,  // Generic type parameters
     pub variants: Vec<EnumVariant>,
+    /// Marked `@non_exhaustive`: matches must have a `_` arm
+    pub is_non_exhaustive: bool,
     pub span: Span,
 }
 
@@ -222,12 +340,14 @@ pub struct Block {
 pub enum Stmt {
     Let(LetStmt),
     Expr(ExprStmt),
+    Defer(DeferStmt),
 }
 
 /// Let binding
 #[derive(Debug, Clone)]
 pub struct LetStmt {
-    pub name: Ident,
+    /// A plain name or a destructuring pattern: let (x, y) = p
+    pub pattern: Pattern,
     pub is_mut: bool,
     pub ty: Option<TypeExpr>,
     pub init: Option<Expr>,
@@ -241,6 +361,13 @@ pub struct ExprStmt {
     pub span: Span,
 }
 
+/// Defer statement: defer { ... } runs the block when the enclosing scope exits
+#[derive(Debug, Clone)]
+pub struct DeferStmt {
+    pub body: Block,
+    pub span: Span,
+}
+
 /// Expressions
 #[derive(Debug, Clone)]
 pub struct Expr {
@@ -268,16 +395,27 @@ pub enum ExprKind {
     Call(Box<Expr>, Vec<CallArg>),
     /// Field access: foo.bar
     Field(Box<Expr>, Ident),
-    /// Struct literal: Point { x: 1, y: 2 }
-    StructLit(Ident, Vec<FieldInit>),
+    /// Struct literal: Point { x: 1, y: 2 }, or { x: 1, y: 2 } with the type
+    /// taken from context; an optional `..base` supplies the remaining fields
+    StructLit(Option<Ident>, Vec<FieldInit>, Option<Box<Expr>>),
     /// If expression: if cond { ... } else { ... }
     If(Box<Expr>, Block, Option<ElseBranch>),
-    /// While loop: while cond { ... }
-    While(Box<Expr>, Block),
-    /// For loop: for x in iter { ... }
-    For(Ident, Box<Expr>, Block),
+    /// While loop: while cond { ... } or while@label cond { ... }
+    While(Option<Ident>, Box<Expr>, Block),  // (label, cond, body)
+    /// For loop: for x in iter { ... } or for@label (a, b) in iter { ... }
+    For(Option<Ident>, Pattern, Box<Expr>, Block),  // (label, pattern, iter, body)
+    /// Infinite loop: loop { ... } or loop@label { ... }
+    Loop(Option<Ident>, Block),
+    /// Return: return, return expr
+    Return(Option<Box<Expr>>),
+    /// Break: break, break value, break@label value
+    Break(Option<Ident>, Option<Box<Expr>>),
+    /// Continue: continue, continue@label
+    Continue(Option<Ident>),
     /// Block expression: { ... }
     Block(Block),
+    /// Block after a call's arguments, passed as its last argument: row(gap: 2) { ... }
+    TrailingBlock(Block),
     /// Assignment: x = expr
     Assign(Box<Expr>, Box<Expr>),
     /// Reference: &expr, &mut expr
@@ -288,12 +426,20 @@ pub enum ExprKind {
     Match(Box<Expr>, Vec<MatchArm>),
     /// Index expression: arr[idx]
     Index(Box<Expr>, Box<Expr>),
-    /// Array literal: [1, 2, 3]
-    ArrayLit(Vec<Expr>),
-    /// Lambda/closure: (x, y) -> x + y
-    Lambda(Vec<LambdaParam>, Box<Expr>),
+    /// List literal: [1, 2, 3], or [..base, if c { x }, for i in 0..n { i }]
+    ArrayLit(Vec<CollectionElem>),
+    /// Map literal: { "k": v, ..extra }
+    MapLit(Vec<CollectionElem>),
+    /// Tuple literal: (1, true), or (x,) for a single element
+    Tuple(Vec<Expr>),
+    /// Lambda/closure: (x, y) -> x + y, or move () -> f(name) to capture by value
+    Lambda(bool, Vec<LambdaParam>, Box<Expr>),  // (is_move, params, body)
     /// Type cast: expr as Type
     Cast(Box<Expr>, TypeExpr),
+    /// Compiler intrinsic: #size_of(T), #write(T, addr, value)
+    Intrinsic(Intrinsic, TypeExpr, Vec<Expr>),  // (intrinsic, type, args)
+    /// Error propagation: expr?
+    Try(Box<Expr>),
     /// String interpolation: "hello {name}!"
     /// Parts alternate between string literals and expressions
     StringInterp(Vec<StringInterpPart>),
@@ -345,9 +491,50 @@ pub enum PatternKind {
     /// Identifier binding: x
     Ident(Ident),
     /// Literal: 42, "hello", true
-    Literal(Expr),
+    Literal(Box<Expr>),
     /// Enum variant: Some(x), None
     Variant(Ident, Vec<Pattern>),
+    /// Tuple: (x, _)
+    Tuple(Vec<Pattern>),
+}
+
+/// Element of a list or map literal
+#[derive(Debug, Clone)]
+pub struct CollectionElem {
+    pub kind: CollectionElemKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum CollectionElemKind {
+    /// List element: x
+    Item(Expr),
+    /// Map entry: "k": v
+    Entry(Box<Expr>, Box<Expr>),
+    /// Conditional elements: if cond { x } else { y }
+    If(Box<Expr>, Box<CollectionElem>, Option<Box<CollectionElem>>),
+    /// One element per iteration: for i in iter { x }
+    For(Pattern, Box<Expr>, Box<CollectionElem>),
+    /// Every element of another collection: ..base
+    Spread(Box<Expr>),
+}
+
+impl CollectionElem {
+    /// Compact pretty print (for inline display)
+    pub fn pretty_print(&self) -> String {
+        match &self.kind {
+            CollectionElemKind::Item(e) => e.pretty_print(),
+            CollectionElemKind::Entry(k, v) => format!("{}: {}", k.pretty_print(), v.pretty_print()),
+            CollectionElemKind::If(cond, then, None) => format!("if {} {{ {} }}", cond.pretty_print(), then.pretty_print()),
+            CollectionElemKind::If(cond, then, Some(els)) => {
+                format!("if {} {{ {} }} else {{ {} }}", cond.pretty_print(), then.pretty_print(), els.pretty_print())
+            }
+            CollectionElemKind::For(pattern, iter, elem) => {
+                format!("for {} in {} {{ {} }}", pattern.pretty_print(), iter.pretty_print(), elem.pretty_print())
+            }
+            CollectionElemKind::Spread(base) => format!("..{}", base.pretty_print()),
+        }
+    }
 }
 
 /// Field initializer in struct literal
@@ -464,6 +651,52 @@ impl std::fmt::Display for UnaryOp {
     }
 }
 
+/// Compiler intrinsics, written `#name(T, args...)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Intrinsic {
+    /// `#size_of(T)`: bytes a `T` takes in memory
+    SizeOf,
+    /// `#align_of(T)`: alignment of a `T` in memory
+    AlignOf,
+    /// `#read(T, addr)`: move the `T` stored at `addr` out of memory
+    Read,
+    /// `#write(T, addr, value)`: move `value` into memory at `addr`,
+    /// without dropping what was there
+    Write,
+}
+
+impl Intrinsic {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "size_of" => Some(Intrinsic::SizeOf),
+            "align_of" => Some(Intrinsic::AlignOf),
+            "read" => Some(Intrinsic::Read),
+            "write" => Some(Intrinsic::Write),
+            _ => None,
+        }
+    }
+
+    /// Number of arguments after the type
+    pub fn arity(self) -> usize {
+        match self {
+            Intrinsic::SizeOf | Intrinsic::AlignOf => 0,
+            Intrinsic::Read => 1,
+            Intrinsic::Write => 2,
+        }
+    }
+}
+
+impl std::fmt::Display for Intrinsic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Intrinsic::SizeOf => write!(f, "#size_of"),
+            Intrinsic::AlignOf => write!(f, "#align_of"),
+            Intrinsic::Read => write!(f, "#read"),
+            Intrinsic::Write => write!(f, "#write"),
+        }
+    }
+}
+
 /// Type expressions
 #[derive(Debug, Clone)]
 pub struct TypeExpr {
@@ -483,6 +716,8 @@ pub enum TypeKind {
     Array(Box<TypeExpr>, Box<Expr>),
     /// Tuple type: (T, U, V)
     Tuple(Vec<TypeExpr>),
+    /// Function type: fn(T, U) -> R or (T, U) -> R
+    Function(Vec<TypeExpr>, Box<TypeExpr>),  // (params, return type)
     /// Unit type: ()
     Unit,
 }
@@ -548,6 +783,10 @@ impl Item {
             Item::Enum(e) => e.pretty_print(indent),
             Item::Trait(t) => t.pretty_print(indent),
             Item::Impl(i) => i.pretty_print(indent),
+            Item::Cfg(c) => {
+                let ind = "  ".repeat(indent);
+                format!("{}@cfg({})\n{}", ind, c.predicate.pretty_print(), c.item.pretty_print(indent))
+            }
         }
     }
 }
@@ -597,7 +836,8 @@ impl FnDef {
             format!("<{}>", params.join(", "))
         };
         
-        let mut out = format!("{}{}FnDef '{}{}'\n", ind, pub_str, self.name.name, generics);
+        let attr_str = if self.is_test { "@test " } else { "" };
+        let mut out = format!("{}{}{}FnDef '{}{}'\n", ind, attr_str, pub_str, self.name.name, generics);
         
         if !self.params.is_empty() {
             out.push_str(&format!("{}  params:\n", ind));
@@ -636,7 +876,8 @@ impl StructDef {
             format!("<{}>", params.join(", "))
         };
         
-        let mut out = format!("{}{}StructDef '{}{}'\n", ind, pub_str, self.name.name, generics);
+        let attr_str = if self.has_defaults { "@defaults " } else { "" };
+        let mut out = format!("{}{}{}StructDef '{}{}'\n", ind, attr_str, pub_str, self.name.name, generics);
         for field in &self.fields {
             out.push_str(&format!("{}  {}: {}\n", ind, field.name.name, field.ty.pretty_print()));
         }
@@ -648,7 +889,8 @@ impl EnumDef {
     pub fn pretty_print(&self, indent: usize) -> String {
         let ind = "  ".repeat(indent);
         let pub_str = if self.is_pub { "pub " } else { "" };
-        let mut out = format!("{}{}EnumDef '{}'\n", ind, pub_str, self.name.name);
+        let attr_str = if self.is_non_exhaustive { "@non_exhaustive " } else { "" };
+        let mut out = format!("{}{}{}EnumDef '{}'\n", ind, attr_str, pub_str, self.name.name);
         for variant in &self.variants {
             if variant.fields.is_empty() {
                 out.push_str(&format!("{}  {}\n", ind, variant.name.name));
@@ -710,7 +952,7 @@ impl Stmt {
             Stmt::Let(l) => {
                 let mut_str = if l.is_mut { "mut " } else { "" };
                 let ty_str = l.ty.as_ref().map(|t| format!(": {}", t.pretty_print())).unwrap_or_default();
-                let mut out = format!("{}Let {}{}{}", ind, mut_str, l.name.name, ty_str);
+                let mut out = format!("{}Let {}{}{}", ind, mut_str, l.pattern.pretty_print(), ty_str);
                 if let Some(init) = &l.init {
                     out.push_str(" =\n");
                     out.push_str(&init.pretty_print_indented(indent + 1));
@@ -724,6 +966,11 @@ impl Stmt {
                 out.push_str(&e.expr.pretty_print_indented(indent + 1));
                 out
             }
+            Stmt::Defer(d) => {
+                let mut out = format!("{}Defer\n", ind);
+                out.push_str(&d.body.pretty_print(indent + 1));
+                out
+            }
         }
     }
 }
@@ -766,12 +1013,17 @@ impl Expr {
                 out.push_str(&e.pretty_print_indented(indent + 1));
                 out
             }
-            ExprKind::StructLit(name, fields) => {
-                let mut out = format!("{}StructLit({})\n", ind, name.name);
+            ExprKind::StructLit(name, fields, spread) => {
+                let name = name.as_ref().map_or("_", |n| n.name.as_str());
+                let mut out = format!("{}StructLit({})\n", ind, name);
                 for field in fields {
                     out.push_str(&format!("{}.{}:\n", "  ".repeat(indent + 1), field.name.name));
                     out.push_str(&field.value.pretty_print_indented(indent + 2));
                 }
+                if let Some(spread) = spread {
+                    out.push_str(&format!("{}..\n", "  ".repeat(indent + 1)));
+                    out.push_str(&spread.pretty_print_indented(indent + 2));
+                }
                 out
             }
             ExprKind::If(cond, then_block, else_branch) => {
@@ -789,25 +1041,47 @@ impl Expr {
                 }
                 out
             }
-            ExprKind::While(cond, body) => {
-                let mut out = format!("{}While\n", ind);
+            ExprKind::While(label, cond, body) => {
+                let mut out = format!("{}While{}\n", ind, label_suffix(label));
                 out.push_str(&format!("{}condition:\n", "  ".repeat(indent + 1)));
                 out.push_str(&cond.pretty_print_indented(indent + 2));
                 out.push_str(&format!("{}body:\n", "  ".repeat(indent + 1)));
                 out.push_str(&body.pretty_print(indent + 2));
                 out
             }
-            ExprKind::For(binding, iter, body) => {
-                let mut out = format!("{}For\n", ind);
-                out.push_str(&format!("{}binding: {}\n", "  ".repeat(indent + 1), binding.name));
+            ExprKind::For(label, pattern, iter, body) => {
+                let mut out = format!("{}For{}\n", ind, label_suffix(label));
+                out.push_str(&format!("{}binding: {}\n", "  ".repeat(indent + 1), pattern.pretty_print()));
                 out.push_str(&format!("{}iter:\n", "  ".repeat(indent + 1)));
                 out.push_str(&iter.pretty_print_indented(indent + 2));
                 out.push_str(&format!("{}body:\n", "  ".repeat(indent + 1)));
                 out.push_str(&body.pretty_print(indent + 2));
                 out
             }
-            ExprKind::Block(block) => {
-                let mut out = format!("{}Block\n", ind);
+            ExprKind::Loop(label, body) => {
+                let mut out = format!("{}Loop{}\n", ind, label_suffix(label));
+                out.push_str(&format!("{}body:\n", "  ".repeat(indent + 1)));
+                out.push_str(&body.pretty_print(indent + 2));
+                out
+            }
+            ExprKind::Return(value) => {
+                let mut out = format!("{}Return\n", ind);
+                if let Some(value) = value {
+                    out.push_str(&value.pretty_print_indented(indent + 1));
+                }
+                out
+            }
+            ExprKind::Break(label, value) => {
+                let mut out = format!("{}Break{}\n", ind, label_suffix(label));
+                if let Some(value) = value {
+                    out.push_str(&value.pretty_print_indented(indent + 1));
+                }
+                out
+            }
+            ExprKind::Continue(label) => format!("{}Continue{}\n", ind, label_suffix(label)),
+            ExprKind::Block(block) | ExprKind::TrailingBlock(block) => {
+                let name = if matches!(self.kind, ExprKind::Block(_)) { "Block" } else { "TrailingBlock" };
+                let mut out = format!("{}{}\n", ind, name);
                 for stmt in &block.stmts {
                     out.push_str(&stmt.pretty_print(indent + 1));
                 }
@@ -852,14 +1126,25 @@ impl Expr {
                 out.push_str(&idx.pretty_print_indented(indent + 2));
                 out
             }
-            ExprKind::ArrayLit(elements) => {
-                let mut out = format!("{}ArrayLit\n", ind);
+            ExprKind::ArrayLit(elements) | ExprKind::MapLit(elements) => {
+                let name = if matches!(self.kind, ExprKind::MapLit(_)) { "MapLit" } else { "ArrayLit" };
+                let mut out = format!("{}{}\n", ind, name);
+                for elem in elements {
+                    match &elem.kind {
+                        CollectionElemKind::Item(e) => out.push_str(&e.pretty_print_indented(indent + 1)),
+                        _ => out.push_str(&format!("{}{}\n", "  ".repeat(indent + 1), elem.pretty_print())),
+                    }
+                }
+                out
+            }
+            ExprKind::Tuple(elements) => {
+                let mut out = format!("{}Tuple\n", ind);
                 for elem in elements {
                     out.push_str(&elem.pretty_print_indented(indent + 1));
                 }
                 out
             }
-            ExprKind::Lambda(params, body) => {
+            ExprKind::Lambda(is_move, params, body) => {
                 let params_str = params.iter()
                     .map(|p| {
                         if let Some(ty) = &p.ty {
@@ -870,13 +1155,25 @@ impl Expr {
                     })
                     .collect::<Vec<_>>()
                     .join(", ");
-                let mut out = format!("{}Lambda(({}))\n", ind, params_str);
+                let mut out = format!("{}Lambda({}({}))\n", ind, if *is_move { "move " } else { "" }, params_str);
                 out.push_str(&body.pretty_print_indented(indent + 1));
                 out
             }
             ExprKind::Cast(expr, ty) => {
                 format!("{}Cast({} as {})\n", ind, expr.pretty_print(), ty.pretty_print())
             }
+            ExprKind::Intrinsic(intrinsic, ty, args) => {
+                let mut out = format!("{}Intrinsic {}({})\n", ind, intrinsic, ty.pretty_print());
+                for arg in args {
+                    out.push_str(&arg.pretty_print_indented(indent + 1));
+                }
+                out
+            }
+            ExprKind::Try(expr) => {
+                let mut out = format!("{}Try\n", ind);
+                out.push_str(&expr.pretty_print_indented(indent + 1));
+                out
+            }
             ExprKind::StringInterp(parts) => {
                 let mut out = format!("{}StringInterp\n", ind);
                 for part in parts {
@@ -910,12 +1207,16 @@ impl Expr {
                 format!("{}({})", callee.pretty_print(), args_str)
             }
             ExprKind::Field(e, f) => format!("{}.{}", e.pretty_print(), f.name),
-            ExprKind::StructLit(name, fields) => {
-                let fields_str = fields.iter()
+            ExprKind::StructLit(name, fields, spread) => {
+                let items_str = fields.iter()
                     .map(|f| format!("{}: {}", f.name.name, f.value.pretty_print()))
+                    .chain(spread.iter().map(|s| format!("..{}", s.pretty_print())))
                     .collect::<Vec<_>>()
                     .join(", ");
-                format!("{} {{ {} }}", name.name, fields_str)
+                match name {
+                    Some(name) => format!("{} {{ {} }}", name.name, items_str),
+                    None => format!("{{ {} }}", items_str),
+                }
             }
             ExprKind::If(cond, then_block, else_branch) => {
                 let then_str = if then_block.stmts.len() == 1 {
@@ -944,9 +1245,21 @@ impl Expr {
                 };
                 format!("if {} {{ {} }}{}", cond.pretty_print(), then_str, else_str)
             }
-            ExprKind::While(cond, _) => format!("while {} {{ ... }}", cond.pretty_print()),
-            ExprKind::For(binding, iter, _) => format!("for {} in {} {{ ... }}", binding.name, iter.pretty_print()),
-            ExprKind::Block(_) => "{ ... }".to_string(),
+            ExprKind::While(label, cond, _) => format!("while{} {} {{ ... }}", label_suffix(label), cond.pretty_print()),
+            ExprKind::For(label, pattern, iter, _) => {
+                format!("for{} {} in {} {{ ... }}", label_suffix(label), pattern.pretty_print(), iter.pretty_print())
+            }
+            ExprKind::Loop(label, _) => format!("loop{} {{ ... }}", label_suffix(label)),
+            ExprKind::Return(value) => match value {
+                Some(value) => format!("return {}", value.pretty_print()),
+                None => "return".to_string(),
+            },
+            ExprKind::Break(label, value) => match value {
+                Some(value) => format!("break{} {}", label_suffix(label), value.pretty_print()),
+                None => format!("break{}", label_suffix(label)),
+            },
+            ExprKind::Continue(label) => format!("continue{}", label_suffix(label)),
+            ExprKind::Block(_) | ExprKind::TrailingBlock(_) => "{ ... }".to_string(),
             ExprKind::Assign(lhs, rhs) => format!("({} = {})", lhs.pretty_print(), rhs.pretty_print()),
             ExprKind::Ref(is_mut, e) => {
                 let mut_str = if *is_mut { "mut " } else { "" };
@@ -961,7 +1274,15 @@ impl Expr {
                 let elems_str = elements.iter().map(|e| e.pretty_print()).collect::<Vec<_>>().join(", ");
                 format!("[{}]", elems_str)
             }
-            ExprKind::Lambda(params, body) => {
+            ExprKind::MapLit(elements) => {
+                let elems_str = elements.iter().map(|e| e.pretty_print()).collect::<Vec<_>>().join(", ");
+                format!("{{ {} }}", elems_str)
+            }
+            ExprKind::Tuple(elements) => {
+                let elems_str = elements.iter().map(|e| e.pretty_print()).collect::<Vec<_>>().join(", ");
+                if elements.len() == 1 { format!("({},)", elems_str) } else { format!("({})", elems_str) }
+            }
+            ExprKind::Lambda(is_move, params, body) => {
                 let params_str = params.iter()
                     .map(|p| {
                         if let Some(ty) = &p.ty {
@@ -972,11 +1293,20 @@ impl Expr {
                     })
                     .collect::<Vec<_>>()
                     .join(", ");
-                format!("({}) -> {}", params_str, body.pretty_print())
+                format!("{}({}) -> {}", if *is_move { "move " } else { "" }, params_str, body.pretty_print())
             }
             ExprKind::Cast(expr, ty) => {
                 format!("{} as {}", expr.pretty_print(), ty.pretty_print())
             }
+            ExprKind::Intrinsic(intrinsic, ty, args) => {
+                let args: Vec<_> = std::iter::once(ty.pretty_print())
+                    .chain(args.iter().map(|a| a.pretty_print()))
+                    .collect();
+                format!("{}({})", intrinsic, args.join(", "))
+            }
+            ExprKind::Try(expr) => {
+                format!("{}?", expr.pretty_print())
+            }
             ExprKind::StringInterp(parts) => {
                 let mut out = String::from("\"");
                 for part in parts {
@@ -996,6 +1326,11 @@ impl Expr {
     }
 }
 
+/// Format an optional loop label as its `@label` suffix
+fn label_suffix(label: &Option<Ident>) -> String {
+    label.as_ref().map(|l| format!("@{}", l.name)).unwrap_or_default()
+}
+
 impl TypeExpr {
     pub fn pretty_print(&self) -> String {
         match &self.kind {
@@ -1017,7 +1352,11 @@ impl TypeExpr {
             }
             TypeKind::Tuple(elems) => {
                 let elems_str = elems.iter().map(|e| e.pretty_print()).collect::<Vec<_>>().join(", ");
-                format!("({})", elems_str)
+                if elems.len() == 1 { format!("({},)", elems_str) } else { format!("({})", elems_str) }
+            }
+            TypeKind::Function(params, ret) => {
+                let params_str = params.iter().map(|p| p.pretty_print()).collect::<Vec<_>>().join(", ");
+                format!("fn({}) -> {}", params_str, ret.pretty_print())
             }
             TypeKind::Unit => "()".to_string(),
         }
@@ -1038,6 +1377,10 @@ impl Pattern {
                     format!("{}({})", name.name, fields_str)
                 }
             }
+            PatternKind::Tuple(elems) => {
+                let elems_str = elems.iter().map(|p| p.pretty_print()).collect::<Vec<_>>().join(", ");
+                if elems.len() == 1 { format!("({},)", elems_str) } else { format!("({})", elems_str) }
+            }
         }
     }
 }
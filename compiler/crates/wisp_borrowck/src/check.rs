@@ -1,22 +1,24 @@
 //! Borrow checking pass
 
-use crate::state::{BorrowConflict, BorrowState, Place};
-use std::collections::HashSet;
-use wisp_hir::DefId;
+use crate::state::{BorrowConflict, BorrowState, LoanId, Place, Projection};
+use std::collections::{HashMap, HashSet};
+use wisp_diagnostics::{codes, Code, Diagnostic};
+use wisp_hir::{DefId, LoopId};
 use wisp_lexer::Span;
-use wisp_types::{Type, TypedProgram, TypedFunction, TypedExpr, TypedExprKind, TypedStmt, TypedBlock, TypedElse};
+use wisp_types::{CaptureMode, Type, TypedProgram, TypedFunction, TypedExpr, TypedExprKind, TypedCollectElem, TypedForIter, TypedStmt, TypedBlock, TypedElse, TypedPattern};
 
 /// A borrow error
 #[derive(Debug, Clone)]
 pub struct BorrowError {
+    pub code: Code,
     pub message: String,
     pub span: Span,
     pub notes: Vec<(String, Span)>,
 }
 
 impl BorrowError {
-    pub fn new(message: String, span: Span) -> Self {
-        Self { message, span, notes: Vec::new() }
+    pub fn new(code: Code, message: String, span: Span) -> Self {
+        Self { code, message, span, notes: Vec::new() }
     }
 
     pub fn with_note(mut self, message: String, span: Span) -> Self {
@@ -25,15 +27,101 @@ impl BorrowError {
     }
 }
 
+impl From<BorrowError> for Diagnostic {
+    fn from(e: BorrowError) -> Self {
+        e.notes.into_iter().fold(
+            Diagnostic::error(e.code, e.message, e.span),
+            |diagnostic, (note, span)| diagnostic.with_label(span, note),
+        )
+    }
+}
+
+/// Moves out of local variables and their fields, recorded for drop
+/// elaboration so that a moved-out value isn't dropped again by its original
+/// owner
+#[derive(Debug, Clone, Default)]
+pub struct MoveData {
+    /// What each moving expression moves out of, with the function it's in
+    /// and its span. Several moves can share a span, such as the fields a
+    /// record literal's spread reads from a variable.
+    moves: HashSet<(DefId, Span, MovePath)>,
+}
+
+impl MoveData {
+    /// Whether the expression at `span` in function `func` moves out of `path`
+    pub fn is_move(&self, func: DefId, span: Span, path: &MovePath) -> bool {
+        self.moves.contains(&(func, span, path.clone()))
+    }
+}
+
+/// A variable, or a field of one reached through fields only, that a value
+/// can be moved out of
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MovePath {
+    pub var: DefId,
+    pub fields: Vec<String>,
+}
+
+impl MovePath {
+    /// The path `expr` names, if it's a variable or a chain of fields of one.
+    /// Moving out through a reference doesn't affect the variable holding
+    /// it, and array elements are never dropped, so those have none.
+    pub fn of(expr: &TypedExpr) -> Option<MovePath> {
+        match &expr.kind {
+            TypedExprKind::Var { def_id, .. } => Some(MovePath { var: *def_id, fields: Vec::new() }),
+            TypedExprKind::Field { expr: base, field, .. } => {
+                let mut path = Self::of(base)?;
+                path.fields.push(field.clone());
+                Some(path)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// States flowing out of an enclosing loop's body
+struct LoopFlow {
+    id: LoopId,
+    /// States at each `break` targeting this loop
+    break_states: Vec<BorrowState>,
+    /// States at each `continue` targeting this loop
+    continue_states: Vec<BorrowState>,
+}
+
+/// A closure value created in the function being checked
+#[derive(Debug, Clone)]
+struct LocalClosure {
+    /// Where the closure is created
+    span: Span,
+    /// Block depth of the innermost variable the closure borrows, if any
+    borrow_depth: Option<usize>,
+}
+
 /// The borrow checker
 pub struct BorrowChecker<'a> {
     program: &'a TypedProgram,
     state: BorrowState,
     errors: Vec<BorrowError>,
     /// Current function being checked
-    current_fn: Option<String>,
+    current_fn: Option<DefId>,
     /// Types that implement Copy (from type checker)
     copy_types: &'a HashSet<DefId>,
+    /// Whether the current path has diverged (after `return`, `break` or `continue`)
+    diverged: bool,
+    /// Enclosing loops, innermost last
+    loop_stack: Vec<LoopFlow>,
+    /// Variables used by the `defer` block being checked, and whether they are used mutably
+    defer_uses: Option<HashMap<DefId, bool>>,
+    /// Variables captured by each closure being checked, innermost last
+    closure_captures: Vec<HashSet<DefId>>,
+    /// Moves out of variables, for drop elaboration
+    moves: MoveData,
+    /// Variables of the current function that hold closures it creates
+    local_closures: HashMap<DefId, LocalClosure>,
+    /// Block depth each variable of the current function is declared at
+    var_depths: HashMap<DefId, usize>,
+    /// Depth of the block being checked, with parameters at 0
+    block_depth: usize,
 }
 
 impl<'a> BorrowChecker<'a> {
@@ -44,10 +132,18 @@ impl<'a> BorrowChecker<'a> {
             errors: Vec::new(),
             current_fn: None,
             copy_types: &program.copy_types,
+            diverged: false,
+            loop_stack: Vec::new(),
+            defer_uses: None,
+            closure_captures: Vec::new(),
+            moves: MoveData::default(),
+            local_closures: HashMap::new(),
+            var_depths: HashMap::new(),
+            block_depth: 0,
         }
     }
 
-    pub fn check(mut self) -> Result<(), Vec<BorrowError>> {
+    pub fn check(mut self) -> Result<MoveData, Vec<BorrowError>> {
         // Check all functions
         for func in &self.program.functions {
             self.check_function(func);
@@ -61,16 +157,19 @@ impl<'a> BorrowChecker<'a> {
         }
 
         if self.errors.is_empty() {
-            Ok(())
+            Ok(self.moves)
         } else {
             Err(self.errors)
         }
     }
 
     fn check_function(&mut self, func: &TypedFunction) {
-        self.current_fn = Some(func.name.clone());
+        self.current_fn = Some(func.def_id);
         // Reset state for each function
         self.state = BorrowState::new();
+        self.diverged = false;
+        self.local_closures.clear();
+        self.var_depths.clear();
 
         // Declare parameters
         for param in &func.params {
@@ -79,11 +178,17 @@ impl<'a> BorrowChecker<'a> {
             // 2. Its type is `&mut T` (mutable reference)
             let is_mut = param.is_mut || matches!(&param.ty, wisp_types::Type::Ref { is_mut: true, .. });
             self.state.declare_var(param.def_id, param.name.clone(), is_mut, true);
+            self.var_depths.insert(param.def_id, 0);
         }
 
         // Check body
         if let Some(body) = &func.body {
             self.check_block(body);
+            if !self.diverged
+                && let Some(TypedStmt::Expr(tail)) = body.stmts.last()
+            {
+                self.check_returned(tail);
+            }
         }
 
         self.current_fn = None;
@@ -92,14 +197,27 @@ impl<'a> BorrowChecker<'a> {
     fn check_block(&mut self, block: &TypedBlock) {
         // Save state for block scope
         let saved_loans = self.state.active_loans.clone();
+        self.block_depth += 1;
 
         for stmt in &block.stmts {
+            // Code after `return`, `break` or `continue` is unreachable
+            if self.diverged {
+                break;
+            }
             self.check_stmt(stmt);
         }
 
+        // The block's value moves out of its tail expression
+        if !self.diverged
+            && let Some(TypedStmt::Expr(tail)) = block.stmts.last()
+        {
+            self.check_move_or_copy(tail);
+        }
+
         // End loans that were created in this block
         // In a real NLL implementation, we'd track loan lifetimes more precisely
         self.state.active_loans = saved_loans;
+        self.block_depth -= 1;
     }
 
     fn check_stmt(&mut self, stmt: &TypedStmt) {
@@ -114,10 +232,34 @@ impl<'a> BorrowChecker<'a> {
 
                 // Then declare the variable
                 self.state.declare_var(*def_id, name.clone(), *is_mut, init.is_some());
+                self.var_depths.insert(*def_id, self.block_depth);
+                if let Some(closure) = init.as_ref().and_then(|init| self.closure_of(init)) {
+                    self.local_closures.insert(*def_id, closure);
+                }
             }
             TypedStmt::Expr(expr) => {
                 self.check_expr(expr);
             }
+            TypedStmt::Defer { body, span } => {
+                // Check the body against the state where it is declared. It only
+                // runs when the scope exits, so its effects don't flow onward.
+                let before = self.state.clone();
+                let outer_uses = self.defer_uses.replace(HashMap::new());
+                self.check_block(body);
+                let uses = std::mem::replace(&mut self.defer_uses, outer_uses).unwrap_or_default();
+                self.state.restore(&before);
+                self.diverged = false;
+
+                // Everything the block uses from the enclosing scope stays
+                // borrowed until that scope ends
+                for (def_id, is_mut) in uses {
+                    if !before.var_states.contains_key(&def_id) {
+                        continue;
+                    }
+                    self.note_defer_use(&Place::var(def_id), is_mut);
+                    self.state.create_deferred_loan(Place::var(def_id), is_mut, *span);
+                }
+            }
         }
     }
 
@@ -134,6 +276,7 @@ impl<'a> BorrowChecker<'a> {
                 if let Err(conflict) = self.state.can_read(&place) {
                     self.report_conflict(conflict, expr.span);
                 }
+                self.note_defer_use(&place, false);
             }
 
             TypedExprKind::Binary { left, right, .. } => {
@@ -141,9 +284,17 @@ impl<'a> BorrowChecker<'a> {
                 self.check_expr(right);
             }
             
-            TypedExprKind::OperatorCall { left, right, .. } => {
+            TypedExprKind::OperatorCall { method_def_id, left, right, .. } => {
                 self.check_expr(left);
                 self.check_expr(right);
+                
+                // Operands the operator method takes by value are moved
+                let params = self.method_params(*method_def_id);
+                for (operand, param) in [left, right].into_iter().zip(params) {
+                    if !matches!(param, Type::Ref { .. }) {
+                        self.check_move_or_copy(operand);
+                    }
+                }
             }
 
             TypedExprKind::Unary { expr: inner, .. } => {
@@ -160,6 +311,7 @@ impl<'a> BorrowChecker<'a> {
                     // Special handling for reference arguments: track the loan to end it after the call
                     if let TypedExprKind::Ref { expr: inner, is_mut } = &arg.kind {
                         if let Some(place) = self.expr_to_place(inner) {
+                            self.note_defer_use(&place, *is_mut);
                             let can_borrow = if *is_mut {
                                 self.state.can_borrow_mut(&place)
                             } else {
@@ -173,6 +325,9 @@ impl<'a> BorrowChecker<'a> {
                                 arg_loans.push(loan_id);
                             }
                         }
+                    } else if matches!(arg.kind, TypedExprKind::Lambda { .. }) {
+                        // A closure passed to the call only holds its captures during the call
+                        arg_loans.extend(self.check_lambda(arg));
                     } else {
                         self.check_expr(arg);
                         // Non-reference arguments may be moved
@@ -186,12 +341,17 @@ impl<'a> BorrowChecker<'a> {
                 }
             }
             
-            TypedExprKind::MethodCall { receiver, args, .. } => {
-                // Check receiver - it's borrowed, not moved
+            TypedExprKind::MethodCall { receiver, method_def_id, args, .. } => {
                 self.check_expr(receiver);
-                // Create a temporary borrow for the receiver
-                // This borrow only lasts for the duration of the call
-                let loan_id = if let Some(place) = self.expr_to_place(receiver) {
+                let takes_self_by_value = self.method_params(*method_def_id).first()
+                    .is_some_and(|p| !matches!(p, Type::Ref { .. }));
+                
+                // A `self` receiver is moved; otherwise create a temporary borrow
+                // for the receiver that only lasts for the duration of the call
+                let loan_id = if takes_self_by_value {
+                    self.check_move_or_copy(receiver);
+                    None
+                } else if let Some(place) = self.expr_to_place(receiver) {
                     if let Err(conflict) = self.state.can_borrow(&place) {
                         self.report_conflict(conflict, expr.span);
                         None
@@ -202,20 +362,34 @@ impl<'a> BorrowChecker<'a> {
                     None
                 };
                 
+                let mut arg_loans = Vec::new();
                 for arg in args {
+                    if matches!(arg.kind, TypedExprKind::Lambda { .. }) {
+                        arg_loans.extend(self.check_lambda(arg));
+                        continue;
+                    }
                     self.check_expr(arg);
                     // Arguments may be moved
                     self.check_move_or_copy(arg);
                 }
                 
-                // End the temporary borrow after the call
-                if let Some(id) = loan_id {
+                // End the temporary borrows after the call
+                for id in loan_id.into_iter().chain(arg_loans) {
                     self.state.end_loan(id);
                 }
             }
 
             TypedExprKind::Field { expr: base, .. } => {
-                self.check_expr(base);
+                // Reading a field of a variable only needs that field, so the
+                // other fields of a partially moved value stay usable
+                if Self::is_field_path(base) && let Some(place) = self.expr_to_place(expr) {
+                    if let Err(conflict) = self.state.can_read(&place) {
+                        self.report_conflict(conflict, expr.span);
+                    }
+                    self.note_defer_use(&place, false);
+                } else {
+                    self.check_expr(base);
+                }
             }
 
             TypedExprKind::Index { expr: base, index } => {
@@ -223,23 +397,34 @@ impl<'a> BorrowChecker<'a> {
                 self.check_expr(index);
             }
 
-            TypedExprKind::ArrayLit(elements) => {
+            TypedExprKind::ArrayLit(elems) | TypedExprKind::Collect { elems, .. } => {
+                for elem in elems {
+                    self.check_collect_elem(elem);
+                }
+            }
+
+            TypedExprKind::Tuple(elements) => {
                 for elem in elements {
                     self.check_expr(elem);
                     self.check_move_or_copy(elem);
                 }
             }
 
-            TypedExprKind::Lambda { body, .. } => {
-                // For now, just check the lambda body
-                // TODO: proper capture analysis
-                self.check_expr(body);
+            TypedExprKind::Lambda { .. } => {
+                self.check_lambda(expr);
             }
 
             TypedExprKind::Cast { expr, .. } => {
                 self.check_expr(expr);
             }
             
+            TypedExprKind::Intrinsic { args, .. } => {
+                // The value given to #write moves into memory
+                for arg in args {
+                    self.check_move_or_copy(arg);
+                }
+            }
+            
             TypedExprKind::StringInterp { parts } => {
                 for part in parts {
                     if let wisp_types::TypedStringInterpPart::Expr(expr) = part {
@@ -248,36 +433,107 @@ impl<'a> BorrowChecker<'a> {
                 }
             }
 
-            TypedExprKind::StructLit { fields, .. } => {
-                for (_, field_expr) in fields {
+            TypedExprKind::StructLit { fields, base, .. } => {
+                for field_expr in fields.iter().map(|(_, e)| e).chain(base.as_deref()) {
                     self.check_expr(field_expr);
                     self.check_move_or_copy(field_expr);
+                    if let Some(closure) = self.closure_of(field_expr) {
+                        self.report_escaping_closure(
+                            &closure,
+                            "cannot store a closure in a struct field, as the struct may outlive the stack frame holding the closure's environment".to_string(),
+                            field_expr.span,
+                        );
+                    }
                 }
             }
 
             TypedExprKind::If { cond, then_block, else_block } => {
                 self.check_expr(cond);
+                
+                // Each branch starts from the state after the condition
+                let before = self.state.clone();
                 self.check_block(then_block);
+                let then_path = (self.state.clone(), self.diverged);
+                
+                self.state.restore(&before);
+                self.diverged = false;
                 if let Some(else_b) = else_block {
                     self.check_else(else_b);
                 }
+                let else_path = (self.state.clone(), self.diverged);
+                
+                self.join_paths(vec![then_path, else_path]);
             }
 
-            TypedExprKind::While { cond, body } => {
-                self.check_expr(cond);
-                self.check_block(body);
+            TypedExprKind::While { loop_id, cond, body } => {
+                self.check_loop(*loop_id, Some(cond), true, expr.span, |this| this.check_block(body));
             }
 
-            TypedExprKind::For { start, end, body, .. } => {
-                self.check_expr(start);
-                self.check_expr(end);
-                self.check_block(body);
+            TypedExprKind::For { loop_id, binding, binding_name, iter, body } => {
+                let loans = self.check_for_iter(iter);
+                self.check_loop(*loop_id, None, true, expr.span, |this| {
+                    this.start_iteration(iter, *binding, binding_name);
+                    this.check_block(body);
+                });
+                for id in loans {
+                    self.state.end_loan(id);
+                }
+            }
+
+            TypedExprKind::Loop { loop_id, body } => {
+                self.check_loop(*loop_id, None, false, expr.span, |this| this.check_block(body));
+            }
+
+            TypedExprKind::Return(value) => {
+                if let Some(value) = value {
+                    self.check_expr(value);
+                    self.check_move_or_copy(value);
+                    self.check_returned(value);
+                }
+                self.diverged = true;
+            }
+
+            TypedExprKind::Break { target, value } => {
+                if let Some(value) = value {
+                    self.check_expr(value);
+                    self.check_move_or_copy(value);
+                }
+                let state = self.state.clone();
+                if let Some(flow) = self.loop_stack.iter_mut().rev().find(|l| l.id == *target) {
+                    flow.break_states.push(state);
+                }
+                self.diverged = true;
+            }
+
+            TypedExprKind::Continue { target } => {
+                let state = self.state.clone();
+                if let Some(flow) = self.loop_stack.iter_mut().rev().find(|l| l.id == *target) {
+                    flow.continue_states.push(state);
+                }
+                self.diverged = true;
             }
 
             TypedExprKind::Match { scrutinee, arms } => {
                 self.check_expr(scrutinee);
+                // Binding part of the value by value takes it out of the scrutinee
+                if arms.iter().any(|arm| self.binds_non_copy(&arm.pattern)) {
+                    self.check_move_or_copy(scrutinee);
+                }
+                
+                // Each arm starts from the state after the scrutinee
+                let before = self.state.clone();
+                let mut paths = Vec::new();
                 for arm in arms {
+                    self.state.restore(&before);
+                    self.diverged = false;
                     self.check_expr(&arm.body);
+                    if !self.diverged {
+                        self.check_move_or_copy(&arm.body);
+                    }
+                    paths.push((self.state.clone(), self.diverged));
+                }
+                if !paths.is_empty() {
+                    self.join_paths(paths);
                 }
             }
 
@@ -291,9 +547,15 @@ impl<'a> BorrowChecker<'a> {
 
                 // Then check we can write to target
                 if let Some(place) = self.expr_to_place(target) {
+                    // Assigning a whole variable gives it a new value, even if the old one was moved
+                    if place.projections.is_empty() && self.state.get_state(place.base).is_some() {
+                        self.state.initialize(place.base);
+                    }
+
                     // Check mutability
                     if !self.is_place_mutable(&place) {
                         self.errors.push(BorrowError::new(
+                            codes::ASSIGN_TO_IMMUTABLE,
                             format!("cannot assign to `{}`, as it is not declared as mutable",
                                 place.display(&self.state.var_names)),
                             expr.span,
@@ -304,6 +566,11 @@ impl<'a> BorrowChecker<'a> {
                     if let Err(conflict) = self.state.can_write(&place) {
                         self.report_conflict(conflict, expr.span);
                     }
+                    self.note_defer_use(&place, true);
+
+                    if let Some(closure) = self.closure_of(value) {
+                        self.check_closure_assignment(&place, closure, expr.span);
+                    }
                 }
 
                 // Value may be moved
@@ -316,6 +583,7 @@ impl<'a> BorrowChecker<'a> {
                 // The borrow itself is what we need to validate
                 
                 if let Some(place) = self.expr_to_place(inner) {
+                    self.note_defer_use(&place, *is_mut);
                     if *is_mut {
                         // Mutable borrow - check we can take a mutable borrow
                         if let Err(conflict) = self.state.can_borrow_mut(&place) {
@@ -345,6 +613,7 @@ impl<'a> BorrowChecker<'a> {
                 for arg in args {
                     if let TypedExprKind::Ref { expr: inner, is_mut } = &arg.kind {
                         if let Some(place) = self.expr_to_place(inner) {
+                            self.note_defer_use(&place, *is_mut);
                             let can_borrow = if *is_mut {
                                 self.state.can_borrow_mut(&place)
                             } else {
@@ -379,6 +648,7 @@ impl<'a> BorrowChecker<'a> {
                 for arg in args {
                     if let TypedExprKind::Ref { expr: inner, is_mut } = &arg.kind {
                         if let Some(place) = self.expr_to_place(inner) {
+                            self.note_defer_use(&place, *is_mut);
                             let can_borrow = if *is_mut {
                                 self.state.can_borrow_mut(&place)
                             } else {
@@ -411,6 +681,7 @@ impl<'a> BorrowChecker<'a> {
                 for arg in args {
                     if let TypedExprKind::Ref { expr: inner, is_mut } = &arg.kind {
                         if let Some(place) = self.expr_to_place(inner) {
+                            self.note_defer_use(&place, *is_mut);
                             let can_borrow = if *is_mut {
                                 self.state.can_borrow_mut(&place)
                             } else {
@@ -445,6 +716,7 @@ impl<'a> BorrowChecker<'a> {
                 for arg in args {
                     if let TypedExprKind::Ref { expr: inner, is_mut } = &arg.kind {
                         if let Some(place) = self.expr_to_place(inner) {
+                            self.note_defer_use(&place, *is_mut);
                             let can_borrow = if *is_mut {
                                 self.state.can_borrow_mut(&place)
                             } else {
@@ -474,6 +746,158 @@ impl<'a> BorrowChecker<'a> {
         }
     }
     
+    /// Continue after a control-flow join point: the state is the merge of
+    /// every path that reaches it. If none does, the join point is unreachable.
+    fn join_paths(&mut self, paths: Vec<(BorrowState, bool)>) {
+        let mut live = paths.into_iter().filter(|(_, diverged)| !diverged).map(|(state, _)| state);
+        match live.next() {
+            Some(first) => {
+                self.state.restore(&first);
+                for state in live {
+                    self.state.merge(&state);
+                }
+                self.diverged = false;
+            }
+            None => self.diverged = true,
+        }
+    }
+
+    fn check_collect_elem(&mut self, elem: &TypedCollectElem) {
+        match elem {
+            TypedCollectElem::Item(value) | TypedCollectElem::Spread(value) => {
+                self.check_expr(value);
+                self.check_move_or_copy(value);
+            }
+            TypedCollectElem::If { cond, then_elem, else_elem } => {
+                self.check_expr(cond);
+                
+                let before = self.state.clone();
+                self.check_collect_elem(then_elem);
+                let then_path = (self.state.clone(), self.diverged);
+                
+                self.state.restore(&before);
+                self.diverged = false;
+                if let Some(else_elem) = else_elem {
+                    self.check_collect_elem(else_elem);
+                }
+                let else_path = (self.state.clone(), self.diverged);
+                
+                self.join_paths(vec![then_path, else_path]);
+            }
+            TypedCollectElem::For { loop_id, binding, binding_name, iter, bindings, elem, span } => {
+                let loans = self.check_for_iter(iter);
+                self.check_loop(*loop_id, None, true, *span, |this| {
+                    this.start_iteration(iter, *binding, binding_name);
+                    for stmt in bindings {
+                        this.check_stmt(stmt);
+                    }
+                    this.check_collect_elem(elem);
+                });
+                for id in loans {
+                    self.state.end_loan(id);
+                }
+            }
+        }
+    }
+
+    /// What a `for` loop iterates is evaluated once, before the loop. An array
+    /// or iterator is moved into the loop. Returns the borrows it creates,
+    /// which last until the loop ends.
+    fn check_for_iter(&mut self, iter: &TypedForIter) -> Vec<LoanId> {
+        let outer_loans: HashSet<LoanId> = self.state.active_loans.keys().copied().collect();
+        match iter {
+            TypedForIter::Range { start, end } => {
+                self.check_expr(start);
+                self.check_expr(end);
+            }
+            TypedForIter::Array(array) => {
+                self.check_expr(array);
+                self.check_move_or_copy(array);
+            }
+            TypedForIter::Next { iter, iter_def, .. } => {
+                self.check_expr(iter);
+                self.check_move_or_copy(iter);
+                self.state.declare_var(*iter_def, "iter".to_string(), true, true);
+            }
+        }
+        self.state.active_loans.keys().filter(|id| !outer_loans.contains(id)).copied().collect()
+    }
+
+    /// Each iteration gets the next item, binding a new value
+    fn start_iteration(&mut self, iter: &TypedForIter, binding: DefId, binding_name: &str) {
+        if let TypedForIter::Next { next, .. } = iter {
+            self.check_expr(next);
+        }
+        self.state.declare_var(binding, binding_name.to_string(), false, true);
+    }
+
+    /// Check a loop body until the moves flowing back to the loop head reach
+    /// a fixpoint, so that a value moved in one iteration is reported when
+    /// it is used again in the next one.
+    fn check_loop(&mut self, id: LoopId, cond: Option<&TypedExpr>, has_normal_exit: bool, loop_span: Span, body: impl Fn(&mut Self)) {
+        let first_error = self.errors.len();
+        let mut entry = self.state.clone();
+        let mut is_repeat = false;
+        
+        loop {
+            let pass_start = self.errors.len();
+            self.state.restore(&entry);
+            self.diverged = false;
+            
+            self.loop_stack.push(LoopFlow { id, break_states: Vec::new(), continue_states: Vec::new() });
+            if let Some(cond) = cond {
+                self.check_expr(cond);
+            }
+            let head = self.state.clone();
+            body(self);
+            let flow = self.loop_stack.pop().unwrap();
+            
+            if is_repeat {
+                self.retain_new_loop_errors(first_error, pass_start, loop_span);
+            }
+            
+            // States that reach the next iteration
+            let mut next_entry = entry.clone();
+            let mut changed = false;
+            for state in &flow.continue_states {
+                changed |= next_entry.merge(state);
+            }
+            if !self.diverged {
+                changed |= next_entry.merge(&self.state);
+            }
+            
+            if !changed {
+                let mut exits: Vec<_> = flow.break_states.into_iter().map(|s| (s, false)).collect();
+                if has_normal_exit {
+                    exits.push((head, false));
+                }
+                self.join_paths(exits);
+                return;
+            }
+            entry = next_entry;
+            is_repeat = true;
+        }
+    }
+
+    /// Drop errors from a repeated loop pass that were already reported,
+    /// and point the remaining use-after-move errors at the previous iteration
+    fn retain_new_loop_errors(&mut self, first_error: usize, pass_start: usize, loop_span: Span) {
+        let new_errors = self.errors.split_off(pass_start);
+        for mut error in new_errors {
+            let duplicate = self.errors[first_error..].iter()
+                .any(|e| e.message == error.message && e.span == error.span);
+            if duplicate {
+                continue;
+            }
+            for (note, span) in &mut error.notes {
+                if note == "value moved here" && span.start >= loop_span.start && span.end <= loop_span.end {
+                    *note = "value moved here, in previous iteration of loop".to_string();
+                }
+            }
+            self.errors.push(error);
+        }
+    }
+
     fn check_else(&mut self, else_branch: &TypedElse) {
         match else_branch {
             TypedElse::Block(block) => self.check_block(block),
@@ -481,6 +905,129 @@ impl<'a> BorrowChecker<'a> {
         }
     }
 
+    /// Check a closure and take its captures, returning the loans it holds
+    fn check_lambda(&mut self, expr: &TypedExpr) -> Vec<LoanId> {
+        let TypedExprKind::Lambda { captures, body, .. } = &expr.kind else {
+            return Vec::new();
+        };
+
+        // Check the body against the state where the closure is created. It
+        // runs when the closure is called, so its effects don't flow onward.
+        let before = self.state.clone();
+        let outer_loops = std::mem::take(&mut self.loop_stack);
+        self.closure_captures.push(captures.iter().map(|c| c.def_id).collect());
+        self.check_expr(body);
+        if !self.diverged {
+            self.check_returned(body);
+        }
+        self.closure_captures.pop();
+        self.loop_stack = outer_loops;
+        self.state.restore(&before);
+        // A `return` inside the lambda doesn't leave the enclosing function
+        self.diverged = false;
+
+        // Captured values are moved into the closure, or copied if they're
+        // Copy; captured references stay borrowed for as long as the closure
+        // is in scope
+        let mut loans = Vec::new();
+        for capture in captures {
+            let place = Place::var(capture.def_id);
+            let copied = capture.mode == CaptureMode::Move && self.is_type_copy(&capture.ty);
+            self.note_defer_use(&place, capture.mode != CaptureMode::Ref && !copied);
+            let result = match capture.mode {
+                CaptureMode::Move if copied => self.state.can_read(&place),
+                CaptureMode::Move => self.state.can_move(&place),
+                // Assignments in the body already reported an immutable variable
+                CaptureMode::MutRef => self.state.can_write(&place),
+                CaptureMode::Ref => self.state.can_borrow(&place),
+            };
+            if let Err(conflict) = result {
+                self.report_conflict(conflict, expr.span);
+                continue;
+            }
+            match capture.mode {
+                CaptureMode::Move if copied => {}
+                CaptureMode::Move => self.state.move_place(&place, expr.span),
+                mode => loans.push(self.state.create_closure_loan(place, mode == CaptureMode::MutRef, expr.span)),
+            }
+        }
+        loans
+    }
+
+    /// The closure `expr` evaluates to, if it's one created in the current
+    /// function. Closures passed in as parameters live in a caller's frame.
+    fn closure_of(&self, expr: &TypedExpr) -> Option<LocalClosure> {
+        match &expr.kind {
+            TypedExprKind::Lambda { captures, .. } => Some(LocalClosure {
+                span: expr.span,
+                // A moved value that isn't Copy stays in its variable's slot
+                borrow_depth: captures.iter()
+                    .filter(|c| c.mode != CaptureMode::Move || !self.is_type_copy(&c.ty))
+                    .map(|c| self.var_depths.get(&c.def_id).copied().unwrap_or(0))
+                    .max(),
+            }),
+            TypedExprKind::Var { def_id, .. } => self.local_closures.get(def_id).cloned(),
+            TypedExprKind::Block(block) => self.closure_of_block(block),
+            TypedExprKind::If { then_block, else_block, .. } => {
+                self.closure_of_block(then_block).or_else(|| match else_block.as_ref()? {
+                    TypedElse::Block(block) => self.closure_of_block(block),
+                    TypedElse::If(if_expr) => self.closure_of(if_expr),
+                })
+            }
+            TypedExprKind::Match { arms, .. } => arms.iter().find_map(|arm| self.closure_of(&arm.body)),
+            TypedExprKind::Tuple(elements) => elements.iter().find_map(|elem| self.closure_of(elem)),
+            _ => None,
+        }
+    }
+
+    fn closure_of_block(&self, block: &TypedBlock) -> Option<LocalClosure> {
+        match block.stmts.last() {
+            Some(TypedStmt::Expr(tail)) => self.closure_of(tail),
+            _ => None,
+        }
+    }
+
+    /// Check that a value returned from the current function or closure
+    /// isn't a closure whose environment is in its stack frame
+    fn check_returned(&mut self, value: &TypedExpr) {
+        if let Some(closure) = self.closure_of(value) {
+            self.report_escaping_closure(
+                &closure,
+                "cannot return a closure from the function that creates it, as its environment lives in that function's stack frame".to_string(),
+                value.span,
+            );
+        }
+    }
+
+    /// Check that a closure assigned to `place` doesn't outlive its
+    /// environment or the variables it borrows
+    fn check_closure_assignment(&mut self, place: &Place, closure: LocalClosure, span: Span) {
+        let target = place.display(&self.state.var_names);
+        let message = if place.projections.contains(&Projection::Deref) {
+            "cannot store a closure through a reference, as its environment lives in this function's stack frame".to_string()
+        } else if place.projections.iter().any(|p| matches!(p, Projection::Field(_))) {
+            "cannot store a closure in a struct field, as the struct may outlive the stack frame holding the closure's environment".to_string()
+        } else if self.closure_captures.last().is_some_and(|captures| captures.contains(&place.base)) {
+            format!("cannot assign a closure to `{}`, which outlives the closure body that creates it", target)
+        } else if closure.borrow_depth.is_some_and(|depth| self.var_depths.get(&place.base).is_some_and(|&target_depth| depth > target_depth)) {
+            format!("cannot assign a closure to `{}`, as it borrows variables that don't live as long as `{}`", target, target)
+        } else {
+            if place.projections.is_empty() {
+                self.local_closures.insert(place.base, closure);
+            }
+            return;
+        };
+        self.report_escaping_closure(&closure, message, span);
+    }
+
+    fn report_escaping_closure(&mut self, closure: &LocalClosure, message: String, span: Span) {
+        let mut error = BorrowError::new(codes::ESCAPING_CLOSURE, message, span);
+        if closure.span != span {
+            error = error.with_note("the closure is created here".to_string(), closure.span);
+        }
+        self.errors.push(error);
+    }
+
     /// Check if an expression results in a move (for non-Copy types)
     fn check_move_or_copy(&mut self, expr: &TypedExpr) {
         // For now, we'll consider primitive types as Copy
@@ -490,12 +1037,81 @@ impl<'a> BorrowChecker<'a> {
         }
 
         if let Some(place) = self.expr_to_place(expr) {
+            // Functions and unit variants are items, not variables: using them moves nothing
+            if self.is_item(place.base) {
+                return;
+            }
             // Check if already moved (error already reported in check_expr)
             if self.state.can_read(&place).is_err() {
                 return;
             }
+            if let Err(conflict) = self.state.can_move(&place) {
+                self.report_conflict(conflict, expr.span);
+                return;
+            }
+            // A closure can be called more than once, so it can't give its captures away
+            if self.closure_captures.last().is_some_and(|captures| captures.contains(&place.base))
+                && !place.projections.contains(&Projection::Deref)
+            {
+                let name = place.display(&self.state.var_names);
+                self.errors.push(BorrowError::new(
+                    codes::MOVE_OUT_OF_CAPTURE,
+                    format!("cannot move out of `{}`, a captured variable in a closure", name),
+                    expr.span,
+                ));
+                return;
+            }
+            // The rest of a value whose type has a `drop` method can't be
+            // dropped without it
+            if let Some(owner) = self.drop_type_moved_out_of(expr) {
+                let name = place.display(&self.state.var_names);
+                let type_name = self.program.ctx.get_type_name(owner).unwrap_or_default();
+                self.errors.push(BorrowError::new(
+                    codes::MOVE_OUT_OF_DROP,
+                    format!("cannot move out of `{}`, a field of `{}`, which implements `Drop`", name, type_name),
+                    expr.span,
+                ));
+                return;
+            }
             // Mark as moved
             self.state.move_place(&place, expr.span);
+            
+            if let Some(func) = self.current_fn
+                && let Some(path) = MovePath::of(expr)
+            {
+                self.moves.moves.insert((func, expr.span, path));
+            }
+        }
+    }
+
+    /// The struct implementing `Drop` that a field expression moves out of, if any
+    fn drop_type_moved_out_of(&self, expr: &TypedExpr) -> Option<DefId> {
+        let TypedExprKind::Field { expr: base, .. } = &expr.kind else { return None };
+        match &base.ty {
+            Type::Struct { def_id, .. } if self.program.drop_impls.contains_key(def_id) => Some(*def_id),
+            _ => self.drop_type_moved_out_of(base),
+        }
+    }
+
+    /// Whether a DefId names a function or enum variant rather than a variable
+    fn is_item(&self, def_id: DefId) -> bool {
+        self.program.ctx.is_enum_variant(def_id).is_some()
+            || self.program.functions.iter().any(|f| f.def_id == def_id)
+            || self.program.extern_functions.iter().any(|f| f.def_id == def_id)
+    }
+
+    /// Parameter types of a method, including `self`
+    fn method_params(&self, method_def_id: DefId) -> Vec<Type> {
+        match self.program.ctx.get_def_type(method_def_id) {
+            Some(Type::Function { params, .. }) => params.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Record a use of a place inside the `defer` block being checked
+    fn note_defer_use(&mut self, place: &Place, is_mut: bool) {
+        if let Some(uses) = &mut self.defer_uses {
+            *uses.entry(place.base).or_insert(false) |= is_mut;
         }
     }
 
@@ -511,8 +1127,10 @@ impl<'a> BorrowChecker<'a> {
             Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::I128 |
             Type::U8 | Type::U16 | Type::U32 | Type::U64 | Type::U128 |
             Type::F32 | Type::F64 | Type::Bool | Type::Char | Type::Unit => true,
-            // References are Copy (they're just pointers)
-            Type::Ref { .. } => true,
+            // References, slices and `str` are Copy (they're just pointers)
+            Type::Ref { .. } | Type::Slice(_) | Type::Str => true,
+            // Tuples are Copy if all their elements are
+            Type::Tuple(elems) => elems.iter().all(|t| self.is_type_copy(t)),
             // Structs are Copy if they have impl Copy
             Type::Struct { def_id, .. } => self.copy_types.contains(def_id),
             // Enums are Copy if they have impl Copy
@@ -524,6 +1142,17 @@ impl<'a> BorrowChecker<'a> {
         }
     }
 
+    /// Whether a pattern binds a value that isn't Copy
+    fn binds_non_copy(&self, pattern: &TypedPattern) -> bool {
+        match pattern {
+            TypedPattern::Binding { ty, .. } => !self.is_type_copy(ty),
+            TypedPattern::Variant { fields, .. } | TypedPattern::Tuple(fields) => {
+                fields.iter().any(|field| self.binds_non_copy(field))
+            }
+            TypedPattern::Wildcard | TypedPattern::Literal(_) => false,
+        }
+    }
+
     /// Convert an expression to a Place (if it represents a place in memory)
     fn expr_to_place(&self, expr: &TypedExpr) -> Option<Place> {
         match &expr.kind {
@@ -543,6 +1172,15 @@ impl<'a> BorrowChecker<'a> {
         }
     }
 
+    /// Whether an expression is a variable or a chain of fields of one
+    fn is_field_path(expr: &TypedExpr) -> bool {
+        match &expr.kind {
+            TypedExprKind::Var { .. } => true,
+            TypedExprKind::Field { expr: base, .. } => Self::is_field_path(base),
+            _ => false,
+        }
+    }
+
     /// Check if a place is mutable
     fn is_place_mutable(&self, place: &Place) -> bool {
         // Check if the root variable is mutable
@@ -563,7 +1201,7 @@ impl<'a> BorrowChecker<'a> {
 
     fn report_conflict(&mut self, conflict: BorrowConflict, span: Span) {
         let message = conflict.display(&self.state.var_names);
-        let mut error = BorrowError::new(message, span);
+        let mut error = BorrowError::new(conflict.code(), message, span);
 
         match &conflict {
             BorrowConflict::UseAfterMove { moved_at, .. } => {
@@ -572,9 +1210,16 @@ impl<'a> BorrowChecker<'a> {
             BorrowConflict::UseWhileMutablyBorrowed { loan, .. } |
             BorrowConflict::WriteWhileBorrowed { loan, .. } |
             BorrowConflict::BorrowWhileMutablyBorrowed { loan, .. } |
-            BorrowConflict::MutBorrowWhileBorrowed { loan, .. } => {
-                let borrow_kind = if loan.is_mut { "mutable" } else { "immutable" };
-                error = error.with_note(format!("{} borrow occurs here", borrow_kind), loan.span);
+            BorrowConflict::MutBorrowWhileBorrowed { loan, .. } |
+            BorrowConflict::MoveWhileBorrowed { loan, .. } => {
+                if loan.deferred {
+                    error = error.with_note("borrowed by `defer` block here".to_string(), loan.span);
+                } else if loan.by_closure {
+                    error = error.with_note("borrowed by closure here".to_string(), loan.span);
+                } else {
+                    let borrow_kind = if loan.is_mut { "mutable" } else { "immutable" };
+                    error = error.with_note(format!("{} borrow occurs here", borrow_kind), loan.span);
+                }
             }
             BorrowConflict::BorrowMutOfImmutable { .. } => {}
         }
@@ -6,6 +6,6 @@
 mod state;
 mod check;
 
-pub use check::{BorrowChecker, BorrowError};
+pub use check::{BorrowChecker, BorrowError, MoveData, MovePath};
 pub use state::*;
 
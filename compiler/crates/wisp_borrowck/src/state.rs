@@ -1,8 +1,9 @@
 //! Borrow state tracking
 
+use wisp_diagnostics::{codes, Code};
 use wisp_hir::DefId;
 use wisp_lexer::Span;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 /// Unique identifier for a borrow/loan
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -95,6 +96,10 @@ pub struct Loan {
     pub is_mut: bool,
     /// Where the borrow was created
     pub span: Span,
+    /// Is this held by a `defer` block until the end of its scope?
+    pub deferred: bool,
+    /// Is this held by a closure for as long as the closure is in scope?
+    pub by_closure: bool,
 }
 
 /// State of a variable
@@ -104,8 +109,8 @@ pub enum VarState {
     Valid,
     /// Variable has been moved
     Moved { to: Span },
-    /// Variable is partially moved (some fields moved)
-    PartiallyMoved { fields: HashSet<String> },
+    /// Variable is partially moved: the named fields were moved out, at the given spans
+    PartiallyMoved { fields: HashMap<String, Span> },
     /// Variable is uninitialized
     Uninitialized,
 }
@@ -167,20 +172,66 @@ impl BorrowState {
         self.var_states.get(&def_id)
     }
 
-    /// Move a place (transfer ownership)
+    /// Move a place (transfer ownership). Moving a field out of a variable
+    /// leaves its other fields usable.
     pub fn move_place(&mut self, place: &Place, span: Span) {
+        if let Some(Projection::Field(field)) = place.projections.first()
+            && !place.projections.contains(&Projection::Deref)
+        {
+            match self.var_states.get_mut(&place.base) {
+                Some(VarState::Moved { .. }) => {}
+                Some(VarState::PartiallyMoved { fields }) => {
+                    fields.entry(field.clone()).or_insert(span);
+                }
+                _ => {
+                    let fields = HashMap::from([(field.clone(), span)]);
+                    self.var_states.insert(place.base, VarState::PartiallyMoved { fields });
+                }
+            }
+            return;
+        }
         self.var_states.insert(place.base, VarState::Moved { to: span });
     }
 
+    /// Where the value at a place was moved out, if it was
+    fn moved_at(&self, place: &Place) -> Option<Span> {
+        match self.var_states.get(&place.base)? {
+            VarState::Moved { to } => Some(*to),
+            VarState::PartiallyMoved { fields } => match place.projections.first() {
+                Some(Projection::Field(field)) => fields.get(field).copied(),
+                _ => fields.values().min_by_key(|span| span.start).copied(),
+            },
+            _ => None,
+        }
+    }
+
     /// Create a new loan
     pub fn create_loan(&mut self, place: Place, is_mut: bool, span: Span) -> LoanId {
         let id = LoanId(self.next_loan_id);
         self.next_loan_id += 1;
-        let loan = Loan { id, place, is_mut, span };
+        let loan = Loan { id, place, is_mut, span, deferred: false, by_closure: false };
         self.active_loans.insert(id, loan);
         id
     }
 
+    /// Create a loan held by a `defer` block
+    pub fn create_deferred_loan(&mut self, place: Place, is_mut: bool, span: Span) -> LoanId {
+        let id = self.create_loan(place, is_mut, span);
+        if let Some(loan) = self.active_loans.get_mut(&id) {
+            loan.deferred = true;
+        }
+        id
+    }
+
+    /// Create a loan held by a closure's captures
+    pub fn create_closure_loan(&mut self, place: Place, is_mut: bool, span: Span) -> LoanId {
+        let id = self.create_loan(place, is_mut, span);
+        if let Some(loan) = self.active_loans.get_mut(&id) {
+            loan.by_closure = true;
+        }
+        id
+    }
+
     /// End a loan
     pub fn end_loan(&mut self, id: LoanId) {
         self.active_loans.remove(&id);
@@ -197,6 +248,54 @@ impl BorrowState {
         }
     }
 
+    /// Continue from a state saved at an earlier program point (e.g. when
+    /// checking the next branch of an `if`), keeping loan ids unique
+    pub fn restore(&mut self, saved: &BorrowState) {
+        let next_loan_id = self.next_loan_id;
+        *self = saved.clone();
+        self.next_loan_id = self.next_loan_id.max(next_loan_id);
+    }
+
+    /// Merge the state at the end of another control-flow path into this one.
+    /// A variable moved on either path is moved afterwards.
+    /// Returns true if a variable became moved.
+    pub fn merge(&mut self, other: &BorrowState) -> bool {
+        let mut changed = false;
+        for (def_id, state) in &other.var_states {
+            match (state, self.var_states.get_mut(def_id)) {
+                (_, Some(VarState::Moved { .. })) => {}
+                (VarState::Moved { .. }, _) => {
+                    self.var_states.insert(*def_id, state.clone());
+                    changed = true;
+                }
+                (VarState::PartiallyMoved { fields: theirs }, Some(VarState::PartiallyMoved { fields: mine })) => {
+                    for (field, span) in theirs {
+                        if !mine.contains_key(field) {
+                            mine.insert(field.clone(), *span);
+                            changed = true;
+                        }
+                    }
+                }
+                (VarState::PartiallyMoved { .. }, _) => {
+                    self.var_states.insert(*def_id, state.clone());
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+        for (id, loan) in &other.active_loans {
+            self.active_loans.entry(*id).or_insert_with(|| loan.clone());
+        }
+        for (def_id, name) in &other.var_names {
+            self.var_names.entry(*def_id).or_insert_with(|| name.clone());
+        }
+        for (def_id, is_mut) in &other.var_mutability {
+            self.var_mutability.entry(*def_id).or_insert(*is_mut);
+        }
+        self.next_loan_id = self.next_loan_id.max(other.next_loan_id);
+        changed
+    }
+
     /// Check for conflicting borrows
     pub fn check_borrow_conflicts(&self, place: &Place, is_mut: bool) -> Vec<&Loan> {
         self.active_loans.values()
@@ -213,10 +312,10 @@ impl BorrowState {
     /// Check if a place can be read (not moved, not mutably borrowed)
     pub fn can_read(&self, place: &Place) -> Result<(), BorrowConflict> {
         // Check if moved
-        if let Some(VarState::Moved { to }) = self.var_states.get(&place.base) {
+        if let Some(moved_at) = self.moved_at(place) {
             return Err(BorrowConflict::UseAfterMove {
                 place: place.clone(),
-                moved_at: *to,
+                moved_at,
             });
         }
 
@@ -233,13 +332,29 @@ impl BorrowState {
         Ok(())
     }
 
+    /// Check if a place can be moved out of (not moved, not borrowed at all)
+    pub fn can_move(&self, place: &Place) -> Result<(), BorrowConflict> {
+        self.can_read(place)?;
+
+        for loan in self.active_loans.values() {
+            if loan.place.conflicts_with(place) {
+                return Err(BorrowConflict::MoveWhileBorrowed {
+                    place: place.clone(),
+                    loan: loan.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     /// Check if a place can be written (not borrowed at all, is mutable)
     pub fn can_write(&self, place: &Place) -> Result<(), BorrowConflict> {
         // Check if moved
-        if let Some(VarState::Moved { to }) = self.var_states.get(&place.base) {
+        if let Some(moved_at) = self.moved_at(place) {
             return Err(BorrowConflict::UseAfterMove {
                 place: place.clone(),
-                moved_at: *to,
+                moved_at,
             });
         }
 
@@ -258,8 +373,26 @@ impl BorrowState {
 
     /// Check if a place can be mutably borrowed
     pub fn can_borrow_mut(&self, place: &Place) -> Result<(), BorrowConflict> {
-        // First check if we can write
-        self.can_write(place)?;
+        // Check if moved
+        if let Some(moved_at) = self.moved_at(place) {
+            return Err(BorrowConflict::UseAfterMove {
+                place: place.clone(),
+                moved_at,
+            });
+        }
+
+        // Check for any borrows
+        for loan in self.active_loans.values() {
+            if loan.place.conflicts_with(place) {
+                let loan = loan.clone();
+                let place = place.clone();
+                return Err(if loan.is_mut {
+                    BorrowConflict::BorrowWhileMutablyBorrowed { place, loan }
+                } else {
+                    BorrowConflict::MutBorrowWhileBorrowed { place, loan }
+                });
+            }
+        }
 
         // Check mutability
         if !self.is_mutable(place.base) && place.projections.is_empty() {
@@ -274,10 +407,10 @@ impl BorrowState {
     /// Check if a place can be immutably borrowed
     pub fn can_borrow(&self, place: &Place) -> Result<(), BorrowConflict> {
         // Check if moved
-        if let Some(VarState::Moved { to }) = self.var_states.get(&place.base) {
+        if let Some(moved_at) = self.moved_at(place) {
             return Err(BorrowConflict::UseAfterMove {
                 place: place.clone(),
-                moved_at: *to,
+                moved_at,
             });
         }
 
@@ -327,9 +460,25 @@ pub enum BorrowConflict {
         place: Place,
         loan: Loan,
     },
+    MoveWhileBorrowed {
+        place: Place,
+        loan: Loan,
+    },
 }
 
 impl BorrowConflict {
+    pub fn code(&self) -> Code {
+        match self {
+            BorrowConflict::UseAfterMove { .. } => codes::USE_AFTER_MOVE,
+            BorrowConflict::UseWhileMutablyBorrowed { .. } => codes::USE_WHILE_MUTABLY_BORROWED,
+            BorrowConflict::WriteWhileBorrowed { .. } => codes::ASSIGN_WHILE_BORROWED,
+            BorrowConflict::BorrowWhileMutablyBorrowed { .. } => codes::ALREADY_MUTABLY_BORROWED,
+            BorrowConflict::BorrowMutOfImmutable { .. } => codes::MUT_BORROW_OF_IMMUTABLE,
+            BorrowConflict::MutBorrowWhileBorrowed { .. } => codes::ALREADY_BORROWED,
+            BorrowConflict::MoveWhileBorrowed { .. } => codes::MOVE_WHILE_BORROWED,
+        }
+    }
+
     pub fn display(&self, names: &HashMap<DefId, String>) -> String {
         match self {
             BorrowConflict::UseAfterMove { place, .. } => {
@@ -350,6 +499,9 @@ impl BorrowConflict {
             BorrowConflict::MutBorrowWhileBorrowed { place, .. } => {
                 format!("cannot borrow `{}` as mutable while also borrowed as immutable", place.display(names))
             }
+            BorrowConflict::MoveWhileBorrowed { place, .. } => {
+                format!("cannot move out of `{}` because it is borrowed", place.display(names))
+            }
         }
     }
 }
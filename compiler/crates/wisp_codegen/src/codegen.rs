@@ -1,19 +1,24 @@
 //! Cranelift code generation
 
 use cranelift_codegen::ir::{
-    types, AbiParam, Block, Function, InstBuilder, Signature, Value, FuncRef,
+    types, AbiParam, Block, Function, InstBuilder, Signature, SourceLoc, StackSlot, Value, FuncRef,
 };
-use cranelift_codegen::isa::CallConv;
+use cranelift_codegen::isa::{self, OwnedTargetIsa};
 use cranelift_codegen::settings::{self, Configurable};
 use cranelift_codegen::Context;
 use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
 use cranelift_module::{Linkage, Module, FuncId, DataDescription, DataId};
 use cranelift_object::{ObjectBuilder, ObjectModule};
+use cranelift_jit::{JITBuilder, JITModule};
 use wisp_mir::*;
 use wisp_mir::{AggregateType, Aggregate};
 use wisp_types::Type;
 use wisp_hir::DefId;
+use wisp_lexer::SourceMap;
 use std::collections::HashMap;
+use std::str::FromStr;
+use target_lexicon::{Architecture, OperatingSystem, Triple};
+use crate::debug::{CompiledFunction, DebugContext};
 
 #[derive(Debug)]
 pub struct CodegenError {
@@ -28,9 +33,49 @@ impl std::fmt::Display for CodegenError {
 
 impl std::error::Error for CodegenError {}
 
-/// Code generator using Cranelift
-pub struct Codegen {
-    module: ObjectModule,
+/// How much Cranelift optimizes the code it generates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OptLevel {
+    None,
+    #[default]
+    Speed,
+    SpeedAndSize,
+}
+
+impl OptLevel {
+    /// The name of each level, as written in a profile
+    pub const NAMES: [&str; 3] = ["none", "speed", "speed_and_size"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(OptLevel::None),
+            "speed" => Some(OptLevel::Speed),
+            "speed_and_size" => Some(OptLevel::SpeedAndSize),
+            _ => None,
+        }
+    }
+
+    /// Its name, which is also the value of Cranelift's `opt_level` setting
+    pub fn name(self) -> &'static str {
+        match self {
+            OptLevel::None => "none",
+            OptLevel::Speed => "speed",
+            OptLevel::SpeedAndSize => "speed_and_size",
+        }
+    }
+}
+
+/// Target triples `Codegen::for_target` accepts
+pub const SUPPORTED_TARGETS: [&str; 3] = [
+    "x86_64-unknown-linux-gnu",
+    "aarch64-unknown-linux-gnu",
+    "riscv64gc-unknown-linux-gnu",
+];
+
+/// Code generator using Cranelift, emitting an object file by default or
+/// compiling into memory with a `JITModule`
+pub struct Codegen<M: Module = ObjectModule> {
+    module: M,
     ctx: Context,
     /// Map from function DefId to Cranelift FuncId
     func_ids: HashMap<DefId, FuncId>,
@@ -44,29 +89,85 @@ pub struct Codegen {
     string_counter: u32,
     /// Map from extern static DefId to DataId
     extern_static_data: HashMap<DefId, DataId>,
+    /// DWARF for the root module's functions, when debug info is enabled
+    debug: Option<DebugContext>,
+}
+
+/// Build an ISA with the settings every Wisp module is compiled with
+fn finish_isa(isa_builder: isa::Builder, opt_level: OptLevel) -> Result<OwnedTargetIsa, CodegenError> {
+    let mut flag_builder = settings::builder();
+    flag_builder.set("opt_level", opt_level.name()).map_err(|e| CodegenError {
+        message: format!("Failed to set opt_level: {}", e),
+    })?;
+    // Position-independent code links into PIE executables, the default
+    // for `cc` on macOS and most Linux distributions, and lets JIT code
+    // reach process symbols through a GOT wherever they are mapped
+    flag_builder.set("is_pic", "true").map_err(|e| CodegenError {
+        message: format!("Failed to set is_pic: {}", e),
+    })?;
+
+    let isa = isa_builder
+        .finish(settings::Flags::new(flag_builder))
+        .map_err(|e| CodegenError {
+            message: format!("Failed to create ISA: {}", e),
+        })?;
+    // Places and pointer arithmetic are lowered to i64 values
+    if isa.pointer_bits() != 64 {
+        return Err(CodegenError {
+            message: format!("Unsupported target '{}': only 64-bit targets are supported", isa.triple()),
+        });
+    }
+    Ok(isa)
+}
+
+/// ISA of the machine the compiler runs on
+fn host_isa(opt_level: OptLevel) -> Result<OwnedTargetIsa, CodegenError> {
+    let isa_builder = cranelift_native::builder().map_err(|e| CodegenError {
+        message: format!("Failed to create ISA builder: {}", e),
+    })?;
+    finish_isa(isa_builder, opt_level)
 }
 
 impl Codegen {
+    /// Code generator for the machine the compiler runs on
     pub fn new() -> Result<Self, CodegenError> {
-        let mut flag_builder = settings::builder();
-        flag_builder.set("opt_level", "speed").map_err(|e| CodegenError {
-            message: format!("Failed to set opt_level: {}", e),
-        })?;
-        // Enable PIC for macOS compatibility
-        flag_builder.set("is_pic", "true").map_err(|e| CodegenError {
-            message: format!("Failed to set is_pic: {}", e),
+        Self::with_opt_level(None, OptLevel::default())
+    }
+
+    /// Code generator for a target triple such as `aarch64-unknown-linux-gnu`
+    pub fn for_target(target: &str) -> Result<Self, CodegenError> {
+        Self::with_opt_level(Some(target), OptLevel::default())
+    }
+
+    /// Code generator for `target`, or for the host when no target is given,
+    /// optimizing at `opt_level`
+    pub fn with_opt_level(target: Option<&str>, opt_level: OptLevel) -> Result<Self, CodegenError> {
+        let Some(target) = target else {
+            return Self::for_isa(host_isa(opt_level)?);
+        };
+        let triple = Triple::from_str(target).map_err(|e| CodegenError {
+            message: format!("Invalid target '{}': {}", target, e),
         })?;
-        
-        let isa_builder = cranelift_native::builder().map_err(|e| CodegenError {
-            message: format!("Failed to create ISA builder: {}", e),
+        let supported_arch = matches!(
+            triple.architecture,
+            Architecture::X86_64 | Architecture::Aarch64(_) | Architecture::Riscv64(_)
+        );
+        if !supported_arch || triple.operating_system != OperatingSystem::Linux {
+            return Err(CodegenError {
+                message: format!(
+                    "Unsupported target '{}' (supported targets: {})",
+                    target,
+                    SUPPORTED_TARGETS.join(", "),
+                ),
+            });
+        }
+        let isa_builder = isa::lookup(triple).map_err(|e| CodegenError {
+            message: format!("Failed to create ISA builder for '{}': {}", target, e),
         })?;
-        
-        let isa = isa_builder
-            .finish(settings::Flags::new(flag_builder))
-            .map_err(|e| CodegenError {
-                message: format!("Failed to create ISA: {}", e),
-            })?;
+        Self::for_isa(finish_isa(isa_builder, opt_level)?)
+    }
 
+    fn for_isa(isa: OwnedTargetIsa) -> Result<Self, CodegenError> {
         let builder = ObjectBuilder::new(
             isa,
             "wisp_output",
@@ -75,9 +176,90 @@ impl Codegen {
             message: format!("Failed to create object builder: {}", e),
         })?;
 
-        let module = ObjectModule::new(builder);
+        Ok(Codegen::with_module(ObjectModule::new(builder)))
+    }
+
+    /// Emit DWARF describing the compiled functions, whose spans point into
+    /// the files of `sources`
+    pub fn enable_debug_info(&mut self, sources: &SourceMap) {
+        self.debug = Some(DebugContext::new(self.module.isa(), sources));
+    }
 
-        Ok(Self {
+    /// Finish compilation and return the object code
+    pub fn finish(self) -> Vec<u8> {
+        let mut product = self.module.finish();
+        if let Some(debug) = self.debug {
+            debug.emit(&mut product).expect("Failed to emit debug info");
+        }
+        product.emit().expect("Failed to emit object code")
+    }
+}
+
+impl Codegen<JITModule> {
+    /// Code generator that compiles into memory for the running process.
+    /// Extern functions and statics are resolved from the process's own symbols.
+    pub fn jit() -> Result<Self, CodegenError> {
+        let builder = JITBuilder::with_isa(host_isa(OptLevel::default())?, cranelift_module::default_libcall_names());
+        Ok(Codegen::with_module(JITModule::new(builder)))
+    }
+
+    /// Finalize the compiled program and call its `main`, returning the exit code
+    pub fn run_main(mut self) -> Result<i32, CodegenError> {
+        let (_, func_id) = *self.func_by_name.get("main").ok_or_else(|| CodegenError {
+            message: "No main function to run".to_string(),
+        })?;
+        let sig = &self.module.declarations().get_function_decl(func_id).signature;
+        if !sig.params.is_empty() || sig.returns.len() != 1 || sig.returns[0].value_type != types::I32 {
+            return Err(CodegenError {
+                message: "main must take no parameters and return () or i32 to be run".to_string(),
+            });
+        }
+
+        // The JIT points unresolved imports at null, so report them before running
+        let declarations = self.module.declarations();
+        let imports = declarations.get_functions()
+            .filter(|(_, decl)| decl.linkage == Linkage::Import)
+            .filter_map(|(_, decl)| decl.name.as_deref())
+            .chain(declarations.get_data_objects()
+                .filter(|(_, decl)| decl.linkage == Linkage::Import)
+                .filter_map(|(_, decl)| decl.name.as_deref()));
+        for name in imports {
+            if !process_has_symbol(name) {
+                return Err(CodegenError {
+                    message: format!("Undefined symbol '{}' (not found in the running process)", name),
+                });
+            }
+        }
+
+        self.module.finalize_definitions().map_err(|e| CodegenError {
+            message: format!("Failed to finalize JIT code: {}", e),
+        })?;
+        let code = self.module.get_finalized_function(func_id);
+        // SAFETY: main was compiled with the host's default calling convention
+        // and the signature checked above, and the module outlives the call
+        let main = unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> i32>(code) };
+        Ok(main())
+    }
+}
+
+/// Whether the running process can resolve `name`, the way the JIT looks up imports
+#[cfg(unix)]
+fn process_has_symbol(name: &str) -> bool {
+    let Ok(name) = std::ffi::CString::new(name) else {
+        return false;
+    };
+    // SAFETY: `name` is a valid NUL-terminated string for the duration of the call
+    !unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) }.is_null()
+}
+
+#[cfg(not(unix))]
+fn process_has_symbol(_name: &str) -> bool {
+    true
+}
+
+impl<M: Module> Codegen<M> {
+    fn with_module(module: M) -> Self {
+        Self {
             module,
             ctx: Context::new(),
             func_ids: HashMap::new(),
@@ -86,7 +268,8 @@ impl Codegen {
             string_data: HashMap::new(),
             string_counter: 0,
             extern_static_data: HashMap::new(),
-        })
+            debug: None,
+        }
     }
     
     /// Get or create a data ID for a string literal
@@ -123,7 +306,22 @@ impl Codegen {
     }
 
     /// Compile a MIR program
+    /// Size in bytes of a pointer on the target, which MIR must be lowered with
+    pub fn pointer_size(&self) -> u32 {
+        self.module.isa().pointer_bytes() as u32
+    }
+
     pub fn compile(&mut self, program: &MirProgram) -> Result<(), CodegenError> {
+        if program.pointer_size != self.pointer_size() {
+            return Err(CodegenError {
+                message: format!(
+                    "MIR was lowered for {}-byte pointers but the target uses {}-byte pointers",
+                    program.pointer_size,
+                    self.pointer_size(),
+                ),
+            });
+        }
+
         // First pass: declare all extern functions
         for ext in &program.extern_functions {
             self.declare_extern_function(ext)?;
@@ -151,9 +349,13 @@ impl Codegen {
             func_return_types.insert(func.name.clone(), func.return_type.clone());
         }
 
+        if let Some(debug) = &mut self.debug {
+            debug.set_aggregates(&program.structs, &program.enums);
+        }
+
         // Fourth pass: define all functions
         for func in &program.functions {
-            self.define_function(func, &program.structs, &program.enums, &func_return_types)?;
+            self.define_function(func, &program.structs, &program.enums, &program.drop_fns, &func_return_types)?;
         }
 
         Ok(())
@@ -194,17 +396,22 @@ impl Codegen {
     }
     
     fn collect_strings_from_rvalue(&mut self, rvalue: &Rvalue) -> Result<(), CodegenError> {
-        match rvalue {
-            Rvalue::Use(Operand::Constant(Constant::Str(s))) => {
+        let operands: Vec<&Operand> = match rvalue {
+            Rvalue::Use(op) | Rvalue::UnaryOp { operand: op, .. } | Rvalue::Cast { operand: op, .. } => vec![op],
+            Rvalue::BinaryOp { left, right, .. } => vec![left, right],
+            Rvalue::Aggregate { operands, .. } => operands.iter().collect(),
+            Rvalue::Ref { .. } | Rvalue::Discriminant(_) => Vec::new(),
+        };
+        for op in operands {
+            if let Operand::Constant(Constant::Str(s)) = op {
                 self.get_or_create_string(s)?;
             }
-            _ => {}
         }
         Ok(())
     }
     
     fn declare_extern_function(&mut self, ext: &MirExternFunction) -> Result<(), CodegenError> {
-        let mut sig = Signature::new(CallConv::SystemV);
+        let mut sig = Signature::new(self.module.isa().default_call_conv());
 
         // Add parameters
         for param_ty in &ext.params {
@@ -233,14 +440,14 @@ impl Codegen {
     }
 
     fn declare_function(&mut self, func: &MirFunction) -> Result<(), CodegenError> {
-        let mut sig = Signature::new(CallConv::SystemV);
+        let mut sig = Signature::new(self.module.isa().default_call_conv());
         
         // Check if this function returns an aggregate (struct or enum) - needs sret handling
         let returns_aggregate = matches!(&func.return_type, Type::Struct { .. } | Type::Enum { .. });
         
         // If returning an aggregate, add an implicit sret pointer as first parameter
         if returns_aggregate {
-            sig.params.push(AbiParam::new(types::I64)); // sret pointer
+            sig.params.push(AbiParam::new(self.module.isa().pointer_type())); // sret pointer
         }
 
         // Add parameters
@@ -292,6 +499,7 @@ impl Codegen {
         func: &MirFunction, 
         structs: &HashMap<DefId, MirStruct>, 
         enums: &HashMap<DefId, MirEnum>,
+        drop_fns: &HashMap<DefId, String>,
         func_return_types: &HashMap<String, Type>
     ) -> Result<(), CodegenError> {
         // Look up by name to handle monomorphized functions (which share the same def_id)
@@ -305,11 +513,11 @@ impl Codegen {
         let returns_aggregate = matches!(&func.return_type, Type::Struct { .. } | Type::Enum { .. });
         
         // Build signature
-        let mut sig = Signature::new(CallConv::SystemV);
+        let mut sig = Signature::new(self.module.isa().default_call_conv());
         
         // If returning an aggregate, add an implicit sret pointer as first parameter
         if returns_aggregate {
-            sig.params.push(AbiParam::new(types::I64)); // sret pointer
+            sig.params.push(AbiParam::new(self.module.isa().pointer_type())); // sret pointer
         }
         
         for param in &func.params {
@@ -386,12 +594,18 @@ impl Codegen {
             &struct_names,
             enums,
             &enum_names,
+            drop_fns,
             func_return_types,
             func,
             returns_aggregate,
         );
+        let debug_info = self.debug.is_some();
+        if debug_info {
+            compiler.enable_debug_info();
+        }
 
         compiler.compile()?;
+        let variable_slots = compiler.variable_slots();
 
         builder.finalize();
 
@@ -407,6 +621,14 @@ impl Codegen {
                 message: format!("Failed to define function '{}': {}", func.name, e),
             })?;
 
+        if debug_info
+            && let Some(debug) = &mut self.debug
+            && let Some(code) = self.ctx.compiled_code()
+        {
+            let compiled = CompiledFunction { func, func_id, code, variable_slots };
+            debug.add_function(compiled, self.module.isa());
+        }
+
         self.module.clear_context(&mut self.ctx);
 
         Ok(())
@@ -428,20 +650,14 @@ impl Codegen {
             Type::F64 => types::F64,
             Type::Bool => types::I8,
             Type::Char => types::I32,
-            Type::Str => types::I64, // str is a pointer to C string
+            Type::Str => self.module.isa().pointer_type(), // str is a pointer to C string
             Type::Unit => types::INVALID, // Unit is zero-sized
             Type::Never => types::INVALID, // Never type is unreachable
-            Type::Ref { .. } => types::I64, // Pointers are 64-bit
-            Type::Struct { .. } => types::I64, // Structs passed as pointers for now
-            _ => types::I64, // Default to 64-bit
+            Type::Ref { .. } => self.module.isa().pointer_type(),
+            Type::Struct { .. } => self.module.isa().pointer_type(), // Structs passed as pointers for now
+            _ => self.module.isa().pointer_type(), // Default to pointer-sized
         }
     }
-
-    /// Finish compilation and return the object code
-    pub fn finish(self) -> Vec<u8> {
-        let product = self.module.finish();
-        product.emit().expect("Failed to emit object code")
-    }
 }
 
 impl Default for Codegen {
@@ -462,6 +678,8 @@ struct FunctionCompiler<'a, 'b> {
     struct_names: &'a HashMap<DefId, String>,
     enums: &'a HashMap<DefId, MirEnum>,
     enum_names: &'a HashMap<DefId, String>,
+    /// Types that implement Drop, mapped to the name of their `drop` method
+    drop_fns: &'a HashMap<DefId, String>,
     /// Map from function NAME to return type (name-keyed because monomorphized functions share DefId)
     func_return_types: &'a HashMap<String, Type>,
     mir_func: &'a MirFunction,
@@ -472,6 +690,9 @@ struct FunctionCompiler<'a, 'b> {
     aggregate_slots: HashMap<u32, (cranelift_codegen::ir::StackSlot, DefId, AggregateType)>,
     /// Map from MIR local to (stack slot, elem type, length) for arrays
     array_slots: HashMap<u32, (cranelift_codegen::ir::StackSlot, Type, usize)>,
+    /// Scalar locals whose address is taken, kept in memory so that writes
+    /// through a reference are seen by the local and vice versa
+    scalar_slots: HashMap<u32, (cranelift_codegen::ir::StackSlot, types::Type)>,
     /// Map from MIR block to Cranelift Block
     blocks: HashMap<u32, Block>,
     /// Next variable index
@@ -482,6 +703,11 @@ struct FunctionCompiler<'a, 'b> {
     sret_def_id: Option<DefId>,
     /// Whether this is the main function
     is_main: bool,
+    /// Whether to record source locations for the line table and mirror
+    /// user variables held in Variables to stack slots debuggers can read
+    debug_info: bool,
+    /// Stack slots mirroring the Variables of user variables
+    debug_slots: HashMap<u32, StackSlot>,
 }
 
 impl<'a, 'b> FunctionCompiler<'a, 'b> {
@@ -496,6 +722,7 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
         struct_names: &'a HashMap<DefId, String>,
         enums: &'a HashMap<DefId, MirEnum>,
         enum_names: &'a HashMap<DefId, String>,
+        drop_fns: &'a HashMap<DefId, String>,
         func_return_types: &'a HashMap<String, Type>,
         mir_func: &'a MirFunction,
         returns_aggregate: bool,
@@ -524,19 +751,36 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
             struct_names,
             enums,
             enum_names,
+            drop_fns,
             func_return_types,
             mir_func,
             locals: HashMap::new(),
             aggregate_slots: HashMap::new(),
             array_slots: HashMap::new(),
+            scalar_slots: HashMap::new(),
             blocks: HashMap::new(),
             sret_ptr: None,
             sret_def_id,
             next_var: 0,
             is_main,
+            debug_info: false,
+            debug_slots: HashMap::new(),
         }
     }
 
+    fn enable_debug_info(&mut self) {
+        self.debug_info = true;
+    }
+
+    /// The stack slot each local in memory is kept in, for debug info
+    fn variable_slots(&self) -> Vec<(u32, StackSlot)> {
+        let aggregates = self.aggregate_slots.iter().map(|(&local, &(slot, ..))| (local, slot));
+        let arrays = self.array_slots.iter().map(|(&local, &(slot, ..))| (local, slot));
+        let scalars = self.scalar_slots.iter().map(|(&local, &(slot, _))| (local, slot));
+        let mirrors = self.debug_slots.iter().map(|(&local, &slot)| (local, slot));
+        aggregates.chain(arrays).chain(scalars).chain(mirrors).collect()
+    }
+
     fn compile(&mut self) -> Result<(), CodegenError> {
         // Create blocks
         for block in &self.mir_func.blocks {
@@ -551,6 +795,9 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
         
         self.builder.append_block_params_for_function_params(entry_block);
         self.builder.switch_to_block(entry_block);
+        if self.debug_info {
+            self.builder.set_srcloc(SourceLoc::new(self.mir_func.span.start as u32));
+        }
         // Don't seal entry block yet - seal all blocks at the end
         
         // Track the parameter offset (sret takes the first slot if present)
@@ -566,6 +813,9 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
             self.sret_ptr = Some(sret_var);
         }
 
+        // Scalars that are borrowed need an address
+        let address_taken = self.address_taken_scalars();
+
         // Declare all locals as variables or stack slots
         for local in &self.mir_func.locals {
             if let Type::Struct { def_id, .. } = &local.ty {
@@ -583,11 +833,7 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                 }
             } else if let Type::Enum { def_id, .. } = &local.ty {
                 // Enums get stack slots (discriminant + payload)
-                let size = if let Some(mir_enum) = self.enums.get(def_id) {
-                    mir_enum.total_size()
-                } else {
-                    16u32 // Default: 8 for discriminant, 8 for payload
-                };
+                let size = self.enum_size(*def_id)?;
                 let slot = self.builder.create_sized_stack_slot(
                     cranelift_codegen::ir::StackSlotData::new(
                         cranelift_codegen::ir::StackSlotKind::ExplicitSlot,
@@ -608,14 +854,15 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                     )
                 );
                 self.array_slots.insert(local.id, (slot, elem_ty.as_ref().clone(), *len));
+            } else if address_taken.contains(&local.id) {
+                let ty = self.convert_type(&local.ty);
+                let slot = self.create_scalar_slot(ty);
+                self.scalar_slots.insert(local.id, (slot, ty));
             } else {
                 // Scalars get variables
-                let var = Variable::from_u32(self.next_var as u32);
-                self.next_var += 1;
                 let ty = self.convert_type(&local.ty);
                 if ty != types::INVALID {
-                    self.builder.declare_var(var, ty);
-                    self.locals.insert(local.id, var);
+                    self.declare_local_var(local, ty);
                 }
             }
         }
@@ -656,11 +903,7 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                 Type::Enum { def_id, .. } => {
                     // Enum parameters are passed as pointers (like structs)
                     // Create a stack slot and copy the data from the pointer
-                    let size = if let Some(mir_enum) = self.enums.get(def_id) {
-                        mir_enum.total_size()
-                    } else {
-                        16u32 // Default
-                    };
+                    let size = self.enum_size(*def_id)?;
                     let slot = self.builder.create_sized_stack_slot(
                         cranelift_codegen::ir::StackSlotData::new(
                             cranelift_codegen::ir::StackSlotKind::ExplicitSlot,
@@ -673,43 +916,55 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                     let ptr_val = self.builder.block_params(entry_block)[block_param_idx];
                     
                     // Copy the entire enum (discriminant + payload)
-                    for offset in (0..size).step_by(8) {
-                        let val = self.builder.ins().load(types::I64, cranelift_codegen::ir::MemFlags::new(), ptr_val, offset as i32);
-                        self.builder.ins().stack_store(val, slot, offset as i32);
-                    }
+                    let slot_addr = self.slot_addr(slot);
+                    self.copy_bytes(slot_addr, ptr_val, size);
                     
                     self.aggregate_slots.insert(param.id, (slot, *def_id, AggregateType::Enum));
                 }
+                Type::Array(elem_ty, len) => {
+                    // Array parameters are passed as pointers too; copy the elements
+                    let elem_size = self.type_size(elem_ty);
+                    let slot = self.builder.create_sized_stack_slot(
+                        cranelift_codegen::ir::StackSlotData::new(
+                            cranelift_codegen::ir::StackSlotKind::ExplicitSlot,
+                            elem_size * (*len as u32),
+                            3, // align to 8 bytes
+                        )
+                    );
+                    let ptr_val = self.builder.block_params(entry_block)[block_param_idx];
+                    let slot_addr = self.slot_addr(slot);
+                    self.copy_bytes(slot_addr, ptr_val, elem_size * (*len as u32));
+                    self.array_slots.insert(param.id, (slot, elem_ty.as_ref().clone(), *len));
+                }
                 Type::Ref { inner, .. } if matches!(inner.as_ref(), Type::Struct { .. }) => {
                     // Reference to struct - the parameter IS the pointer, store it as a variable
                     // When accessing fields, we'll load through this pointer
-                    let var = Variable::from_u32(self.next_var as u32);
-                    self.next_var += 1;
-                    self.builder.declare_var(var, types::I64); // pointer type
+                    let var = self.declare_local_var(param, types::I64); // pointer type
                     let param_val = self.builder.block_params(entry_block)[block_param_idx];
-                    self.builder.def_var(var, param_val);
-                    self.locals.insert(param.id, var);
+                    self.def_local_var(param.id, var, param_val);
                 }
                 Type::Ref { inner, .. } if matches!(inner.as_ref(), Type::Enum { .. }) => {
                     // Reference to enum - the parameter IS the pointer, store it as a variable
                     // When dereferencing (*self), we'll load through this pointer
-                    let var = Variable::from_u32(self.next_var as u32);
-                    self.next_var += 1;
-                    self.builder.declare_var(var, types::I64); // pointer type
+                    let var = self.declare_local_var(param, types::I64); // pointer type
+                    let param_val = self.builder.block_params(entry_block)[block_param_idx];
+                    self.def_local_var(param.id, var, param_val);
+                }
+                _ if address_taken.contains(&param.id) => {
+                    // Borrowed scalar parameters are copied into memory
+                    let ty = self.convert_type(&param.ty);
+                    let slot = self.create_scalar_slot(ty);
                     let param_val = self.builder.block_params(entry_block)[block_param_idx];
-                    self.builder.def_var(var, param_val);
-                    self.locals.insert(param.id, var);
+                    self.builder.ins().stack_store(param_val, slot, 0);
+                    self.scalar_slots.insert(param.id, (slot, ty));
                 }
                 _ => {
                     // Scalar parameters
-                    let var = Variable::from_u32(self.next_var as u32);
-                    self.next_var += 1;
                     let ty = self.convert_type(&param.ty);
                     if ty != types::INVALID {
-                        self.builder.declare_var(var, ty);
+                        let var = self.declare_local_var(param, ty);
                         let param_val = self.builder.block_params(entry_block)[block_param_idx];
-                        self.builder.def_var(var, param_val);
-                        self.locals.insert(param.id, var);
+                        self.def_local_var(param.id, var, param_val);
                     }
                 }
             }
@@ -726,7 +981,105 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
         Ok(())
     }
     
+    /// Locals of primitive type that have a reference taken to them as a whole
+    fn address_taken_scalars(&self) -> std::collections::HashSet<u32> {
+        let mut taken = std::collections::HashSet::new();
+        for block in &self.mir_func.blocks {
+            for stmt in &block.statements {
+                if let StatementKind::Assign { rvalue: Rvalue::Ref { place, .. }, .. } = &stmt.kind
+                    && place.projections.is_empty()
+                {
+                    taken.insert(place.local);
+                }
+            }
+        }
+        let is_primitive = |ty: &Type| !matches!(ty,
+            Type::Struct { .. } | Type::Enum { .. } | Type::Array(..) | Type::Ref { .. } | Type::Unit | Type::Never
+        );
+        self.mir_func.params.iter().chain(&self.mir_func.locals)
+            .filter(|l| taken.contains(&l.id) && is_primitive(&l.ty))
+            .map(|l| l.id)
+            .collect()
+    }
+
+    fn create_scalar_slot(&mut self, ty: types::Type) -> cranelift_codegen::ir::StackSlot {
+        self.builder.create_sized_stack_slot(
+            cranelift_codegen::ir::StackSlotData::new(
+                cranelift_codegen::ir::StackSlotKind::ExplicitSlot,
+                ty.bytes(),
+                3, // align to 8 bytes
+            )
+        )
+    }
+
+    /// Declare the Variable holding a scalar local. With debug info, a user
+    /// variable also gets a stack slot its definitions are mirrored to.
+    fn declare_local_var(&mut self, local: &MirLocal, ty: types::Type) -> Variable {
+        let var = Variable::from_u32(self.next_var as u32);
+        self.next_var += 1;
+        self.builder.declare_var(var, ty);
+        self.locals.insert(local.id, var);
+        if self.debug_info && local.is_user_variable() {
+            let slot = self.create_scalar_slot(ty);
+            self.debug_slots.insert(local.id, slot);
+        }
+        var
+    }
+
+    /// Define the Variable holding `local`, and its debug slot if it has one
+    fn def_local_var(&mut self, local: u32, var: Variable, value: Value) {
+        self.builder.def_var(var, value);
+        if let Some(&slot) = self.debug_slots.get(&local) {
+            self.builder.ins().stack_store(value, slot, 0);
+        }
+    }
+
+    /// Set a whole scalar local to `value`
+    fn define_local(&mut self, local: u32, value: Value) {
+        if let Some(&(slot, _)) = self.scalar_slots.get(&local) {
+            self.builder.ins().stack_store(value, slot, 0);
+        } else if let Some(&var) = self.locals.get(&local) {
+            self.def_local_var(local, var, value);
+        }
+    }
+
     /// Get the size of a type in bytes
+    /// Size of the layout of an enum, which every copy of one must use
+    fn enum_size(&self, def_id: DefId) -> Result<u32, CodegenError> {
+        self.enums.get(&def_id).map(|e| e.total_size()).ok_or_else(|| CodegenError {
+            message: format!("No layout for enum {:?}", def_id),
+        })
+    }
+
+    /// Size of the layout of a struct or enum
+    fn aggregate_size(&self, ty: &Type) -> Result<u32, CodegenError> {
+        match ty {
+            Type::Struct { def_id, .. } => self.structs.get(def_id).map(|s| s.total_size()).ok_or_else(|| CodegenError {
+                message: format!("No layout for struct {:?}", def_id),
+            }),
+            Type::Enum { def_id, .. } => self.enum_size(*def_id),
+            _ => Err(CodegenError { message: format!("Not an aggregate: {:?}", ty) }),
+        }
+    }
+
+    /// Copy `size` bytes from `src` to `dst` as whole 8-byte words followed by
+    /// a 4-, 2- and 1-byte tail, so nothing past either end is touched
+    fn copy_bytes(&mut self, dst: Value, src: Value, size: u32) {
+        let flags = cranelift_codegen::ir::MemFlags::new();
+        let mut offset = 0;
+        for (width, ty) in [(8, types::I64), (4, types::I32), (2, types::I16), (1, types::I8)] {
+            while size - offset >= width {
+                let val = self.builder.ins().load(ty, flags, src, offset as i32);
+                self.builder.ins().store(flags, val, dst, offset as i32);
+                offset += width;
+            }
+        }
+    }
+
+    fn slot_addr(&mut self, slot: StackSlot) -> Value {
+        self.builder.ins().stack_addr(types::I64, slot, 0)
+    }
+
     fn type_size(&self, ty: &Type) -> u32 {
         match ty {
             Type::I8 | Type::U8 | Type::Bool => 1,
@@ -751,6 +1104,117 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
         }
     }
 
+    /// Address of element `idx` of an array starting at `base`
+    fn array_elem_addr(&mut self, base: Value, elem_ty: &Type, idx: &Operand) -> Result<Value, CodegenError> {
+        let idx_val = self.compile_operand(idx)?
+            .ok_or_else(|| CodegenError { message: "Invalid array index".to_string() })?;
+        let elem_size = self.type_size(elem_ty);
+        let elem_size = self.builder.ins().iconst(types::I64, elem_size as i64);
+        let idx_i64 = if self.builder.func.dfg.value_type(idx_val) == types::I64 {
+            idx_val
+        } else {
+            self.builder.ins().sextend(types::I64, idx_val)
+        };
+        let offset = self.builder.ins().imul(idx_i64, elem_size);
+        Ok(self.builder.ins().iadd(base, offset))
+    }
+
+    /// Element type of a local declared as a reference to an array
+    fn ref_array_elem_type(&self, local: u32) -> Option<Type> {
+        let local_ty = self.mir_func.params.iter()
+            .find(|p| p.id == local)
+            .map(|p| &p.ty)
+            .or_else(|| self.mir_func.locals.iter().find(|l| l.id == local).map(|l| &l.ty));
+        match local_ty {
+            Some(Type::Ref { inner, .. }) => match inner.as_ref() {
+                Type::Array(elem, _) => Some(elem.as_ref().clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Address of the array element `place` names (`a[i]` of an array local
+    /// or `(*r)[i]` through a reference to one), followed through the rest of
+    /// its projections, with the type found there. Structs and enums are
+    /// stored inline in arrays, so their address is their value.
+    fn array_element_addr(&mut self, place: &Place) -> Result<Option<(Value, Type)>, CodegenError> {
+        let (base, elem_ty, rest) = if let Some(&(slot, ref elem_ty, _)) = self.array_slots.get(&place.local) {
+            let elem_ty = elem_ty.clone();
+            (self.slot_addr(slot), elem_ty, place.projections.as_slice())
+        } else if let Some(&var) = self.locals.get(&place.local)
+            && let [PlaceProjection::Deref, rest @ ..] = place.projections.as_slice()
+            && let Some(elem_ty) = self.ref_array_elem_type(place.local)
+        {
+            (self.builder.use_var(var), elem_ty, rest)
+        } else {
+            return Ok(None);
+        };
+        let [PlaceProjection::Index(idx_operand), rest @ ..] = rest else {
+            return Ok(None);
+        };
+        let mut addr = self.array_elem_addr(base, &elem_ty, idx_operand)?;
+        let mut ty = elem_ty;
+        for proj in rest {
+            match (proj, &ty) {
+                (PlaceProjection::Field(idx, _), Type::Struct { def_id, .. }) => {
+                    let Some(mir_struct) = self.structs.get(def_id) else { return Ok(None) };
+                    let offset = mir_struct.field_offset(*idx);
+                    let field_ty = mir_struct.fields[*idx].1.clone();
+                    addr = self.builder.ins().iadd_imm(addr, offset as i64);
+                    ty = field_ty;
+                }
+                (PlaceProjection::Field(idx, _), Type::Enum { def_id, .. }) => {
+                    let Some(mir_enum) = self.enums.get(def_id) else { return Ok(None) };
+                    let offset = mir_enum.field_offset(*idx);
+                    let field_ty = mir_enum.field_type(*idx).cloned().unwrap_or(Type::I64);
+                    addr = self.builder.ins().iadd_imm(addr, offset as i64);
+                    ty = field_ty;
+                }
+                (PlaceProjection::Deref, Type::Ref { inner, .. }) => {
+                    let inner = inner.as_ref().clone();
+                    addr = self.builder.ins().load(types::I64, cranelift_codegen::ir::MemFlags::new(), addr, 0);
+                    ty = inner;
+                }
+                (PlaceProjection::Index(idx_operand), Type::Array(elem, _)) => {
+                    let elem = elem.as_ref().clone();
+                    addr = self.array_elem_addr(addr, &elem, idx_operand)?;
+                    ty = elem;
+                }
+                _ => return Ok(None),
+            }
+        }
+        Ok(Some((addr, ty)))
+    }
+
+    /// Copy a struct or enum into or out of an array element. Returns false
+    /// if the assignment isn't one.
+    fn copy_aggregate_element(&mut self, place: &Place, rvalue: &Rvalue) -> Result<bool, CodegenError> {
+        let Rvalue::Use(Operand::Copy(src) | Operand::Move(src)) = rvalue else {
+            return Ok(false);
+        };
+        let is_aggregate = |ty: &Type| matches!(ty, Type::Struct { .. } | Type::Enum { .. });
+        let (dst_addr, src_addr, ty) = match (self.array_element_addr(place)?, self.array_element_addr(src)?) {
+            (Some((dst_addr, ty)), Some((src_addr, _))) if is_aggregate(&ty) => (dst_addr, src_addr, ty),
+            (Some((dst_addr, ty)), None) if is_aggregate(&ty) => match self.aggregate_slots.get(&src.local) {
+                // A whole enum in a slot loads as its discriminant, so take its address
+                Some(&(slot, _, _)) if src.projections.is_empty() => (dst_addr, self.slot_addr(slot), ty),
+                _ => match self.load_from_place(src)? {
+                    Some(src_addr) => (dst_addr, src_addr, ty),
+                    None => return Ok(false),
+                },
+            },
+            (None, Some((src_addr, ty))) if is_aggregate(&ty) => match self.aggregate_slots.get(&place.local) {
+                Some(&(slot, _, _)) if place.projections.is_empty() => (self.slot_addr(slot), src_addr, ty),
+                _ => return Ok(false),
+            },
+            _ => return Ok(false),
+        };
+        let size = self.aggregate_size(&ty)?;
+        self.copy_bytes(dst_addr, src_addr, size);
+        Ok(true)
+    }
+
     fn compile_block(&mut self, block: &BasicBlock) -> Result<(), CodegenError> {
         let cl_block = *self.blocks.get(&block.id).unwrap();
         
@@ -761,10 +1225,16 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
 
         // Compile statements
         for stmt in &block.statements {
+            if self.debug_info {
+                self.builder.set_srcloc(SourceLoc::new(stmt.span.start as u32));
+            }
             self.compile_statement(stmt)?;
         }
 
         // Compile terminator
+        if self.debug_info {
+            self.builder.set_srcloc(SourceLoc::new(block.terminator_span.start as u32));
+        }
         self.compile_terminator(&block.terminator)?;
 
         Ok(())
@@ -773,6 +1243,10 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
     fn compile_statement(&mut self, stmt: &Statement) -> Result<(), CodegenError> {
         match &stmt.kind {
             StatementKind::Assign { place, rvalue } => {
+                if self.copy_aggregate_element(place, rvalue)? {
+                    return Ok(());
+                }
+
                 // Check if this is a struct aggregate assignment
                 if let Rvalue::Aggregate { kind: AggregateKind::Struct(def_id, _), operands } = rvalue {
                     // Get the destination stack slot
@@ -859,8 +1333,11 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                 }
                 
                 if let Rvalue::Use(Operand::Copy(src_place) | Operand::Move(src_place)) = rvalue {
-                    // Check if this is an aggregate copy (struct or enum)
-                    if let Some(&(src_slot, src_def_id, agg_type)) = self.aggregate_slots.get(&src_place.local) {
+                    // Check if this is an aggregate copy (struct or enum). A field of an
+                    // aggregate is only a pointer to its data, handled by store_to_place.
+                    if let Some(&(src_slot, src_def_id, agg_type)) = self.aggregate_slots.get(&src_place.local)
+                        && src_place.projections.is_empty()
+                    {
                         if let Some(&(dst_slot, _, _)) = self.aggregate_slots.get(&place.local) {
                             match agg_type {
                                 AggregateType::Struct => {
@@ -877,33 +1354,28 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                                 }
                                 AggregateType::Enum => {
                                     // Copy enum by copying discriminant + payload
-                                    let enum_size = if let Some(mir_enum) = self.enums.get(&src_def_id) {
-                                        mir_enum.total_size()
-                                    } else {
-                                        16u32
-                                    };
-                                    // Copy 8 bytes at a time
-                                    for offset in (0..enum_size).step_by(8) {
-                                        let val = self.builder.ins().stack_load(types::I64, src_slot, offset as i32);
-                                        self.builder.ins().stack_store(val, dst_slot, offset as i32);
-                                    }
+                                    let enum_size = self.enum_size(src_def_id)?;
+                                    let src_addr = self.slot_addr(src_slot);
+                                    let dst_addr = self.slot_addr(dst_slot);
+                                    self.copy_bytes(dst_addr, src_addr, enum_size);
                                     return Ok(());
                                 }
                             }
                         }
                     }
-                    // Check if this is an array copy
-                    if let Some(&(src_slot, ref src_elem_ty, src_len)) = self.array_slots.get(&src_place.local) {
+                    // Check if this is an array copy (not of a single element)
+                    if let Some(&(src_slot, ref src_elem_ty, src_len)) = self.array_slots.get(&src_place.local)
+                        && src_place.projections.is_empty()
+                    {
                         let src_elem_ty = src_elem_ty.clone();
-                        if let Some(&(dst_slot, _, _)) = self.array_slots.get(&place.local) {
-                            // Copy array by copying each element
-                            let elem_size = self.type_size(&src_elem_ty);
-                            let cl_ty = self.convert_type(&src_elem_ty);
-                            for i in 0..src_len {
-                                let offset = (i as u32 * elem_size) as i32;
-                                let val = self.builder.ins().stack_load(cl_ty, src_slot, offset);
-                                self.builder.ins().stack_store(val, dst_slot, offset);
-                            }
+                        if let Some(&(dst_slot, _, _)) = self.array_slots.get(&place.local)
+                            && place.projections.is_empty()
+                        {
+                            // Copy the bytes of every element
+                            let size = self.type_size(&src_elem_ty) * src_len as u32;
+                            let src_addr = self.slot_addr(src_slot);
+                            let dst_addr = self.slot_addr(dst_slot);
+                            self.copy_bytes(dst_addr, src_addr, size);
                             return Ok(());
                         }
                     }
@@ -925,19 +1397,10 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                                         let ptr = self.builder.use_var(var);
                                         
                                         // Copy enum data from pointer to destination slot
-                                        let enum_size = if let Some(mir_enum) = self.enums.get(src_enum_id) {
-                                            mir_enum.total_size()
-                                        } else if let Some(mir_enum) = self.enums.get(&dst_def_id) {
-                                            mir_enum.total_size()
-                                        } else {
-                                            16u32
-                                        };
-                                        
-                                        // Copy 8 bytes at a time
-                                        for offset in (0..enum_size).step_by(8) {
-                                            let val = self.builder.ins().load(types::I64, cranelift_codegen::ir::MemFlags::new(), ptr, offset as i32);
-                                            self.builder.ins().stack_store(val, dst_slot, offset as i32);
-                                        }
+                                        let enum_size = self.enum_size(*src_enum_id)
+                                            .or_else(|_| self.enum_size(dst_def_id))?;
+                                        let dst_addr = self.slot_addr(dst_slot);
+                                        self.copy_bytes(dst_addr, ptr, enum_size);
                                         return Ok(());
                                     }
                                 }
@@ -957,7 +1420,7 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                     }
                 }
             }
-            StatementKind::StorageLive(_) | StatementKind::StorageDead(_) | StatementKind::Nop => {
+            StatementKind::StorageLive(_) | StatementKind::StorageDead(_) | StatementKind::Deinit(_) | StatementKind::Nop => {
                 // No-op for now
             }
         }
@@ -1052,7 +1515,13 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
             Rvalue::Ref { place, .. } => {
                 // Compute the address of the place
                 // For locals, we use stack_addr; for struct/enum fields, we compute the offset
-                if let Some(&(slot, def_id, agg_type)) = self.aggregate_slots.get(&place.local) {
+                if let Some((addr, _)) = self.array_element_addr(place)? {
+                    Ok(Some(addr))
+                } else if let Some(&(slot, _, _)) = self.array_slots.get(&place.local) {
+                    Ok(Some(self.slot_addr(slot)))
+                } else if let Some(&(slot, _)) = self.scalar_slots.get(&place.local) {
+                    Ok(Some(self.builder.ins().stack_addr(types::I64, slot, 0)))
+                } else if let Some(&(slot, def_id, agg_type)) = self.aggregate_slots.get(&place.local) {
                     // Reference to an aggregate (struct/enum) or its field
                     if place.projections.is_empty() {
                         // Reference to the whole aggregate
@@ -1089,6 +1558,11 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                         }
                     }
                 } else if let Some(&var) = self.locals.get(&place.local) {
+                    // Reborrowing through a reference (`&*r`) is the reference itself
+                    if matches!(place.projections.as_slice(), [PlaceProjection::Deref]) {
+                        return Ok(Some(self.builder.use_var(var)));
+                    }
+
                     // Check if this is a reference to a struct field through a pointer
                     if !place.projections.is_empty() {
                         // Find the local's type to check if it's a reference to a struct
@@ -1142,11 +1616,8 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                     AggregateKind::Enum(enum_def_id, variant_idx, _variant_def_id) => {
                         // Create enum value: store discriminant + payload
                         // Get enum size from MirEnum
-                        let (size, payload_offset) = if let Some(mir_enum) = self.enums.get(enum_def_id) {
-                            (mir_enum.total_size(), mir_enum.payload_offset())
-                        } else {
-                            (16u32, 8u32) // Default: 8 bytes discriminant + 8 bytes payload
-                        };
+                        let size = self.enum_size(*enum_def_id)?;
+                        let payload_offset = self.enums[enum_def_id].payload_offset();
                         
                         // Allocate stack slot for the enum
                         let slot = self.builder.create_sized_stack_slot(
@@ -1376,6 +1847,14 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
     }
 
     fn load_from_place(&mut self, place: &Place) -> Result<Option<Value>, CodegenError> {
+        if let Some((addr, ty)) = self.array_element_addr(place)? {
+            if matches!(ty, Type::Struct { .. } | Type::Enum { .. }) {
+                return Ok(Some(addr));
+            }
+            let cl_ty = self.convert_type(&ty);
+            return Ok(Some(self.builder.ins().load(cl_ty, cranelift_codegen::ir::MemFlags::new(), addr, 0)));
+        }
+
         // Check if this is an aggregate (struct or enum) access
         if let Some(&(slot, def_id, agg_type)) = self.aggregate_slots.get(&place.local) {
             match agg_type {
@@ -1416,36 +1895,15 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
             }
         }
         
-        // Check if this is an array access
-        if let Some(&(slot, ref elem_ty, _len)) = self.array_slots.get(&place.local) {
-            let elem_ty = elem_ty.clone();  // Clone to avoid borrow issues
-            for proj in &place.projections {
-                if let PlaceProjection::Index(idx_operand) = proj {
-                    // Compile the index operand
-                    let idx_val = self.compile_operand(idx_operand)?
-                        .ok_or_else(|| CodegenError { message: "Invalid array index".to_string() })?;
-                    
-                    // Compute element offset: index * element_size
-                    let elem_size = self.type_size(&elem_ty);
-                    let elem_size_val = self.builder.ins().iconst(types::I64, elem_size as i64);
-                    // Extend index to i64 for address calculation
-                    let idx_i64 = self.builder.ins().sextend(types::I64, idx_val);
-                    let offset = self.builder.ins().imul(idx_i64, elem_size_val);
-                    
-                    // Get base address of array
-                    let base_addr = self.builder.ins().stack_addr(types::I64, slot, 0);
-                    let elem_addr = self.builder.ins().iadd(base_addr, offset);
-                    
-                    // Load the element
-                    let cl_ty = self.convert_type(&elem_ty);
-                    let val = self.builder.ins().load(cl_ty, cranelift_codegen::ir::MemFlags::new(), elem_addr, 0);
-                    return Ok(Some(val));
-                }
-            }
-            // Loading whole array - return address
+        // Loading whole array - return address
+        if let Some(&(slot, _, _)) = self.array_slots.get(&place.local) {
             let addr = self.builder.ins().stack_addr(types::I64, slot, 0);
             return Ok(Some(addr));
         }
+
+        if let Some(&(slot, ty)) = self.scalar_slots.get(&place.local) {
+            return Ok(Some(self.builder.ins().stack_load(ty, slot, 0)));
+        }
         
         if let Some(&var) = self.locals.get(&place.local) {
             // Check if this is a reference to a struct with field access
@@ -1497,6 +1955,10 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                                 }
                             }
                         }
+                        Type::Array(..) => {
+                            // The whole array is its address
+                            return Ok(Some(ptr));
+                        }
                         _ => {
                             // Handle dereference of primitive references
                             for proj in &place.projections {
@@ -1519,6 +1981,16 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
     }
 
     fn store_to_place(&mut self, place: &Place, value: Value) -> Result<(), CodegenError> {
+        if let Some((addr, ty)) = self.array_element_addr(place)? {
+            if matches!(ty, Type::Struct { .. } | Type::Enum { .. }) {
+                let size = self.aggregate_size(&ty)?;
+                self.copy_bytes(addr, value, size);
+            } else {
+                self.builder.ins().store(cranelift_codegen::ir::MemFlags::new(), value, addr, 0);
+            }
+            return Ok(());
+        }
+
         // Check if this is an aggregate (struct or enum) store
         if let Some(&(slot, def_id, agg_type)) = self.aggregate_slots.get(&place.local) {
             match agg_type {
@@ -1532,7 +2004,15 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                             }
                         }
                     }
-                    // Storing to whole struct slot not supported this way
+                    // A whole struct value is the address of its data: copy it field by field
+                    if let Some(mir_struct) = self.structs.get(&def_id) {
+                        for (i, (_, field_ty)) in mir_struct.fields.iter().enumerate() {
+                            let offset = mir_struct.field_offset(i) as i32;
+                            let cl_ty = self.convert_type(field_ty);
+                            let val = self.builder.ins().load(cl_ty, cranelift_codegen::ir::MemFlags::new(), value, offset);
+                            self.builder.ins().stack_store(val, slot, offset);
+                        }
+                    }
                     return Ok(());
                 }
                 AggregateType::Enum => {
@@ -1554,43 +2034,39 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
             }
         }
         
-        // Check if this is an array element store
-        if let Some(&(slot, ref elem_ty, _len)) = self.array_slots.get(&place.local) {
-            let elem_ty = elem_ty.clone();  // Clone to avoid borrow issues
-            for proj in &place.projections {
-                if let PlaceProjection::Index(idx_operand) = proj {
-                    // Compile the index operand
-                    let idx_val = self.compile_operand(idx_operand)?
-                        .ok_or_else(|| CodegenError { message: "Invalid array index".to_string() })?;
-                    
-                    // Compute element offset: index * element_size
-                    let elem_size = self.type_size(&elem_ty);
-                    let elem_size_val = self.builder.ins().iconst(types::I64, elem_size as i64);
-                    // Extend index to i64 for address calculation
-                    let idx_i64 = self.builder.ins().sextend(types::I64, idx_val);
-                    let offset = self.builder.ins().imul(idx_i64, elem_size_val);
-                    
-                    // Get base address of array
-                    let base_addr = self.builder.ins().stack_addr(types::I64, slot, 0);
-                    let elem_addr = self.builder.ins().iadd(base_addr, offset);
-                    
-                    // Store the element
-                    self.builder.ins().store(cranelift_codegen::ir::MemFlags::new(), value, elem_addr, 0);
-                    return Ok(());
-                }
-            }
-            // Storing to whole array not supported
+        // Storing to whole array not supported
+        if self.array_slots.contains_key(&place.local) {
+            return Ok(());
+        }
+
+        if let Some(&(slot, _)) = self.scalar_slots.get(&place.local) {
+            self.builder.ins().stack_store(value, slot, 0);
             return Ok(());
         }
         
         if let Some(&var) = self.locals.get(&place.local) {
             if place.projections.is_empty() {
-                self.builder.def_var(var, value);
+                self.def_local_var(place.local, var, value);
             } else {
                 // Handle storing through a reference (e.g., self.field = value where self is &mut T)
                 // The var holds a pointer, we need to store at the appropriate offset
                 let ptr = self.builder.use_var(var);
                 
+                // Storing a whole struct or enum through a reference copies its data
+                if matches!(place.projections.as_slice(), [PlaceProjection::Deref]) {
+                    let pointee = self.mir_func.params.iter().chain(&self.mir_func.locals)
+                        .find(|l| l.id == place.local)
+                        .and_then(|l| match &l.ty {
+                            Type::Ref { inner, .. } if matches!(inner.as_ref(), Type::Struct { .. } | Type::Enum { .. }) => Some(inner.as_ref().clone()),
+                            _ => None,
+                        });
+                    if let Some(pointee) = pointee {
+                        let size = self.aggregate_size(&pointee)?;
+                        self.copy_bytes(ptr, value, size);
+                        return Ok(());
+                    }
+                }
+                
                 // Calculate total offset from projections
                 let mut offset: i32 = 0;
                 for proj in &place.projections {
@@ -1690,6 +2166,13 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                             }
                         }
                     }
+                    // Copy the return enum (discriminant + payload) to the sret pointer
+                    if let Some(&(slot, _, AggregateType::Enum)) = self.aggregate_slots.get(&0) {
+                        let enum_size = self.enum_size(def_id)?;
+                        let sret_ptr = self.builder.use_var(sret_var);
+                        let slot_addr = self.slot_addr(slot);
+                        self.copy_bytes(sret_ptr, slot_addr, enum_size);
+                    }
                     // Return void
                     self.builder.ins().return_(&[]);
                 } else if self.is_main && matches!(self.mir_func.return_type, Type::Unit) {
@@ -1825,24 +2308,29 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                     // Get the return value (if any) - skip for aggregate returns (handled via sret)
                     if !callee_returns_aggregate {
                         let results = self.builder.inst_results(call);
-                        if !results.is_empty() {
-                            if let Some(&var) = self.locals.get(&destination.local) {
-                                self.builder.def_var(var, results[0]);
-                            }
+                        if let Some(&result) = results.first() {
+                            self.define_local(destination.local, result);
                         }
                     }
-                } else if let Some(func_ptr) = self.compile_operand(func).ok().flatten() {
-                    // Indirect call through function pointer
+                } else if let Some(closure) = self.compile_operand(func).ok().flatten() {
+                    // Indirect call through a closure: its first field is the code
+                    // pointer, and the closure itself is passed after any sret pointer
+                    let code_ptr = self.builder.ins().load(types::I64, cranelift_codegen::ir::MemFlags::new(), closure, 0);
+                    let env_idx = if callee_returns_aggregate { 1 } else { 0 };
+                    arg_vals.insert(env_idx.min(arg_vals.len()), closure);
+
                     // Build the signature for the indirect call
-                    let mut sig = Signature::new(CallConv::SystemV);
+                    let mut sig = Signature::new(self.builder.func.signature.call_conv);
                     
                     // Add parameter types based on the arguments
                     for arg in &arg_vals {
                         sig.params.push(AbiParam::new(self.builder.func.dfg.value_type(*arg)));
                     }
                     
-                    // Get return type from destination local
-                    if let Some(local) = self.mir_func.locals.iter().find(|l| l.id == destination.local) {
+                    // Get return type from destination local; aggregates come back through sret
+                    if !callee_returns_aggregate
+                        && let Some(local) = self.mir_func.locals.iter().find(|l| l.id == destination.local)
+                    {
                         let ret_ty = self.convert_type(&local.ty);
                         if ret_ty != types::INVALID {
                             sig.returns.push(AbiParam::new(ret_ty));
@@ -1850,20 +2338,18 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                     }
                     
                     let sig_ref = self.builder.import_signature(sig);
-                    let call = self.builder.ins().call_indirect(sig_ref, func_ptr, &arg_vals);
+                    let call = self.builder.ins().call_indirect(sig_ref, code_ptr, &arg_vals);
                     
                     // Get the return value
                     let results = self.builder.inst_results(call);
-                    if !results.is_empty() {
-                        if let Some(&var) = self.locals.get(&destination.local) {
-                            self.builder.def_var(var, results[0]);
-                        }
+                    if let Some(&result) = results.first() {
+                        self.define_local(destination.local, result);
                     }
                 } else {
                     // Fallback: store zero for unknown calls
                     if let Some(&var) = self.locals.get(&destination.local) {
                         let zero = self.builder.ins().iconst(types::I32, 0);
-                        self.builder.def_var(var, zero);
+                        self.def_local_var(destination.local, var, zero);
                     }
                 }
 
@@ -1871,6 +2357,14 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
                 self.builder.ins().jump(target_block, &[]);
             }
 
+            Terminator::Drop { place, target } => {
+                if let Some((ty, addr)) = self.dropped_value(place) {
+                    self.emit_drop_glue(&ty, addr);
+                }
+                let target_block = *self.blocks.get(target).unwrap();
+                self.builder.ins().jump(target_block, &[]);
+            }
+
             Terminator::Unreachable => {
                 self.builder.ins().trap(cranelift_codegen::ir::TrapCode::unwrap_user(1));
             }
@@ -1879,6 +2373,127 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
         Ok(())
     }
 
+    /// The type and address of the local or field a `Drop` drops
+    fn dropped_value(&mut self, place: &Place) -> Option<(Type, Value)> {
+        let mut ty = self.mir_func.params.iter().chain(&self.mir_func.locals)
+            .find(|l| l.id == place.local)?
+            .ty.clone();
+        let slot = match self.aggregate_slots.get(&place.local) {
+            Some(&(slot, _, _)) => slot,
+            None => self.array_slots.get(&place.local)?.0,
+        };
+        let mut addr = self.slot_addr(slot);
+        for proj in &place.projections {
+            let PlaceProjection::Field(idx, _) = proj else { return None };
+            let Type::Struct { def_id, type_args } = &ty else { return None };
+            let mir_struct = self.structs.get(def_id)?;
+            let field_ty = substitute_type(&mir_struct.fields.get(*idx)?.1, type_args);
+            // Struct fields hold a pointer to the struct's data; other
+            // aggregates in fields can't be dropped on their own
+            if !matches!(field_ty, Type::Struct { .. }) {
+                return None;
+            }
+            let offset = mir_struct.field_offset(*idx);
+            addr = self.builder.ins().load(types::I64, cranelift_codegen::ir::MemFlags::new(), addr, offset as i32);
+            ty = field_ty;
+        }
+        Some((ty, addr))
+    }
+
+    /// Drop the value at `addr`: call its `drop` method, then drop what it
+    /// holds, which is a struct's fields, the fields of an enum's current
+    /// variant or each element of an array
+    fn emit_drop_glue(&mut self, ty: &Type, addr: Value) {
+        match ty {
+            Type::Struct { def_id, .. } => {
+                self.call_drop_fn(*def_id, addr);
+                let Some(mir_struct) = self.structs.get(def_id) else { return };
+                for (field_idx, (_, field_ty)) in mir_struct.fields.iter().enumerate() {
+                    self.drop_field(field_ty, addr, mir_struct.field_offset(field_idx));
+                }
+            }
+            Type::Enum { def_id, type_args } => {
+                self.call_drop_fn(*def_id, addr);
+                let Some(mir_enum) = self.enums.get(def_id) else { return };
+                let payload_offset = mir_enum.payload_offset();
+                let variants: Vec<(usize, Vec<Type>)> = mir_enum.variants.iter().enumerate()
+                    .map(|(idx, (_, _, fields))| (idx, fields.iter().map(|f| substitute_type(f, type_args)).collect::<Vec<_>>()))
+                    .filter(|(_, fields)| fields.iter().any(|f| needs_drop(f, self.drop_fns, self.structs, self.enums)))
+                    .collect();
+                if variants.is_empty() {
+                    return;
+                }
+
+                // Switch on the discriminant to the variant whose fields are live
+                let discr = self.builder.ins().load(types::I64, cranelift_codegen::ir::MemFlags::new(), addr, 0);
+                let done = self.builder.create_block();
+                for (variant_idx, fields) in variants {
+                    let variant_block = self.builder.create_block();
+                    let next = self.builder.create_block();
+                    let is_variant = self.builder.ins().icmp_imm(cranelift_codegen::ir::condcodes::IntCC::Equal, discr, variant_idx as i64);
+                    self.builder.ins().brif(is_variant, variant_block, &[], next, &[]);
+                    self.builder.switch_to_block(variant_block);
+                    for (i, field_ty) in fields.iter().enumerate() {
+                        // Payload fields are laid out 8 bytes apart
+                        self.drop_field(field_ty, addr, payload_offset + i as u32 * 8);
+                    }
+                    self.builder.ins().jump(done, &[]);
+                    self.builder.switch_to_block(next);
+                }
+                self.builder.ins().jump(done, &[]);
+                self.builder.switch_to_block(done);
+            }
+            Type::Array(elem, len) => {
+                if *len == 0 || !needs_drop(elem, self.drop_fns, self.structs, self.enums) {
+                    return;
+                }
+
+                // Elements are stored inline, so loop over their addresses
+                let stride = self.type_size(elem) as i64;
+                let header = self.builder.create_block();
+                let body = self.builder.create_block();
+                let done = self.builder.create_block();
+                self.builder.append_block_param(header, types::I64);
+                let zero = self.builder.ins().iconst(types::I64, 0);
+                self.builder.ins().jump(header, &[zero]);
+
+                self.builder.switch_to_block(header);
+                let idx = self.builder.block_params(header)[0];
+                let more = self.builder.ins().icmp_imm(cranelift_codegen::ir::condcodes::IntCC::SignedLessThan, idx, *len as i64);
+                self.builder.ins().brif(more, body, &[], done, &[]);
+
+                self.builder.switch_to_block(body);
+                let offset = self.builder.ins().imul_imm(idx, stride);
+                let elem_addr = self.builder.ins().iadd(addr, offset);
+                self.emit_drop_glue(elem, elem_addr);
+                let next = self.builder.ins().iadd_imm(idx, 1);
+                self.builder.ins().jump(header, &[next]);
+
+                self.builder.switch_to_block(done);
+            }
+            _ => {}
+        }
+    }
+
+    /// Call the `drop` method of the type `def_id`, if it has one
+    fn call_drop_fn(&mut self, def_id: DefId, addr: Value) {
+        if let Some(drop_fn) = self.drop_fns.get(&def_id)
+            && let Some(&func_ref) = self.func_refs_by_name.get(drop_fn)
+        {
+            self.builder.ins().call(func_ref, &[addr]);
+        }
+    }
+
+    /// Drop the field at `offset` in the aggregate at `addr`. A struct field
+    /// holds a pointer to the struct's data; an enum in a field only keeps
+    /// its discriminant, so there's no payload to drop.
+    fn drop_field(&mut self, field_ty: &Type, addr: Value, offset: u32) {
+        if matches!(field_ty, Type::Struct { .. }) && needs_drop(field_ty, self.drop_fns, self.structs, self.enums) {
+            let field_addr = self.builder.ins().load(types::I64, cranelift_codegen::ir::MemFlags::new(), addr, offset as i32);
+            self.emit_drop_glue(field_ty, field_addr);
+        }
+    }
+
     fn convert_type(&self, ty: &Type) -> types::Type {
         match ty {
             Type::I8 => types::I8,
@@ -1904,3 +2519,107 @@ impl<'a, 'b> FunctionCompiler<'a, 'b> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object::{Architecture as ObjArchitecture, BinaryFormat, Object, ObjectSection};
+    use wisp_lexer::Span;
+
+    /// Source `exit_program`'s spans point into, with `code` on line 2
+    const EXIT_SOURCE: &str = "fn main() -> i32 {\n    0\n}\n";
+
+    /// `fn main() -> i32 { code }`, laid out for 8-byte pointers
+    fn exit_program(code: i64) -> MirProgram {
+        let mut program = MirProgram::new(8);
+        program.functions.push(MirFunction {
+            def_id: DefId::new(0),
+            name: "main".to_string(),
+            params: vec![],
+            return_type: Type::I32,
+            locals: vec![MirLocal { id: 0, name: "_0".to_string(), ty: Type::I32, is_arg: false }],
+            blocks: vec![BasicBlock {
+                id: 0,
+                statements: vec![Statement {
+                    kind: StatementKind::Assign {
+                        place: Place::local(0),
+                        rvalue: Rvalue::Use(Operand::Constant(Constant::Int(code, Type::I32))),
+                    },
+                    span: Span::new(23, 24),
+                }],
+                terminator: Terminator::Return,
+                terminator_span: Span::new(25, 26),
+            }],
+            span: Span::new(0, 26),
+        });
+        program
+    }
+
+    #[test]
+    fn test_emit_object_for_supported_targets() {
+        let expected = [ObjArchitecture::X86_64, ObjArchitecture::Aarch64, ObjArchitecture::Riscv64];
+        for (target, arch) in SUPPORTED_TARGETS.iter().zip(expected) {
+            let mut codegen = Codegen::for_target(target).unwrap();
+            assert_eq!(codegen.pointer_size(), 8);
+            codegen.compile(&exit_program(0)).unwrap();
+            let bytes = codegen.finish();
+            let file = object::File::parse(&*bytes).unwrap();
+            assert_eq!(file.format(), BinaryFormat::Elf, "{}", target);
+            assert_eq!(file.architecture(), arch, "{}", target);
+            assert!(file.is_64(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn test_debug_info_sections() {
+        let mut codegen = Codegen::for_target(SUPPORTED_TARGETS[0]).unwrap();
+        let mut sources = SourceMap::new();
+        sources.add("exit.ws".into(), EXIT_SOURCE.to_string());
+        codegen.enable_debug_info(&sources);
+        codegen.compile(&exit_program(0)).unwrap();
+        let bytes = codegen.finish();
+        let file = object::File::parse(&*bytes).unwrap();
+        for name in [".debug_info", ".debug_abbrev", ".debug_line", ".debug_frame"] {
+            let section = file.section_by_name(name).unwrap_or_else(|| panic!("missing {}", name));
+            assert!(section.size() > 0, "{}", name);
+        }
+        let debug_line = file.section_by_name(".debug_line").unwrap().data().unwrap();
+        assert!(debug_line.windows(7).any(|w| w == b"exit.ws"));
+    }
+
+    #[test]
+    fn test_opt_levels() {
+        for name in OptLevel::NAMES {
+            let opt_level = OptLevel::from_name(name).unwrap();
+            assert_eq!(opt_level.name(), name);
+            let mut codegen = Codegen::with_opt_level(Some(SUPPORTED_TARGETS[0]), opt_level).unwrap();
+            codegen.compile(&exit_program(0)).unwrap();
+            assert!(!codegen.finish().is_empty(), "{}", name);
+        }
+        assert_eq!(OptLevel::from_name("fast"), None);
+    }
+
+    #[test]
+    fn test_jit_runs_main() {
+        let mut codegen = Codegen::jit().unwrap();
+        codegen.compile(&exit_program(42)).unwrap();
+        assert_eq!(codegen.run_main().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_enum_without_layout_is_an_error() {
+        let mut program = exit_program(0);
+        let enum_ty = Type::Enum { def_id: DefId::new(99), type_args: vec![] };
+        program.functions[0].locals.push(MirLocal { id: 1, name: "e".to_string(), ty: enum_ty, is_arg: false });
+        let mut codegen = Codegen::new().unwrap();
+        let error = codegen.compile(&program).unwrap_err();
+        assert!(error.message.contains("No layout for enum"), "{}", error);
+    }
+
+    #[test]
+    fn test_reject_unsupported_target() {
+        for target in ["i686-unknown-linux-gnu", "x86_64-pc-windows-msvc", "not-a-triple"] {
+            assert!(Codegen::for_target(target).is_err(), "{}", target);
+        }
+    }
+}
@@ -0,0 +1,467 @@
+//! DWARF debug information for `-g` builds
+//!
+//! Describes every compiled function: a line table built from the source
+//! locations Cranelift tracks through compilation, call frames so debuggers
+//! can unwind, and the variables of each function with their types.
+
+use cranelift_codegen::ir::{Endianness, StackSlot};
+use cranelift_codegen::isa::unwind::UnwindInfo;
+use cranelift_codegen::isa::TargetIsa;
+use cranelift_codegen::CompiledCode;
+use cranelift_module::FuncId;
+use cranelift_object::ObjectProduct;
+use gimli::write::{
+    Address, AttributeValue, CieId, DwarfUnit, EndianVec, Expression, FileId, FrameTable,
+    LineProgram, LineString, Range, RangeList, RelocateWriter, Relocation, RelocationTarget,
+    Sections, UnitEntryId,
+};
+use gimli::{Encoding, Format, LineEncoding, RunTimeEndian, SectionId};
+use object::write::{self as obj, Object};
+use object::{RelocationEncoding, RelocationFlags, RelocationKind, SectionKind};
+use std::collections::HashMap;
+use wisp_hir::DefId;
+use wisp_lexer::{SourceMap, Span};
+use wisp_mir::{substitute_type, MirEnum, MirFunction, MirLocal, MirStruct};
+use wisp_types::Type;
+
+/// Bytes between the lowest stack slot's frame area and the canonical frame
+/// address: the saved frame pointer and return address (or link register)
+/// every frame with stack slots sets up on the supported targets
+const SETUP_AREA_SIZE: i64 = 16;
+
+/// DWARF being built for an object file
+pub(crate) struct DebugContext {
+    dwarf: DwarfUnit,
+    frames: FrameTable,
+    /// Entry the functions' call frame descriptions share, if the target has one
+    cie: Option<CieId>,
+    sources: SourceMap,
+    /// Line program file of each source file, by file index
+    files: Vec<FileId>,
+    /// Code ranges of the described functions, covered by the compile unit
+    ranges: Vec<Range>,
+    types: HashMap<Type, UnitEntryId>,
+    structs: HashMap<DefId, MirStruct>,
+    enums: HashMap<DefId, MirEnum>,
+    endian: RunTimeEndian,
+    pointer_size: u8,
+}
+
+/// A function's compiled code and the stack slot each of its variables lives in
+pub(crate) struct CompiledFunction<'a> {
+    pub func: &'a MirFunction,
+    pub func_id: FuncId,
+    pub code: &'a CompiledCode,
+    pub variable_slots: Vec<(u32, StackSlot)>,
+}
+
+impl DebugContext {
+    pub(crate) fn new(isa: &dyn TargetIsa, sources: &SourceMap) -> Self {
+        let pointer_size = isa.pointer_bytes();
+        let encoding = Encoding { format: Format::Dwarf32, version: 4, address_size: pointer_size };
+        let comp_dir = std::env::current_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_else(|_| ".".to_string());
+        let paths: Vec<String> = sources.iter().map(|(_, file)| file.path.display().to_string()).collect();
+        let file_name = paths.first().cloned().unwrap_or_default();
+
+        let mut dwarf = DwarfUnit::new(encoding);
+        dwarf.unit.line_program = LineProgram::new(
+            encoding,
+            LineEncoding::default(),
+            LineString::String(comp_dir.clone().into_bytes()),
+            LineString::String(file_name.clone().into_bytes()),
+            None,
+        );
+        let dir = dwarf.unit.line_program.default_directory();
+        let files = paths.into_iter()
+            .map(|path| dwarf.unit.line_program.add_file(LineString::String(path.into_bytes()), dir, None))
+            .collect();
+
+        let root = dwarf.unit.root();
+        let cu = dwarf.unit.get_mut(root);
+        cu.set(gimli::DW_AT_producer, AttributeValue::String(concat!("wisp ", env!("CARGO_PKG_VERSION")).into()));
+        // Debuggers have no Wisp mode; C's is the closest for printing values
+        cu.set(gimli::DW_AT_language, AttributeValue::Language(gimli::DW_LANG_C99));
+        cu.set(gimli::DW_AT_name, AttributeValue::String(file_name.into_bytes()));
+        cu.set(gimli::DW_AT_comp_dir, AttributeValue::String(comp_dir.into_bytes()));
+        cu.set(gimli::DW_AT_low_pc, AttributeValue::Address(Address::Constant(0)));
+
+        let mut frames = FrameTable::default();
+        let cie = isa.create_systemv_cie().map(|cie| frames.add_cie(cie));
+
+        let endian = match isa.endianness() {
+            Endianness::Little => RunTimeEndian::Little,
+            Endianness::Big => RunTimeEndian::Big,
+        };
+
+        DebugContext {
+            dwarf,
+            frames,
+            cie,
+            sources: sources.clone(),
+            files,
+            ranges: Vec::new(),
+            types: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            endian,
+            pointer_size,
+        }
+    }
+
+    /// Set the structs and enums that variable types refer to
+    pub(crate) fn set_aggregates(&mut self, structs: &HashMap<DefId, MirStruct>, enums: &HashMap<DefId, MirEnum>) {
+        self.structs = structs.clone();
+        self.enums = enums.clone();
+    }
+
+    /// 1-based line and column of the start of a span
+    fn line_col(&self, span: Span) -> (u64, u64) {
+        let (line, column) = self.sources.line_col(span);
+        (line as u64, column as u64)
+    }
+
+    /// Describe a function: its line table rows, call frame, and a subprogram
+    /// entry with its parameters and variables
+    pub(crate) fn add_function(&mut self, compiled: CompiledFunction<'_>, isa: &dyn TargetIsa) {
+        let CompiledFunction { func, func_id, code, variable_slots } = compiled;
+        let address = Address::Symbol { symbol: func_id.as_u32() as usize, addend: 0 };
+        let size = code.buffer.total_size() as u64;
+        self.ranges.push(Range::StartLength { begin: address, length: size });
+
+        // Line table: the declaration for the prologue, then each statement.
+        // Source locations are offsets into the function's own file
+        let file = self.files[func.span.file.index()];
+        let (decl_line, decl_column) = self.line_col(func.span);
+        let mut rows = vec![(0, decl_line, decl_column)];
+        for srcloc in code.buffer.get_srclocs_sorted() {
+            if !srcloc.loc.is_default() {
+                let span = func.span.with_start(srcloc.loc.bits() as usize);
+                let (line, column) = self.line_col(span);
+                rows.push((srcloc.start, line, column));
+            }
+        }
+        let program = &mut self.dwarf.unit.line_program;
+        program.begin_sequence(Some(address));
+        let mut last_line = None;
+        for (offset, line, column) in rows {
+            if last_line == Some(line) {
+                continue;
+            }
+            last_line = Some(line);
+            let row = program.row();
+            row.address_offset = offset as u64;
+            row.file = file;
+            row.line = line;
+            row.column = column;
+            program.generate_row();
+        }
+        program.end_sequence(size);
+
+        if let Some(cie) = self.cie
+            && let Ok(Some(UnwindInfo::SystemV(info))) = code.create_unwind_info(isa)
+        {
+            self.frames.add_fde(cie, info.to_fde(address));
+        }
+
+        let root = self.dwarf.unit.root();
+        let subprogram = self.dwarf.unit.add(root, gimli::DW_TAG_subprogram);
+        let return_type = self.type_entry(&func.return_type);
+        let entry = self.dwarf.unit.get_mut(subprogram);
+        entry.set(gimli::DW_AT_name, AttributeValue::String(func.name.clone().into_bytes()));
+        entry.set(gimli::DW_AT_decl_file, AttributeValue::FileIndex(Some(file)));
+        entry.set(gimli::DW_AT_decl_line, AttributeValue::Udata(decl_line));
+        entry.set(gimli::DW_AT_low_pc, AttributeValue::Address(address));
+        entry.set(gimli::DW_AT_high_pc, AttributeValue::Udata(size));
+        let mut frame_base = Expression::new();
+        frame_base.op(gimli::DW_OP_call_frame_cfa);
+        entry.set(gimli::DW_AT_frame_base, AttributeValue::Exprloc(frame_base));
+        if let Some(ty) = return_type {
+            entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(ty));
+        }
+
+        // Stack slots are addressed from the bottom of the fixed frame area,
+        // which ends where the clobbered registers and setup area begin
+        let frame_top = code.frame_size as i64 + SETUP_AREA_SIZE;
+        let slots: HashMap<u32, StackSlot> = variable_slots.into_iter().collect();
+        for local in func.params.iter().chain(&func.locals) {
+            if !local.is_user_variable() {
+                continue;
+            }
+            let Some(&slot) = slots.get(&local.id) else { continue };
+            let offset = code.sized_stackslot_offsets[slot] as i64 - frame_top;
+            self.add_variable(subprogram, local, offset);
+        }
+    }
+
+    fn add_variable(&mut self, subprogram: UnitEntryId, local: &MirLocal, frame_offset: i64) {
+        let tag = if local.is_arg { gimli::DW_TAG_formal_parameter } else { gimli::DW_TAG_variable };
+        let ty = self.type_entry(&local.ty);
+        let variable = self.dwarf.unit.add(subprogram, tag);
+        let entry = self.dwarf.unit.get_mut(variable);
+        entry.set(gimli::DW_AT_name, AttributeValue::String(local.name.clone().into_bytes()));
+        if let Some(ty) = ty {
+            entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(ty));
+        }
+        let mut location = Expression::new();
+        location.op_fbreg(frame_offset);
+        entry.set(gimli::DW_AT_location, AttributeValue::Exprloc(location));
+    }
+
+    /// Entry describing a type, or `None` for types without values
+    fn type_entry(&mut self, ty: &Type) -> Option<UnitEntryId> {
+        if let Some(&entry) = self.types.get(ty) {
+            return Some(entry);
+        }
+        let root = self.dwarf.unit.root();
+        let entry = match ty {
+            Type::Unit | Type::Never | Type::Error | Type::Var(_) => return None,
+            Type::Str => {
+                let c_char = self.base_type("c_char", gimli::DW_ATE_signed_char, 1);
+                let pointer = self.pointer_type(Some(c_char));
+                let entry = self.dwarf.unit.add(root, gimli::DW_TAG_typedef);
+                let typedef = self.dwarf.unit.get_mut(entry);
+                typedef.set(gimli::DW_AT_name, AttributeValue::String(b"str".to_vec()));
+                typedef.set(gimli::DW_AT_type, AttributeValue::UnitRef(pointer));
+                entry
+            }
+            Type::Ref { inner, .. } => {
+                let inner = self.type_entry(inner);
+                self.pointer_type(inner)
+            }
+            Type::Struct { def_id, .. } => {
+                let mir_struct = self.structs.get(def_id)?.clone();
+                let entry = self.structure_type(&mir_struct.name, mir_struct.compute_total_size());
+                // Register before the fields, which may point back at the struct
+                self.types.insert(ty.clone(), entry);
+                for (i, (name, field_ty)) in mir_struct.fields.iter().enumerate() {
+                    let field_ty = self.field_type(field_ty);
+                    self.member(entry, name, field_ty, mir_struct.compute_field_offset(i));
+                }
+                entry
+            }
+            Type::Enum { def_id, type_args } => {
+                let mir_enum = self.enums.get(def_id)?.clone();
+                let name = self.type_name(ty);
+                let entry = self.structure_type(&name, mir_enum.compute_total_size());
+                self.types.insert(ty.clone(), entry);
+                let discriminant = self.type_entry(&Type::I64);
+                self.member(entry, "discriminant", discriminant, 0);
+                // The payload is a union of the variants' fields, which are
+                // laid out 8 bytes apart after the discriminant
+                let payload = self.dwarf.unit.add(root, gimli::DW_TAG_union_type);
+                let union_type = self.dwarf.unit.get_mut(payload);
+                union_type.set(gimli::DW_AT_name, AttributeValue::String(format!("{}::payload", name).into_bytes()));
+                union_type.set(gimli::DW_AT_byte_size, AttributeValue::Udata(mir_enum.max_payload_size().next_multiple_of(8) as u64));
+                for (variant_name, _, fields) in &mir_enum.variants {
+                    let variant = self.structure_type(&format!("{}::{}", name, variant_name), fields.len() as u32 * 8);
+                    for (i, field_ty) in fields.iter().enumerate() {
+                        let field_ty = self.field_type(&substitute_type(field_ty, type_args));
+                        self.member(variant, &format!("_{}", i), field_ty, i as u32 * 8);
+                    }
+                    self.member(payload, variant_name, Some(variant), 0);
+                }
+                self.member(entry, "payload", Some(payload), mir_enum.payload_offset());
+                entry
+            }
+            Type::Array(elem, len) => {
+                let elem = self.type_entry(elem);
+                let entry = self.dwarf.unit.add(root, gimli::DW_TAG_array_type);
+                if let Some(elem) = elem {
+                    self.dwarf.unit.get_mut(entry).set(gimli::DW_AT_type, AttributeValue::UnitRef(elem));
+                }
+                let range = self.dwarf.unit.add(entry, gimli::DW_TAG_subrange_type);
+                self.dwarf.unit.get_mut(range).set(gimli::DW_AT_count, AttributeValue::Udata(*len as u64));
+                entry
+            }
+            // Slices, functions and closures are opaque pointers
+            Type::Slice(_) | Type::Tuple(_) | Type::Function { .. } | Type::TypeParam { .. } => self.pointer_type(None),
+            _ => {
+                let (name, encoding, size) = primitive(ty)?;
+                self.base_type(name, encoding, size)
+            }
+        };
+        self.types.insert(ty.clone(), entry);
+        Some(entry)
+    }
+
+    /// Name of a type as written in Wisp
+    fn type_name(&self, ty: &Type) -> String {
+        let with_args = |name: &str, type_args: &[Type]| {
+            if type_args.is_empty() {
+                name.to_string()
+            } else {
+                let args: Vec<_> = type_args.iter().map(|arg| self.type_name(arg)).collect();
+                format!("{}<{}>", name, args.join(", "))
+            }
+        };
+        match ty {
+            Type::Struct { def_id, type_args } => {
+                with_args(self.structs.get(def_id).map_or("?", |s| s.name.as_str()), type_args)
+            }
+            Type::Enum { def_id, type_args } => {
+                with_args(self.enums.get(def_id).map_or("?", |e| e.name.as_str()), type_args)
+            }
+            Type::Ref { is_mut: true, inner } => format!("&mut {}", self.type_name(inner)),
+            Type::Ref { is_mut: false, inner } => format!("&{}", self.type_name(inner)),
+            Type::Array(elem, len) => format!("[{}; {}]", self.type_name(elem), len),
+            Type::Str => "str".to_string(),
+            Type::Unit => "()".to_string(),
+            _ => primitive(ty).map_or("?", |(name, ..)| name).to_string(),
+        }
+    }
+
+    /// Type of a struct or enum field: aggregates are stored as pointers to them
+    fn field_type(&mut self, ty: &Type) -> Option<UnitEntryId> {
+        match ty {
+            Type::Struct { .. } | Type::Enum { .. } | Type::Tuple(_) => {
+                self.type_entry(&Type::Ref { is_mut: false, inner: Box::new(ty.clone()) })
+            }
+            _ => self.type_entry(ty),
+        }
+    }
+
+    fn base_type(&mut self, name: &str, encoding: gimli::DwAte, size: u64) -> UnitEntryId {
+        let root = self.dwarf.unit.root();
+        let entry = self.dwarf.unit.add(root, gimli::DW_TAG_base_type);
+        let base = self.dwarf.unit.get_mut(entry);
+        base.set(gimli::DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
+        base.set(gimli::DW_AT_encoding, AttributeValue::Encoding(encoding));
+        base.set(gimli::DW_AT_byte_size, AttributeValue::Udata(size));
+        entry
+    }
+
+    fn pointer_type(&mut self, pointee: Option<UnitEntryId>) -> UnitEntryId {
+        let root = self.dwarf.unit.root();
+        let entry = self.dwarf.unit.add(root, gimli::DW_TAG_pointer_type);
+        let pointer = self.dwarf.unit.get_mut(entry);
+        pointer.set(gimli::DW_AT_byte_size, AttributeValue::Udata(self.pointer_size as u64));
+        if let Some(pointee) = pointee {
+            pointer.set(gimli::DW_AT_type, AttributeValue::UnitRef(pointee));
+        }
+        entry
+    }
+
+    fn structure_type(&mut self, name: &str, size: u32) -> UnitEntryId {
+        let root = self.dwarf.unit.root();
+        let entry = self.dwarf.unit.add(root, gimli::DW_TAG_structure_type);
+        let structure = self.dwarf.unit.get_mut(entry);
+        structure.set(gimli::DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
+        structure.set(gimli::DW_AT_byte_size, AttributeValue::Udata(size as u64));
+        entry
+    }
+
+    fn member(&mut self, parent: UnitEntryId, name: &str, ty: Option<UnitEntryId>, offset: u32) {
+        let entry = self.dwarf.unit.add(parent, gimli::DW_TAG_member);
+        let member = self.dwarf.unit.get_mut(entry);
+        member.set(gimli::DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
+        if let Some(ty) = ty {
+            member.set(gimli::DW_AT_type, AttributeValue::UnitRef(ty));
+        }
+        member.set(gimli::DW_AT_data_member_location, AttributeValue::Udata(offset as u64));
+    }
+
+    /// Write the DWARF sections into the object, relocated against the
+    /// function symbols and each other
+    pub(crate) fn emit(mut self, product: &mut ObjectProduct) -> Result<(), gimli::write::Error> {
+        let ranges = self.dwarf.unit.ranges.add(RangeList(std::mem::take(&mut self.ranges)));
+        let root = self.dwarf.unit.root();
+        self.dwarf.unit.get_mut(root).set(gimli::DW_AT_ranges, AttributeValue::RangeListRef(ranges));
+
+        let mut sections = Sections::new(SectionWriter::new(self.endian));
+        self.dwarf.write(&mut sections)?;
+        self.frames.write_debug_frame(&mut sections.debug_frame)?;
+
+        let mut written = Vec::new();
+        sections.for_each(|id, section| -> Result<(), gimli::write::Error> {
+            if !section.data.slice().is_empty() {
+                written.push((id, section));
+            }
+            Ok(())
+        })?;
+        let object: &mut Object<'static> = &mut product.object;
+        let section_ids: HashMap<SectionId, obj::SectionId> = written.iter()
+            .map(|(id, section)| {
+                let section_id = object.add_section(Vec::new(), id.name().as_bytes().to_vec(), SectionKind::Debug);
+                object.set_section_data(section_id, section.data.slice().to_vec(), 1);
+                (*id, section_id)
+            })
+            .collect();
+
+        for (id, section) in &written {
+            for reloc in &section.relocs {
+                let symbol = match reloc.target {
+                    RelocationTarget::Symbol(func) => product.function_symbol(FuncId::from_u32(func as u32)),
+                    RelocationTarget::Section(target) => {
+                        let target = section_ids.get(&target).ok_or(gimli::write::Error::InvalidReference)?;
+                        product.object.section_symbol(*target)
+                    }
+                };
+                let relocation = obj::Relocation {
+                    offset: reloc.offset as u64,
+                    symbol,
+                    addend: reloc.addend,
+                    flags: RelocationFlags::Generic {
+                        kind: RelocationKind::Absolute,
+                        encoding: RelocationEncoding::Generic,
+                        size: reloc.size * 8,
+                    },
+                };
+                product.object.add_relocation(section_ids[id], relocation)
+                    .map_err(|_| gimli::write::Error::InvalidAddress)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Name, DWARF encoding and size of a primitive type
+fn primitive(ty: &Type) -> Option<(&'static str, gimli::DwAte, u64)> {
+    Some(match ty {
+        Type::I8 => ("i8", gimli::DW_ATE_signed, 1),
+        Type::I16 => ("i16", gimli::DW_ATE_signed, 2),
+        Type::I32 => ("i32", gimli::DW_ATE_signed, 4),
+        Type::I64 => ("i64", gimli::DW_ATE_signed, 8),
+        Type::I128 => ("i128", gimli::DW_ATE_signed, 16),
+        Type::U8 => ("u8", gimli::DW_ATE_unsigned, 1),
+        Type::U16 => ("u16", gimli::DW_ATE_unsigned, 2),
+        Type::U32 => ("u32", gimli::DW_ATE_unsigned, 4),
+        Type::U64 => ("u64", gimli::DW_ATE_unsigned, 8),
+        Type::U128 => ("u128", gimli::DW_ATE_unsigned, 16),
+        Type::F32 => ("f32", gimli::DW_ATE_float, 4),
+        Type::F64 => ("f64", gimli::DW_ATE_float, 8),
+        Type::Bool => ("bool", gimli::DW_ATE_boolean, 1),
+        Type::Char => ("char", gimli::DW_ATE_UTF, 4),
+        _ => return None,
+    })
+}
+
+/// Section data with the relocations its addresses and offsets need
+#[derive(Clone)]
+struct SectionWriter {
+    data: EndianVec<RunTimeEndian>,
+    relocs: Vec<Relocation>,
+}
+
+impl SectionWriter {
+    fn new(endian: RunTimeEndian) -> Self {
+        SectionWriter { data: EndianVec::new(endian), relocs: Vec::new() }
+    }
+}
+
+impl RelocateWriter for SectionWriter {
+    type Writer = EndianVec<RunTimeEndian>;
+
+    fn writer(&self) -> &Self::Writer {
+        &self.data
+    }
+
+    fn writer_mut(&mut self) -> &mut Self::Writer {
+        &mut self.data
+    }
+
+    fn relocate(&mut self, relocation: Relocation) {
+        self.relocs.push(relocation);
+    }
+}
@@ -3,6 +3,7 @@
 //! This module compiles MIR to native machine code.
 
 mod codegen;
+mod debug;
 
-pub use codegen::{Codegen, CodegenError};
+pub use codegen::{Codegen, CodegenError, OptLevel, SUPPORTED_TARGETS};
 
@@ -0,0 +1,108 @@
+//! The stable codes diagnostics are reported with
+//!
+//! A code names one kind of problem. Once published it is never reused for
+//! something else, so it can be searched for and explained later. Errors
+//! start with `E` and warnings with `W`.
+
+use crate::Code;
+
+/// Defines each code as a constant, and `ALL` as the list of them
+macro_rules! codes {
+    ($($(#[$doc:meta])* $name:ident = $code:literal;)*) => {
+        $($(#[$doc])* pub const $name: Code = Code($code);)*
+
+        /// Every code, in the order they are defined
+        pub const ALL: &[Code] = &[$($name),*];
+    };
+}
+
+codes! {
+    // Parsing
+
+    /// The source doesn't follow Wisp's grammar
+    SYNTAX_ERROR = "E0001";
+    /// An import of a module that can't be found or read
+    UNRESOLVED_IMPORT = "E0432";
+
+    // Name resolution
+
+    /// A variable, variant or module item that isn't defined
+    UNRESOLVED_NAME = "E0425";
+    /// A type, struct or trait that isn't defined
+    UNDEFINED_TYPE = "E0412";
+    /// A namespace used where a value is expected
+    NOT_A_VALUE = "E0423";
+    /// A private item used from outside its module
+    PRIVATE_ITEM = "E0603";
+    /// `break` or `continue` outside a loop, or a bad loop label
+    LOOP_CONTROL = "E0268";
+    /// Control flow that would leave a `defer` block
+    DEFER_ESCAPE = "E0735";
+    /// A generic trait used without all of its type arguments
+    MISSING_TYPE_ARGUMENT = "E0107";
+
+    // Type checking
+
+    /// A value whose type isn't the one its context needs
+    MISMATCHED_TYPES = "E0308";
+    /// A call with missing, extra or misnamed arguments
+    WRONG_ARGUMENTS = "E0061";
+    /// A type used in a way that needs a trait it doesn't implement
+    TRAIT_NOT_IMPLEMENTED = "E0277";
+    /// A type that can't be inferred without an annotation
+    TYPE_ANNOTATIONS_NEEDED = "E0282";
+    /// A field access on a type without that field
+    NO_FIELD = "E0609";
+    /// A struct literal that doesn't set every field
+    MISSING_FIELDS = "E0063";
+    /// A call of something that isn't a function
+    NOT_CALLABLE = "E0618";
+    /// Indexing a type that can't be indexed
+    CANNOT_INDEX = "E0608";
+    /// Dereferencing something that isn't a reference
+    CANNOT_DEREF = "E0614";
+    /// An `as` cast between types that can't be converted
+    INVALID_CAST = "E0605";
+    /// A `match` that doesn't cover every value
+    NON_EXHAUSTIVE_PATTERNS = "E0004";
+    /// A variant pattern with the wrong number of fields
+    PATTERN_FIELD_COUNT = "E0023";
+    /// A `Copy` impl the type can't have
+    INVALID_COPY_IMPL = "E0204";
+    /// A trait or language method with the wrong signature
+    INCOMPATIBLE_SIGNATURE = "E0053";
+    /// A function that an attribute relies on is missing
+    MISSING_ASSOCIATED_FUNCTION = "E0599";
+
+    // Borrow checking
+
+    /// A use of a value after it has been moved
+    USE_AFTER_MOVE = "E0382";
+    /// A borrow of a place that is already borrowed mutably
+    ALREADY_MUTABLY_BORROWED = "E0499";
+    /// A mutable borrow of a place that is already borrowed
+    ALREADY_BORROWED = "E0502";
+    /// A use of a place while it is borrowed mutably
+    USE_WHILE_MUTABLY_BORROWED = "E0503";
+    /// A move out of a place while it is borrowed
+    MOVE_WHILE_BORROWED = "E0505";
+    /// An assignment to a place while it is borrowed
+    ASSIGN_WHILE_BORROWED = "E0506";
+    /// A move out of a variable captured by a closure
+    MOVE_OUT_OF_CAPTURE = "E0507";
+    /// A move out of a field of a value whose type implements `Drop`
+    MOVE_OUT_OF_DROP = "E0509";
+    /// A closure that outlives the stack frame or variables it was created with
+    ESCAPING_CLOSURE = "E0373";
+    /// An assignment to a variable that isn't `mut`
+    ASSIGN_TO_IMMUTABLE = "E0384";
+    /// A mutable borrow of a place that isn't `mut`
+    MUT_BORROW_OF_IMMUTABLE = "E0596";
+
+    // Warnings
+
+    /// A struct literal field that is set more than once
+    DUPLICATE_FIELD = "W0001";
+    /// A `match` arm that earlier arms already cover
+    UNREACHABLE_PATTERN = "W0002";
+}
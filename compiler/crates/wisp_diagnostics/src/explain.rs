@@ -0,0 +1,1035 @@
+//! Long-form explanations of diagnostic codes, shown by `wisp explain`
+//!
+//! Every code in `codes::ALL` has an entry, with an example that produces it
+//! and the same example fixed.
+
+use crate::{codes, Code};
+
+/// What a code means, with an example that produces it and a fixed version
+pub struct Explanation {
+    pub code: Code,
+    /// A few words naming the problem
+    pub title: &'static str,
+    description: &'static str,
+    erroneous: &'static str,
+    fixed: &'static str,
+}
+
+impl Explanation {
+    pub fn description(&self) -> &'static str {
+        self.description.trim_start_matches('\n')
+    }
+
+    /// A whole program that is reported with this code
+    pub fn erroneous(&self) -> &'static str {
+        self.erroneous.trim_start_matches('\n')
+    }
+
+    /// The erroneous program changed so that it compiles
+    pub fn fixed(&self) -> &'static str {
+        self.fixed.trim_start_matches('\n')
+    }
+}
+
+/// The explanation of a code such as `E0382`, ignoring case
+pub fn explain(code: &str) -> Option<&'static Explanation> {
+    EXPLANATIONS.iter().find(|e| e.code.as_str().eq_ignore_ascii_case(code))
+}
+
+static EXPLANATIONS: &[Explanation] = &[
+    // Parsing
+
+    Explanation {
+        code: codes::SYNTAX_ERROR,
+        title: "syntax error",
+        description: "
+The source doesn't follow Wisp's grammar, so it can't be parsed. The message
+says what the parser expected and what it found instead. An expression that
+is cut short, an unclosed brace or a keyword in the wrong place are common
+causes.
+",
+        erroneous: r#"
+fn main() {
+    let x = 1 +
+}
+"#,
+        fixed: r#"
+fn main() {
+    let x = 1 + 2
+}
+"#,
+    },
+    Explanation {
+        code: codes::UNRESOLVED_IMPORT,
+        title: "unresolved import",
+        description: "
+An `import` names a module that can't be found or read. `std.` imports are
+looked up in the standard library, and `@.` imports in the project root,
+the directory holding `wisp.toml`. Each segment of the path after the first
+is a directory, and the last one names a `.ws` file.
+",
+        erroneous: r#"
+import std.fmt
+
+fn main() {
+    fmt.print(&1)
+}
+"#,
+        fixed: r#"
+import std.io
+
+fn main() {
+    io.print(&1)
+}
+"#,
+    },
+
+    // Name resolution
+
+    Explanation {
+        code: codes::UNRESOLVED_NAME,
+        title: "unresolved name",
+        description: "
+A variable, function, enum variant or namespace item is used, but nothing of
+that name is in scope. Check the spelling; when a name with a similar
+spelling is in scope, it is suggested. Items of another module have to be
+imported before they can be used.
+",
+        erroneous: r#"
+fn main() {
+    let total = 10
+    let doubled = totl * 2
+}
+"#,
+        fixed: r#"
+fn main() {
+    let total = 10
+    let doubled = total * 2
+}
+"#,
+    },
+    Explanation {
+        code: codes::UNDEFINED_TYPE,
+        title: "undefined type",
+        description: "
+A type, struct or trait is named, but no type of that name is defined or
+imported. Check the spelling, or import the module that defines it.
+",
+        erroneous: r#"
+struct Point { x: i32, y: i32 }
+
+fn origin() -> Pointt {
+    Point { x: 0, y: 0 }
+}
+
+fn main() {}
+"#,
+        fixed: r#"
+struct Point { x: i32, y: i32 }
+
+fn origin() -> Point {
+    Point { x: 0, y: 0 }
+}
+
+fn main() {}
+"#,
+    },
+    Explanation {
+        code: codes::NOT_A_VALUE,
+        title: "namespace used as a value",
+        description: "
+A namespace, such as an imported module, is used where a value is expected.
+Namespaces only group items; use one of the items inside it instead.
+",
+        erroneous: r#"
+import std
+
+fn main() {
+    let out = std.io
+}
+"#,
+        fixed: r#"
+import std
+
+fn main() {
+    std.io.print(&1)
+}
+"#,
+    },
+    Explanation {
+        code: codes::PRIVATE_ITEM,
+        title: "private item",
+        description: "
+An item of another module is used, but it isn't marked `pub`. Items are
+private to the module that defines them unless they are declared with `pub`,
+so only its public functions, types and traits can be used from outside.
+",
+        erroneous: r#"
+import std.io
+
+fn main() {
+    let out = io.stdout_stream()
+}
+"#,
+        fixed: r#"
+import std.io
+
+fn main() {
+    io.print(&1)
+}
+"#,
+    },
+    Explanation {
+        code: codes::LOOP_CONTROL,
+        title: "`break` or `continue` outside a loop",
+        description: "
+`break` and `continue` only make sense inside `loop`, `while` and `for`, and
+`break @name` needs an enclosing loop labelled `@name`. A `defer` block is
+not part of the loop around it, so it can't `break` or `continue` it either.
+",
+        erroneous: r#"
+fn main() {
+    let x = 1
+    break
+}
+"#,
+        fixed: r#"
+fn main() {
+    loop {
+        break
+    }
+}
+"#,
+    },
+    Explanation {
+        code: codes::DEFER_ESCAPE,
+        title: "control flow out of a `defer` block",
+        description: "
+A `defer` block runs when its scope ends, after the scope's own control flow
+has already been decided, so it can't `return`, use `?`, or `break` or
+`continue` a loop outside itself. Work out what you need before the `defer`,
+or move the control flow out of it.
+",
+        erroneous: r#"
+fn first() -> i32 {
+    defer {
+        return 1
+    }
+    0
+}
+
+fn main() {}
+"#,
+        fixed: r#"
+fn first() -> i32 {
+    defer {
+        let done = 1
+    }
+    0
+}
+
+fn main() {}
+"#,
+    },
+    Explanation {
+        code: codes::MISSING_TYPE_ARGUMENT,
+        title: "missing type argument",
+        description: "
+A generic trait is used without a type for each of its parameters. Unlike
+function calls, trait impls and bounds can't infer them; write them out in
+angle brackets.
+",
+        erroneous: r#"
+trait Convert<T> {
+    fn convert(&self) -> T
+}
+
+struct Meters { value: i32 }
+
+impl Convert for Meters {
+    fn convert(&self) -> i32 { self.value }
+}
+
+fn main() {}
+"#,
+        fixed: r#"
+trait Convert<T> {
+    fn convert(&self) -> T
+}
+
+struct Meters { value: i32 }
+
+impl Convert<i32> for Meters {
+    fn convert(&self) -> i32 { self.value }
+}
+
+fn main() {}
+"#,
+    },
+
+    // Type checking
+
+    Explanation {
+        code: codes::MISMATCHED_TYPES,
+        title: "mismatched types",
+        description: "
+A value has a different type from the one its context needs: an annotated
+`let`, a function argument or return value, a condition, or the operands of
+an operator. Wisp doesn't convert between types implicitly; change the value,
+the annotation, or convert explicitly with `as`.
+",
+        erroneous: r#"
+fn main() {
+    let ready: bool = 1
+}
+"#,
+        fixed: r#"
+fn main() {
+    let ready: bool = true
+}
+"#,
+    },
+    Explanation {
+        code: codes::WRONG_ARGUMENTS,
+        title: "wrong number of arguments",
+        description: "
+A function is called with missing or extra arguments, or with a named
+argument that doesn't match a parameter. Each parameter needs exactly one
+argument, given either by position or by name.
+",
+        erroneous: r#"
+fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+fn main() {
+    let sum = add(1)
+}
+"#,
+        fixed: r#"
+fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+fn main() {
+    let sum = add(1, 2)
+}
+"#,
+    },
+    Explanation {
+        code: codes::TRAIT_NOT_IMPLEMENTED,
+        title: "trait not implemented",
+        description: "
+A type is used in a way that needs a trait it doesn't implement, such as
+iterating over a value that isn't iterable, or passing it to a generic
+function whose bounds it doesn't meet. Implement the trait for the type, or
+use a value of a type that does.
+",
+        erroneous: r#"
+fn main() {
+    for i in 5 {
+        let x = i
+    }
+}
+"#,
+        fixed: r#"
+fn main() {
+    for i in 0..5 {
+        let x = i
+    }
+}
+"#,
+    },
+    Explanation {
+        code: codes::TYPE_ANNOTATIONS_NEEDED,
+        title: "type annotations needed",
+        description: "
+The type of a value can't be worked out from how it is used, for example an
+empty array literal whose element type is never fixed. Add a type
+annotation.
+",
+        erroneous: r#"
+fn main() {
+    let items = []
+}
+"#,
+        fixed: r#"
+fn main() {
+    let items: [i32; 0] = []
+}
+"#,
+    },
+    Explanation {
+        code: codes::NO_FIELD,
+        title: "no such field",
+        description: "
+A field is accessed on a value whose type doesn't have a field of that name.
+Check the spelling against the struct's definition.
+",
+        erroneous: r#"
+struct Point { x: i32, y: i32 }
+
+fn main() {
+    let p = Point { x: 1, y: 2 }
+    let z = p.z
+}
+"#,
+        fixed: r#"
+struct Point { x: i32, y: i32 }
+
+fn main() {
+    let p = Point { x: 1, y: 2 }
+    let y = p.y
+}
+"#,
+    },
+    Explanation {
+        code: codes::MISSING_FIELDS,
+        title: "missing struct fields",
+        description: "
+A struct literal has to set every field of the struct. Set the missing ones,
+or mark the struct `@defaults` and give it a `defaults()` function to fill in
+the rest.
+",
+        erroneous: r#"
+struct Point { x: i32, y: i32 }
+
+fn main() {
+    let p = Point { x: 1 }
+}
+"#,
+        fixed: r#"
+struct Point { x: i32, y: i32 }
+
+fn main() {
+    let p = Point { x: 1, y: 0 }
+}
+"#,
+    },
+    Explanation {
+        code: codes::NOT_CALLABLE,
+        title: "call of a non-function",
+        description: "
+Something is called with `()` but its type isn't a function or a closure.
+",
+        erroneous: r#"
+fn main() {
+    let count = 5
+    let n = count()
+}
+"#,
+        fixed: r#"
+fn count() -> i32 {
+    5
+}
+
+fn main() {
+    let n = count()
+}
+"#,
+    },
+    Explanation {
+        code: codes::CANNOT_INDEX,
+        title: "cannot index",
+        description: "
+A value is indexed with `[]` but its type can't be indexed. Arrays, slices
+and types implementing `Index` can be.
+",
+        erroneous: r#"
+fn main() {
+    let count = 5
+    let first = count[0]
+}
+"#,
+        fixed: r#"
+fn main() {
+    let counts = [5, 6]
+    let first = counts[0]
+}
+"#,
+    },
+    Explanation {
+        code: codes::CANNOT_DEREF,
+        title: "cannot dereference",
+        description: "
+A value is dereferenced with `*` but it isn't a reference or a pointer.
+",
+        erroneous: r#"
+fn main() {
+    let x = 5
+    let y = *x
+}
+"#,
+        fixed: r#"
+fn main() {
+    let x = 5
+    let r = &x
+    let y = *r
+}
+"#,
+    },
+    Explanation {
+        code: codes::INVALID_CAST,
+        title: "invalid cast",
+        description: "
+An `as` cast between two types that can't be converted into each other.
+`as` converts between numeric types, and between integers and `char`; other
+values need a function that builds the new value.
+",
+        erroneous: r#"
+struct Point { x: i32, y: i32 }
+
+fn main() {
+    let p = Point { x: 1, y: 2 }
+    let n = p as i32
+}
+"#,
+        fixed: r#"
+struct Point { x: i32, y: i32 }
+
+fn main() {
+    let p = Point { x: 1, y: 2 }
+    let n = p.x as i64
+}
+"#,
+    },
+    Explanation {
+        code: codes::NON_EXHAUSTIVE_PATTERNS,
+        title: "non-exhaustive patterns",
+        description: "
+A `match` has to handle every value its scrutinee can have, and these arms
+miss some; the message says which. Add an arm for them, or a `_` arm to catch
+everything else.
+",
+        erroneous: r#"
+enum Light { Red, Yellow, Green }
+
+fn wait(light: Light) -> i32 {
+    match light {
+        Red -> 30,
+        Yellow -> 5,
+    }
+}
+
+fn main() {}
+"#,
+        fixed: r#"
+enum Light { Red, Yellow, Green }
+
+fn wait(light: Light) -> i32 {
+    match light {
+        Red -> 30,
+        Yellow -> 5,
+        Green -> 0,
+    }
+}
+
+fn main() {}
+"#,
+    },
+    Explanation {
+        code: codes::PATTERN_FIELD_COUNT,
+        title: "wrong number of fields in a pattern",
+        description: "
+A pattern for a tuple variant has to bind each of the variant's fields, and
+this one has too few or too many. Use `_` for fields you don't need.
+",
+        erroneous: r#"
+enum Shape { Rect(i32, i32), Circle(i32) }
+
+fn area(shape: Shape) -> i32 {
+    match shape {
+        Rect(w) -> w,
+        Circle(r) -> 3 * r * r,
+    }
+}
+
+fn main() {}
+"#,
+        fixed: r#"
+enum Shape { Rect(i32, i32), Circle(i32) }
+
+fn area(shape: Shape) -> i32 {
+    match shape {
+        Rect(w, h) -> w * h,
+        Circle(r) -> 3 * r * r,
+    }
+}
+
+fn main() {}
+"#,
+    },
+    Explanation {
+        code: codes::INVALID_COPY_IMPL,
+        title: "invalid `Copy` impl",
+        description: "
+A type can only be `Copy` if copying its bytes gives an independent value:
+every field has to be `Copy` itself, and the type can't implement `Drop`.
+Implement `Copy` for the field types first, or leave the type movable.
+",
+        erroneous: r#"
+import std.ops.Copy
+
+struct Meters { value: i32 }
+
+struct Span { start: Meters, end: Meters }
+
+impl Copy for Span {}
+
+fn main() {}
+"#,
+        fixed: r#"
+import std.ops.Copy
+
+struct Meters { value: i32 }
+
+impl Copy for Meters {}
+
+struct Span { start: Meters, end: Meters }
+
+impl Copy for Span {}
+
+fn main() {}
+"#,
+    },
+    Explanation {
+        code: codes::INCOMPATIBLE_SIGNATURE,
+        title: "incompatible signature",
+        description: "
+A method that the language calls for you doesn't have the signature it
+needs, such as an operator method with the wrong types, an `index` that
+doesn't return a reference, a `next` that doesn't return an `Option`, or a
+`defaults()` that takes arguments. Change it to match.
+",
+        erroneous: r#"
+@defaults
+struct Config { retries: i32, verbose: bool }
+
+impl Config {
+    fn defaults(retries: i32) -> Config {
+        Config { retries: retries, verbose: false }
+    }
+}
+
+fn main() {
+    let c: Config = { verbose: true }
+}
+"#,
+        fixed: r#"
+@defaults
+struct Config { retries: i32, verbose: bool }
+
+impl Config {
+    fn defaults() -> Config {
+        Config { retries: 3, verbose: false }
+    }
+}
+
+fn main() {
+    let c: Config = { verbose: true }
+}
+"#,
+    },
+    Explanation {
+        code: codes::MISSING_ASSOCIATED_FUNCTION,
+        title: "missing associated function",
+        description: "
+An attribute relies on a function the type doesn't define. A struct marked
+`@defaults` needs a `defaults()` function returning the struct, which fills
+in the fields a literal leaves out.
+",
+        erroneous: r#"
+@defaults
+struct Config { retries: i32, verbose: bool }
+
+fn main() {
+    let c: Config = { verbose: true }
+}
+"#,
+        fixed: r#"
+@defaults
+struct Config { retries: i32, verbose: bool }
+
+impl Config {
+    fn defaults() -> Config {
+        Config { retries: 3, verbose: false }
+    }
+}
+
+fn main() {
+    let c: Config = { verbose: true }
+}
+"#,
+    },
+
+    // Borrow checking
+
+    Explanation {
+        code: codes::USE_AFTER_MOVE,
+        title: "use of a moved value",
+        description: "
+A value whose type isn't `Copy` is moved when it is assigned to another
+variable, passed to a function by value or captured by a closure, and the
+variable it was moved out of can't be used afterwards. Borrow the value with
+`&` instead, or implement `Copy` for types that are cheap to copy.
+",
+        erroneous: r#"
+struct Token { id: i32 }
+
+fn consume(t: Token) {}
+
+fn main() {
+    let t = Token { id: 1 }
+    consume(t)
+    consume(t)
+}
+"#,
+        fixed: r#"
+struct Token { id: i32 }
+
+fn inspect(t: &Token) {}
+
+fn consume(t: Token) {}
+
+fn main() {
+    let t = Token { id: 1 }
+    inspect(&t)
+    consume(t)
+}
+"#,
+    },
+    Explanation {
+        code: codes::ALREADY_MUTABLY_BORROWED,
+        title: "already borrowed mutably",
+        description: "
+A place is borrowed while a mutable borrow of it is still alive. A mutable
+borrow has to be the only way to reach the place, so no other borrow, shared
+or mutable, can exist at the same time. End the first borrow, for example by
+moving it into a smaller scope, before borrowing again.
+",
+        erroneous: r#"
+fn main() {
+    let mut count = 0
+    let a = &mut count
+    let b = &mut count
+}
+"#,
+        fixed: r#"
+fn main() {
+    let mut count = 0
+    {
+        let a = &mut count
+    }
+    let b = &mut count
+}
+"#,
+    },
+    Explanation {
+        code: codes::ALREADY_BORROWED,
+        title: "mutable borrow of a borrowed place",
+        description: "
+A place is borrowed mutably while a shared borrow of it is still alive. The
+shared borrow promises that the value doesn't change under it, so it has to
+end before the mutable borrow starts.
+",
+        erroneous: r#"
+fn main() {
+    let mut count = 0
+    let r = &count
+    let m = &mut count
+}
+"#,
+        fixed: r#"
+fn main() {
+    let mut count = 0
+    {
+        let r = &count
+    }
+    let m = &mut count
+}
+"#,
+    },
+    Explanation {
+        code: codes::USE_WHILE_MUTABLY_BORROWED,
+        title: "use while borrowed mutably",
+        description: "
+A place is read while a mutable borrow of it is still alive. Until the
+mutable borrow ends, the place can only be reached through it.
+",
+        erroneous: r#"
+fn main() {
+    let mut count = 0
+    let m = &mut count
+    let copy = count
+}
+"#,
+        fixed: r#"
+fn main() {
+    let mut count = 0
+    {
+        let m = &mut count
+    }
+    let copy = count
+}
+"#,
+    },
+    Explanation {
+        code: codes::MOVE_WHILE_BORROWED,
+        title: "move out of a borrowed value",
+        description: "
+A value is moved while a borrow of it is still alive, which would leave the
+borrow pointing at a value that's gone. End the borrow before moving.
+",
+        erroneous: r#"
+struct Token { id: i32 }
+
+fn main() {
+    let t = Token { id: 1 }
+    let r = &t
+    let moved = t
+}
+"#,
+        fixed: r#"
+struct Token { id: i32 }
+
+fn main() {
+    let t = Token { id: 1 }
+    {
+        let r = &t
+    }
+    let moved = t
+}
+"#,
+    },
+    Explanation {
+        code: codes::ASSIGN_WHILE_BORROWED,
+        title: "assignment to a borrowed place",
+        description: "
+A place is assigned to while a borrow of it is still alive, which would
+change the value under the borrow. End the borrow before assigning.
+",
+        erroneous: r#"
+fn main() {
+    let mut count = 0
+    let r = &count
+    count = 1
+}
+"#,
+        fixed: r#"
+fn main() {
+    let mut count = 0
+    {
+        let r = &count
+    }
+    count = 1
+}
+"#,
+    },
+    Explanation {
+        code: codes::MOVE_OUT_OF_CAPTURE,
+        title: "move out of a captured variable",
+        description: "
+A closure moves a value out of a variable it captured. The closure can be
+called more than once, so it can't give its captured value away. Pass the
+value to the closure as a parameter instead.
+",
+        erroneous: r#"
+struct Token { id: i32 }
+
+fn consume(t: Token) {}
+
+fn main() {
+    let t = Token { id: 1 }
+    let f = () -> consume(t)
+}
+"#,
+        fixed: r#"
+struct Token { id: i32 }
+
+fn consume(t: Token) {}
+
+fn main() {
+    let f = (t: Token) -> consume(t)
+    f(Token { id: 1 })
+}
+"#,
+    },
+    Explanation {
+        code: codes::MOVE_OUT_OF_DROP,
+        title: "move out of a type that implements Drop",
+        description: "
+A field is moved out of a value whose type implements `Drop`. The value's
+`drop` method runs when it goes out of scope and expects every field to
+still be there, so none can be moved out. Borrow the field, or give the
+type a method that takes `self` and returns the field.
+",
+        erroneous: r#"
+import std.ops.Drop
+
+struct Name { text: i32 }
+struct File { name: Name }
+
+impl Drop for File {
+    fn drop(&mut self) {}
+}
+
+fn take(n: Name) {}
+
+fn main() {
+    let f = File { name: Name { text: 1 } }
+    take(f.name)
+}
+"#,
+        fixed: r#"
+import std.ops.Drop
+
+struct Name { text: i32 }
+struct File { name: Name }
+
+impl Drop for File {
+    fn drop(&mut self) {}
+}
+
+fn show(n: &Name) {}
+
+fn main() {
+    let f = File { name: Name { text: 1 } }
+    show(&f.name)
+}
+"#,
+    },
+    Explanation {
+        code: codes::ESCAPING_CLOSURE,
+        title: "closure escapes its stack frame",
+        description: "
+A closure is returned, stored in a struct field or through a reference, or
+assigned to a variable that outlives it. A closure's environment lives in
+the stack frame of the function that creates it, next to the variables it
+borrows, so it can only be used while that function runs and those
+variables are in scope. Pass the closure down to the function that calls
+it instead.
+",
+        erroneous: r#"
+fn adder(n: i32) -> (i32) -> i32 {
+    (x: i32) -> x + n
+}
+
+fn main() {
+    let add = adder(1)
+    let three = add(2)
+}
+"#,
+        fixed: r#"
+fn apply(f: (i32) -> i32, x: i32) -> i32 {
+    f(x)
+}
+
+fn main() {
+    let n = 1
+    let three = apply((x: i32) -> x + n, 2)
+}
+"#,
+    },
+    Explanation {
+        code: codes::ASSIGN_TO_IMMUTABLE,
+        title: "assignment to an immutable variable",
+        description: "
+A variable is assigned to after its `let`, but it isn't declared `mut`.
+Variables can only be changed if they are declared with `let mut`.
+",
+        erroneous: r#"
+fn main() {
+    let count = 0
+    count = 1
+}
+"#,
+        fixed: r#"
+fn main() {
+    let mut count = 0
+    count = 1
+}
+"#,
+    },
+    Explanation {
+        code: codes::MUT_BORROW_OF_IMMUTABLE,
+        title: "mutable borrow of an immutable variable",
+        description: "
+A variable is borrowed with `&mut`, but it isn't declared `mut`. Declare it
+with `let mut` to allow changing it through the borrow.
+",
+        erroneous: r#"
+fn main() {
+    let count = 0
+    let r = &mut count
+}
+"#,
+        fixed: r#"
+fn main() {
+    let mut count = 0
+    let r = &mut count
+}
+"#,
+    },
+
+    // Warnings
+
+    Explanation {
+        code: codes::DUPLICATE_FIELD,
+        title: "duplicate field",
+        description: "
+A struct literal sets the same field more than once. Only the last value is
+kept, so the earlier ones are ignored. A `..base` spread sets every field
+where it stands: fields listed before it are replaced by the base's, and
+those after it replace the base's.
+",
+        erroneous: r#"
+struct Point { x: i32, y: i32 }
+
+fn main() {
+    let p = Point { x: 1, y: 2, x: 3 }
+}
+"#,
+        fixed: r#"
+struct Point { x: i32, y: i32 }
+
+fn main() {
+    let p = Point { x: 3, y: 2 }
+}
+"#,
+    },
+    Explanation {
+        code: codes::UNREACHABLE_PATTERN,
+        title: "unreachable pattern",
+        description: "
+A `match` arm can never be taken, because the arms before it already cover
+every value it matches. Arms are tried in order, so put more specific
+patterns before wildcards, or remove the arm.
+",
+        erroneous: r#"
+enum Light { Red, Yellow, Green }
+
+fn wait(light: Light) -> i32 {
+    match light {
+        Red -> 30,
+        _ -> 5,
+        Green -> 0,
+    }
+}
+
+fn main() {}
+"#,
+        fixed: r#"
+enum Light { Red, Yellow, Green }
+
+fn wait(light: Light) -> i32 {
+    match light {
+        Red -> 30,
+        Green -> 0,
+        _ -> 5,
+    }
+}
+
+fn main() {}
+"#,
+    },
+];
@@ -0,0 +1,348 @@
+//! Diagnostics for Wisp
+//!
+//! Every compiler phase reports its problems as a `Diagnostic`: a severity,
+//! a stable code, a primary label and any number of secondary labels, notes
+//! and help. Diagnostics are rendered against a `SourceMap` with ariadne.
+
+pub mod codes;
+pub mod explain;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::IsTerminal;
+
+use ariadne::{Color, Config, IndexType, Report, ReportKind, Source};
+use wisp_lexer::{FileId, SourceMap, Span};
+
+/// A stable diagnostic code such as `E0308`; see `codes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Code(&'static str);
+
+impl Code {
+    pub fn as_str(self) -> &'static str {
+        self.0
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A span of source and what to say about it
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: Option<String>,
+}
+
+/// An edit that would fix the problem: replace `span` with `replacement`
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub span: Span,
+    pub replacement: String,
+    pub message: String,
+}
+
+/// A problem found in the source
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    /// Where the problem is
+    pub primary: Label,
+    /// Other places that explain it, such as where a value was moved
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    pub fn error(code: Code, message: impl Into<String>, span: Span) -> Self {
+        Self::new(Severity::Error, code, message.into(), span)
+    }
+
+    pub fn warning(code: Code, message: impl Into<String>, span: Span) -> Self {
+        Self::new(Severity::Warning, code, message.into(), span)
+    }
+
+    fn new(severity: Severity, code: Code, message: String, span: Span) -> Self {
+        Self {
+            severity,
+            code,
+            message,
+            primary: Label { span, message: None },
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+            suggestions: Vec::new(),
+        }
+    }
+
+    /// Say something about the primary span itself, instead of repeating
+    /// the message under it
+    pub fn with_primary_message(mut self, message: impl Into<String>) -> Self {
+        self.primary.message = Some(message.into());
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label { span, message: Some(message.into()) });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_suggestion(mut self, span: Span, replacement: impl Into<String>, message: impl Into<String>) -> Self {
+        self.suggestions.push(Suggestion { span, replacement: replacement.into(), message: message.into() });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render with source snippets, coloured if `color` is set
+    pub fn render(&self, sources: &SourceMap, color: bool) -> String {
+        let mut out = Vec::new();
+        self.report(sources, color)
+            .write(SourceCache::new(sources), &mut out)
+            .expect("writing a diagnostic to memory can't fail");
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    /// Print to stderr, coloured when stderr is a terminal
+    pub fn eprint(&self, sources: &SourceMap) {
+        eprint!("{}", self.render(sources, std::io::stderr().is_terminal()));
+    }
+
+    fn report(&self, sources: &SourceMap, color: bool) -> Report<'static, (FileId, std::ops::Range<usize>)> {
+        let (kind, primary_color) = match self.severity {
+            Severity::Error => (ReportKind::Error, Color::Red),
+            Severity::Warning => (ReportKind::Warning, Color::Yellow),
+        };
+        let primary = &self.primary;
+        let mut report = Report::build(kind, primary.span.file, primary.span.start)
+            .with_code(self.code)
+            .with_message(&self.message)
+            .with_config(Config::default().with_color(color).with_index_type(IndexType::Byte))
+            .with_label(
+                label(sources, primary, primary_color)
+                    .with_message(primary.message.as_ref().unwrap_or(&self.message))
+                    .with_priority(1),
+            );
+        for secondary in &self.secondary {
+            report = report.with_label(label(sources, secondary, Color::Blue));
+        }
+        if !self.notes.is_empty() {
+            report = report.with_note(self.notes.join("\n"));
+        }
+        let help: Vec<String> = self.help.iter().cloned()
+            .chain(self.suggestions.iter().map(|s| format!("{}: `{}`", s.message, s.replacement)))
+            .collect();
+        if !help.is_empty() {
+            report = report.with_help(help.join("\n"));
+        }
+        report.finish()
+    }
+
+    /// The diagnostic as a single line of JSON, with every span given as a
+    /// file and a range of 1-based lines and columns
+    pub fn to_json(&self, sources: &SourceMap) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let (file, range) = json_location(sources, self.primary.span);
+        serde_json::json!({
+            "code": self.code.as_str(),
+            "severity": severity,
+            "message": self.message,
+            "file": file,
+            "range": range,
+            "labels": self.secondary.iter().map(|label| {
+                let (file, range) = json_location(sources, label.span);
+                serde_json::json!({ "message": label.message, "file": file, "range": range })
+            }).collect::<Vec<_>>(),
+            "notes": self.notes,
+            "help": self.help,
+            "suggestions": self.suggestions.iter().map(|suggestion| {
+                let (file, range) = json_location(sources, suggestion.span);
+                serde_json::json!({
+                    "message": suggestion.message,
+                    "file": file,
+                    "range": range,
+                    "replacement": suggestion.replacement,
+                })
+            }).collect::<Vec<_>>(),
+        })
+        .to_string()
+    }
+}
+
+/// An ariadne label for `label`, widening an empty span to the character
+/// after it so that it still gets an underline
+fn label(sources: &SourceMap, label: &Label, color: Color) -> ariadne::Label<(FileId, std::ops::Range<usize>)> {
+    let span = label.span;
+    let text = sources.text(span.file);
+    let mut end = span.end.min(text.len());
+    let start = span.start.min(end);
+    if start == end {
+        end += text[start..].chars().next().map_or(0, char::len_utf8);
+    }
+    let mut ariadne_label = ariadne::Label::new((span.file, start..end)).with_color(color);
+    if let Some(message) = &label.message {
+        ariadne_label = ariadne_label.with_message(message);
+    }
+    ariadne_label
+}
+
+/// The file of a span and its start and end as 1-based lines and columns
+fn json_location(sources: &SourceMap, span: Span) -> (String, serde_json::Value) {
+    let file = sources.get(span.file);
+    let position = |offset| {
+        let (line, column) = file.line_col(offset);
+        serde_json::json!({ "line": line, "column": column })
+    };
+    let range = serde_json::json!({ "start": position(span.start), "end": position(span.end) });
+    (file.path.display().to_string(), range)
+}
+
+/// The candidate closest to `name` by edit distance, if it's close enough to
+/// be a likely misspelling. Names under three characters get no suggestion:
+/// one edit turns them into almost anything
+pub fn similar_name<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = name.chars().count() / 3;
+    if max_distance == 0 {
+        return None;
+    }
+    candidates.into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Edit distance between two strings in characters, where swapping two
+/// adjacent characters counts as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Lets ariadne read files out of a `SourceMap`
+struct SourceCache<'a> {
+    sources: &'a SourceMap,
+    files: HashMap<FileId, Source<&'a str>>,
+}
+
+impl<'a> SourceCache<'a> {
+    fn new(sources: &'a SourceMap) -> Self {
+        Self { sources, files: HashMap::new() }
+    }
+}
+
+impl<'a> ariadne::Cache<FileId> for SourceCache<'a> {
+    type Storage = &'a str;
+
+    fn fetch(&mut self, file: &FileId) -> Result<&Source<&'a str>, Box<dyn fmt::Debug + '_>> {
+        let sources = self.sources;
+        Ok(self.files.entry(*file).or_insert_with(|| Source::from(sources.text(*file))))
+    }
+
+    fn display<'b>(&self, file: &'b FileId) -> Option<Box<dyn fmt::Display + 'b>> {
+        Some(Box::new(self.sources.path(*file).display().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_render_labels_across_lines() {
+        let mut sources = SourceMap::new();
+        let file = sources.add(PathBuf::from("moved.ws"), "let s = make()\nlet t = s\nuse(\n    s)\n".to_string());
+        let diagnostic = Diagnostic::error(codes::USE_AFTER_MOVE, "use of moved value: `s`", Span::in_file(file, 25, 36))
+            .with_label(Span::in_file(file, 23, 24), "value moved here")
+            .with_help("clone it before the move");
+        let rendered = diagnostic.render(&sources, false);
+
+        assert!(rendered.contains("[E0382] Error: use of moved value: `s`"), "{}", rendered);
+        assert!(rendered.contains("moved.ws:3:1"), "{}", rendered);
+        assert!(rendered.contains("4 │ ├─▶     s)"), "{}", rendered);
+        assert!(rendered.contains("value moved here"), "{}", rendered);
+        assert!(rendered.contains("Help: clone it before the move"), "{}", rendered);
+        assert!(!rendered.contains('\u{1b}'), "{}", rendered);
+    }
+
+    #[test]
+    fn test_json_has_locations_and_suggestions() {
+        let mut sources = SourceMap::new();
+        let file = sources.add(PathBuf::from("typo.ws"), "fn main() {\n    print(lenght)\n}\n".to_string());
+        let diagnostic = Diagnostic::error(codes::UNRESOLVED_NAME, "undefined variable 'lenght'", Span::in_file(file, 22, 28))
+            .with_suggestion(Span::in_file(file, 22, 28), "length", "a name with a similar spelling exists");
+        let json: serde_json::Value = serde_json::from_str(&diagnostic.to_json(&sources)).unwrap();
+
+        assert_eq!(json["code"], "E0425");
+        assert_eq!(json["severity"], "error");
+        assert_eq!(json["file"], "typo.ws");
+        assert_eq!(json["range"]["start"], serde_json::json!({ "line": 2, "column": 11 }));
+        assert_eq!(json["range"]["end"], serde_json::json!({ "line": 2, "column": 17 }));
+        assert_eq!(json["suggestions"][0]["replacement"], "length");
+        assert_eq!(similar_name("lenght", ["length", "len", "height"]), Some("length"));
+        assert_eq!(similar_name("x", ["count", "total"]), None);
+        assert_eq!(similar_name("f", ["A", "g"]), None);
+        assert_eq!(similar_name("ab", ["ac"]), None);
+        assert_eq!(similar_name("cout", ["count", "A"]), Some("count"));
+    }
+
+    #[test]
+    fn test_every_code_is_explained() {
+        let mut seen = std::collections::HashSet::new();
+        for code in codes::ALL {
+            assert!(seen.insert(code.as_str()), "{} is defined twice", code);
+            let explanation = explain::explain(code.as_str()).unwrap_or_else(|| panic!("{} has no explanation", code));
+            assert_eq!(explanation.code, *code);
+            assert!(!explanation.erroneous().is_empty() && !explanation.fixed().is_empty(), "{}", code);
+        }
+        assert_eq!(explain::explain("e0382").map(|e| e.code), Some(codes::USE_AFTER_MOVE));
+        assert!(explain::explain("E9999").is_none());
+    }
+}
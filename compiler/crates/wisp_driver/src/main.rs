@@ -2,15 +2,22 @@ use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use clap::{Parser, Subcommand, CommandFactory};
+use clap::{Parser, Subcommand, CommandFactory, ValueEnum};
 use clap_complete::{generate, Shell};
-use wisp_lexer::{Lexer, Token};
+use wisp_lexer::{FileId, Lexer, SourceMap, Token};
+use wisp_diagnostics::Diagnostic;
+use wisp_ast::{SourceFileWithImports, TargetCfg};
 use wisp_parser::{Parser as WispParser, parse_with_imports, parse_with_imports_structured};
 use wisp_hir::Resolver;
 use wisp_types::TypeChecker;
-use wisp_borrowck::BorrowChecker;
-use wisp_mir::lower_program;
-use wisp_codegen::Codegen;
+use wisp_borrowck::{BorrowChecker, MoveData};
+use wisp_mir::{lower_program, HOST_POINTER_SIZE};
+use wisp_codegen::{Codegen, SUPPORTED_TARGETS};
+
+mod manifest;
+mod test_runner;
+
+use manifest::{Manifest, Profile};
 
 #[derive(Parser)]
 #[command(name = "wisp")]
@@ -24,15 +31,69 @@ struct Cli {
 enum Commands {
     /// Compile and run the program
     Run {
-        /// The .ws file to compile and run
+        /// The .ws file to compile and run (default: the project's bin, from wisp.toml)
         #[arg(value_hint = clap::ValueHint::FilePath)]
-        file: PathBuf,
+        file: Option<PathBuf>,
+        /// The bin of the project to run, when it has more than one
+        #[arg(long, conflicts_with = "file")]
+        bin: Option<String>,
+        /// Build with the project's release profile
+        #[arg(long, conflicts_with = "file")]
+        release: bool,
+        /// Command used to link the object file (default: cc)
+        #[arg(long, conflicts_with = "jit")]
+        linker: Option<String>,
+        /// Compile into memory and run in-process instead of linking an executable
+        #[arg(long)]
+        jit: bool,
+        /// Emit DWARF debug info
+        #[arg(short = 'g', conflicts_with = "jit")]
+        debug: bool,
+        /// How to print errors and warnings
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
     },
     /// Compile to executable
     Build {
-        /// The .ws file to compile
+        /// The .ws file to compile (default: every bin of the project, from wisp.toml)
         #[arg(value_hint = clap::ValueHint::FilePath)]
-        file: PathBuf,
+        file: Option<PathBuf>,
+        /// Only build this bin of the project
+        #[arg(long, conflicts_with = "file")]
+        bin: Option<String>,
+        /// Build with the project's release profile
+        #[arg(long, conflicts_with = "file")]
+        release: bool,
+        /// Target triple to compile for (default: the host)
+        #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(SUPPORTED_TARGETS))]
+        target: Option<String>,
+        /// Command used to link the object file (default: cc, or <arch>-linux-gnu-gcc when cross-compiling)
+        #[arg(long)]
+        linker: Option<String>,
+        /// Emit DWARF debug info
+        #[arg(short = 'g')]
+        debug: bool,
+        /// How to print errors and warnings
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+    /// Run the `@test` functions of a file, or of every file under a directory
+    Test {
+        /// A .ws file, or a directory to search for files with tests (default: the current directory)
+        #[arg(value_hint = clap::ValueHint::AnyPath)]
+        path: Option<PathBuf>,
+        /// Only run the tests whose name contains this
+        #[arg(long)]
+        filter: Option<String>,
+    },
+    /// Format source files in place
+    Fmt {
+        /// .ws files, or directories to format every .ws file under (default: the current directory)
+        #[arg(value_hint = clap::ValueHint::AnyPath)]
+        paths: Vec<PathBuf>,
+        /// Don't write the files, list those that aren't formatted and fail if there are any
+        #[arg(long)]
+        check: bool,
     },
     /// Start the language server
     Lsp,
@@ -59,12 +120,18 @@ enum Commands {
         /// The .ws file to type check
         #[arg(value_hint = clap::ValueHint::FilePath)]
         file: PathBuf,
+        /// How to print errors and warnings
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
     },
     /// Show borrow checking output
     Borrow {
         /// The .ws file to borrow check
         #[arg(value_hint = clap::ValueHint::FilePath)]
         file: PathBuf,
+        /// How to print errors and warnings
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
     },
     /// Show MIR output
     Mir {
@@ -77,6 +144,17 @@ enum Commands {
         /// The .ws file to compile
         #[arg(value_hint = clap::ValueHint::FilePath)]
         file: PathBuf,
+        /// Target triple to compile for (default: the host)
+        #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(SUPPORTED_TARGETS))]
+        target: Option<String>,
+        /// Emit DWARF debug info
+        #[arg(short = 'g')]
+        debug: bool,
+    },
+    /// Explain an error or warning code, such as E0382
+    Explain {
+        /// The code to explain
+        code: String,
     },
     /// Generate shell completions
     Completions {
@@ -86,17 +164,46 @@ enum Commands {
     },
 }
 
+/// How diagnostics are printed
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum MessageFormat {
+    /// With the source they point at
+    #[default]
+    Human,
+    /// As one JSON object per line
+    Json,
+}
+
 fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Run { file } => {
+        Commands::Run { file: None, bin, release, linker, jit, debug, message_format } => {
+            run_project(bin.as_deref(), release, linker.as_deref(), jit, debug, message_format);
+        }
+        Commands::Run { file: Some(file), linker, jit, debug, message_format, .. } => {
             let source = read_file(&file);
-            run_and_execute(&source, file.to_str().unwrap());
+            if jit {
+                run_jit(&source, file.to_str().unwrap(), message_format);
+            } else {
+                let settings = BuildSettings::for_file(None, linker.as_deref(), debug, message_format);
+                run_and_execute(&source, file.to_str().unwrap(), &settings);
+            }
+        }
+        Commands::Build { file: None, bin, release, target, linker, debug, message_format } => {
+            build_project(bin.as_deref(), release, target.as_deref(), linker.as_deref(), debug, message_format);
         }
-        Commands::Build { file } => {
+        Commands::Build { file: Some(file), target, linker, debug, message_format, .. } => {
             let source = read_file(&file);
-            run_build(&source, file.to_str().unwrap());
+            let settings = BuildSettings::for_file(target.as_deref(), linker.as_deref(), debug, message_format);
+            run_build(&source, file.to_str().unwrap(), &settings);
+        }
+        Commands::Test { path, filter } => {
+            test_runner::run_tests(path.as_deref().unwrap_or(Path::new(".")), filter.as_deref());
+        }
+        Commands::Fmt { paths, check } => {
+            let paths = if paths.is_empty() { vec![PathBuf::from(".")] } else { paths };
+            run_fmt(&paths, check);
         }
         Commands::Lsp => {
             run_lsp();
@@ -113,21 +220,24 @@ fn main() {
             let source = read_file(&file);
             run_resolver(&source, file.to_str().unwrap());
         }
-        Commands::Check { file } => {
+        Commands::Check { file, message_format } => {
             let source = read_file(&file);
-            run_type_check(&source, file.to_str().unwrap());
+            run_type_check(&source, file.to_str().unwrap(), message_format);
         }
-        Commands::Borrow { file } => {
+        Commands::Borrow { file, message_format } => {
             let source = read_file(&file);
-            run_borrow_check(&source, file.to_str().unwrap());
+            run_borrow_check(&source, file.to_str().unwrap(), message_format);
         }
         Commands::Mir { file } => {
             let source = read_file(&file);
             run_mir(&source, file.to_str().unwrap());
         }
-        Commands::EmitObj { file } => {
+        Commands::EmitObj { file, target, debug } => {
             let source = read_file(&file);
-            run_codegen(&source, file.to_str().unwrap());
+            run_codegen(&source, file.to_str().unwrap(), target.as_deref(), debug);
+        }
+        Commands::Explain { code } => {
+            explain(&code);
         }
         Commands::Completions { shell } => {
             generate_completions(shell);
@@ -145,6 +255,97 @@ fn read_file(path: &Path) -> String {
     }
 }
 
+/// `path` itself if it's a file, otherwise the `.ws` files under it, skipping
+/// hidden directories such as `.build`
+fn source_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    collect_source_files(path, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn collect_source_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_source_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "ws") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Format every file in `paths`, or with `check` list the files that formatting
+/// would change; exits with 1 if a file doesn't parse or, with `check`, isn't
+/// formatted
+fn run_fmt(paths: &[PathBuf], check: bool) {
+    let mut failed = false;
+    for path in paths {
+        let files = match source_files(path) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("Error reading '{}': {}", path.display(), e);
+                failed = true;
+                continue;
+            }
+        };
+        for file in files {
+            let source = read_file(&file);
+            match wisp_fmt::format(&source) {
+                Ok(formatted) if formatted == source => {}
+                Ok(_) if check => {
+                    println!("{}", file.display());
+                    failed = true;
+                }
+                Ok(formatted) => {
+                    if let Err(e) = fs::write(&file, formatted) {
+                        eprintln!("Error writing file '{}': {}", file.display(), e);
+                        failed = true;
+                    }
+                }
+                Err(e) => {
+                    report(&root_sources(&source, file.to_str().unwrap()), e, MessageFormat::Human);
+                    failed = true;
+                }
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Print the explanation of a diagnostic code with its examples
+fn explain(code: &str) {
+    let Some(explanation) = wisp_diagnostics::explain::explain(code) else {
+        eprintln!("Error: no explanation for '{}'", code);
+        std::process::exit(1);
+    };
+    println!("{}: {}\n", explanation.code, explanation.title);
+    println!("{}", explanation.description());
+    println!("Erroneous example:\n");
+    print_indented(explanation.erroneous());
+    println!("\nFixed:\n");
+    print_indented(explanation.fixed());
+}
+
+fn print_indented(text: &str) {
+    for line in text.lines() {
+        if line.is_empty() {
+            println!();
+        } else {
+            println!("    {}", line);
+        }
+    }
+}
+
 fn generate_completions(shell: Shell) {
     let mut cmd = Cli::command();
     generate(shell, &mut cmd, "wisp", &mut io::stdout());
@@ -159,44 +360,95 @@ fn get_build_dir() -> PathBuf {
     build_dir
 }
 
-/// Compile and run a Wisp program
-fn run_and_execute(source: &str, file_path: &str) {
-    let build_dir = get_build_dir();
-    
-    let file_stem = Path::new(file_path)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("program");
-    
-    let obj_path = build_dir.join(format!("{}.o", file_stem));
-    let exe_path = build_dir.join(file_stem);
-    
-    // Compile to object file
-    if let Err(()) = compile_to_object(source, file_path, &obj_path) {
-        std::process::exit(1);
+/// Linker command for a target: `cc` for the host, a GNU cross toolchain otherwise
+fn default_linker(target: Option<&str>) -> String {
+    let target = match target {
+        Some(triple) => TargetCfg::from_triple(triple),
+        None => return "cc".to_string(),
+    };
+    if target == TargetCfg::host() {
+        "cc".to_string()
+    } else {
+        format!("{}-linux-gnu-gcc", target.arch)
     }
-    
-    // Link with cc
-    let link_status = Command::new("cc")
-        .arg(&obj_path)
+}
+
+/// Link an object file into an executable, with the libraries `libs`
+fn link(linker: &str, obj_path: &Path, exe_path: &Path, libs: &[String]) -> Result<(), ()> {
+    let link_status = Command::new(linker)
+        .arg(obj_path)
         .arg("-o")
-        .arg(&exe_path)
+        .arg(exe_path)
+        .args(libs.iter().map(|lib| format!("-l{}", lib)))
         .status();
-    
+
     match link_status {
-        Ok(status) if status.success() => {}
+        Ok(status) if status.success() => Ok(()),
         Ok(status) => {
             eprintln!("Linking failed with exit code: {:?}", status.code());
-            std::process::exit(1);
+            Err(())
         }
         Err(e) => {
-            eprintln!("Failed to run linker: {}", e);
-            std::process::exit(1);
+            eprintln!("Failed to run linker '{}': {}", linker, e);
+            Err(())
         }
     }
-    
-    // Execute the program
-    let run_status = Command::new(&exe_path)
+}
+
+/// Code generator for `target`, or for the host when no target is given,
+/// with the settings of `profile`; debug info points into the files of `sources`
+fn create_codegen(target: Option<&str>, profile: Profile, sources: &SourceMap) -> Result<Codegen, ()> {
+    let mut codegen = Codegen::with_opt_level(target, profile.opt_level)
+        .map_err(|e| eprintln!("Codegen error: {}", e))?;
+    if profile.debug {
+        codegen.enable_debug_info(sources);
+    }
+    Ok(codegen)
+}
+
+/// Source map holding the file compilation starts from
+fn root_sources(source: &str, file_path: &str) -> SourceMap {
+    let mut sources = SourceMap::new();
+    sources.add(PathBuf::from(file_path), source.to_string());
+    sources
+}
+
+/// `@cfg` properties of `target`, or of the host when no target is given
+fn target_cfg(target: Option<&str>) -> TargetCfg {
+    target.map_or_else(TargetCfg::host, TargetCfg::from_triple)
+}
+
+/// How an executable is compiled and linked
+struct BuildSettings<'a> {
+    /// Target triple to compile for, or the host
+    target: Option<&'a str>,
+    linker: Option<&'a str>,
+    profile: Profile,
+    /// Libraries to link, passed to the linker as `-l<lib>`
+    libs: &'a [String],
+    format: MessageFormat,
+}
+
+impl<'a> BuildSettings<'a> {
+    /// Settings for a file built on its own, outside of any project
+    fn for_file(target: Option<&'a str>, linker: Option<&'a str>, debug: bool, format: MessageFormat) -> Self {
+        let profile = Profile { debug, ..Profile::default() };
+        BuildSettings { target, linker, profile, libs: &[], format }
+    }
+}
+
+/// Compile and run a Wisp program
+fn run_and_execute(source: &str, file_path: &str, settings: &BuildSettings) {
+    let exe_path = match build_executable(source, file_path, &get_build_dir(), file_stem(file_path), settings) {
+        Ok(exe_path) => exe_path,
+        Err(()) => std::process::exit(1),
+    };
+    execute(&exe_path);
+}
+
+/// Run an executable and exit with its exit code
+fn execute(exe_path: &Path) -> ! {
+    let run_status = Command::new(exe_path)
         .status();
     
     match run_status {
@@ -210,63 +462,149 @@ fn run_and_execute(source: &str, file_path: &str) {
     }
 }
 
-/// Compile a Wisp program to an executable
-fn run_build(source: &str, file_path: &str) {
-    let build_dir = get_build_dir();
-    
-    let file_stem = Path::new(file_path)
+/// Name of the executable built from a file outside of any project
+fn file_stem(file_path: &str) -> &str {
+    Path::new(file_path)
         .file_stem()
         .and_then(|s| s.to_str())
-        .unwrap_or("program");
-    
-    let obj_path = build_dir.join(format!("{}.o", file_stem));
-    let exe_path = build_dir.join(file_stem);
-    
-    // Compile to object file
-    if let Err(()) = compile_to_object(source, file_path, &obj_path) {
+        .unwrap_or("program")
+}
+
+/// The manifest of the project the current directory is in; exits if there
+/// isn't one or it's invalid
+fn current_manifest() -> Manifest {
+    let dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    match Manifest::find(&dir) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Settings for building the bins of `manifest` with its release or dev
+/// profile, `debug` adding debug info to either
+fn project_settings<'a>(manifest: &'a Manifest, release: bool, debug: bool, target: Option<&'a str>, linker: Option<&'a str>, format: MessageFormat) -> BuildSettings<'a> {
+    let mut profile = manifest.profile(release);
+    profile.debug |= debug;
+    BuildSettings { target, linker, profile, libs: &manifest.libs, format }
+}
+
+/// Build the bin `bin` of the current project, or all of them, into its output
+/// directory
+fn build_project(bin: Option<&str>, release: bool, target: Option<&str>, linker: Option<&str>, debug: bool, format: MessageFormat) {
+    let manifest = current_manifest();
+    let bins = manifest.select_bins(bin).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
         std::process::exit(1);
+    });
+    let settings = project_settings(&manifest, release, debug, target, linker, format);
+    let profile_name = if release { "release" } else { "dev" };
+    println!("Building {} v{} ({})", manifest.name, manifest.version, profile_name);
+    for bin in bins {
+        let source = read_file(&bin.path);
+        match build_executable(&source, bin.path.to_str().unwrap(), &manifest.output_dir, &bin.name, &settings) {
+            Ok(exe_path) => println!("Built: {}", exe_path.display()),
+            Err(()) => std::process::exit(1),
+        }
     }
-    
-    // Link with cc
-    let link_status = Command::new("cc")
-        .arg(&obj_path)
-        .arg("-o")
-        .arg(&exe_path)
-        .status();
-    
-    match link_status {
-        Ok(status) if status.success() => {
-            println!("Built: {}", exe_path.display());
-            // Clean up object file
-            let _ = fs::remove_file(&obj_path);
+}
+
+/// Build and run the bin `bin` of the current project, or its only bin
+fn run_project(bin: Option<&str>, release: bool, linker: Option<&str>, jit: bool, debug: bool, format: MessageFormat) {
+    let manifest = current_manifest();
+    let bin = manifest.bin_to_run(bin).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    let source = read_file(&bin.path);
+    let file_path = bin.path.to_str().unwrap();
+    if jit {
+        run_jit(&source, file_path, format);
+    } else {
+        let settings = project_settings(&manifest, release, debug, None, linker, format);
+        match build_executable(&source, file_path, &manifest.output_dir, &bin.name, &settings) {
+            Ok(exe_path) => execute(&exe_path),
+            Err(()) => std::process::exit(1),
         }
-        Ok(status) => {
-            eprintln!("Linking failed with exit code: {:?}", status.code());
+    }
+}
+
+/// Compile a Wisp program into memory and run its `main` in this process
+fn run_jit(source: &str, file_path: &str, format: MessageFormat) {
+    let mut codegen = match Codegen::jit() {
+        Ok(cg) => cg,
+        Err(e) => {
+            eprintln!("Codegen error: {}", e);
             std::process::exit(1);
         }
+    };
+
+    let mut sources = root_sources(source, file_path);
+    let (typed, moves) = match run_frontend(&mut sources, &TargetCfg::host(), format) {
+        Ok(frontend) => frontend,
+        Err(()) => std::process::exit(1),
+    };
+    let mir = lower_program(&typed, &moves, codegen.pointer_size());
+
+    if let Err(e) = codegen.compile(&mir) {
+        eprintln!("Compilation error: {}", e);
+        std::process::exit(1);
+    }
+
+    match codegen.run_main() {
+        // Exiting through libc flushes the program's stdio buffers
+        Ok(code) => std::process::exit(code),
         Err(e) => {
-            eprintln!("Failed to run linker: {}", e);
+            eprintln!("Failed to run program: {}", e);
             std::process::exit(1);
         }
     }
 }
 
+/// Compile a Wisp program to an executable
+fn run_build(source: &str, file_path: &str, settings: &BuildSettings) {
+    match build_executable(source, file_path, &get_build_dir(), file_stem(file_path), settings) {
+        Ok(exe_path) => println!("Built: {}", exe_path.display()),
+        Err(()) => std::process::exit(1),
+    }
+}
+
+/// Compile and link a program into the executable `name` in `build_dir`,
+/// returning its path
+fn build_executable(source: &str, file_path: &str, build_dir: &Path, name: &str, settings: &BuildSettings) -> Result<PathBuf, ()> {
+    if let Err(e) = fs::create_dir_all(build_dir) {
+        eprintln!("Failed to create '{}': {}", build_dir.display(), e);
+        return Err(());
+    }
+    let obj_path = build_dir.join(format!("{}.o", name));
+    let exe_path = build_dir.join(name);
+    
+    // Compile to object file
+    compile_to_object(source, file_path, &obj_path, settings.target, settings.profile, settings.format)?;
+    
+    let linker = settings.linker.map_or_else(|| default_linker(settings.target), str::to_string);
+    link(&linker, &obj_path, &exe_path, settings.libs)?;
+    // Clean up object file
+    let _ = fs::remove_file(&obj_path);
+    Ok(exe_path)
+}
+
 /// Compile source to an object file
-fn compile_to_object(source: &str, file_path: &str, output_path: &Path) -> Result<(), ()> {
+fn compile_to_object(source: &str, file_path: &str, output_path: &Path, target: Option<&str>, profile: Profile, format: MessageFormat) -> Result<(), ()> {
     // Run full frontend pipeline
-    let typed = run_frontend(source, file_path)?;
-    
-    // Lower to MIR
-    let mir = lower_program(&typed);
+    let mut sources = root_sources(source, file_path);
+    let (typed, moves) = run_frontend(&mut sources, &target_cfg(target), format)?;
+    write_object(&sources, &typed, &moves, output_path, target, profile)
+}
+
+/// Generate code for a checked program and write it as an object file
+fn write_object(sources: &SourceMap, typed: &wisp_types::TypedProgram, moves: &MoveData, output_path: &Path, target: Option<&str>, profile: Profile) -> Result<(), ()> {
+    let mut codegen = create_codegen(target, profile, sources)?;
     
-    // Generate code
-    let mut codegen = match Codegen::new() {
-        Ok(cg) => cg,
-        Err(e) => {
-            eprintln!("Codegen error: {}", e);
-            return Err(());
-        }
-    };
+    // Lower to MIR, laid out for the target's pointer size
+    let mir = lower_program(typed, moves, codegen.pointer_size());
     
     if let Err(e) = codegen.compile(&mir) {
         eprintln!("Compilation error: {}", e);
@@ -338,25 +676,7 @@ fn run_parser(source: &str, file_path: &str) {
             println!("Structs:   {}", struct_count);
         }
         Err(e) => {
-            eprintln!("Parse error: {}", e);
-            
-            // Show context around error
-            let lines: Vec<&str> = source.lines().collect();
-            let mut char_count = 0;
-            for (line_num, line) in lines.iter().enumerate() {
-                let line_start = char_count;
-                let line_end = char_count + line.len();
-                
-                if e.span.start >= line_start && e.span.start <= line_end {
-                    eprintln!("\n  {} | {}", line_num + 1, line);
-                    let col = e.span.start - line_start;
-                    eprintln!("  {} | {}^", " ".repeat((line_num + 1).to_string().len()), " ".repeat(col));
-                    break;
-                }
-                
-                char_count = line_end + 1; // +1 for newline
-            }
-            
+            report(&root_sources(source, file_path), e, MessageFormat::Human);
             std::process::exit(1);
         }
     }
@@ -365,9 +685,10 @@ fn run_parser(source: &str, file_path: &str) {
 fn token_type_name(token: &Token) -> &'static str {
     match token {
         Token::Fn | Token::Let | Token::Mut | Token::If | Token::Else |
-        Token::While | Token::For | Token::In | Token::Return | Token::Struct |
+        Token::While | Token::For | Token::In | Token::Return | Token::Loop |
+        Token::Break | Token::Continue | Token::Struct |
         Token::Enum | Token::Trait | Token::Impl | Token::Pub | Token::Const |
-        Token::True | Token::False | Token::Match | Token::Defer | Token::Import |
+        Token::True | Token::False | Token::Match | Token::Defer | Token::Move | Token::Import |
         Token::As | Token::Type | Token::Where | Token::SelfLower | Token::SelfUpper |
         Token::Extern | Token::Static => "KEYWORD",
         
@@ -388,7 +709,7 @@ fn token_type_name(token: &Token) -> &'static str {
         Token::LBracket | Token::RBracket => "DELIMITER",
         
         Token::Comma | Token::Colon | Token::ColonColon | Token::Semi |
-        Token::Dot | Token::DotDot | Token::Arrow | Token::At => "PUNCTUATION",
+        Token::Dot | Token::DotDot | Token::Arrow | Token::At | Token::Hash => "PUNCTUATION",
         
         Token::Eof => "EOF",
     }
@@ -415,10 +736,11 @@ fn run_resolver(source: &str, file_path: &str) {
     println!("=== Name Resolution for {} ===\n", file_path);
     
     // First parse with imports
-    let ast = match parse_with_imports(source, Path::new(file_path)) {
+    let mut sources = root_sources(source, file_path);
+    let ast = match parse_with_imports(&mut sources, FileId::ROOT) {
         Ok(ast) => ast,
         Err(e) => {
-            eprintln!("{}", e);
+            report(&sources, e, MessageFormat::Human);
             std::process::exit(1);
         }
     };
@@ -429,24 +751,21 @@ fn run_resolver(source: &str, file_path: &str) {
             println!("{}", hir.pretty_print());
         }
         Err(errors) => {
-            eprintln!("Resolution errors:");
-            for e in &errors {
-                eprintln!("  {}", e);
-                show_error_context(source, e.span);
-            }
+            report(&sources, errors, MessageFormat::Human);
             std::process::exit(1);
         }
     }
 }
 
-fn run_type_check(source: &str, file_path: &str) {
+fn run_type_check(source: &str, file_path: &str, format: MessageFormat) {
     println!("=== Type Check for {} ===\n", file_path);
     
     // Parse with imports
-    let ast = match parse_with_imports(source, Path::new(file_path)) {
+    let mut sources = root_sources(source, file_path);
+    let ast = match parse_with_imports(&mut sources, FileId::ROOT) {
         Ok(ast) => ast,
         Err(e) => {
-            eprintln!("{}", e);
+            report(&sources, e, format);
             std::process::exit(1);
         }
     };
@@ -455,11 +774,7 @@ fn run_type_check(source: &str, file_path: &str) {
     let hir = match Resolver::resolve(&ast) {
         Ok(hir) => hir,
         Err(errors) => {
-            eprintln!("Resolution errors:");
-            for e in &errors {
-                eprintln!("  {}", e);
-                show_error_context(source, e.span);
-            }
+            report(&sources, errors, format);
             std::process::exit(1);
         }
     };
@@ -467,29 +782,26 @@ fn run_type_check(source: &str, file_path: &str) {
     // Type check
     match TypeChecker::check(&hir) {
         Ok(typed) => {
+            report(&sources, typed.warnings.clone(), format);
             println!("{}", typed.pretty_print());
             println!("Type checking successful!");
         }
         Err(errors) => {
-            eprintln!("Type errors:");
-            for e in &errors {
-                let (line, col) = offset_to_line_col(source, e.span.start);
-                eprintln!("  {} at {}:{}", e.message, line, col);
-                show_error_context(source, e.span);
-            }
+            report(&sources, errors, format);
             std::process::exit(1);
         }
     }
 }
 
-fn run_borrow_check(source: &str, file_path: &str) {
+fn run_borrow_check(source: &str, file_path: &str, format: MessageFormat) {
     println!("=== Borrow Check for {} ===\n", file_path);
     
     // Parse with imports
-    let ast = match parse_with_imports(source, Path::new(file_path)) {
+    let mut sources = root_sources(source, file_path);
+    let ast = match parse_with_imports(&mut sources, FileId::ROOT) {
         Ok(ast) => ast,
         Err(e) => {
-            eprintln!("{}", e);
+            report(&sources, e, format);
             std::process::exit(1);
         }
     };
@@ -498,25 +810,19 @@ fn run_borrow_check(source: &str, file_path: &str) {
     let hir = match Resolver::resolve(&ast) {
         Ok(hir) => hir,
         Err(errors) => {
-            eprintln!("Resolution errors:");
-            for e in &errors {
-                eprintln!("  {}", e);
-                show_error_context(source, e.span);
-            }
+            report(&sources, errors, format);
             std::process::exit(1);
         }
     };
     
     // Type check
     let typed = match TypeChecker::check(&hir) {
-        Ok(typed) => typed,
+        Ok(typed) => {
+            report(&sources, typed.warnings.clone(), format);
+            typed
+        }
         Err(errors) => {
-            eprintln!("Type errors:");
-            for e in &errors {
-                let (line, col) = offset_to_line_col(source, e.span.start);
-                eprintln!("  {} at {}:{}", e.message, line, col);
-                show_error_context(source, e.span);
-            }
+            report(&sources, errors, format);
             std::process::exit(1);
         }
     };
@@ -524,22 +830,12 @@ fn run_borrow_check(source: &str, file_path: &str) {
     // Borrow check
     let checker = BorrowChecker::new(&typed);
     match checker.check() {
-        Ok(()) => {
+        Ok(_) => {
             println!("{}", typed.pretty_print());
             println!("Borrow checking successful!");
         }
         Err(errors) => {
-            eprintln!("Borrow check errors:");
-            for e in &errors {
-                let (line, col) = offset_to_line_col(source, e.span.start);
-                eprintln!("  {} at {}:{}", e.message, line, col);
-                show_error_context(source, e.span);
-                for (note, note_span) in &e.notes {
-                    let (note_line, note_col) = offset_to_line_col(source, note_span.start);
-                    eprintln!("  note: {} at {}:{}", note, note_line, note_col);
-                    show_error_context(source, *note_span);
-                }
-            }
+            report(&sources, errors, format);
             std::process::exit(1);
         }
     }
@@ -549,38 +845,36 @@ fn run_mir(source: &str, file_path: &str) {
     println!("=== MIR for {} ===\n", file_path);
     
     // Run full frontend pipeline
-    let typed = match run_frontend(source, file_path) {
-        Ok(typed) => typed,
+    let mut sources = root_sources(source, file_path);
+    let (typed, moves) = match run_frontend(&mut sources, &TargetCfg::host(), MessageFormat::Human) {
+        Ok(frontend) => frontend,
         Err(()) => std::process::exit(1),
     };
     
     // Lower to MIR
-    let mir = lower_program(&typed);
+    let mir = lower_program(&typed, &moves, HOST_POINTER_SIZE);
     println!("{}", mir.pretty_print());
 }
 
-fn run_codegen(source: &str, file_path: &str) {
+fn run_codegen(source: &str, file_path: &str, target: Option<&str>, debug: bool) {
     println!("=== Compiling {} ===\n", file_path);
     
     // Run full frontend pipeline
-    let typed = match run_frontend(source, file_path) {
-        Ok(typed) => typed,
+    let mut sources = root_sources(source, file_path);
+    let (typed, moves) = match run_frontend(&mut sources, &target_cfg(target), MessageFormat::Human) {
+        Ok(frontend) => frontend,
+        Err(()) => std::process::exit(1),
+    };
+
+    let mut codegen = match create_codegen(target, Profile { debug, ..Profile::default() }, &sources) {
+        Ok(cg) => cg,
         Err(()) => std::process::exit(1),
     };
     
     // Lower to MIR
-    let mir = lower_program(&typed);
+    let mir = lower_program(&typed, &moves, codegen.pointer_size());
     println!("MIR generated: {} functions", mir.functions.len());
     
-    // Generate code
-    let mut codegen = match Codegen::new() {
-        Ok(cg) => cg,
-        Err(e) => {
-            eprintln!("Codegen error: {}", e);
-            std::process::exit(1);
-        }
-    };
-    
     if let Err(e) = codegen.compile(&mir) {
         eprintln!("Compilation error: {}", e);
         std::process::exit(1);
@@ -596,109 +890,79 @@ fn run_codegen(source: &str, file_path: &str) {
     }
     
     println!("Wrote {} bytes to {}", obj_bytes.len(), output_path.display());
-    println!("\nTo link: cc {} -o {}", 
+    println!("\nTo link: {} {} -o {}", 
+        default_linker(target),
         output_path.display(),
         Path::new(file_path).with_extension("").display()
     );
 }
 
-fn run_frontend(source: &str, file_path: &str) -> Result<wisp_types::TypedProgram, ()> {
-    // Parse with imports, preserving namespace structure
+/// Parse the root file of `sources` with its imports, which are added to
+/// `sources`, and resolve, type check and borrow check the program,
+/// reporting any problems in `format`
+fn run_frontend(sources: &mut SourceMap, target: &TargetCfg, format: MessageFormat) -> Result<(wisp_types::TypedProgram, MoveData), ()> {
+    let (program, diagnostics) = check_program(sources, target);
+    report(sources, diagnostics, format);
+    program.ok_or(())
+}
+
+/// Run the frontend over the root file of `sources`, returning the checked
+/// program if there were no errors, and every problem found
+fn check_program(sources: &mut SourceMap, target: &TargetCfg) -> (Option<(wisp_types::TypedProgram, MoveData)>, Vec<Diagnostic>) {
+    match parse_program(sources, target) {
+        Ok(ast) => check_parsed(&ast),
+        Err(errors) => (None, errors),
+    }
+}
+
+/// Parse the root file of `sources` with its imports, which are added to
+/// `sources`, keeping each import's namespace
+fn parse_program(sources: &mut SourceMap, target: &TargetCfg) -> Result<SourceFileWithImports, Vec<Diagnostic>> {
     let mut visited = std::collections::HashSet::new();
-    let file_path = Path::new(file_path);
+    let file_path = sources.path(FileId::ROOT);
     if let Ok(canonical) = file_path.canonicalize() {
         visited.insert(canonical);
     } else {
         visited.insert(file_path.to_path_buf());
     }
-    let base_dir = file_path.parent().unwrap_or(Path::new("."));
-    
-    let ast_with_imports = match parse_with_imports_structured(source, base_dir, &mut visited) {
-        Ok(ast) => ast,
-        Err(e) => {
-            eprintln!("{}", e);
-            return Err(());
-        }
-    };
     
+    parse_with_imports_structured(sources, FileId::ROOT, &mut visited, target)
+}
+
+/// Resolve, type check and borrow check a parsed program
+fn check_parsed(ast: &SourceFileWithImports) -> (Option<(wisp_types::TypedProgram, MoveData)>, Vec<Diagnostic>) {
     // Resolve with namespace support
-    let hir = match Resolver::resolve_with_imports(&ast_with_imports) {
+    let hir = match Resolver::resolve_with_imports(ast) {
         Ok(hir) => hir,
-        Err(errors) => {
-            eprintln!("Resolution errors:");
-            for e in &errors {
-                eprintln!("  {}", e);
-                show_error_context(source, e.span);
-            }
-            return Err(());
-        }
+        Err(errors) => return (None, errors.into_iter().map(Into::into).collect()),
     };
     
     // Type check
     let typed = match TypeChecker::check(&hir) {
         Ok(typed) => typed,
-        Err(errors) => {
-            eprintln!("Type errors:");
-            for e in &errors {
-                let (line, col) = offset_to_line_col(source, e.span.start);
-                eprintln!("  {} at {}:{}", e.message, line, col);
-                show_error_context(source, e.span);
-            }
-            return Err(());
-        }
+        Err(errors) => return (None, errors.into_iter().map(Into::into).collect()),
     };
+    let mut diagnostics: Vec<Diagnostic> = typed.warnings.iter().cloned().map(Into::into).collect();
     
     // Borrow check
     let checker = BorrowChecker::new(&typed);
-    if let Err(errors) = checker.check() {
-        eprintln!("Borrow check errors:");
-        for e in &errors {
-            eprintln!("  {}", e.message);
-            show_error_context(source, e.span);
-            for (note, note_span) in &e.notes {
-                eprintln!("  note: {}", note);
-                show_error_context(source, *note_span);
-            }
-        }
-        return Err(());
-    }
-    
-    Ok(typed)
-}
-
-/// Convert byte offset to line:column
-fn offset_to_line_col(source: &str, offset: usize) -> (usize, usize) {
-    let lines: Vec<&str> = source.lines().collect();
-    let mut char_count = 0;
-    for (line_num, line) in lines.iter().enumerate() {
-        let line_start = char_count;
-        let line_end = char_count + line.len();
-        
-        if offset >= line_start && offset <= line_end {
-            let col = offset - line_start;
-            return (line_num + 1, col + 1); // 1-indexed
+    match checker.check() {
+        Ok(moves) => (Some((typed, moves)), diagnostics),
+        Err(errors) => {
+            diagnostics.extend(errors.into_iter().map(Into::into));
+            (None, diagnostics)
         }
-        
-        char_count = line_end + 1; // +1 for newline
     }
-    (1, 1) // Fallback
 }
 
-fn show_error_context(source: &str, span: wisp_lexer::Span) {
-    let lines: Vec<&str> = source.lines().collect();
-    let mut char_count = 0;
-    for (line_num, line) in lines.iter().enumerate() {
-        let line_start = char_count;
-        let line_end = char_count + line.len();
-        
-        if span.start >= line_start && span.start <= line_end {
-            eprintln!("\n  {} | {}", line_num + 1, line);
-            let col = span.start - line_start;
-            eprintln!("  {} | {}^", " ".repeat((line_num + 1).to_string().len()), " ".repeat(col));
-            break;
+/// Print each problem to stderr in the chosen format
+fn report<D: Into<Diagnostic>>(sources: &SourceMap, diagnostics: impl IntoIterator<Item = D>, format: MessageFormat) {
+    for diagnostic in diagnostics {
+        let diagnostic = diagnostic.into();
+        match format {
+            MessageFormat::Human => diagnostic.eprint(sources),
+            MessageFormat::Json => eprintln!("{}", diagnostic.to_json(sources)),
         }
-        
-        char_count = line_end + 1; // +1 for newline
     }
 }
 
@@ -707,3 +971,55 @@ fn show_error_context(source: &str, span: wisp_lexer::Span) {
 async fn run_lsp() {
     wisp_lsp::run_server().await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wisp_diagnostics::{codes, explain};
+
+    /// Diagnostics for `source`, checked as if it were one of the repository's
+    /// examples so that `std` and `@` imports resolve
+    fn check_example(source: &str) -> Vec<Diagnostic> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../../examples/explain.ws");
+        let mut sources = root_sources(source, path.to_str().unwrap());
+        check_program(&mut sources, &TargetCfg::host()).1
+    }
+
+    #[test]
+    fn test_explanation_examples_produce_their_code() {
+        for code in codes::ALL {
+            let explanation = explain::explain(code.as_str()).unwrap();
+
+            let erroneous = check_example(explanation.erroneous());
+            assert!(
+                erroneous.iter().any(|d| d.code == *code),
+                "erroneous example of {} reports {:?}",
+                code,
+                erroneous.iter().map(|d| (d.code, &d.message)).collect::<Vec<_>>(),
+            );
+
+            let fixed = check_example(explanation.fixed());
+            assert!(
+                fixed.iter().all(|d| !d.is_error() && d.code != *code),
+                "fixed example of {} reports {:?}",
+                code,
+                fixed.iter().map(|d| (d.code, &d.message)).collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_every_syntax_error_is_reported() {
+        let diagnostics = check_example("fn a( {}\nfn b() { let = 1; }\nfn main() {}\n");
+        let syntax_errors = diagnostics.iter().filter(|d| d.code == codes::SYNTAX_ERROR).count();
+        assert_eq!(syntax_errors, 2, "{:?}", diagnostics.iter().map(|d| &d.message).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_missing_return_type_is_a_help() {
+        let diagnostics = check_example("fn f() { 1 }\nfn main() { f(); }\n");
+        let mismatch = diagnostics.iter().find(|d| d.code == codes::MISMATCHED_TYPES).unwrap();
+        assert_eq!(mismatch.message, "expected `()`, found `i32`");
+        assert_eq!(mismatch.help.as_deref(), Some("add `-> i32` to the function signature if you want to return a value"));
+    }
+}
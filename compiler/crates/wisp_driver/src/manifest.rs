@@ -0,0 +1,285 @@
+//! `wisp.toml`: the manifest `wisp build` and `wisp run` read when they aren't
+//! given a file, naming the project's executables, where they're written, the
+//! libraries they link and the settings of each profile
+//!
+//! ```toml
+//! [project]
+//! name = "hello"
+//! version = "0.1.0"
+//!
+//! [[bin]]
+//! name = "hello"
+//! path = "src/main.ws"
+//!
+//! [build]
+//! output-dir = ".build"
+//! libs = ["m"]
+//!
+//! [profile.release]
+//! opt-level = "speed"
+//! debug = false
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use wisp_codegen::OptLevel;
+
+pub const MANIFEST_NAME: &str = "wisp.toml";
+
+/// Entry point of a project without `[[bin]]` targets, relative to its root
+const DEFAULT_ENTRY: &str = "src/main.ws";
+
+const DEFAULT_OUTPUT_DIR: &str = ".build";
+
+/// A project's manifest, with every path in it joined onto the project's root
+#[derive(Debug)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    /// At least one, with distinct names
+    pub bins: Vec<Bin>,
+    pub output_dir: PathBuf,
+    /// Libraries every executable links, passed to the linker as `-l<lib>`
+    pub libs: Vec<String>,
+    dev: Profile,
+    release: Profile,
+}
+
+/// An executable of the project, named after the project unless it says
+/// otherwise
+#[derive(Debug, PartialEq)]
+pub struct Bin {
+    pub name: String,
+    /// The file with its `main`
+    pub path: PathBuf,
+}
+
+/// Settings a program is compiled with; the default is what files outside a
+/// project are built with
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Profile {
+    pub opt_level: OptLevel,
+    /// Emit DWARF debug info
+    pub debug: bool,
+}
+
+impl Profile {
+    fn dev() -> Self {
+        Profile { opt_level: OptLevel::None, debug: true }
+    }
+
+    fn release() -> Self {
+        Profile { opt_level: OptLevel::Speed, debug: false }
+    }
+}
+
+impl Manifest {
+    /// Read the manifest of the project `dir` is in, looking for `wisp.toml`
+    /// in it and then in each of its parents
+    pub fn find(dir: &Path) -> Result<Self, String> {
+        let root = wisp_parser::find_project_root(dir)
+            .ok_or_else(|| format!("could not find {} in '{}' or any parent directory", MANIFEST_NAME, dir.display()))?;
+        let path = root.join(MANIFEST_NAME);
+        let text = fs::read_to_string(&path).map_err(|e| format!("could not read '{}': {}", path.display(), e))?;
+        Self::parse(&text, &root).map_err(|e| format!("invalid manifest '{}': {}", path.display(), e))
+    }
+
+    /// Parse the text of the manifest of the project at `root`
+    pub fn parse(text: &str, root: &Path) -> Result<Self, String> {
+        let raw: RawManifest = toml::from_str(text).map_err(|e| e.message().to_string())?;
+        let name = raw.project.name;
+
+        let bins = if raw.bins.is_empty() {
+            vec![Bin { name: name.clone(), path: root.join(DEFAULT_ENTRY) }]
+        } else {
+            raw.bins
+                .into_iter()
+                .map(|bin| Bin { name: bin.name.unwrap_or_else(|| name.clone()), path: root.join(bin.path) })
+                .collect()
+        };
+        let mut names = HashSet::new();
+        for bin in &bins {
+            if !names.insert(&bin.name) {
+                return Err(format!("more than one [[bin]] is named '{}'", bin.name));
+            }
+        }
+
+        Ok(Manifest {
+            name,
+            version: raw.project.version,
+            bins,
+            output_dir: root.join(raw.build.output_dir.as_deref().unwrap_or(Path::new(DEFAULT_OUTPUT_DIR))),
+            libs: raw.build.libs,
+            dev: raw.profile.dev.unwrap_or_default().apply("dev", Profile::dev())?,
+            release: raw.profile.release.unwrap_or_default().apply("release", Profile::release())?,
+        })
+    }
+
+    /// The release profile, or the dev profile
+    pub fn profile(&self, release: bool) -> Profile {
+        if release { self.release } else { self.dev }
+    }
+
+    /// The bin called `name`, or every bin when no name is given
+    pub fn select_bins(&self, name: Option<&str>) -> Result<Vec<&Bin>, String> {
+        match name {
+            None => Ok(self.bins.iter().collect()),
+            Some(name) => match self.bins.iter().find(|bin| bin.name == name) {
+                Some(bin) => Ok(vec![bin]),
+                None => Err(format!("no bin target named '{}' (available: {})", name, self.bin_names())),
+            },
+        }
+    }
+
+    /// The bin to run: the one called `name`, or the only one there is
+    pub fn bin_to_run(&self, name: Option<&str>) -> Result<&Bin, String> {
+        match (name, &self.bins[..]) {
+            (Some(_), _) => Ok(self.select_bins(name)?[0]),
+            (None, [bin]) => Ok(bin),
+            (None, _) => Err(format!("the project has several bin targets, choose one with --bin (available: {})", self.bin_names())),
+        }
+    }
+
+    fn bin_names(&self) -> String {
+        self.bins.iter().map(|bin| bin.name.as_str()).collect::<Vec<_>>().join(", ")
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawManifest {
+    project: RawProject,
+    #[serde(default, rename = "bin")]
+    bins: Vec<RawBin>,
+    #[serde(default)]
+    build: RawBuild,
+    #[serde(default)]
+    profile: RawProfiles,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProject {
+    name: String,
+    version: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawBin {
+    name: Option<String>,
+    path: PathBuf,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RawBuild {
+    output_dir: Option<PathBuf>,
+    #[serde(default)]
+    libs: Vec<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfiles {
+    dev: Option<RawProfile>,
+    release: Option<RawProfile>,
+}
+
+/// The settings a `[profile.*]` table changes from the profile's defaults
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RawProfile {
+    opt_level: Option<String>,
+    debug: Option<bool>,
+}
+
+impl RawProfile {
+    fn apply(self, name: &str, mut profile: Profile) -> Result<Profile, String> {
+        if let Some(opt_level) = self.opt_level {
+            profile.opt_level = OptLevel::from_name(&opt_level).ok_or_else(|| {
+                format!(
+                    "unknown opt-level '{}' in [profile.{}] (expected one of: {})",
+                    opt_level,
+                    name,
+                    OptLevel::NAMES.join(", "),
+                )
+            })?;
+        }
+        profile.debug = self.debug.unwrap_or(profile.debug);
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let text = r#"
+[project]
+name = "tools"
+version = "0.2.0"
+
+[[bin]]
+path = "src/main.ws"
+
+[[bin]]
+name = "bench"
+path = "bench/main.ws"
+
+[build]
+output-dir = "out"
+libs = ["m", "pthread"]
+
+[profile.dev]
+opt-level = "speed"
+"#;
+        let manifest = Manifest::parse(text, Path::new("proj")).unwrap();
+        assert_eq!((manifest.name.as_str(), manifest.version.as_str()), ("tools", "0.2.0"));
+        assert_eq!(
+            manifest.bins,
+            [
+                Bin { name: "tools".into(), path: "proj/src/main.ws".into() },
+                Bin { name: "bench".into(), path: "proj/bench/main.ws".into() },
+            ]
+        );
+        assert_eq!(manifest.output_dir, Path::new("proj/out"));
+        assert_eq!(manifest.libs, ["m", "pthread"]);
+        assert_eq!(manifest.profile(false), Profile { opt_level: OptLevel::Speed, debug: true });
+        assert_eq!(manifest.profile(true), Profile::release());
+
+        assert_eq!(manifest.bin_to_run(Some("bench")).unwrap().name, "bench");
+        assert!(manifest.bin_to_run(None).unwrap_err().contains("tools, bench"));
+        assert!(manifest.select_bins(Some("fuzz")).is_err());
+    }
+
+    #[test]
+    fn test_manifest_defaults() {
+        let manifest = Manifest::parse("[project]\nname = \"hello\"\nversion = \"0.1.0\"\n", Path::new("")).unwrap();
+        assert_eq!(manifest.bins, [Bin { name: "hello".into(), path: DEFAULT_ENTRY.into() }]);
+        assert_eq!(manifest.output_dir, Path::new(DEFAULT_OUTPUT_DIR));
+        assert!(manifest.libs.is_empty());
+        assert_eq!(manifest.profile(false), Profile::dev());
+        assert_eq!(manifest.bin_to_run(None).unwrap().name, "hello");
+    }
+
+    #[test]
+    fn test_reject_invalid_manifests() {
+        let project = "[project]\nname = \"hello\"\nversion = \"0.1.0\"\n";
+        for (extra, error) in [
+            ("[profile.dev]\nopt-level = \"fast\"\n", "unknown opt-level 'fast' in [profile.dev]"),
+            ("[[bin]]\npath = \"a.ws\"\n[[bin]]\npath = \"b.ws\"\n", "more than one [[bin]] is named 'hello'"),
+            ("[build]\nlinker = \"ld\"\n", "unknown field `linker`"),
+            ("[profile.bench]\n", "unknown field `bench`"),
+        ] {
+            let message = Manifest::parse(&format!("{}{}", project, extra), Path::new("")).unwrap_err();
+            assert!(message.contains(error), "{}", message);
+        }
+        assert!(Manifest::parse("[project]\nname = \"hello\"\n", Path::new("")).unwrap_err().contains("version"));
+    }
+}
@@ -0,0 +1,275 @@
+//! `wisp test`: builds each file that has `@test` functions with a generated
+//! `main` that runs one of them, then runs every test in its own process, so
+//! that a panic or a crash only fails that test
+
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use wisp_ast::{Item, SourceFileWithImports, TargetCfg};
+use wisp_diagnostics::Diagnostic;
+use wisp_lexer::SourceMap;
+use wisp_parser::Parser as WispParser;
+
+use crate::manifest::Profile;
+use crate::{check_parsed, default_linker, get_build_dir, link, parse_program, report, root_sources, source_files, write_object, MessageFormat};
+
+/// Environment variable the harness reads the index of the test to run from
+const TEST_INDEX_VAR: &str = "WISP_TEST";
+
+/// Run the tests in `path`, a file or a directory to search for files with
+/// tests, whose names contain `filter`; exits with 1 if any test fails or a
+/// file doesn't compile
+pub fn run_tests(path: &Path, filter: Option<&str>) {
+    let files = match test_files(path) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Error reading '{}': {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut summary = Summary::default();
+    for file in &files {
+        run_file(file, filter, &mut summary);
+    }
+
+    let result = if summary.is_ok() { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {}. {} passed; {} failed; {} filtered out",
+        result, summary.passed, summary.failed, summary.filtered_out
+    );
+    if summary.broken_files > 0 {
+        println!("{} file(s) failed to compile", summary.broken_files);
+    }
+    if !summary.is_ok() {
+        std::process::exit(1);
+    }
+}
+
+/// Counts over every file tested
+#[derive(Default)]
+struct Summary {
+    passed: usize,
+    failed: usize,
+    filtered_out: usize,
+    broken_files: usize,
+}
+
+impl Summary {
+    fn is_ok(&self) -> bool {
+        self.failed == 0 && self.broken_files == 0
+    }
+}
+
+/// `path` itself if it's a file, otherwise the `.ws` files under it that
+/// mention `@test`
+fn test_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let files = source_files(path)?;
+    Ok(files.into_iter().filter(|file| fs::read_to_string(file).is_ok_and(|text| text.contains("@test"))).collect())
+}
+
+/// Build the tests of one file and run those selected by `filter`
+fn run_file(path: &Path, filter: Option<&str>, summary: &mut Summary) {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Error reading file '{}': {}", path.display(), e);
+            summary.broken_files += 1;
+            return;
+        }
+    };
+    let mut sources = root_sources(&source, path.to_str().unwrap());
+    let mut ast = match parse_program(&mut sources, &TargetCfg::host()) {
+        Ok(ast) => ast,
+        Err(e) => {
+            report(&sources, e, MessageFormat::Human);
+            summary.broken_files += 1;
+            return;
+        }
+    };
+
+    let (tests, filtered_out): (Vec<String>, Vec<String>) = test_names(&ast)
+        .into_iter()
+        .partition(|name| filter.is_none_or(|filter| name.contains(filter)));
+    summary.filtered_out += filtered_out.len();
+    if tests.is_empty() {
+        return;
+    }
+
+    let exe = match build_harness(path, &mut sources, &mut ast, &tests) {
+        Ok(exe) => exe,
+        Err(()) => {
+            summary.broken_files += 1;
+            return;
+        }
+    };
+
+    println!("\nrunning {} test{} from {}", tests.len(), if tests.len() == 1 { "" } else { "s" }, path.display());
+    let mut failures = Vec::new();
+    for (index, name) in tests.iter().enumerate() {
+        let run = run_test(&exe, index);
+        println!("test {} ... {}", name, run.outcome);
+        if matches!(run.outcome, Outcome::Passed) {
+            summary.passed += 1;
+        } else {
+            summary.failed += 1;
+            failures.push((name, run));
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, run) in failures {
+            println!("\n---- {} ----", name);
+            for (stream, output) in [("stdout", &run.stdout), ("stderr", &run.stderr)] {
+                if !output.is_empty() {
+                    println!("{}:\n{}", stream, output.trim_end());
+                }
+            }
+        }
+    }
+}
+
+/// Names of the `@test` functions of the root file, in source order
+fn test_names(ast: &SourceFileWithImports) -> Vec<String> {
+    ast.local_items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Function(f) if f.is_test => Some(f.name.name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Replace the root file's `main` with one that runs the test whose index is
+/// in `WISP_TEST`, then compile and link it, returning the executable
+fn build_harness(path: &Path, sources: &mut SourceMap, ast: &mut SourceFileWithImports, tests: &[String]) -> Result<PathBuf, ()> {
+    if let Err(e) = add_harness_main(sources, ast, tests) {
+        report(sources, e, MessageFormat::Human);
+        return Err(());
+    }
+    let (program, diagnostics) = check_parsed(ast);
+    report(sources, diagnostics, MessageFormat::Human);
+    let (typed, moves) = program.ok_or(())?;
+
+    let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("program");
+    let build_dir = get_build_dir();
+    let obj_path = build_dir.join(format!("{}-test.o", file_stem));
+    let exe_path = build_dir.join(format!("{}-test", file_stem));
+    write_object(sources, &typed, &moves, &obj_path, None, Profile::default())?;
+    link(&default_linker(None), &obj_path, &exe_path, &[])?;
+    let _ = fs::remove_file(&obj_path);
+    Ok(exe_path)
+}
+
+fn add_harness_main(sources: &mut SourceMap, ast: &mut SourceFileWithImports, tests: &[String]) -> Result<(), Vec<Diagnostic>> {
+    let mut harness = String::new();
+    for (name, declaration) in [
+        ("getenv", "extern fn getenv(name: str) -> str"),
+        ("atoi", "extern fn atoi(s: str) -> i32"),
+    ] {
+        let declared = ast.local_items.iter().any(|item| match item {
+            Item::ExternFunction(f) => f.name.name == name,
+            Item::Function(f) => f.name.name == name,
+            _ => false,
+        });
+        if !declared {
+            writeln!(harness, "{}", declaration).unwrap();
+        }
+    }
+    writeln!(harness, "fn main() {{\n    match atoi(getenv(\"{}\")) {{", TEST_INDEX_VAR).unwrap();
+    for (index, name) in tests.iter().enumerate() {
+        writeln!(harness, "        {} -> {}(),", index, name).unwrap();
+    }
+    harness.push_str("        _ -> {},\n    }\n}\n");
+
+    let file = sources.add(PathBuf::from("<test harness>"), harness);
+    let harness = WispParser::parse_file(sources.text(file), file)
+        .map_err(|errors| errors.into_iter().map(Diagnostic::from).collect::<Vec<_>>())?;
+    ast.local_items.retain(|item| !matches!(item, Item::Function(f) if f.name.name == "main"));
+    ast.local_items.extend(harness.items);
+    Ok(())
+}
+
+/// How a test ended
+enum Outcome {
+    Passed,
+    /// Called `panic`, for example through a failed assertion
+    Panicked,
+    /// Exited with an error some other way, or couldn't be run
+    Failed(String),
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "ok"),
+            Outcome::Panicked => write!(f, "FAILED (panicked)"),
+            Outcome::Failed(reason) => write!(f, "FAILED ({})", reason),
+        }
+    }
+}
+
+/// A finished test with the output it printed
+struct TestRun {
+    outcome: Outcome,
+    stdout: String,
+    stderr: String,
+}
+
+/// Run test `index` of the harness `exe` in a child process
+fn run_test(exe: &Path, index: usize) -> TestRun {
+    let output = match Command::new(exe).env(TEST_INDEX_VAR, index.to_string()).output() {
+        Ok(output) => output,
+        Err(e) => {
+            return TestRun {
+                outcome: Outcome::Failed(format!("couldn't run '{}': {}", exe.display(), e)),
+                stdout: String::new(),
+                stderr: String::new(),
+            };
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    let outcome = if output.status.success() {
+        Outcome::Passed
+    } else if stderr.contains("panic: ") {
+        Outcome::Panicked
+    } else {
+        match output.status.code() {
+            Some(code) => Outcome::Failed(format!("exit code {}", code)),
+            None => Outcome::Failed("killed by a signal".to_string()),
+        }
+    };
+    TestRun { outcome, stdout, stderr }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_harness_replaces_main() {
+        let source = "extern fn atoi(s: str) -> i32\n\nfn main() {}\n\n@test\nfn second() {}\n\n@test\nfn first() {}\n";
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../../examples/harness.ws");
+        let mut sources = root_sources(source, path.to_str().unwrap());
+        let mut ast = parse_program(&mut sources, &TargetCfg::host()).unwrap();
+        let tests = test_names(&ast);
+        assert_eq!(tests, ["second", "first"]);
+
+        add_harness_main(&mut sources, &mut ast, &tests).unwrap();
+        let harness = sources.text(sources.iter().last().unwrap().0);
+        assert!(harness.contains("0 -> second(),\n        1 -> first(),"), "{}", harness);
+        assert!(!harness.contains("extern fn atoi"), "{}", harness);
+        let mains = ast.local_items.iter().filter(|item| matches!(item, Item::Function(f) if f.name.name == "main")).count();
+        assert_eq!(mains, 1);
+        let (program, diagnostics) = check_parsed(&ast);
+        assert!(program.is_some(), "{:?}", diagnostics.iter().map(|d| &d.message).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,42 @@
+//! What the `wisp` binary prints and how it exits for programs it rejects
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Write `source` to `<name>.ws` in a directory of its own and run
+/// `wisp <command>` on it
+fn wisp(command: &str, name: &str, source: &str) -> Output {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("cli").join(name);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.ws", name));
+    fs::write(&path, source).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_wisp")).arg(command).arg(&path).current_dir(&dir).output().unwrap()
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[test]
+fn test_misused_attributes_are_reported() {
+    let source = "@cfg(target_oss = \"linux\")\nfn platform() -> i32 { 1 }\n\n@bogus\nfn helper() -> i32 { 2 }\n\nfn main() -> i32 {\n    platform() + helper()\n}\n";
+    let output = wisp("build", "misused_attributes", source);
+    let stderr = text(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("unknown cfg predicate 'target_oss'"), "{}", stderr);
+    assert!(stderr.contains("unknown attribute '@bogus'"), "{}", stderr);
+    // Both items survive, so nothing else is reported
+    assert!(!stderr.contains("undefined"), "{}", stderr);
+}
+
+#[test]
+fn test_misused_test_attribute_fails_wisp_test() {
+    let source = "@test\nfn returns() -> i32 { 1 }\n\n@test\nfn passes() {}\n\nfn main() {}\n";
+    let output = wisp("test", "misused_test_attribute", source);
+    let stderr = text(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("'@test' functions can't take parameters or return a value"), "{}", stderr);
+    let stdout = text(&output.stdout);
+    assert!(stdout.contains("test result: FAILED. 0 passed") && stdout.contains("1 file(s) failed to compile"), "{}", stdout);
+}
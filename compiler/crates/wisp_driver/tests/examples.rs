@@ -0,0 +1,348 @@
+//! Golden tests over `examples/*.ws`
+//!
+//! Every example is built with `wisp build`, or only checked when it has no
+//! `main`. An example that doesn't compile marks each error and warning on
+//! the line it points at, with the diagnostic's code or part of its message:
+//!
+//! ```text
+//! let t = s  //~ ERROR use of moved value
+//! s.len()
+//! //~^ ERROR E0382
+//! ```
+//!
+//! where each `^` moves the mark up a line. An example that builds is run,
+//! and its stdout and exit code are compared with `<name>.stdout` and
+//! `<name>.exit` next to it, which are left out when empty or 0. A line
+//! `//@ snapshot: parse, mir` also compares the output of `wisp parse` and
+//! `wisp mir` with `<name>.parse` and `<name>.mir`; any of the phases `lex`,
+//! `parse`, `resolve`, `check`, `borrow` and `mir` can be named. An example
+//! with a line `//@ ignore: reason` isn't tested.
+//!
+//! `cargo test -p wisp_driver --test examples -- --bless` rewrites the
+//! snapshots from the actual output. Other arguments run only the examples
+//! whose name contains one of them.
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Phases whose output can be snapshotted, named as `wisp` subcommands
+const PHASES: &[&str] = &["lex", "parse", "resolve", "check", "borrow", "mir"];
+
+/// How long building or running one example may take
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn main() {
+    let mut bless = false;
+    let mut filters = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--bless" => bless = true,
+            // Flags cargo passes on to every test binary, such as `--quiet`
+            flag if flag.starts_with('-') => {}
+            _ => filters.push(arg),
+        }
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../..").canonicalize().unwrap();
+    let mut examples: Vec<PathBuf> = fs::read_dir(root.join("examples"))
+        .expect("examples directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ws"))
+        .filter(|path| filters.is_empty() || filters.iter().any(|f| name(path).contains(f.as_str())))
+        .collect();
+    examples.sort();
+
+    println!("\nrunning {} examples", examples.len());
+    let results = Mutex::new(Vec::new());
+    let next = AtomicUsize::new(0);
+    let threads = thread::available_parallelism().map_or(4, |n| n.get());
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while let Some(path) = examples.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = check_example(&root, path, bless);
+                    results.lock().unwrap().push((name(path).to_string(), result));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort();
+    let (mut passed, mut failed, mut ignored) = (0, 0, 0);
+    for (name, result) in &results {
+        let status = match result {
+            Ok(None) => {
+                passed += 1;
+                "ok".to_string()
+            }
+            Ok(Some(reason)) => {
+                ignored += 1;
+                format!("ignored, {}", reason)
+            }
+            Err(_) => {
+                failed += 1;
+                "FAILED".to_string()
+            }
+        };
+        println!("example {} ... {}", name, status);
+    }
+    for (name, result) in &results {
+        if let Err(problems) = result {
+            println!("\n---- {} ----", name);
+            for problem in problems {
+                println!("{}", problem.trim_end());
+            }
+        }
+    }
+    println!(
+        "\nexample result: {}. {} passed; {} failed; {} ignored",
+        if failed == 0 { "ok" } else { "FAILED" },
+        passed,
+        failed,
+        ignored
+    );
+    if failed > 0 {
+        if !bless {
+            println!("run with `-- --bless` to accept the new output of examples that compile");
+        }
+        std::process::exit(1);
+    }
+}
+
+fn name(path: &Path) -> &str {
+    path.file_stem().and_then(|s| s.to_str()).unwrap()
+}
+
+/// Test one example, returning everything about it that doesn't match what
+/// it expects, or the reason it's ignored
+fn check_example(root: &Path, path: &Path, bless: bool) -> Result<Option<String>, Vec<String>> {
+    let source = fs::read_to_string(path).unwrap();
+    if let Some(reason) = source.lines().find_map(|line| line.trim().strip_prefix("//@ ignore:")) {
+        return Ok(Some(reason.trim().to_string()));
+    }
+    let mut problems = Vec::new();
+    let name = name(path);
+    let work_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("examples").join(name);
+    fs::create_dir_all(&work_dir).unwrap();
+
+    // Build, or check a library-like example, and match its diagnostics
+    let has_main = source.lines().any(|line| line.starts_with("fn main(") || line.starts_with("pub fn main("));
+    let command = if has_main { "build" } else { "borrow" };
+    let output = match wisp(&work_dir, &[command, "--message-format=json", path.to_str().unwrap()]) {
+        Ok(output) => output,
+        Err(problem) => return Err(vec![format!("wisp {}: {}", command, problem)]),
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let diagnostics: Vec<serde_json::Value> = stderr.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+    match_annotations(path, &annotations(&source), &diagnostics, &mut problems);
+    let compiled = !diagnostics.iter().any(|d| d["severity"] == "error");
+    if compiled && !output.status.success() {
+        problems.push(format!("wisp {} failed without a diagnostic:\n{}", command, stderr));
+    }
+
+    // Run it and compare what it printed
+    if has_main && compiled && output.status.success() {
+        let exe = work_dir.join(".build").join(name);
+        match run(Command::new(&exe).current_dir(&work_dir)) {
+            Ok(output) => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                check_snapshot(&path.with_extension("stdout"), &stdout, bless, &mut problems);
+                let exit = match output.status.code() {
+                    Some(0) => String::new(),
+                    Some(code) => format!("{}\n", code),
+                    None => "killed by a signal\n".to_string(),
+                };
+                check_snapshot(&path.with_extension("exit"), &exit, bless, &mut problems);
+            }
+            Err(problem) => problems.push(format!("running {}: {}", exe.display(), problem)),
+        }
+    }
+
+    // Compare the phases it snapshots, run from the repository root so that
+    // the output names it by its relative path
+    let relative = Path::new("examples").join(path.file_name().unwrap());
+    for phase in snapshot_phases(&source, &mut problems) {
+        match wisp(root, &[phase, relative.to_str().unwrap()]) {
+            Ok(output) => {
+                let mut actual = String::from_utf8_lossy(&output.stdout).into_owned();
+                actual.push_str(&String::from_utf8_lossy(&output.stderr));
+                check_snapshot(&path.with_extension(phase), &actual, bless, &mut problems);
+            }
+            Err(problem) => problems.push(format!("wisp {}: {}", phase, problem)),
+        }
+    }
+    if problems.is_empty() { Ok(None) } else { Err(problems) }
+}
+
+/// A diagnostic an example expects, from a `//~` comment
+#[derive(Debug)]
+struct Annotation {
+    line: usize,
+    severity: &'static str,
+    text: String,
+}
+
+/// The `//~ ERROR text` and `//~^ WARNING text` comments of `source`; a line
+/// may hold several
+fn annotations(source: &str) -> Vec<Annotation> {
+    let mut annotations = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        for mark in line.split("//~").skip(1) {
+            let up = mark.len() - mark.trim_start_matches('^').len();
+            let mark = mark[up..].trim();
+            let (severity, text) = mark.split_once(' ').unwrap_or((mark, ""));
+            let severity = match severity {
+                "ERROR" => "error",
+                "WARNING" => "warning",
+                other => panic!("line {}: expected ERROR or WARNING after `//~`, found `{}`", index + 1, other),
+            };
+            annotations.push(Annotation { line: index + 1 - up, severity, text: text.trim().to_string() });
+        }
+    }
+    annotations
+}
+
+/// Pair every reported diagnostic with an annotation, and report those left
+/// over on either side
+fn match_annotations(path: &Path, annotations: &[Annotation], diagnostics: &[serde_json::Value], problems: &mut Vec<String>) {
+    let mut matched = vec![false; annotations.len()];
+    for diagnostic in diagnostics {
+        let severity = diagnostic["severity"].as_str().unwrap_or_default();
+        let code = diagnostic["code"].as_str().unwrap_or_default();
+        let message = diagnostic["message"].as_str().unwrap_or_default();
+        let file = diagnostic["file"].as_str().unwrap_or_default();
+        let line = diagnostic["range"]["start"]["line"].as_u64().unwrap_or_default() as usize;
+        let found = annotations.iter().enumerate().position(|(i, annotation)| {
+            !matched[i]
+                && Path::new(file) == path
+                && annotation.line == line
+                && annotation.severity == severity
+                && (annotation.text == code || message.contains(&annotation.text))
+        });
+        match found {
+            Some(i) => matched[i] = true,
+            None if Path::new(file) == path => {
+                problems.push(format!("unexpected {} on line {}: [{}] {}", severity, line, code, message))
+            }
+            None => problems.push(format!("unexpected {} in {} on line {}: [{}] {}", severity, file, line, code, message)),
+        }
+    }
+    for (annotation, _) in annotations.iter().zip(&matched).filter(|(_, matched)| !**matched) {
+        problems.push(format!(
+            "expected {} on line {} matching `{}`, but none was reported",
+            annotation.severity, annotation.line, annotation.text
+        ));
+    }
+}
+
+/// Phases named by `//@ snapshot:` lines
+fn snapshot_phases<'a>(source: &'a str, problems: &mut Vec<String>) -> Vec<&'a str> {
+    let mut phases = Vec::new();
+    for line in source.lines() {
+        let Some(list) = line.trim().strip_prefix("//@ snapshot:") else { continue };
+        for phase in list.split(',').map(str::trim) {
+            if PHASES.contains(&phase) {
+                phases.push(phase);
+            } else {
+                problems.push(format!("unknown phase `{}` to snapshot (expected one of {})", phase, PHASES.join(", ")));
+            }
+        }
+    }
+    phases
+}
+
+/// Compare `actual` with the snapshot at `path`, which doesn't exist when
+/// `actual` is empty; with `bless`, make the snapshot match instead
+fn check_snapshot(path: &Path, actual: &str, bless: bool, problems: &mut Vec<String>) {
+    let expected = fs::read_to_string(path).unwrap_or_default();
+    if expected == actual {
+        return;
+    }
+    if bless {
+        if actual.is_empty() {
+            fs::remove_file(path).unwrap();
+        } else {
+            fs::write(path, actual).unwrap();
+        }
+        return;
+    }
+    problems.push(format!("{} doesn't match (- expected, + actual):\n{}", path.display(), diff(&expected, actual)));
+}
+
+/// The lines that differ between `expected` and `actual`, from their longest
+/// common subsequence
+fn diff(expected: &str, actual: &str) -> String {
+    let a: Vec<&str> = expected.lines().collect();
+    let b: Vec<&str> = actual.lines().collect();
+    let mut common = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            common[i][j] = if a[i] == b[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || common[i][j + 1] >= common[i + 1][j]) {
+            out.push_str(&format!("+{}\n", b[j]));
+            j += 1;
+        } else {
+            out.push_str(&format!("-{}\n", a[i]));
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Run the `wisp` binary in `dir`
+fn wisp(dir: &Path, args: &[&str]) -> Result<Output, String> {
+    run(Command::new(env!("CARGO_BIN_EXE_wisp")).args(args).current_dir(dir))
+}
+
+/// Run `command` to completion, killing it after `TIMEOUT`
+fn run(command: &mut Command) -> Result<Output, String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    // Read the pipes as the child writes, so that it never blocks on a full one
+    let stdout = read_in_background(child.stdout.take().unwrap());
+    let stderr = read_in_background(child.stderr.take().unwrap());
+    let status = wait_with_timeout(&mut child)?;
+    Ok(Output { status, stdout: stdout.join().unwrap(), stderr: stderr.join().unwrap() })
+}
+
+fn read_in_background(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        bytes
+    })
+}
+
+fn wait_with_timeout(child: &mut Child) -> Result<std::process::ExitStatus, String> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            return Ok(status);
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("timed out after {}s", TIMEOUT.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
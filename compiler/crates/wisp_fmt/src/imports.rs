@@ -0,0 +1,173 @@
+//! Sorting imports: each run of consecutive imports is split into std,
+//! external (`pkg.`) and internal (`@.`) groups, in that order, with a blank
+//! line between groups and the imports of a group sorted by path
+
+use std::mem;
+
+use wisp_lexer::{Token, Trivia};
+
+use crate::syntax::{SyntaxItem, SyntaxTree};
+
+/// The groups imports are sorted into, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ImportGroup {
+    Std,
+    External,
+    Internal,
+}
+
+/// An import being sorted, with the comments that move along with it
+struct Import {
+    group: ImportGroup,
+    path: String,
+    item: SyntaxItem,
+    /// Comment lines right above it
+    comments: Vec<Trivia>,
+    /// Comment at the end of its last line
+    trailing: Vec<Trivia>,
+}
+
+pub fn sort_imports(tree: &mut SyntaxTree) {
+    let mut start = 0;
+    while start < tree.items.len() {
+        let len = run_len(&tree.items[start..]);
+        if len > 1 {
+            sort_run(tree, start, len);
+        }
+        start += len.max(1);
+    }
+}
+
+/// How many of the first `items` are imports to sort together; a comment with
+/// a blank line after it, which may be about all the imports below it, ends
+/// the run
+fn run_len(items: &[SyntaxItem]) -> usize {
+    let mut len = 0;
+    for item in items {
+        if import_group(item).is_none() {
+            break;
+        }
+        if len > 0 {
+            let leading = &item.first_token().leading;
+            let rest = &leading[trailing_len(leading)..];
+            if rest.iter().filter(|t| matches!(t, Trivia::Comment(_))).count() != attached_comments(rest).len() / 2 {
+                break;
+            }
+        }
+        len += 1;
+    }
+    len
+}
+
+fn sort_run(tree: &mut SyntaxTree, start: usize, len: usize) {
+    let mut imports: Vec<Import> = Vec::new();
+    for (index, mut item) in tree.items.drain(start..start + len).enumerate() {
+        // The trivia before an import starts with the comment at the end of
+        // the line before it; comments above the first import stay in place
+        let mut comments = Vec::new();
+        if index > 0 {
+            let mut leading = mem::take(&mut item.first_token_mut().leading);
+            let rest = leading.split_off(trailing_len(&leading));
+            imports.last_mut().unwrap().trailing = leading;
+            comments = attached_comments(&rest);
+        }
+        imports.push(Import {
+            group: import_group(&item).unwrap(),
+            path: import_path(&item),
+            item,
+            comments,
+            trailing: Vec::new(),
+        });
+    }
+    let next = match tree.items.get_mut(start) {
+        Some(item) => item.first_token_mut(),
+        None => &mut tree.eof,
+    };
+    let rest = next.leading.split_off(trailing_len(&next.leading));
+    imports.last_mut().unwrap().trailing = mem::replace(&mut next.leading, rest);
+
+    let mut first_leading = mem::take(&mut imports[0].item.first_token_mut().leading);
+    imports.sort_by(|a, b| (a.group, &a.path).cmp(&(b.group, &b.path)));
+
+    let mut sorted = Vec::with_capacity(imports.len());
+    let mut previous: Option<(ImportGroup, Vec<Trivia>)> = None;
+    for mut import in imports {
+        let mut leading = match previous {
+            None => mem::take(&mut first_leading),
+            Some((group, mut leading)) => {
+                leading.push(Trivia::Newline);
+                if group != import.group {
+                    leading.push(Trivia::Newline);
+                }
+                leading
+            }
+        };
+        leading.extend(import.comments);
+        import.item.first_token_mut().leading = leading;
+        previous = Some((import.group, import.trailing));
+        sorted.push(import.item);
+    }
+    let (_, trailing) = previous.unwrap();
+    let next = match tree.items.get_mut(start) {
+        Some(item) => item.first_token_mut(),
+        None => &mut tree.eof,
+    };
+    next.leading.splice(0..0, trailing);
+    tree.items.splice(start..start, sorted);
+}
+
+/// The group of an import, or `None` if `item` isn't one
+fn import_group(item: &SyntaxItem) -> Option<ImportGroup> {
+    let mut body = item.body();
+    if body.next()?.token != Token::Import {
+        return None;
+    }
+    // `import { a, b } from path` names its path last
+    let mut path = body.skip_while(|t| matches!(&t.token, Token::LBrace)).skip_while(|t| matches!(&t.token, Token::Ident(s) if s == "from"));
+    Some(match path.next().map(|t| &t.token) {
+        Some(Token::At) => ImportGroup::Internal,
+        Some(Token::Ident(s)) if s == "pkg" => ImportGroup::External,
+        _ => ImportGroup::Std,
+    })
+}
+
+/// The text of an import after `import`, to sort by
+fn import_path(item: &SyntaxItem) -> String {
+    let mut path = String::new();
+    for token in item.tokens().into_iter().skip_while(|t| t.token != Token::Import).skip(1) {
+        path.push_str(&token.text);
+    }
+    path
+}
+
+/// How many of the trivia before a token belong to the line before it: a
+/// comment at the end of that line
+fn trailing_len(leading: &[Trivia]) -> usize {
+    let line_end = leading.iter().position(|t| *t == Trivia::Newline).unwrap_or(leading.len());
+    if leading[..line_end].iter().any(|t| matches!(t, Trivia::Comment(_))) { line_end } else { 0 }
+}
+
+/// The comment lines right above a token, without a blank line between them,
+/// each followed by its line break
+fn attached_comments(leading: &[Trivia]) -> Vec<Trivia> {
+    let mut comments = Vec::new();
+    let mut newlines = 0;
+    for trivia in leading.iter().rev() {
+        match trivia {
+            Trivia::Newline => {
+                newlines += 1;
+                if newlines > 1 {
+                    break;
+                }
+            }
+            Trivia::Comment(_) => {
+                comments.push(Trivia::Newline);
+                comments.push(trivia.clone());
+                newlines = 0;
+            }
+            Trivia::Whitespace(_) => {}
+        }
+    }
+    comments.reverse();
+    comments
+}
@@ -0,0 +1,294 @@
+//! `wisp fmt`: formats Wisp source on its lossless syntax tree, so that every
+//! comment and `@cfg`'d item is kept
+//!
+//! Lines are kept as they are written; formatting reindents them by four
+//! spaces for each bracket left open, and one more for a line that continues
+//! an expression with an operator, normalizes the spaces between tokens,
+//! keeps at most one blank line in a row, and sorts imports (see `imports`).
+
+mod imports;
+pub mod syntax;
+
+use wisp_diagnostics::Diagnostic;
+use wisp_lexer::{FileId, Lexer, LosslessToken, Token, Trivia};
+use wisp_parser::Parser;
+
+use syntax::SyntaxTree;
+
+const INDENT: &str = "    ";
+
+/// Format the source of a file; it must parse, and the diagnostics say where
+/// it doesn't
+pub fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    Parser::parse_file(source, FileId::ROOT).map_err(|errors| errors.into_iter().map(Diagnostic::from).collect::<Vec<_>>())?;
+    let tokens = Lexer::tokenize_lossless(source, FileId::ROOT).expect("the source was lexed by the parser");
+    let mut tree = SyntaxTree::new(tokens);
+    imports::sort_imports(&mut tree);
+    Ok(Printer::default().print(&tree.tokens()))
+}
+
+#[derive(Default)]
+struct Printer<'a> {
+    out: String,
+    /// Indentation of the lines inside each open bracket, and of its closing
+    /// line
+    open: Vec<(usize, usize)>,
+    /// Indentation of the current line
+    indent: usize,
+    /// The last line printed ends with an opening bracket
+    after_open: bool,
+    /// The last two tokens printed, and whether they're on the same line
+    prev: Option<&'a LosslessToken>,
+    before_prev: Option<&'a LosslessToken>,
+    prev_same_line: bool,
+    /// In an import, whose path may be written `std/io`
+    in_import: bool,
+}
+
+impl<'a> Printer<'a> {
+    fn print(mut self, tokens: &[&'a LosslessToken]) -> String {
+        for (i, &token) in tokens.iter().enumerate() {
+            let mut newlines = 0;
+            for trivia in &token.leading {
+                match trivia {
+                    Trivia::Newline => newlines += 1,
+                    Trivia::Whitespace(_) => {}
+                    Trivia::Comment(comment) => {
+                        if newlines == 0 && !self.out.is_empty() {
+                            // Keep comments at the end of lines lined up
+                            match &token.leading[..] {
+                                [Trivia::Whitespace(space), ..] => self.out.push_str(space),
+                                _ => self.out.push(' '),
+                            }
+                        } else {
+                            let indent = self.open.last().map_or(0, |&(inner, _)| inner);
+                            self.new_line(newlines, indent);
+                        }
+                        self.out.push_str(comment.trim_end());
+                        self.after_open = false;
+                        newlines = 0;
+                    }
+                }
+            }
+            if token.token == Token::Eof {
+                break;
+            }
+
+            let on_new_line = newlines > 0 || self.prev.is_none();
+            if on_new_line {
+                // A line starting with closing brackets lines up with the
+                // line that opened the first of them
+                let closing = tokens[i..]
+                    .iter()
+                    .enumerate()
+                    .take_while(|(j, t)| is_close(&t.token) && (*j == 0 || !t.starts_line()))
+                    .count();
+                let indent = if closing > 0 {
+                    let closed = self.open.len().saturating_sub(closing);
+                    self.open.get(closed).map_or(0, |&(_, outer)| outer)
+                } else {
+                    let inner = self.open.last().map_or(0, |&(inner, _)| inner);
+                    inner + usize::from(self.continues_expression(token))
+                };
+                self.new_line(if closing > 0 { newlines.min(1) } else { newlines }, indent);
+                self.indent = indent;
+                self.in_import &= !self.open.is_empty();
+            } else if self.space_before(token) {
+                self.out.push(' ');
+            }
+            self.out.push_str(&token.text);
+
+            if is_open(&token.token) {
+                self.open.push((self.indent + 1, self.indent));
+            } else if is_close(&token.token) {
+                self.open.pop();
+            }
+            self.in_import |= token.token == Token::Import;
+            self.after_open = is_open(&token.token);
+            self.before_prev = self.prev;
+            self.prev = Some(token);
+            self.prev_same_line = !on_new_line;
+        }
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    /// End the current line, keeping one blank line if `newlines` had any,
+    /// except after an opening bracket, and indent the next
+    fn new_line(&mut self, newlines: usize, indent: usize) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+            if newlines > 1 && !self.after_open {
+                self.out.push('\n');
+            }
+        }
+        for _ in 0..indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Whether `token` starts a line that carries on the expression of the
+    /// line before, like `.method()` or `&& condition`
+    fn continues_expression(&self, token: &LosslessToken) -> bool {
+        let continues = matches!(
+            token.token,
+            Token::Dot
+                | Token::Plus
+                | Token::Minus
+                | Token::Star
+                | Token::Slash
+                | Token::Percent
+                | Token::EqEq
+                | Token::NotEq
+                | Token::Lt
+                | Token::Gt
+                | Token::LtEq
+                | Token::GtEq
+                | Token::AndAnd
+                | Token::OrOr
+                | Token::Amp
+                | Token::Pipe
+                | Token::Caret
+                | Token::As
+        );
+        continues && self.prev.is_some_and(|prev| ends_expression(&prev.token))
+    }
+
+    /// Whether to put a space between the last token printed and `next` on the
+    /// same line
+    fn space_before(&self, next: &LosslessToken) -> bool {
+        let prev = self.prev.expect("a token was printed on this line");
+        let (a, b) = (&prev.token, &next.token);
+        // `-x`, `*x`, `&x` and `!x`, after something that isn't an operand
+        let prev_is_unary = match a {
+            Token::Minus | Token::Star | Token::Amp => !self.before_prev.is_some_and(|before| {
+                ends_expression(&before.token) || (before.token == Token::RBrace && self.prev_same_line)
+            }),
+            Token::Not => true,
+            _ => false,
+        };
+        let space = match (a, b) {
+            (Token::LParen | Token::LBracket, _) | (_, Token::RParen | Token::RBracket) => false,
+            (Token::LBrace, _) | (_, Token::RBrace) => !(*a == Token::LBrace && *b == Token::RBrace),
+            (_, Token::Comma | Token::Semi | Token::Dot | Token::Question | Token::Colon | Token::ColonColon) => false,
+            (Token::Dot | Token::At | Token::Hash | Token::ColonColon, _) => false,
+            // Labels and lifetimes: `for@outer`, `xs@x`, `&@a`
+            (Token::Ident(_) | Token::For | Token::While | Token::Loop | Token::Break | Token::Continue | Token::Amp, Token::At) => false,
+            (Token::Slash, _) | (_, Token::Slash) if self.in_import => false,
+            // `a..b`, `..rest`
+            (Token::DotDot, _) => false,
+            (_, Token::DotDot) if ends_expression(a) => false,
+            _ if prev_is_unary => false,
+            // Generics and closures can't be told from comparisons and `|`
+            (Token::Lt | Token::Gt | Token::Pipe, _) | (_, Token::Lt | Token::Gt | Token::Pipe) => {
+                !next.leading.is_empty()
+            }
+            (_, Token::LParen | Token::LBracket) => !matches!(
+                a,
+                Token::Ident(_) | Token::SelfLower | Token::SelfUpper | Token::RParen | Token::RBracket | Token::Fn
+            ),
+            _ => true,
+        };
+        space || would_merge(&prev.text, &next.text)
+    }
+}
+
+fn is_open(token: &Token) -> bool {
+    matches!(token, Token::LParen | Token::LBracket | Token::LBrace)
+}
+
+fn is_close(token: &Token) -> bool {
+    matches!(token, Token::RParen | Token::RBracket | Token::RBrace)
+}
+
+/// Whether an expression can end with `token`, making an operator after it
+/// binary
+fn ends_expression(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Ident(_)
+            | Token::IntLiteral(_)
+            | Token::FloatLiteral(_)
+            | Token::StringLiteral(_)
+            | Token::CharLiteral(_)
+            | Token::True
+            | Token::False
+            | Token::SelfLower
+            | Token::SelfUpper
+            | Token::RParen
+            | Token::RBracket
+            | Token::Question
+    )
+}
+
+/// Whether two tokens written without a space between them would lex
+/// differently, like `&` `&` as `&&`
+fn would_merge(a: &str, b: &str) -> bool {
+    Lexer::tokenize(&format!("{}{}", a, b)).map_or(true, |tokens| tokens.len() != 3 || tokens[0].span.end != a.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_format_reindents_and_spaces() {
+        let source = "fn  main()->i32{\nlet x=-1+2*3\n  let v:Vec<i32> =Vec.new()\nif x>0&&!done{\n\n\nreturn x}\n        let total = v.iter()\n.map((n: i32) -> n*2)\n.sum()\n  for@outer i in 0..10 { break@outer }\n\n\n\n    x\n}";
+        let expected = "fn main() -> i32 {\n    let x = -1 + 2 * 3\n    let v: Vec<i32> = Vec.new()\n    if x>0 && !done {\n        return x }\n    let total = v.iter()\n        .map((n: i32) -> n * 2)\n        .sum()\n    for@outer i in 0..10 { break@outer }\n\n    x\n}\n";
+        assert_eq!(format(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_keeps_comments_and_cfg_items() {
+        let source = "// The streams\n@cfg(target_os = \"linux\")\nextern static stdout: i64   // glibc\n@cfg(not(target_os = \"linux\"))\nextern static __stdoutp: i64\n\nfn main() {\n    let x = 1 // one\n        // after\n}\n";
+        let expected = "// The streams\n@cfg(target_os = \"linux\")\nextern static stdout: i64   // glibc\n@cfg(not(target_os = \"linux\"))\nextern static __stdoutp: i64\n\nfn main() {\n    let x = 1 // one\n    // after\n}\n";
+        assert_eq!(format(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_imports_are_grouped_and_sorted() {
+        let source = "// Imports\nimport @.util.strings\nimport std.string  // String\n// Printing\nimport std.io.{ print }\nimport pkg.json\n\nimport std.ops\n\n// Not moved\n\nimport std.vec\nimport std.iter\n\nfn main() {}\n";
+        let expected = "// Imports\n// Printing\nimport std.io.{ print }\nimport std.ops\nimport std.string  // String\n\nimport pkg.json\n\nimport @.util.strings\n\n// Not moved\n\nimport std.iter\nimport std.vec\n\nfn main() {}\n";
+        assert_eq!(format(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_is_idempotent_and_lossless_on_examples() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../..");
+        for dir in ["examples", "std"] {
+            for entry in fs::read_dir(root.join(dir)).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_none_or(|ext| ext != "ws") {
+                    continue;
+                }
+                let source = fs::read_to_string(&path).unwrap();
+                let formatted = format(&source).unwrap_or_else(|e| panic!("{}: {}", path.display(), e[0].message));
+                assert_eq!(format(&formatted).unwrap(), formatted, "{} formats differently twice", path.display());
+
+                // Only whitespace changes and imports move
+                let (tokens, comments) = sorted_tokens_and_comments(&source);
+                assert_eq!(sorted_tokens_and_comments(&formatted), (tokens, comments), "{}", path.display());
+            }
+        }
+    }
+
+    fn sorted_tokens_and_comments(source: &str) -> (Vec<String>, Vec<String>) {
+        let tokens = Lexer::tokenize_lossless(source, FileId::ROOT).unwrap();
+        let mut texts: Vec<String> = tokens.iter().map(|t| t.text.clone()).collect();
+        let mut comments: Vec<String> = tokens
+            .iter()
+            .flat_map(|t| &t.leading)
+            .filter_map(|trivia| match trivia {
+                Trivia::Comment(text) => Some(text.trim_end().to_string()),
+                _ => None,
+            })
+            .collect();
+        texts.sort();
+        comments.sort();
+        (texts, comments)
+    }
+}
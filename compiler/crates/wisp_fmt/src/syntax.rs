@@ -0,0 +1,216 @@
+//! A lossless syntax tree: a file's top-level items, each a sequence of
+//! tokens and bracketed groups, with every comment and whitespace kept as
+//! trivia of the token after it
+
+use std::fmt;
+use std::iter::Peekable;
+use std::vec;
+
+use wisp_lexer::{LosslessToken, Token};
+
+/// The items of a file and the trivia after the last one
+#[derive(Debug, Clone)]
+pub struct SyntaxTree {
+    pub items: Vec<SyntaxItem>,
+    pub eof: LosslessToken,
+}
+
+/// A top-level item with the attributes and `pub` before it
+#[derive(Debug, Clone)]
+pub struct SyntaxItem {
+    pub nodes: Vec<SyntaxNode>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxNode {
+    Token(LosslessToken),
+    /// Tokens between matching `()`, `[]` or `{}`
+    Group {
+        open: LosslessToken,
+        nodes: Vec<SyntaxNode>,
+        close: LosslessToken,
+    },
+}
+
+impl SyntaxTree {
+    /// Build the tree of the tokens of `Lexer::tokenize_lossless`; a bracket
+    /// without a match is kept as a plain token
+    pub fn new(tokens: Vec<LosslessToken>) -> Self {
+        let mut tokens = tokens.into_iter().peekable();
+        let mut items: Vec<SyntaxItem> = Vec::new();
+        let mut prefix = ItemPrefix::Start;
+        let mut after_amp = false;
+        loop {
+            let token = tokens.next().expect("tokens end with Eof");
+            if token.token == Token::Eof {
+                return SyntaxTree { items, eof: token };
+            }
+            let starts_item = match token.token {
+                // Not `&@a T` or `import @.path`
+                Token::At => !after_amp && tokens.peek().is_none_or(|next| next.token != Token::Dot),
+                ref other => is_item_start(other),
+            };
+            if items.is_empty() || (prefix == ItemPrefix::Body && starts_item) {
+                items.push(SyntaxItem { nodes: Vec::new() });
+                prefix = ItemPrefix::Start;
+            }
+            prefix = prefix.next(&token.token);
+            after_amp = token.token == Token::Amp;
+            let nodes = group(token, &mut tokens);
+            items.last_mut().unwrap().nodes.extend(nodes);
+        }
+    }
+
+    /// Every token in source order, ending with `Eof`
+    pub fn tokens(&self) -> Vec<&LosslessToken> {
+        let mut tokens: Vec<_> = self.items.iter().flat_map(SyntaxItem::tokens).collect();
+        tokens.push(&self.eof);
+        tokens
+    }
+}
+
+impl SyntaxItem {
+    /// The first token, which holds the trivia before the item
+    pub fn first_token(&self) -> &LosslessToken {
+        match &self.nodes[0] {
+            SyntaxNode::Token(token) | SyntaxNode::Group { open: token, .. } => token,
+        }
+    }
+
+    pub fn first_token_mut(&mut self) -> &mut LosslessToken {
+        match &mut self.nodes[0] {
+            SyntaxNode::Token(token) | SyntaxNode::Group { open: token, .. } => token,
+        }
+    }
+
+    pub fn tokens(&self) -> Vec<&LosslessToken> {
+        let mut tokens = Vec::new();
+        collect_tokens(&self.nodes, &mut tokens);
+        tokens
+    }
+
+    /// The tokens after the attributes and visibility, outside groups
+    pub fn body(&self) -> impl Iterator<Item = &LosslessToken> {
+        let mut prefix = ItemPrefix::Start;
+        self.nodes.iter().filter_map(move |node| {
+            let token = match node {
+                SyntaxNode::Token(token) => token,
+                SyntaxNode::Group { open, .. } => open,
+            };
+            prefix = prefix.next(&token.token);
+            (prefix == ItemPrefix::Body).then_some(token)
+        })
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    /// The source the tree was built from
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.tokens() {
+            for trivia in &token.leading {
+                f.write_str(trivia.text())?;
+            }
+            f.write_str(&token.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where an item's leading attributes and `pub` end: an item starts on one of
+/// its keywords, except right after `@attribute(...)`, `pub(...)` or `extern`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ItemPrefix {
+    Start,
+    /// After `@`
+    Attribute,
+    /// After `@name` or `pub`, which may take `(...)`
+    Arguments,
+    Body,
+}
+
+impl ItemPrefix {
+    fn next(self, token: &Token) -> Self {
+        match (self, token) {
+            (ItemPrefix::Body, _) => ItemPrefix::Body,
+            (_, Token::At) => ItemPrefix::Attribute,
+            (ItemPrefix::Attribute, Token::Ident(_)) => ItemPrefix::Arguments,
+            (ItemPrefix::Arguments, Token::LParen) => ItemPrefix::Start,
+            (_, Token::Pub) => ItemPrefix::Arguments,
+            (_, Token::Extern) => ItemPrefix::Start,
+            _ => ItemPrefix::Body,
+        }
+    }
+}
+
+fn is_item_start(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::At
+            | Token::Pub
+            | Token::Fn
+            | Token::Struct
+            | Token::Enum
+            | Token::Trait
+            | Token::Impl
+            | Token::Import
+            | Token::Extern
+            | Token::Const
+            | Token::Static
+            | Token::Type
+    )
+}
+
+/// `open`, and if it opens a group everything up to the matching close
+fn group(open: LosslessToken, tokens: &mut Peekable<vec::IntoIter<LosslessToken>>) -> Vec<SyntaxNode> {
+    let close = match open.token {
+        Token::LParen => Token::RParen,
+        Token::LBracket => Token::RBracket,
+        Token::LBrace => Token::RBrace,
+        _ => return vec![SyntaxNode::Token(open)],
+    };
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next_if(|t| t.token != Token::Eof) {
+        if token.token == close {
+            return vec![SyntaxNode::Group { open, nodes, close: token }];
+        }
+        nodes.extend(group(token, tokens));
+    }
+    // Unclosed: leave the rest of the file flat after the bracket
+    let mut flat = vec![SyntaxNode::Token(open)];
+    flat.extend(nodes);
+    flat
+}
+
+fn collect_tokens<'a>(nodes: &'a [SyntaxNode], tokens: &mut Vec<&'a LosslessToken>) {
+    for node in nodes {
+        match node {
+            SyntaxNode::Token(token) => tokens.push(token),
+            SyntaxNode::Group { open, nodes, close } => {
+                tokens.push(open);
+                collect_tokens(nodes, tokens);
+                tokens.push(close);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wisp_lexer::{FileId, Lexer};
+
+    #[test]
+    fn test_tree_keeps_source_and_splits_items() {
+        let source = "import @.util  // helpers\n\n@cfg(target_os = \"linux\")\npub extern static stdout: i64\n@test\nfn tail(xs@x: &[u8]) -> &@x [u8] { &xs[1..] }\n// end\n";
+        let tree = SyntaxTree::new(Lexer::tokenize_lossless(source, FileId::ROOT).unwrap());
+        assert_eq!(tree.to_string(), source);
+
+        let starts: Vec<Vec<&str>> = tree
+            .items
+            .iter()
+            .map(|item| item.body().take(2).map(|t| t.text.as_str()).collect())
+            .collect();
+        assert_eq!(starts, [["import", "@"], ["static", "stdout"], ["fn", "tail"]]);
+        assert!(matches!(tree.items[2].nodes.last(), Some(SyntaxNode::Group { nodes, .. }) if nodes.len() == 3));
+    }
+}
@@ -14,6 +14,10 @@ impl DefId {
     }
 }
 
+/// Unique identifier for loops (targets of `break` and `continue`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LoopId(pub u32);
+
 /// Unique identifier for modules (source files)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub struct ModuleId(pub u32);
@@ -211,7 +215,9 @@ impl ResolvedProgram {
         out.push_str("=== Resolved Program ===\n\n");
         
         out.push_str("--- Globals ---\n");
-        for (name, id) in &self.globals {
+        let mut globals: Vec<_> = self.globals.iter().collect();
+        globals.sort_by_key(|(_, id)| id.0);
+        for (name, id) in globals {
             let def = self.defs.get(id).unwrap();
             out.push_str(&format!("  {} -> {:?} ({:?})\n", name, id, def.kind));
         }
@@ -308,7 +314,9 @@ impl Default for ResolvedProgram {
 pub struct ResolvedStruct {
     pub def_id: DefId,
     pub name: String,
+    pub type_params: Vec<ResolvedTypeParam>,
     pub fields: Vec<ResolvedField>,
+    pub has_defaults: bool,
     pub span: Span,
 }
 
@@ -328,6 +336,7 @@ pub struct ResolvedEnum {
     pub name: String,
     pub type_params: Vec<ResolvedTypeParam>,
     pub variants: Vec<ResolvedVariant>,
+    pub is_non_exhaustive: bool,
     pub span: Span,
 }
 
@@ -406,6 +415,12 @@ pub enum ResolvedType {
     Ref { is_mut: bool, inner: Box<ResolvedType> },
     /// Slice type
     Slice { elem: Box<ResolvedType> },
+    /// Fixed-size array type: [T; N]
+    Array { elem: Box<ResolvedType>, size: usize },
+    /// Function type
+    Function { params: Vec<ResolvedType>, ret: Box<ResolvedType> },
+    /// Tuple type
+    Tuple(Vec<ResolvedType>),
     /// Unit type
     Unit,
     /// Self type (in trait/impl context)
@@ -433,6 +448,8 @@ pub enum ResolvedStmt {
         span: Span,
     },
     Expr(ResolvedExpr),
+    /// Block run when the enclosing scope exits
+    Defer { body: ResolvedBlock, span: Span },
 }
 
 /// Resolved expression
@@ -483,9 +500,12 @@ pub enum ResolvedExprKind {
     
     /// Struct literal
     StructLit {
-        struct_def: DefId,
+        /// None for a `{ ... }` literal whose struct comes from the expected type
+        struct_def: Option<DefId>,
         /// (field_name, field_name_span, value_expr)
         fields: Vec<(String, Span, ResolvedExpr)>,
+        /// `..base` supplying the fields that aren't listed
+        spread: Option<Box<ResolvedExpr>>,
     },
     
     /// If expression
@@ -497,21 +517,48 @@ pub enum ResolvedExprKind {
     
     /// While loop
     While {
+        loop_id: LoopId,
         cond: Box<ResolvedExpr>,
         body: ResolvedBlock,
     },
     
-    /// For loop
+    /// For loop. A pattern binds a hidden local holding each item, and the
+    /// body starts with `let`s destructuring it.
     For {
+        loop_id: LoopId,
         binding: DefId,
         binding_name: String,
         iter: Box<ResolvedExpr>,
+        /// Hidden local holding an `Iterator` while the loop runs
+        iter_def: DefId,
+        body: ResolvedBlock,
+    },
+    
+    /// Infinite loop
+    Loop {
+        loop_id: LoopId,
         body: ResolvedBlock,
     },
     
+    /// Return from the enclosing function
+    Return(Option<Box<ResolvedExpr>>),
+    
+    /// Break out of the target loop, optionally with a value
+    Break {
+        target: LoopId,
+        value: Option<Box<ResolvedExpr>>,
+    },
+    
+    /// Continue with the next iteration of the target loop
+    Continue { target: LoopId },
+    
     /// Block expression
     Block(ResolvedBlock),
     
+    /// Block passed as a call's last argument; the parameter type decides
+    /// whether it is a value or a list of elements
+    TrailingBlock(ResolvedBlock),
+    
     /// Assignment
     Assign {
         target: Box<ResolvedExpr>,
@@ -539,11 +586,19 @@ pub enum ResolvedExprKind {
         index: Box<ResolvedExpr>,
     },
     
-    /// Array literal
-    ArrayLit(Vec<ResolvedExpr>),
+    /// List literal; the expected type decides whether it builds an array
+    /// or a `FromIterator` collection
+    ArrayLit(Vec<ResolvedCollectionElem>),
+    
+    /// Map literal, building a `FromIterator` collection of `(key, value)` pairs
+    MapLit(Vec<ResolvedCollectionElem>),
     
-    /// Lambda/closure
+    /// Tuple literal
+    Tuple(Vec<ResolvedExpr>),
+    
+    /// Lambda/closure; a `move` closure captures every variable by value
     Lambda {
+        is_move: bool,
         params: Vec<ResolvedLambdaParam>,
         body: Box<ResolvedExpr>,
     },
@@ -554,6 +609,21 @@ pub enum ResolvedExprKind {
         target_type: ResolvedType,
     },
     
+    /// Compiler intrinsic: #size_of(T), #read(T, addr), ...
+    Intrinsic {
+        intrinsic: wisp_ast::Intrinsic,
+        ty: ResolvedType,
+        args: Vec<ResolvedExpr>,
+    },
+    
+    /// Error propagation: expr?
+    /// The bindings hold the payload of `Some`/`Ok` and of `Err` once desugared into a match
+    Try {
+        expr: Box<ResolvedExpr>,
+        value_binding: DefId,
+        error_binding: DefId,
+    },
+    
     /// String interpolation: "hello {name}!"
     StringInterp {
         parts: Vec<ResolvedStringInterpPart>,
@@ -592,6 +662,43 @@ pub struct ResolvedCallArg {
     pub span: Span,
 }
 
+/// Resolved element of a list or map literal
+#[derive(Debug, Clone)]
+pub struct ResolvedCollectionElem {
+    pub kind: ResolvedCollectionElemKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ResolvedCollectionElemKind {
+    Item(ResolvedExpr),
+    Entry(ResolvedExpr, ResolvedExpr),
+    If {
+        cond: ResolvedExpr,
+        then_elem: Box<ResolvedCollectionElem>,
+        else_elem: Option<Box<ResolvedCollectionElem>>,
+    },
+    /// The loop id is never a `break` target; it only identifies the loop
+    For {
+        loop_id: LoopId,
+        binding: DefId,
+        binding_name: String,
+        iter: ResolvedExpr,
+        iter_def: DefId,
+        /// `let`s destructuring the item, run before the element
+        bindings: Vec<ResolvedStmt>,
+        elem: Box<ResolvedCollectionElem>,
+    },
+    /// A spread of anything but an array is walked like a `for` element,
+    /// with a hidden local for each item
+    Spread {
+        base: ResolvedExpr,
+        loop_id: LoopId,
+        item: DefId,
+        iter_def: DefId,
+    },
+}
+
 /// Resolved else branch
 #[derive(Debug, Clone)]
 pub enum ResolvedElse {
@@ -623,5 +730,6 @@ pub enum ResolvedPatternKind {
         variant_def: DefId,
         fields: Vec<ResolvedPattern>,
     },
+    Tuple(Vec<ResolvedPattern>),
 }
 
@@ -2,14 +2,18 @@
 
 use std::collections::{HashMap, HashSet};
 use wisp_ast::*;
+use wisp_diagnostics::{codes, similar_name, Code, Diagnostic};
 use wisp_lexer::Span;
 use crate::hir::*;
 
 /// Errors during name resolution
 #[derive(Debug, Clone)]
 pub struct ResolveError {
+    pub code: Code,
     pub message: String,
     pub span: Span,
+    /// A name that was probably meant instead of the text at `span`
+    pub suggestion: Option<String>,
 }
 
 impl std::fmt::Display for ResolveError {
@@ -20,6 +24,16 @@ impl std::fmt::Display for ResolveError {
 
 impl std::error::Error for ResolveError {}
 
+impl From<ResolveError> for Diagnostic {
+    fn from(e: ResolveError) -> Self {
+        let diagnostic = Diagnostic::error(e.code, e.message, e.span);
+        match e.suggestion {
+            Some(name) => diagnostic.with_suggestion(e.span, name, "a name with a similar spelling exists"),
+            None => diagnostic,
+        }
+    }
+}
+
 /// Scope for name resolution
 #[derive(Debug, Clone)]
 struct Scope {
@@ -58,6 +72,15 @@ impl Scope {
         }
     }
 
+    /// Every name visible from this scope
+    fn visible_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.names.keys().map(String::as_str).collect();
+        if let Some(parent) = &self.parent {
+            names.extend(parent.visible_names());
+        }
+        names
+    }
+
     fn into_parent(self) -> Option<Scope> {
         self.parent.map(|b| *b)
     }
@@ -126,12 +149,18 @@ pub struct Resolver {
     trait_type_params: HashMap<DefId, Vec<(String, Option<TypeExpr>)>>,
     /// Current namespace being populated (when processing an import's items)
     current_namespace: Option<String>,
-    /// Items that have already been resolved (by span start..end)
-    resolved_items: HashSet<(usize, usize)>,
+    /// Items that have already been resolved (by span)
+    resolved_items: HashSet<Span>,
     /// Module registry
     modules: ModuleRegistry,
     /// Current module being resolved
     current_module: ModuleId,
+    /// Next LoopId to assign
+    next_loop_id: u32,
+    /// Enclosing loops, innermost last: (label, loop id)
+    loop_stack: Vec<(Option<String>, LoopId)>,
+    /// Whether we are inside a `defer` block
+    in_defer: bool,
 }
 
 impl Resolver {
@@ -152,6 +181,9 @@ impl Resolver {
             resolved_items: HashSet::new(),
             modules: ModuleRegistry::new(),
             current_module: ModuleId::root(),
+            next_loop_id: 0,
+            loop_stack: Vec::new(),
+            in_defer: false,
         }
     }
 
@@ -201,6 +233,22 @@ impl Resolver {
         id
     }
 
+    /// Define a compiler-introduced local that cannot be named from source
+    fn define_hidden_local(&mut self, name: &str, span: Span) -> DefId {
+        let id = self.fresh_id();
+        let info = DefInfo {
+            id,
+            name: name.to_string(),
+            kind: DefKind::Local,
+            span,
+            parent: None,
+            module_id: self.current_module,
+            is_pub: false,
+        };
+        self.defs.insert(id, info);
+        id
+    }
+
     fn define_global(&mut self, name: String, kind: DefKind, span: Span, is_pub: bool) -> DefId {
         let id = self.fresh_id();
         let info = DefInfo {
@@ -224,19 +272,19 @@ impl Resolver {
             .or_insert_with(Scope::new)
             .define(name.clone(), id);
         
-        // Also add to namespace if we're processing an import (only public items)
+        // Also add to namespace if we're processing an import. Private items
+        // are added too, so that using one reports it as private rather than
+        // missing; lookups through a namespace check visibility.
         if let Some(ref ns_name) = self.current_namespace {
-            if is_pub {
-                if let Some(ns) = self.namespaces.get_mut(ns_name) {
-                    ns.define(name, id);
-                }
+            if let Some(ns) = self.namespaces.get_mut(ns_name) {
+                ns.define(name, id);
             }
         }
         
         id
     }
     
-    /// Lookup a name in a namespace (only returns public items)
+    /// Lookup a name in a namespace, public or not
     fn lookup_in_namespace(&self, namespace: &str, name: &str) -> Option<DefId> {
         self.namespaces.get(namespace).and_then(|ns| ns.lookup(name))
     }
@@ -264,6 +312,7 @@ impl Resolver {
             // Different module - check if public
             if !def_info.is_pub {
                 self.error(
+                    codes::PRIVATE_ITEM,
                     format!("'{}' is private", def_info.name),
                     access_span,
                 );
@@ -292,15 +341,11 @@ impl Resolver {
                 // Recursively collect path from base
                 if let Some(mut path) = self.collect_namespace_path(base) {
                     // Check if this field is a child namespace
-                    if let Some(ns) = self.lookup_child_namespace(&path, &field.name) {
-                        eprintln!("DEBUG collect_namespace_path: path={:?}, field={}, ns.children.keys={:?}, ns.names.keys={:?}", 
-                            path, field.name, ns.children.keys().collect::<Vec<_>>(), ns.names.keys().collect::<Vec<_>>());
-                        if ns.children.contains_key(&field.name) || !ns.names.is_empty() {
-                            path.push(field.name.clone());
-                            return Some(path);
-                        }
-                    } else {
-                        eprintln!("DEBUG collect_namespace_path: lookup_child_namespace({:?}, {}) returned None", path, field.name);
+                    if let Some(ns) = self.lookup_child_namespace(&path, &field.name)
+                        && (ns.children.contains_key(&field.name) || !ns.names.is_empty())
+                    {
+                        path.push(field.name.clone());
+                        return Some(path);
                     }
                 }
                 None
@@ -342,6 +387,7 @@ impl Resolver {
             let is_pub = self.defs.get(&def_id).map(|d| d.is_pub).unwrap_or(false);
             if !is_pub {
                 self.error(
+                    codes::PRIVATE_ITEM,
                     format!("'{}' is private", item_name),
                     field_span,
                 );
@@ -368,6 +414,7 @@ impl Resolver {
         // Item not found in namespace
         let ns_display = ns_path.join(".");
         self.error(
+            codes::UNRESOLVED_NAME,
             format!("cannot find '{}' in namespace '{}'", item_name, ns_display),
             field_span,
         );
@@ -423,6 +470,7 @@ impl Resolver {
                     // Check visibility - only public items can be imported
                     if !is_pub {
                         self.error(
+                            codes::PRIVATE_ITEM,
                             format!("'{}' is private and cannot be imported", item.name.name),
                             item.span,
                         );
@@ -435,6 +483,7 @@ impl Resolver {
                         self.scope.define(name.clone(), def_id);
                     } else {
                         self.error(
+                            codes::UNRESOLVED_NAME,
                             format!("cannot find '{}' in module", item.name.name),
                             item.span,
                         );
@@ -465,8 +514,67 @@ impl Resolver {
         None
     }
 
-    fn error(&mut self, message: String, span: Span) {
-        self.errors.push(ResolveError { message, span });
+    fn error(&mut self, code: Code, message: String, span: Span) {
+        self.errors.push(ResolveError { code, message, span, suggestion: None });
+    }
+
+    /// Report an undefined name, suggesting a visible one it may be a typo of
+    fn undefined_name_error(&mut self, message: String, name: &str, span: Span) {
+        let mut candidates = self.scope.visible_names();
+        if let Some(module_scope) = self.module_scopes.get(&self.current_module) {
+            candidates.extend(module_scope.visible_names());
+        }
+        candidates.extend(self.globals.keys().map(String::as_str));
+        let suggestion = similar_name(name, candidates).map(str::to_string);
+        self.errors.push(ResolveError { code: codes::UNRESOLVED_NAME, message, span, suggestion });
+    }
+
+    fn new_loop_id(&mut self) -> LoopId {
+        let id = LoopId(self.next_loop_id);
+        self.next_loop_id += 1;
+        id
+    }
+
+    /// Push a new loop onto the loop stack and return its id
+    fn enter_loop(&mut self, label: &Option<Ident>) -> LoopId {
+        let id = self.new_loop_id();
+        if let Some(label) = label
+            && self.loop_stack.iter().any(|(l, _)| l.as_deref() == Some(label.name.as_str()))
+        {
+            self.error(codes::LOOP_CONTROL, format!("label '@{}' shadows an enclosing loop label", label.name), label.span);
+        }
+        self.loop_stack.push((label.as_ref().map(|l| l.name.clone()), id));
+        id
+    }
+
+    /// Find the loop targeted by a `break` or `continue`
+    fn find_loop(&mut self, label: &Option<Ident>, keyword: &str, span: Span) -> Option<LoopId> {
+        match label {
+            Some(label) => {
+                let found = self.loop_stack.iter().rev()
+                    .find(|(l, _)| l.as_deref() == Some(label.name.as_str()))
+                    .map(|(_, id)| *id);
+                if found.is_none() {
+                    if self.in_defer {
+                        self.error(codes::DEFER_ESCAPE, format!("'{}' cannot leave a 'defer' block", keyword), span);
+                    } else {
+                        self.error(codes::LOOP_CONTROL, format!("use of undeclared loop label '@{}'", label.name), label.span);
+                    }
+                }
+                found
+            }
+            None => {
+                let found = self.loop_stack.last().map(|(_, id)| *id);
+                if found.is_none() {
+                    if self.in_defer {
+                        self.error(codes::DEFER_ESCAPE, format!("'{}' cannot leave a 'defer' block", keyword), span);
+                    } else {
+                        self.error(codes::LOOP_CONTROL, format!("'{}' outside of a loop", keyword), span);
+                    }
+                }
+                found
+            }
+        }
     }
 
     fn push_scope(&mut self) {
@@ -513,10 +621,8 @@ impl Resolver {
                         
                         // Add to namespace if we're processing an import
                         if let Some(ref ns_name) = self.current_namespace {
-                            if e.is_pub {
-                                if let Some(ns) = self.namespaces.get_mut(ns_name) {
-                                    ns.define(variant.name.name.clone(), variant_id);
-                                }
+                            if let Some(ns) = self.namespaces.get_mut(ns_name) {
+                                ns.define(variant.name.name.clone(), variant_id);
                             }
                         }
                     }
@@ -536,6 +642,9 @@ impl Resolver {
                 Item::Impl(_) => {
                     // Impl blocks don't define a global name
                 }
+                Item::Cfg(_) => {
+                    // `@cfg` items are configured away before resolution
+                }
             }
         }
 
@@ -581,6 +690,7 @@ impl Resolver {
                         program.impls.push(resolved);
                     }
                 }
+                Item::Cfg(_) => {}
             }
         }
 
@@ -735,10 +845,8 @@ impl Resolver {
                                 
                                 // Add to namespace if we're processing an import
                                 if let Some(ref ns_name) = self.current_namespace {
-                                    if e.is_pub {
-                                        if let Some(ns) = self.namespaces.get_mut(ns_name) {
-                                            ns.define(variant.name.name.clone(), variant_id);
-                                        }
+                                    if let Some(ns) = self.namespaces.get_mut(ns_name) {
+                                        ns.define(variant.name.name.clone(), variant_id);
                                     }
                                 }
                             }
@@ -788,7 +896,7 @@ impl Resolver {
                             }
                         }
                     }
-                    Item::Impl(_) => {}
+                    Item::Impl(_) | Item::Cfg(_) => {}
                 }
             }
             
@@ -809,6 +917,7 @@ impl Resolver {
                         // Check visibility - only public items can be imported
                         if !is_pub {
                             self.error(
+                                codes::PRIVATE_ITEM,
                                 format!("'{}' is private and cannot be imported", item.name.name),
                                 item.span,
                             );
@@ -825,6 +934,7 @@ impl Resolver {
                             .define(name.clone(), def_id);
                     } else {
                         self.error(
+                            codes::UNRESOLVED_NAME,
                             format!("cannot find '{}' in module", item.name.name),
                             item.span,
                         );
@@ -858,7 +968,7 @@ impl Resolver {
                 Item::ExternStatic(s) => {
                     self.define_global(s.name.name.clone(), DefKind::ExternStatic, s.span, s.is_pub);
                 }
-                Item::Impl(_) => {}
+                Item::Impl(_) | Item::Cfg(_) => {}
             }
         }
         
@@ -897,16 +1007,17 @@ impl Resolver {
     
     /// Helper to resolve a single item
     /// Get the span key for an item (for deduplication)
-    fn item_span_key(item: &Item) -> (usize, usize) {
+    fn item_span_key(item: &Item) -> Span {
         match item {
-            Item::Import(i) => (i.span.start, i.span.end),
-            Item::Function(f) => (f.span.start, f.span.end),
-            Item::ExternFunction(f) => (f.span.start, f.span.end),
-            Item::ExternStatic(s) => (s.span.start, s.span.end),
-            Item::Struct(s) => (s.span.start, s.span.end),
-            Item::Enum(e) => (e.span.start, e.span.end),
-            Item::Trait(t) => (t.span.start, t.span.end),
-            Item::Impl(i) => (i.span.start, i.span.end),
+            Item::Import(i) => i.span,
+            Item::Function(f) => f.span,
+            Item::ExternFunction(f) => f.span,
+            Item::ExternStatic(s) => s.span,
+            Item::Struct(s) => s.span,
+            Item::Enum(e) => e.span,
+            Item::Trait(t) => t.span,
+            Item::Impl(i) => i.span,
+            Item::Cfg(c) => c.span,
         }
     }
     
@@ -955,6 +1066,7 @@ impl Resolver {
                     program.impls.push(resolved);
                 }
             }
+            Item::Cfg(_) => {}
         }
     }
 
@@ -965,7 +1077,8 @@ impl Resolver {
         self.push_scope();
         
         // Add type parameters to scope
-        for type_param in &s.type_params {
+        let mut type_params = Vec::new();
+        for (index, type_param) in s.type_params.iter().enumerate() {
             let param_id = self.fresh_id();
             let param_info = DefInfo {
                 id: param_id,
@@ -978,6 +1091,18 @@ impl Resolver {
             };
             self.defs.insert(param_id, param_info);
             self.scope.define(type_param.name.name.clone(), param_id);
+            
+            let bounds: Vec<_> = type_param.bounds.iter()
+                .map(|b| self.resolve_type(b))
+                .collect();
+            type_params.push(ResolvedTypeParam {
+                def_id: param_id,
+                index: index as u32,
+                name: type_param.name.name.clone(),
+                bounds,
+                default: None,
+                span: type_param.span,
+            });
         }
         
         let mut fields = Vec::new();
@@ -1008,7 +1133,9 @@ impl Resolver {
         Some(ResolvedStruct {
             def_id,
             name: s.name.name.clone(),
+            type_params,
             fields,
+            has_defaults: s.has_defaults,
             span: s.span,
         })
     }
@@ -1069,7 +1196,7 @@ impl Resolver {
                 span: variant.span,
                 parent: Some(def_id),
                 module_id: self.current_module,
-                is_pub: false, // Variants inherit visibility from enum
+                is_pub: e.is_pub, // Variants inherit visibility from enum
             };
             self.defs.insert(variant_id, variant_info);
             
@@ -1115,6 +1242,7 @@ impl Resolver {
             name: e.name.name.clone(),
             type_params,
             variants,
+            is_non_exhaustive: e.is_non_exhaustive,
             span: e.span,
         })
     }
@@ -1200,7 +1328,7 @@ impl Resolver {
         
         let trait_def = i.trait_name.as_ref().and_then(|name| {
             self.lookup(&name.name).or_else(|| {
-                self.error(format!("undefined trait '{}'", name.name), name.span);
+                self.error(codes::UNDEFINED_TYPE, format!("undefined trait '{}'", name.name), name.span);
                 None
             })
         });
@@ -1238,6 +1366,7 @@ impl Resolver {
                 } else {
                     // No default provided, this is an error
                     self.error(
+                        codes::MISSING_TYPE_ARGUMENT,
                         format!("missing type argument for trait parameter '{}'", trait_params[idx].0),
                         i.span,
                     );
@@ -1260,7 +1389,7 @@ impl Resolver {
         for method in &i.methods {
             // Pass the impl target as parent so methods get their own DefId
             // For primitives (impl_target_id = None), we still want to create methods
-            if let Some(resolved) = self.resolve_impl_method(method, impl_target_id, is_impl_context) {
+            if let Some(resolved) = self.resolve_impl_method(method, impl_target_id, is_impl_context, type_params.len() as u32) {
                 methods.push(resolved);
             }
         }
@@ -1279,8 +1408,9 @@ impl Resolver {
         })
     }
 
-    /// Resolve a method inside an impl block (always creates a new DefId)
-    fn resolve_impl_method(&mut self, f: &FnDef, parent: Option<DefId>, _is_impl_context: bool) -> Option<ResolvedFunction> {
+    /// Resolve a method inside an impl block (always creates a new DefId).
+    /// The method's own type parameters are numbered after the impl's, starting at `first_type_param`.
+    fn resolve_impl_method(&mut self, f: &FnDef, parent: Option<DefId>, _is_impl_context: bool, first_type_param: u32) -> Option<ResolvedFunction> {
         // Always create a new DefId for impl methods (even for primitives)
         let def_id = {
             let id = self.fresh_id();
@@ -1327,7 +1457,7 @@ impl Resolver {
             
             type_params.push(ResolvedTypeParam {
                 def_id: param_id,
-                index: index as u32,
+                index: first_type_param + index as u32,
                 name: type_param.name.name.clone(),
                 bounds,
                 default,
@@ -1559,6 +1689,7 @@ impl Resolver {
                         // Check visibility - types from other modules must be public
                         if !is_pub {
                             self.error(
+                                codes::PRIVATE_ITEM,
                                 format!("type '{}' is private", type_name),
                                 ty.span,
                             );
@@ -1590,7 +1721,7 @@ impl Resolver {
                 }
                 
                 // Unknown type
-                self.error(format!("undefined type '{}'", name), ident.span);
+                self.error(codes::UNDEFINED_TYPE, format!("undefined type '{}'", name), ident.span);
                 ResolvedType::Error
             }
             TypeKind::Ref(is_mut, inner) => {
@@ -1606,10 +1737,24 @@ impl Resolver {
                     elem: Box::new(elem_resolved),
                 }
             }
+            TypeKind::Function(params, ret) => {
+                let params = params.iter().map(|p| self.resolve_type(p)).collect();
+                let ret = self.resolve_type(ret);
+                ResolvedType::Function { params, ret: Box::new(ret) }
+            }
+            TypeKind::Tuple(elems) => {
+                ResolvedType::Tuple(elems.iter().map(|e| self.resolve_type(e)).collect())
+            }
             TypeKind::Unit => ResolvedType::Unit,
-            TypeKind::Array(_, _) | TypeKind::Tuple(_) => {
-                // TODO: implement these
-                ResolvedType::Error
+            TypeKind::Array(elem, size) => {
+                let elem = Box::new(self.resolve_type(elem));
+                match size.kind {
+                    ExprKind::IntLiteral(n) if n >= 0 => ResolvedType::Array { elem, size: n as usize },
+                    _ => {
+                        self.error(codes::SYNTAX_ERROR, "array size must be an integer literal".to_string(), size.span);
+                        ResolvedType::Error
+                    }
+                }
             }
         }
     }
@@ -1617,9 +1762,15 @@ impl Resolver {
     fn resolve_block(&mut self, block: &Block) -> ResolvedBlock {
         self.push_scope();
         
-        let stmts: Vec<_> = block.stmts.iter()
-            .map(|s| self.resolve_stmt(s))
-            .collect();
+        let mut stmts = Vec::new();
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let(l) if matches!(l.pattern.kind, PatternKind::Tuple(_)) => {
+                    self.resolve_destructuring_let(l, &mut stmts);
+                }
+                _ => stmts.push(self.resolve_stmt(stmt)),
+            }
+        }
         
         self.pop_scope();
         
@@ -1636,9 +1787,13 @@ impl Resolver {
                 let init = l.init.as_ref().map(|e| self.resolve_expr(e));
                 let ty = l.ty.as_ref().map(|t| self.resolve_type(t));
                 
-                // Now define the binding
+                // Now define the binding; `let _ = ...` still evaluates into a local
+                let name = match &l.pattern.kind {
+                    PatternKind::Ident(ident) => ident.name.clone(),
+                    _ => "_".to_string(),
+                };
                 let def_id = self.define(
-                    l.name.name.clone(),
+                    name.clone(),
                     DefKind::Local,
                     l.span,
                     None,
@@ -1648,7 +1803,7 @@ impl Resolver {
                 
                 ResolvedStmt::Let {
                     def_id,
-                    name: l.name.name.clone(),
+                    name,
                     is_mut: l.is_mut,
                     ty,
                     init,
@@ -1658,6 +1813,102 @@ impl Resolver {
             Stmt::Expr(e) => {
                 ResolvedStmt::Expr(self.resolve_expr(&e.expr))
             }
+            Stmt::Defer(d) => {
+                // Deferred code runs on scope exit, so it cannot jump out of the scope itself
+                let outer_loops = std::mem::take(&mut self.loop_stack);
+                let outer_in_defer = std::mem::replace(&mut self.in_defer, true);
+                let body = self.resolve_block(&d.body);
+                self.loop_stack = outer_loops;
+                self.in_defer = outer_in_defer;
+                ResolvedStmt::Defer { body, span: d.span }
+            }
+        }
+    }
+
+    /// Desugar `let (a, b) = e` into a hidden local holding the tuple and one
+    /// `let` per named element: `let a = tuple.0; let b = tuple.1`
+    fn resolve_destructuring_let(&mut self, l: &LetStmt, out: &mut Vec<ResolvedStmt>) {
+        let init = l.init.as_ref().map(|e| self.resolve_expr(e));
+        if init.is_none() {
+            self.error(codes::SYNTAX_ERROR, "a destructuring `let` needs an initializer".to_string(), l.span);
+        }
+        let ty = l.ty.as_ref().map(|t| self.resolve_type(t));
+
+        let tuple = self.define_hidden_local("tuple", l.pattern.span);
+        self.current_locals.push(tuple);
+        out.push(ResolvedStmt::Let {
+            def_id: tuple,
+            name: "tuple".to_string(),
+            is_mut: false,
+            ty,
+            init,
+            span: l.span,
+        });
+        self.bind_tuple_elements(tuple, &l.pattern, l.is_mut, out);
+    }
+
+    /// Define the binding of a `for` loop. Any pattern but a name binds a
+    /// hidden local holding the item, destructured by the `let`s pushed to `out`.
+    fn define_loop_binding(&mut self, pattern: &Pattern, out: &mut Vec<ResolvedStmt>) -> (DefId, String) {
+        match &pattern.kind {
+            PatternKind::Ident(ident) => {
+                let def_id = self.define(ident.name.clone(), DefKind::Local, ident.span, None, false);
+                (def_id, ident.name.clone())
+            }
+            _ => {
+                let item = self.define_hidden_local("tuple", pattern.span);
+                self.bind_tuple_elements(item, pattern, false, out);
+                (item, "tuple".to_string())
+            }
+        }
+    }
+
+    fn bind_tuple_elements(&mut self, tuple: DefId, pattern: &Pattern, is_mut: bool, out: &mut Vec<ResolvedStmt>) {
+        let PatternKind::Tuple(elems) = &pattern.kind else { return };
+        for (i, elem) in elems.iter().enumerate() {
+            let base = ResolvedExpr {
+                kind: ResolvedExprKind::Var { name: "tuple".to_string(), def_id: tuple },
+                span: pattern.span,
+            };
+            let field = ResolvedExpr {
+                kind: ResolvedExprKind::Field {
+                    expr: Box::new(base),
+                    field: i.to_string(),
+                    field_def: None,
+                    field_span: elem.span,
+                },
+                span: elem.span,
+            };
+            match &elem.kind {
+                PatternKind::Ident(ident) => {
+                    let def_id = self.define(ident.name.clone(), DefKind::Local, ident.span, None, false);
+                    self.current_locals.push(def_id);
+                    out.push(ResolvedStmt::Let {
+                        def_id,
+                        name: ident.name.clone(),
+                        is_mut,
+                        ty: None,
+                        init: Some(field),
+                        span: elem.span,
+                    });
+                }
+                PatternKind::Tuple(_) => {
+                    // Nested tuples are moved into their own hidden local first
+                    let inner = self.define_hidden_local("tuple", elem.span);
+                    self.current_locals.push(inner);
+                    out.push(ResolvedStmt::Let {
+                        def_id: inner,
+                        name: "tuple".to_string(),
+                        is_mut: false,
+                        ty: None,
+                        init: Some(field),
+                        span: elem.span,
+                    });
+                    self.bind_tuple_elements(inner, elem, is_mut, out);
+                }
+                // `_` leaves the element in place; the parser rejects anything else
+                _ => {}
+            }
         }
     }
 
@@ -1675,7 +1926,7 @@ impl Resolver {
                         def_id,
                     },
                     None => {
-                        self.error(format!("undefined variable '{}'", ident.name), ident.span);
+                        self.undefined_name_error(format!("undefined variable '{}'", ident.name), &ident.name, ident.span);
                         ResolvedExprKind::Error
                     }
                 }
@@ -1725,6 +1976,7 @@ impl Resolver {
                             // Check visibility - items from other modules must be public
                             if !is_pub {
                                 self.error(
+                                    codes::PRIVATE_ITEM,
                                     format!("'{}' is private", field.name),
                                     field.span,
                                 );
@@ -1749,6 +2001,7 @@ impl Resolver {
                                 }
                             }
                             self.error(
+                                codes::UNRESOLVED_NAME,
                                 format!("cannot find '{}' in namespace '{}'", field.name, ident.name),
                                 field.span,
                             );
@@ -1769,21 +2022,24 @@ impl Resolver {
                 }
             }
             
-            ExprKind::StructLit(name, fields) => {
-                match self.lookup(&name.name) {
-                    Some(struct_def) => {
-                        let resolved_fields: Vec<_> = fields.iter()
-                            .map(|f| (f.name.name.clone(), f.name.span, self.resolve_expr(&f.value)))
-                            .collect();
-                        ResolvedExprKind::StructLit {
-                            struct_def,
-                            fields: resolved_fields,
+            ExprKind::StructLit(name, fields, spread) => {
+                let struct_def = match name {
+                    Some(name) => match self.lookup(&name.name) {
+                        Some(struct_def) => Some(struct_def),
+                        None => {
+                            self.error(codes::UNDEFINED_TYPE, format!("undefined struct '{}'", name.name), name.span);
+                            return ResolvedExpr { kind: ResolvedExprKind::Error, span: expr.span };
                         }
-                    }
-                    None => {
-                        self.error(format!("undefined struct '{}'", name.name), name.span);
-                        ResolvedExprKind::Error
-                    }
+                    },
+                    None => None,
+                };
+                let resolved_fields: Vec<_> = fields.iter()
+                    .map(|f| (f.name.name.clone(), f.name.span, self.resolve_expr(&f.value)))
+                    .collect();
+                ResolvedExprKind::StructLit {
+                    struct_def,
+                    fields: resolved_fields,
+                    spread: spread.as_ref().map(|s| Box::new(self.resolve_expr(s))),
                 }
             }
             
@@ -1800,31 +2056,79 @@ impl Resolver {
                 }
             }
             
-            ExprKind::While(cond, body) => {
+            ExprKind::While(label, cond, body) => {
+                let resolved_cond = self.resolve_expr(cond);
+                let loop_id = self.enter_loop(label);
+                let resolved_body = self.resolve_block(body);
+                self.loop_stack.pop();
+                
                 ResolvedExprKind::While {
-                    cond: Box::new(self.resolve_expr(cond)),
-                    body: self.resolve_block(body),
+                    loop_id,
+                    cond: Box::new(resolved_cond),
+                    body: resolved_body,
                 }
             }
             
-            ExprKind::For(binding, iter, body) => {
+            ExprKind::For(label, pattern, iter, body) => {
                 // Resolve the iterator expression first (before entering the loop scope)
                 let resolved_iter = self.resolve_expr(iter);
+                let iter_def = self.define_hidden_local("iter", iter.span);
                 
                 // Create a new scope for the loop body with the binding
                 self.push_scope();
-                let binding_def = self.define(binding.name.clone(), DefKind::Local, binding.span, None, false);
-                let resolved_body = self.resolve_block(body);
+                let mut bindings = Vec::new();
+                let (binding_def, binding_name) = self.define_loop_binding(pattern, &mut bindings);
+                let loop_id = self.enter_loop(label);
+                let mut resolved_body = self.resolve_block(body);
+                self.loop_stack.pop();
                 self.pop_scope();
                 
+                bindings.append(&mut resolved_body.stmts);
+                resolved_body.stmts = bindings;
                 ResolvedExprKind::For {
+                    loop_id,
                     binding: binding_def,
-                    binding_name: binding.name.clone(),
+                    binding_name,
                     iter: Box::new(resolved_iter),
+                    iter_def,
                     body: resolved_body,
                 }
             }
             
+            ExprKind::Loop(label, body) => {
+                let loop_id = self.enter_loop(label);
+                let resolved_body = self.resolve_block(body);
+                self.loop_stack.pop();
+                
+                ResolvedExprKind::Loop { loop_id, body: resolved_body }
+            }
+            
+            ExprKind::Return(value) => {
+                if self.in_defer {
+                    self.error(codes::DEFER_ESCAPE, "'return' is not allowed inside a 'defer' block".to_string(), expr.span);
+                }
+                ResolvedExprKind::Return(value.as_ref().map(|v| Box::new(self.resolve_expr(v))))
+            }
+            
+            ExprKind::Break(label, value) => {
+                let value = value.as_ref().map(|v| Box::new(self.resolve_expr(v)));
+                match self.find_loop(label, "break", expr.span) {
+                    Some(target) => ResolvedExprKind::Break { target, value },
+                    None => ResolvedExprKind::Error,
+                }
+            }
+            
+            ExprKind::Continue(label) => {
+                match self.find_loop(label, "continue", expr.span) {
+                    Some(target) => ResolvedExprKind::Continue { target },
+                    None => ResolvedExprKind::Error,
+                }
+            }
+            
+            ExprKind::TrailingBlock(block) => {
+                ResolvedExprKind::TrailingBlock(self.resolve_block(block))
+            }
+            
             ExprKind::Block(block) => {
                 ResolvedExprKind::Block(self.resolve_block(block))
             }
@@ -1863,11 +2167,23 @@ impl Resolver {
             
             ExprKind::ArrayLit(elements) => {
                 ResolvedExprKind::ArrayLit(
+                    elements.iter().map(|e| self.resolve_collection_elem(e)).collect()
+                )
+            }
+            
+            ExprKind::MapLit(elements) => {
+                ResolvedExprKind::MapLit(
+                    elements.iter().map(|e| self.resolve_collection_elem(e)).collect()
+                )
+            }
+            
+            ExprKind::Tuple(elements) => {
+                ResolvedExprKind::Tuple(
                     elements.iter().map(|e| self.resolve_expr(e)).collect()
                 )
             }
             
-            ExprKind::Lambda(params, body) => {
+            ExprKind::Lambda(is_move, params, body) => {
                 // Create a new scope for lambda body
                 self.push_scope();
                 
@@ -1883,10 +2199,16 @@ impl Resolver {
                     }
                 }).collect();
                 
+                // Loops outside the lambda cannot be targeted from its body
+                let outer_loops = std::mem::take(&mut self.loop_stack);
+                let outer_in_defer = std::mem::replace(&mut self.in_defer, false);
                 let resolved_body = self.resolve_expr(body);
+                self.loop_stack = outer_loops;
+                self.in_defer = outer_in_defer;
                 self.pop_scope();
                 
                 ResolvedExprKind::Lambda {
+                    is_move: *is_move,
                     params: resolved_params,
                     body: Box::new(resolved_body),
                 }
@@ -1901,6 +2223,26 @@ impl Resolver {
                 }
             }
             
+            ExprKind::Intrinsic(intrinsic, ty, args) => {
+                ResolvedExprKind::Intrinsic {
+                    intrinsic: *intrinsic,
+                    ty: self.resolve_type(ty),
+                    args: args.iter().map(|a| self.resolve_expr(a)).collect(),
+                }
+            }
+            
+            ExprKind::Try(inner) => {
+                if self.in_defer {
+                    self.error(codes::DEFER_ESCAPE, "'?' is not allowed inside a 'defer' block".to_string(), expr.span);
+                }
+                let resolved_expr = self.resolve_expr(inner);
+                ResolvedExprKind::Try {
+                    expr: Box::new(resolved_expr),
+                    value_binding: self.define_hidden_local("value", expr.span),
+                    error_binding: self.define_hidden_local("error", expr.span),
+                }
+            }
+            
             ExprKind::StringInterp(parts) => {
                 let resolved_parts = parts.iter().map(|part| {
                     match part {
@@ -1922,6 +2264,49 @@ impl Resolver {
         }
     }
 
+    fn resolve_collection_elem(&mut self, elem: &CollectionElem) -> ResolvedCollectionElem {
+        let kind = match &elem.kind {
+            CollectionElemKind::Item(value) => ResolvedCollectionElemKind::Item(self.resolve_expr(value)),
+            CollectionElemKind::Entry(key, value) => {
+                ResolvedCollectionElemKind::Entry(self.resolve_expr(key), self.resolve_expr(value))
+            }
+            CollectionElemKind::If(cond, then_elem, else_elem) => ResolvedCollectionElemKind::If {
+                cond: self.resolve_expr(cond),
+                then_elem: Box::new(self.resolve_collection_elem(then_elem)),
+                else_elem: else_elem.as_ref().map(|e| Box::new(self.resolve_collection_elem(e))),
+            },
+            CollectionElemKind::For(pattern, iter, inner) => {
+                let resolved_iter = self.resolve_expr(iter);
+                let iter_def = self.define_hidden_local("iter", iter.span);
+                
+                // Not pushed on the loop stack: `break` in an element leaves the enclosing loop
+                self.push_scope();
+                let mut bindings = Vec::new();
+                let (binding_def, binding_name) = self.define_loop_binding(pattern, &mut bindings);
+                let loop_id = self.new_loop_id();
+                let resolved_elem = self.resolve_collection_elem(inner);
+                self.pop_scope();
+                
+                ResolvedCollectionElemKind::For {
+                    loop_id,
+                    binding: binding_def,
+                    binding_name,
+                    iter: resolved_iter,
+                    iter_def,
+                    bindings,
+                    elem: Box::new(resolved_elem),
+                }
+            }
+            CollectionElemKind::Spread(base) => ResolvedCollectionElemKind::Spread {
+                base: self.resolve_expr(base),
+                loop_id: self.new_loop_id(),
+                item: self.define_hidden_local("item", base.span),
+                iter_def: self.define_hidden_local("iter", base.span),
+            },
+        };
+        ResolvedCollectionElem { kind, span: elem.span }
+    }
+
     fn resolve_match_arm(&mut self, arm: &MatchArm) -> ResolvedMatchArm {
         self.push_scope();
         
@@ -1988,11 +2373,15 @@ impl Resolver {
                         }
                     }
                     None => {
-                        self.error(format!("undefined variant '{}'", name.name), name.span);
+                        self.error(codes::UNRESOLVED_NAME, format!("undefined variant '{}'", name.name), name.span);
                         ResolvedPatternKind::Wildcard
                     }
                 }
             }
+            
+            PatternKind::Tuple(elems) => {
+                ResolvedPatternKind::Tuple(elems.iter().map(|p| self.resolve_pattern(p)).collect())
+            }
         };
         
         ResolvedPattern {
@@ -1,5 +1,11 @@
 use logos::Logos;
 
+mod source_map;
+mod trivia;
+
+pub use source_map::{FileId, SourceMap, SourceText};
+pub use trivia::{LosslessToken, Trivia};
+
 /// Process escape sequences in a string literal
 fn process_escape_sequences(s: &str) -> String {
     let mut result = String::with_capacity(s.len());
@@ -30,16 +36,40 @@ fn process_escape_sequences(s: &str) -> String {
     result
 }
 
-/// Span in source code (byte offsets)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Span in source code (byte offsets into one file of a `SourceMap`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Span {
+    pub file: FileId,
     pub start: usize,
     pub end: usize,
 }
 
 impl Span {
+    /// Span in the root file
     pub fn new(start: usize, end: usize) -> Self {
-        Self { start, end }
+        Self { file: FileId::ROOT, start, end }
+    }
+
+    pub fn in_file(file: FileId, start: usize, end: usize) -> Self {
+        Self { file, start, end }
+    }
+
+    /// From the start of this span to the end of `end`
+    pub fn to(self, end: Span) -> Self {
+        Self { end: end.end, ..self }
+    }
+
+    /// From the start of this span to the start of `end`
+    pub fn until(self, end: Span) -> Self {
+        Self { end: end.start, ..self }
+    }
+
+    pub fn with_start(self, start: usize) -> Self {
+        Self { start, ..self }
+    }
+
+    pub fn with_end(self, end: usize) -> Self {
+        Self { end, ..self }
     }
 }
 
@@ -73,6 +103,12 @@ pub enum Token {
     In,
     #[token("return")]
     Return,
+    #[token("loop")]
+    Loop,
+    #[token("break")]
+    Break,
+    #[token("continue")]
+    Continue,
     #[token("struct")]
     Struct,
     #[token("enum")]
@@ -93,6 +129,8 @@ pub enum Token {
     Match,
     #[token("defer")]
     Defer,
+    #[token("move")]
+    Move,
     #[token("import")]
     Import,
     #[token("as")]
@@ -213,6 +251,8 @@ pub enum Token {
     Arrow,
     #[token("@")]
     At,
+    #[token("#")]
+    Hash,
 
     // === Special ===
     Eof,
@@ -230,6 +270,9 @@ impl std::fmt::Display for Token {
             Token::For => write!(f, "for"),
             Token::In => write!(f, "in"),
             Token::Return => write!(f, "return"),
+            Token::Loop => write!(f, "loop"),
+            Token::Break => write!(f, "break"),
+            Token::Continue => write!(f, "continue"),
             Token::Struct => write!(f, "struct"),
             Token::Enum => write!(f, "enum"),
             Token::Trait => write!(f, "trait"),
@@ -240,6 +283,7 @@ impl std::fmt::Display for Token {
             Token::False => write!(f, "false"),
             Token::Match => write!(f, "match"),
             Token::Defer => write!(f, "defer"),
+            Token::Move => write!(f, "move"),
             Token::Import => write!(f, "import"),
             Token::As => write!(f, "as"),
             Token::Type => write!(f, "type"),
@@ -290,6 +334,7 @@ impl std::fmt::Display for Token {
             Token::DotDot => write!(f, ".."),
             Token::Arrow => write!(f, "->"),
             Token::At => write!(f, "@"),
+            Token::Hash => write!(f, "#"),
             Token::Eof => write!(f, "EOF"),
         }
     }
@@ -298,20 +343,32 @@ impl std::fmt::Display for Token {
 /// Lexer wrapper that produces SpannedTokens
 pub struct Lexer<'src> {
     inner: logos::Lexer<'src, Token>,
+    file: FileId,
     finished: bool,
 }
 
 impl<'src> Lexer<'src> {
     pub fn new(source: &'src str) -> Self {
+        Self::with_file(source, FileId::ROOT)
+    }
+
+    /// Lexer whose spans point into `file`
+    pub fn with_file(source: &'src str, file: FileId) -> Self {
         Self {
             inner: Token::lexer(source),
+            file,
             finished: false,
         }
     }
 
     /// Tokenize the entire source into a Vec
     pub fn tokenize(source: &str) -> Result<Vec<SpannedToken>, LexError> {
-        let mut lexer = Lexer::new(source);
+        Self::tokenize_file(source, FileId::ROOT)
+    }
+
+    /// Tokenize the source of `file` into a Vec
+    pub fn tokenize_file(source: &str, file: FileId) -> Result<Vec<SpannedToken>, LexError> {
+        let mut lexer = Lexer::with_file(source, file);
         let mut tokens = Vec::new();
         
         loop {
@@ -330,7 +387,7 @@ impl<'src> Lexer<'src> {
         if self.finished {
             return Ok(SpannedToken {
                 token: Token::Eof,
-                span: Span::new(0, 0),
+                span: Span::in_file(self.file, 0, 0),
             });
         }
 
@@ -339,14 +396,14 @@ impl<'src> Lexer<'src> {
                 let span = self.inner.span();
                 Ok(SpannedToken {
                     token,
-                    span: Span::new(span.start, span.end),
+                    span: Span::in_file(self.file, span.start, span.end),
                 })
             }
             Some(Err(())) => {
                 let span = self.inner.span();
                 Err(LexError {
                     message: format!("unexpected character: '{}'", self.inner.slice()),
-                    span: Span::new(span.start, span.end),
+                    span: Span::in_file(self.file, span.start, span.end),
                 })
             }
             None => {
@@ -354,7 +411,7 @@ impl<'src> Lexer<'src> {
                 let len = self.inner.source().len();
                 Ok(SpannedToken {
                     token: Token::Eof,
-                    span: Span::new(len, len),
+                    span: Span::in_file(self.file, len, len),
                 })
             }
         }
@@ -378,6 +435,7 @@ impl std::error::Error for LexError {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
 
     #[test]
     fn test_basic_tokens() {
@@ -396,5 +454,41 @@ mod tests {
         assert!(matches!(tokens[9].token, Token::RBrace));
         assert!(matches!(tokens[10].token, Token::Eof));
     }
-}
 
+    #[test]
+    fn test_spans_locate_their_file() {
+        let mut sources = SourceMap::new();
+        let root = sources.add("main.ws".into(), "fn main() {}\n".to_string());
+        let lib = sources.add("lib.ws".into(), "// helper\nfn helper() {}\n".to_string());
+        assert_eq!(root, FileId::ROOT);
+
+        let tokens = Lexer::tokenize_file(sources.text(lib), lib).unwrap();
+        let name = tokens[1].span;
+        assert_eq!(name.file, lib);
+        assert_eq!(sources.line_col(name), (2, 4));
+        assert_eq!(sources.location(name), "lib.ws:2:4");
+        assert_eq!(sources.get(lib).line(2), "fn helper() {}");
+        assert_eq!(sources.find(Path::new("lib.ws")), Some(lib));
+    }
+
+    #[test]
+    fn test_lossless_tokens_keep_trivia() {
+        let source = "// header\nfn main() {  // entry\r\n\n    let x = \"a\\tb\"\n}\n";
+        let tokens = Lexer::tokenize_lossless(source, FileId::ROOT).unwrap();
+
+        assert_eq!(tokens[0].leading, [Trivia::Comment("// header".to_string()), Trivia::Newline]);
+        assert_eq!(tokens[5].text, "let");
+        assert!(tokens[5].starts_line());
+        assert_eq!(tokens[5].leading[0], Trivia::Whitespace("  ".to_string()));
+        assert_eq!(tokens[5].leading[1], Trivia::Comment("// entry\r".to_string()));
+        assert_eq!(tokens[8].text, "\"a\\tb\"");
+        assert_eq!(tokens.last().unwrap().token, Token::Eof);
+        assert_eq!(tokens.last().unwrap().leading, [Trivia::Newline]);
+
+        let printed: String = tokens
+            .iter()
+            .flat_map(|t| t.leading.iter().map(Trivia::text).chain([t.text.as_str()]))
+            .collect();
+        assert_eq!(printed, source);
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use crate::Span;
+
+/// Identifies a source file in a `SourceMap`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(u32);
+
+impl FileId {
+    /// The file compilation starts from; sources parsed on their own use it too
+    pub const ROOT: FileId = FileId(0);
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A source file's path and text
+#[derive(Debug, Clone)]
+pub struct SourceText {
+    pub path: PathBuf,
+    pub text: String,
+    /// Byte offset of the start of each line
+    line_starts: Vec<usize>,
+}
+
+impl SourceText {
+    fn new(path: PathBuf, text: String) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        Self { path, text, line_starts }
+    }
+
+    /// 1-based line and column of a byte offset
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        (line, offset - self.line_starts[line - 1] + 1)
+    }
+
+    /// Text of a 1-based line, without its line ending
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self.line_starts.get(line).map_or(self.text.len(), |&next| next - 1);
+        self.text[start..end].trim_end_matches('\r')
+    }
+}
+
+/// Every source file of a compilation, indexed by `FileId`
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceText>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file; the first one added is `FileId::ROOT`
+    pub fn add(&mut self, path: PathBuf, text: String) -> FileId {
+        self.files.push(SourceText::new(path, text));
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    /// The file added with `path`, if any
+    pub fn find(&self, path: &Path) -> Option<FileId> {
+        self.files.iter().position(|f| f.path == path).map(|i| FileId(i as u32))
+    }
+
+    pub fn get(&self, file: FileId) -> &SourceText {
+        &self.files[file.index()]
+    }
+
+    pub fn path(&self, file: FileId) -> &Path {
+        &self.get(file).path
+    }
+
+    pub fn text(&self, file: FileId) -> &str {
+        &self.get(file).text
+    }
+
+    /// 1-based line and column of the start of a span
+    pub fn line_col(&self, span: Span) -> (usize, usize) {
+        self.get(span.file).line_col(span.start)
+    }
+
+    /// `path:line:col` of the start of a span
+    pub fn location(&self, span: Span) -> String {
+        let (line, col) = self.line_col(span);
+        format!("{}:{}:{}", self.path(span.file).display(), line, col)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (FileId, &SourceText)> {
+        self.files.iter().enumerate().map(|(i, f)| (FileId(i as u32), f))
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{FileId, LexError, Lexer, Span, Token};
+
+/// Whitespace or a comment between two tokens
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    /// Spaces, tabs and carriage returns
+    Whitespace(String),
+    /// One line break
+    Newline,
+    /// A `//` comment, without the line break that ends it
+    Comment(String),
+}
+
+impl Trivia {
+    pub fn text(&self) -> &str {
+        match self {
+            Trivia::Whitespace(s) | Trivia::Comment(s) => s,
+            Trivia::Newline => "\n",
+        }
+    }
+}
+
+/// A token with its source text and the trivia before it
+#[derive(Debug, Clone, PartialEq)]
+pub struct LosslessToken {
+    pub leading: Vec<Trivia>,
+    pub token: Token,
+    pub text: String,
+    pub span: Span,
+}
+
+impl LosslessToken {
+    /// Whether a line break comes between this token and the one before it
+    pub fn starts_line(&self) -> bool {
+        self.leading.contains(&Trivia::Newline)
+    }
+}
+
+impl Lexer<'_> {
+    /// Tokenize the source of `file`, keeping the whitespace and comments
+    /// before each token; the trivia at the end of the file belongs to the
+    /// final `Eof`. Writing out the trivia and text of every token gives back
+    /// the source exactly.
+    pub fn tokenize_lossless(source: &str, file: FileId) -> Result<Vec<LosslessToken>, LexError> {
+        let mut end_of_previous = 0;
+        let tokens = Self::tokenize_file(source, file)?
+            .into_iter()
+            .map(|spanned| {
+                let leading = split_trivia(&source[end_of_previous..spanned.span.start]);
+                end_of_previous = spanned.span.end;
+                LosslessToken {
+                    leading,
+                    text: source[spanned.span.start..spanned.span.end].to_string(),
+                    token: spanned.token,
+                    span: spanned.span,
+                }
+            })
+            .collect();
+        Ok(tokens)
+    }
+}
+
+/// Split the text the lexer skipped between two tokens
+fn split_trivia(mut text: &str) -> Vec<Trivia> {
+    let mut trivia = Vec::new();
+    while !text.is_empty() {
+        let len = if let Some(rest) = text.strip_prefix('\n') {
+            trivia.push(Trivia::Newline);
+            text.len() - rest.len()
+        } else if text.starts_with("//") {
+            let len = text.find('\n').unwrap_or(text.len());
+            trivia.push(Trivia::Comment(text[..len].to_string()));
+            len
+        } else {
+            let len = text.find(['\n', '/']).unwrap_or(text.len());
+            trivia.push(Trivia::Whitespace(text[..len].to_string()));
+            len
+        };
+        text = &text[len..];
+    }
+    trivia
+}
@@ -10,9 +10,9 @@ use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
 
-use wisp_ast::{Item, SourceFile, StructField};
-use wisp_lexer::Span;
-use wisp_parser::{parse_with_imports, parse_with_imports_structured, Parser};
+use wisp_ast::{Item, SourceFile, StructField, TargetCfg};
+use wisp_lexer::{FileId, SourceMap, Span};
+use wisp_parser::{parse_with_imports, parse_with_imports_structured};
 use wisp_hir::{DefId, Resolver};
 use wisp_borrowck::BorrowChecker;
 
@@ -85,6 +85,8 @@ struct DocumentState {
     imported_symbols: HashSet<String>,
     /// Diagnostics published for this document
     diagnostics: Vec<Diagnostic>,
+    /// Files the analysis read, with the document as the root file
+    sources: SourceMap,
 }
 
 /// The Wisp LSP backend
@@ -123,39 +125,22 @@ impl WispLanguageServer {
 
         // Get file path from URI
         let file_path = uri.to_file_path().unwrap_or_else(|_| PathBuf::from("."));
-        let file_str = file_path.to_string_lossy().to_string();
 
         // Run parser with import resolution (structured for proper namespace handling)
         let base_dir = file_path.parent().unwrap_or(Path::new("."));
         
-        // First, try to parse with error recovery to collect all parse errors
-        if let Ok(parse_result) = Parser::parse_with_recovery(text) {
-            // Add all parse errors as diagnostics
-            for err in &parse_result.errors {
-                diagnostics.push(Diagnostic {
-                    range: offset_to_range(text, err.span.start, err.span.end),
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    source: Some("wisp".to_string()),
-                    message: err.message.clone(),
-                    ..Default::default()
-                });
-            }
-        }
-        
+        // The document is the root file; imported files are added as they are parsed
+        let mut sources = SourceMap::new();
+        sources.add(file_path.clone(), text.to_string());
+
+        // Every parse error of the document, or the first problem with its imports
         let mut visited = std::collections::HashSet::new();
-        let ast_with_imports = match parse_with_imports_structured(text, base_dir, &mut visited) {
+        let ast_with_imports = match parse_with_imports_structured(&mut sources, FileId::ROOT, &mut visited, &TargetCfg::host()) {
             Ok(ast) => ast,
-            Err(err) => {
-                diagnostics.push(Diagnostic {
-                    range: Range {
-                        start: Position { line: 0, character: 0 },
-                        end: Position { line: 0, character: 1 },
-                    },
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    source: Some("wisp".to_string()),
-                    message: err,
-                    ..Default::default()
-                });
+            Err(errors) => {
+                for err in errors {
+                    diagnostics.push(to_lsp_diagnostic(&sources, err));
+                }
                 if let Ok(mut docs) = self.documents.write() {
                     // Keep old document state but update source
                     if let Some(old_doc) = docs.get_mut(uri) {
@@ -176,6 +161,7 @@ impl WispLanguageServer {
                             std_symbols: HashMap::new(),
                             imported_symbols: HashSet::new(),
                             diagnostics: Vec::new(),
+                            sources,
                         });
                     }
                 }
@@ -310,7 +296,7 @@ impl WispLanguageServer {
         
         // Also get flat AST for collecting function/struct/trait info
         // If parsing fails, preserve previous document state but still show diagnostics
-        let ast = match parse_with_imports(text, &file_path) {
+        let ast = match parse_with_imports(&mut sources, FileId::ROOT) {
             Ok(ast) => ast,
             Err(_) => {
                 // Parse error - preserve previous document state so hover/completion still works
@@ -336,6 +322,7 @@ impl WispLanguageServer {
                             std_symbols: HashMap::new(),
                             imported_symbols: HashSet::new(),
                             diagnostics: Vec::new(),
+                            sources,
                         });
                     }
                 }
@@ -344,7 +331,7 @@ impl WispLanguageServer {
             }
         };
 
-        // Collect function, struct, and trait info from current file only
+        // Collect function, struct, and trait info, each naming the file it is in
         // (Traits from imports were already collected above)
         for item in &ast.items {
             match item {
@@ -364,7 +351,7 @@ impl WispLanguageServer {
                             signature: format!("fn {}({}){}", func.name.name, params_str.join(", "), ret),
                             params,
                             span: func.name.span,
-                            file: file_str.clone(),
+                            file: sources.path(func.name.span.file).to_string_lossy().to_string(),
                         }
                     );
                 }
@@ -382,7 +369,7 @@ impl WispLanguageServer {
                             fields,
                             definition: format!("struct {} {{\n{}\n}}", s.name.name, fields_str.join(",\n")),
                             span: s.name.span,
-                            file: file_str.clone(),
+                            file: sources.path(s.name.span.file).to_string_lossy().to_string(),
                         }
                     );
                 }
@@ -421,7 +408,7 @@ impl WispLanguageServer {
                             type_params: Vec::new(), // Not collecting type params for local traits yet
                             definition,
                             span: t.name.span,
-                            file: file_str.clone(),
+                            file: sources.path(t.name.span.file).to_string_lossy().to_string(),
                         }
                     );
                 }
@@ -441,7 +428,7 @@ impl WispLanguageServer {
                             signature: format!("extern fn {}({}){}", func.name.name, params_str.join(", "), ret),
                             params,
                             span: func.name.span,
-                            file: file_str.clone(),
+                            file: sources.path(func.name.span.file).to_string_lossy().to_string(),
                         }
                     );
                 }
@@ -482,7 +469,7 @@ impl WispLanguageServer {
                                 signature: signature.clone(),
                                 params,
                                 span: method.name.span,
-                                file: file_str.clone(),
+                                file: sources.path(method.name.span.file).to_string_lossy().to_string(),
                             }
                         );
                         
@@ -501,7 +488,7 @@ impl WispLanguageServer {
             Ok(resolved) => resolved,
             Err(errors) => {
                 for err in errors {
-                    diagnostics.push(span_to_diagnostic(text, err.span, &err.message, DiagnosticSeverity::ERROR));
+                    diagnostics.push(to_lsp_diagnostic(&sources, err.into()));
                 }
                 if let Ok(mut docs) = self.documents.write() {
                     // Preserve variables from previous successful analysis
@@ -525,6 +512,7 @@ impl WispLanguageServer {
                         std_symbols: std_symbols.clone(),
                         imported_symbols: imported_symbols.clone(),
                         diagnostics: diagnostics.clone(),
+                        sources,
                     });
                 }
                 self.client.publish_diagnostics(uri.clone(), diagnostics, None).await;
@@ -537,7 +525,10 @@ impl WispLanguageServer {
         
         // Add type errors to diagnostics
         for err in type_errors {
-            diagnostics.push(span_to_diagnostic(text, err.span, &err.message, DiagnosticSeverity::ERROR));
+            diagnostics.push(to_lsp_diagnostic(&sources, err.into()));
+        }
+        for warning in &typed.warnings {
+            diagnostics.push(to_lsp_diagnostic(&sources, warning.clone().into()));
         }
         
         // Continue processing - use the partial TypedProgram for hover/completion
@@ -545,17 +536,16 @@ impl WispLanguageServer {
 
         // Use compiler's type info directly (recorded during type checking)
         // Filter to only include spans from the current file
-        let source_len = text.len();
-        for ((start, end), type_str) in typed.all_span_types() {
-            if *end <= source_len {
-                type_info.insert((*start, *end), type_str.clone());
+        for (span, type_str) in typed.all_span_types() {
+            if span.file == FileId::ROOT {
+                type_info.insert((span.start, span.end), type_str.clone());
             }
         }
         
         // Collect span→definition mappings from compiler
-        for ((start, end), def_id) in typed.ctx.all_span_definitions() {
-            if *end <= source_len {
-                span_definitions.insert((*start, *end), *def_id);
+        for (span, def_id) in typed.ctx.all_span_definitions() {
+            if span.file == FileId::ROOT {
+                span_definitions.insert((span.start, span.end), *def_id);
             }
         }
         
@@ -573,10 +563,10 @@ impl WispLanguageServer {
         }
         
         // Collect named argument info from AST (named arg labels are not in typed AST)
-        collect_named_args_from_ast(&ast, &functions, &mut type_info, source_len);
+        collect_named_args_from_ast(&ast, &functions, &mut type_info, FileId::ROOT);
         
         // Collect variable definitions and types for go-to-definition and method completion
-        collect_variable_defs(&typed, &mut variable_defs, &mut variable_types, &typed.ctx, source_len);
+        collect_variable_defs(&typed, &mut variable_defs, &mut variable_types, &typed.ctx, FileId::ROOT);
         
         // Collect namespace info from resolved program
         let mut namespaces = HashMap::new();
@@ -588,7 +578,7 @@ impl WispLanguageServer {
         let checker = BorrowChecker::new(&typed);
         if let Err(borrow_errors) = checker.check() {
             for err in borrow_errors {
-                diagnostics.push(span_to_diagnostic(text, err.span, &err.message, DiagnosticSeverity::ERROR));
+                diagnostics.push(to_lsp_diagnostic(&sources, err.into()));
             }
         }
 
@@ -622,6 +612,7 @@ impl WispLanguageServer {
                 std_symbols,
                 imported_symbols,
                 diagnostics: diagnostics.clone(),
+                sources,
             });
         }
 
@@ -654,6 +645,7 @@ impl LanguageServer for WispLanguageServer {
                     work_done_progress_options: Default::default(),
                     resolve_provider: None,
                 })),
+                document_formatting_provider: Some(OneOf::Left(true)),
                 ..Default::default()
             },
             server_info: Some(ServerInfo {
@@ -789,11 +781,17 @@ impl LanguageServer for WispLanguageServer {
                 
                 if let Some((_, def_id)) = best_span {
                     // Look up the definition span
-                    if let Some(def_span) = doc.def_spans.get(&def_id) {
-                        return Ok(Some(GotoDefinitionResponse::Scalar(Location {
-                            uri: uri.clone(),
-                            range: offset_to_range(&doc.source, def_span.start, def_span.end),
-                        })));
+                    if let Some(&def_span) = doc.def_spans.get(&def_id) {
+                        if def_span.file == FileId::ROOT {
+                            return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                                uri: uri.clone(),
+                                range: offset_to_range(&doc.source, def_span.start, def_span.end),
+                            })));
+                        }
+                        // Defined in an imported file
+                        if let Some(location) = span_location(&doc.sources, def_span) {
+                            return Ok(Some(GotoDefinitionResponse::Scalar(location)));
+                        }
                     }
                 }
                 
@@ -1237,6 +1235,28 @@ impl LanguageServer for WispLanguageServer {
         Ok(None)
     }
 
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let source = match self.documents.read() {
+            Ok(docs) => match docs.get(&params.text_document.uri) {
+                Some(doc) => doc.source.clone(),
+                None => return Ok(None),
+            },
+            Err(_) => return Ok(None),
+        };
+
+        // A document that doesn't parse is left alone; its diagnostics say why
+        let Ok(formatted) = wisp_fmt::format(&source) else {
+            return Ok(None);
+        };
+        if formatted == source {
+            return Ok(Some(vec![]));
+        }
+        Ok(Some(vec![TextEdit {
+            range: offset_to_range(&source, 0, source.len()),
+            new_text: formatted,
+        }]))
+    }
+
     async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
         let uri = &params.text_document.uri;
         
@@ -1733,14 +1753,14 @@ fn get_module_item_suggestions(file_path: Option<&PathBuf>, import_path: &str) -
     
     let mut items = Vec::new();
     
-    for item in ast.items {
+    for item in wisp_ast::configure_items(ast.items, &TargetCfg::host()) {
         let (name, kind, is_pub) = match item {
             wisp_ast::Item::Function(f) => (f.name.name, CompletionItemKind::FUNCTION, f.is_pub),
             wisp_ast::Item::Struct(s) => (s.name.name, CompletionItemKind::STRUCT, s.is_pub),
             wisp_ast::Item::Enum(e) => (e.name.name, CompletionItemKind::ENUM, e.is_pub),
             wisp_ast::Item::Trait(t) => (t.name.name, CompletionItemKind::INTERFACE, t.is_pub),
             wisp_ast::Item::Impl(_) => continue, // Skip impls
-            wisp_ast::Item::Import(_) => continue, // Skip imports
+            wisp_ast::Item::Import(_) | wisp_ast::Item::Cfg(_) => continue, // Skip imports
             wisp_ast::Item::ExternFunction(f) => (f.name.name, CompletionItemKind::FUNCTION, f.is_pub),
             wisp_ast::Item::ExternStatic(s) => (s.name.name, CompletionItemKind::VARIABLE, s.is_pub),
         };
@@ -1916,22 +1936,65 @@ fn is_ident_char(c: char) -> bool {
     c.is_alphanumeric() || c == '_'
 }
 
-/// Convert a span to an LSP Diagnostic
-fn span_to_diagnostic(source: &str, span: Span, message: &str, severity: DiagnosticSeverity) -> Diagnostic {
-    let range = offset_to_range(source, span.start, span.end);
+/// Convert a compiler diagnostic to an LSP Diagnostic on the document
+///
+/// Problems in imported files are reported at the top of the document, naming
+/// the file, with their location as related information. Secondary labels
+/// become related information too, and notes, help and suggestions are
+/// appended to the message.
+fn to_lsp_diagnostic(sources: &SourceMap, diagnostic: wisp_diagnostics::Diagnostic) -> Diagnostic {
+    let span = diagnostic.primary.span;
+    let mut related: Vec<DiagnosticRelatedInformation> = Vec::new();
+    let (range, mut message) = if span.file == FileId::ROOT {
+        (offset_to_range(sources.text(span.file), span.start, span.end), diagnostic.message.clone())
+    } else {
+        related.extend(span_location(sources, span).map(|location| DiagnosticRelatedInformation {
+            location,
+            message: diagnostic.message.clone(),
+        }));
+        (Range::default(), format!("{}: {}", sources.location(span), diagnostic.message))
+    };
+    for label in &diagnostic.secondary {
+        related.extend(span_location(sources, label.span).map(|location| DiagnosticRelatedInformation {
+            location,
+            message: label.message.clone().unwrap_or_default(),
+        }));
+    }
+    for note in &diagnostic.notes {
+        message.push_str(&format!("\nnote: {}", note));
+    }
+    if let Some(help) = &diagnostic.help {
+        message.push_str(&format!("\nhelp: {}", help));
+    }
+    for suggestion in &diagnostic.suggestions {
+        message.push_str(&format!("\nhelp: {}: `{}`", suggestion.message, suggestion.replacement));
+    }
+    let severity = match diagnostic.severity {
+        wisp_diagnostics::Severity::Error => DiagnosticSeverity::ERROR,
+        wisp_diagnostics::Severity::Warning => DiagnosticSeverity::WARNING,
+    };
     Diagnostic {
         range,
         severity: Some(severity),
-        code: None,
+        code: Some(NumberOrString::String(diagnostic.code.to_string())),
         code_description: None,
         source: Some("wisp".to_string()),
-        message: message.to_string(),
-        related_information: None,
+        message,
+        related_information: (!related.is_empty()).then_some(related),
         tags: None,
         data: None,
     }
 }
 
+/// LSP Location of a span in any file of the source map
+fn span_location(sources: &SourceMap, span: Span) -> Option<Location> {
+    let uri = Url::from_file_path(sources.path(span.file)).ok()?;
+    Some(Location {
+        uri,
+        range: offset_to_range(sources.text(span.file), span.start, span.end),
+    })
+}
+
 /// Convert byte offset to LSP Position
 fn offset_to_position(source: &str, offset: usize) -> Position {
     let mut line = 0u32;
@@ -2073,19 +2136,19 @@ fn collect_named_args_from_ast(
     ast: &SourceFile,
     functions: &HashMap<String, FunctionInfo>,
     type_info: &mut HashMap<(usize, usize), String>,
-    source_len: usize,
+    file: FileId,
 ) {
     for item in &ast.items {
         match item {
             Item::Function(func) => {
                 if let Some(ref body) = func.body {
-                    collect_named_args_from_block(body, functions, type_info, source_len);
+                    collect_named_args_from_block(body, functions, type_info, file);
                 }
             }
             Item::Impl(imp) => {
                 for method in &imp.methods {
                     if let Some(ref body) = method.body {
-                        collect_named_args_from_block(body, functions, type_info, source_len);
+                        collect_named_args_from_block(body, functions, type_info, file);
                     }
                 }
             }
@@ -2098,17 +2161,20 @@ fn collect_named_args_from_block(
     block: &wisp_ast::Block,
     functions: &HashMap<String, FunctionInfo>,
     type_info: &mut HashMap<(usize, usize), String>,
-    source_len: usize,
+    file: FileId,
 ) {
     for stmt in &block.stmts {
         match stmt {
             wisp_ast::Stmt::Let(let_stmt) => {
                 if let Some(ref init) = let_stmt.init {
-                    collect_named_args_from_expr(init, functions, type_info, source_len);
+                    collect_named_args_from_expr(init, functions, type_info, file);
                 }
             }
             wisp_ast::Stmt::Expr(expr_stmt) => {
-                collect_named_args_from_expr(&expr_stmt.expr, functions, type_info, source_len);
+                collect_named_args_from_expr(&expr_stmt.expr, functions, type_info, file);
+            }
+            wisp_ast::Stmt::Defer(defer_stmt) => {
+                collect_named_args_from_block(&defer_stmt.body, functions, type_info, file);
             }
         }
     }
@@ -2118,9 +2184,9 @@ fn collect_named_args_from_expr(
     expr: &wisp_ast::Expr,
     functions: &HashMap<String, FunctionInfo>,
     type_info: &mut HashMap<(usize, usize), String>,
-    source_len: usize,
+    file: FileId,
 ) {
-    if expr.span.end > source_len {
+    if expr.span.file != file {
         return;
     }
     
@@ -2151,68 +2217,77 @@ fn collect_named_args_from_expr(
                             }
                         }
                         // Recurse into the argument value
-                        collect_named_args_from_expr(&arg.value, functions, type_info, source_len);
+                        collect_named_args_from_expr(&arg.value, functions, type_info, file);
                     }
                 } else {
                     // Function not found, still recurse into args
                     for arg in args {
-                        collect_named_args_from_expr(&arg.value, functions, type_info, source_len);
+                        collect_named_args_from_expr(&arg.value, functions, type_info, file);
                     }
                 }
             } else {
                 for arg in args {
-                    collect_named_args_from_expr(&arg.value, functions, type_info, source_len);
+                    collect_named_args_from_expr(&arg.value, functions, type_info, file);
                 }
             }
-            collect_named_args_from_expr(callee, functions, type_info, source_len);
+            collect_named_args_from_expr(callee, functions, type_info, file);
         }
         wisp_ast::ExprKind::Binary(left, _, right) => {
-            collect_named_args_from_expr(left, functions, type_info, source_len);
-            collect_named_args_from_expr(right, functions, type_info, source_len);
+            collect_named_args_from_expr(left, functions, type_info, file);
+            collect_named_args_from_expr(right, functions, type_info, file);
         }
         wisp_ast::ExprKind::Unary(_, inner) => {
-            collect_named_args_from_expr(inner, functions, type_info, source_len);
+            collect_named_args_from_expr(inner, functions, type_info, file);
         }
         wisp_ast::ExprKind::If(cond, then_block, else_block) => {
-            collect_named_args_from_expr(cond, functions, type_info, source_len);
-            collect_named_args_from_block(then_block, functions, type_info, source_len);
+            collect_named_args_from_expr(cond, functions, type_info, file);
+            collect_named_args_from_block(then_block, functions, type_info, file);
             if let Some(else_branch) = else_block {
                 match else_branch {
-                    wisp_ast::ElseBranch::Block(block) => collect_named_args_from_block(block, functions, type_info, source_len),
-                    wisp_ast::ElseBranch::If(if_expr) => collect_named_args_from_expr(if_expr, functions, type_info, source_len),
+                    wisp_ast::ElseBranch::Block(block) => collect_named_args_from_block(block, functions, type_info, file),
+                    wisp_ast::ElseBranch::If(if_expr) => collect_named_args_from_expr(if_expr, functions, type_info, file),
                 }
             }
         }
-        wisp_ast::ExprKind::While(cond, body) => {
-            collect_named_args_from_expr(cond, functions, type_info, source_len);
-            collect_named_args_from_block(body, functions, type_info, source_len);
+        wisp_ast::ExprKind::While(_, cond, body) => {
+            collect_named_args_from_expr(cond, functions, type_info, file);
+            collect_named_args_from_block(body, functions, type_info, file);
+        }
+        wisp_ast::ExprKind::Loop(_, body) => {
+            collect_named_args_from_block(body, functions, type_info, file);
+        }
+        wisp_ast::ExprKind::Return(Some(value)) | wisp_ast::ExprKind::Break(_, Some(value)) => {
+            collect_named_args_from_expr(value, functions, type_info, file);
         }
-        wisp_ast::ExprKind::Block(block) => {
-            collect_named_args_from_block(block, functions, type_info, source_len);
+        wisp_ast::ExprKind::Block(block) | wisp_ast::ExprKind::TrailingBlock(block) => {
+            collect_named_args_from_block(block, functions, type_info, file);
         }
         wisp_ast::ExprKind::Field(inner, _) => {
-            collect_named_args_from_expr(inner, functions, type_info, source_len);
+            collect_named_args_from_expr(inner, functions, type_info, file);
         }
         wisp_ast::ExprKind::Ident(_) => {
             // Get function name for field access like Type.method
         }
         wisp_ast::ExprKind::Index(inner, index) => {
-            collect_named_args_from_expr(inner, functions, type_info, source_len);
-            collect_named_args_from_expr(index, functions, type_info, source_len);
+            collect_named_args_from_expr(inner, functions, type_info, file);
+            collect_named_args_from_expr(index, functions, type_info, file);
         }
         wisp_ast::ExprKind::Ref(_, inner) => {
-            collect_named_args_from_expr(inner, functions, type_info, source_len);
+            collect_named_args_from_expr(inner, functions, type_info, file);
         }
-        wisp_ast::ExprKind::Deref(inner) => {
-            collect_named_args_from_expr(inner, functions, type_info, source_len);
+        wisp_ast::ExprKind::Deref(inner) | wisp_ast::ExprKind::Try(inner) => {
+            collect_named_args_from_expr(inner, functions, type_info, file);
         }
         wisp_ast::ExprKind::Assign(target, value) => {
-            collect_named_args_from_expr(target, functions, type_info, source_len);
-            collect_named_args_from_expr(value, functions, type_info, source_len);
+            collect_named_args_from_expr(target, functions, type_info, file);
+            collect_named_args_from_expr(value, functions, type_info, file);
         }
-        wisp_ast::ExprKind::StructLit(_, fields) => {
+        wisp_ast::ExprKind::StructLit(_, fields, spread) => {
             for field in fields {
-                collect_named_args_from_expr(&field.value, functions, type_info, source_len);
+                collect_named_args_from_expr(&field.value, functions, type_info, file);
+            }
+            if let Some(spread) = spread {
+                collect_named_args_from_expr(spread, functions, type_info, file);
             }
         }
         _ => {}
@@ -2225,30 +2300,30 @@ fn collect_variable_defs(
     variable_defs: &mut HashMap<String, (usize, usize)>,
     variable_types: &mut HashMap<String, String>,
     ctx: &wisp_types::TypeContext,
-    source_len: usize,
+    file: FileId,
 ) {
     // Collect function parameters and body variables
     for func in &program.functions {
-        if func.span.end > source_len { continue; }
+        if func.span.file != file { continue; }
         for param in &func.params {
             variable_defs.insert(param.name.clone(), (param.span.start, param.span.end));
             variable_types.insert(param.name.clone(), param.ty.display(ctx));
         }
         if let Some(ref body) = func.body {
-            collect_block_variable_defs(body, variable_defs, variable_types, ctx, source_len);
+            collect_block_variable_defs(body, variable_defs, variable_types, ctx, file);
         }
     }
     
     // Collect impl method parameters
     for imp in &program.impls {
         for method in &imp.methods {
-            if method.span.end > source_len { continue; }
+            if method.span.file != file { continue; }
             for param in &method.params {
                 variable_defs.insert(param.name.clone(), (param.span.start, param.span.end));
                 variable_types.insert(param.name.clone(), param.ty.display(ctx));
             }
             if let Some(ref body) = method.body {
-                collect_block_variable_defs(body, variable_defs, variable_types, ctx, source_len);
+                collect_block_variable_defs(body, variable_defs, variable_types, ctx, file);
             }
         }
     }
@@ -2259,21 +2334,24 @@ fn collect_block_variable_defs(
     variable_defs: &mut HashMap<String, (usize, usize)>,
     variable_types: &mut HashMap<String, String>,
     ctx: &wisp_types::TypeContext,
-    source_len: usize,
+    file: FileId,
 ) {
     for stmt in &block.stmts {
         match stmt {
             wisp_types::TypedStmt::Let { name, span, init, ty, .. } => {
-                if span.end <= source_len {
+                if span.file == file {
                     variable_defs.insert(name.clone(), (span.start, span.end));
                     variable_types.insert(name.clone(), ty.display(ctx));
                 }
                 if let Some(init) = init {
-                    collect_expr_variable_defs(init, variable_defs, variable_types, ctx, source_len);
+                    collect_expr_variable_defs(init, variable_defs, variable_types, ctx, file);
                 }
             }
             wisp_types::TypedStmt::Expr(expr) => {
-                collect_expr_variable_defs(expr, variable_defs, variable_types, ctx, source_len);
+                collect_expr_variable_defs(expr, variable_defs, variable_types, ctx, file);
+            }
+            wisp_types::TypedStmt::Defer { body, .. } => {
+                collect_block_variable_defs(body, variable_defs, variable_types, ctx, file);
             }
         }
     }
@@ -2284,25 +2362,26 @@ fn collect_expr_variable_defs(
     variable_defs: &mut HashMap<String, (usize, usize)>,
     variable_types: &mut HashMap<String, String>,
     ctx: &wisp_types::TypeContext,
-    source_len: usize,
+    file: FileId,
 ) {
-    if expr.span.end > source_len { return; }
+    if expr.span.file != file { return; }
     
     match &expr.kind {
         wisp_types::TypedExprKind::Block(block) => {
-            collect_block_variable_defs(block, variable_defs, variable_types, ctx, source_len);
+            collect_block_variable_defs(block, variable_defs, variable_types, ctx, file);
         }
         wisp_types::TypedExprKind::If { then_block, else_block, .. } => {
-            collect_block_variable_defs(then_block, variable_defs, variable_types, ctx, source_len);
+            collect_block_variable_defs(then_block, variable_defs, variable_types, ctx, file);
             if let Some(else_branch) = else_block {
                 match else_branch {
-                    wisp_types::TypedElse::Block(block) => collect_block_variable_defs(block, variable_defs, variable_types, ctx, source_len),
-                    wisp_types::TypedElse::If(if_expr) => collect_expr_variable_defs(if_expr, variable_defs, variable_types, ctx, source_len),
+                    wisp_types::TypedElse::Block(block) => collect_block_variable_defs(block, variable_defs, variable_types, ctx, file),
+                    wisp_types::TypedElse::If(if_expr) => collect_expr_variable_defs(if_expr, variable_defs, variable_types, ctx, file),
                 }
             }
         }
-        wisp_types::TypedExprKind::While { body, .. } => {
-            collect_block_variable_defs(body, variable_defs, variable_types, ctx, source_len);
+        wisp_types::TypedExprKind::While { body, .. }
+        | wisp_types::TypedExprKind::Loop { body, .. } => {
+            collect_block_variable_defs(body, variable_defs, variable_types, ctx, file);
         }
         _ => {}
     }
@@ -2314,11 +2393,11 @@ fn collect_variable_defs_from_resolved(
     resolved: &wisp_hir::ResolvedProgram,
     variable_defs: &mut HashMap<String, (usize, usize)>,
     variable_types: &mut HashMap<String, String>,
-    source_len: usize,
+    file: FileId,
 ) {
     // Collect from functions
     for func in &resolved.functions {
-        if func.span.end > source_len { continue; }
+        if func.span.file != file { continue; }
         
         // Function parameters
         for param in &func.params {
@@ -2328,14 +2407,14 @@ fn collect_variable_defs_from_resolved(
         
         // Function body
         if let Some(ref body) = func.body {
-            collect_resolved_block_vars(body, variable_defs, variable_types, source_len);
+            collect_resolved_block_vars(body, variable_defs, variable_types, file);
         }
     }
     
     // Collect from impl methods
     for imp in &resolved.impls {
         for method in &imp.methods {
-            if method.span.end > source_len { continue; }
+            if method.span.file != file { continue; }
             
             for param in &method.params {
                 variable_defs.insert(param.name.clone(), (param.span.start, param.span.end));
@@ -2343,7 +2422,7 @@ fn collect_variable_defs_from_resolved(
             }
             
             if let Some(ref body) = method.body {
-                collect_resolved_block_vars(body, variable_defs, variable_types, source_len);
+                collect_resolved_block_vars(body, variable_defs, variable_types, file);
             }
         }
     }
@@ -2353,23 +2432,26 @@ fn collect_resolved_block_vars(
     block: &wisp_hir::ResolvedBlock,
     variable_defs: &mut HashMap<String, (usize, usize)>,
     variable_types: &mut HashMap<String, String>,
-    source_len: usize,
+    file: FileId,
 ) {
     for stmt in &block.stmts {
         match stmt {
             wisp_hir::ResolvedStmt::Let { name, span, ty, init, .. } => {
-                if span.end <= source_len {
+                if span.file == file {
                     variable_defs.insert(name.clone(), (span.start, span.end));
                     if let Some(ty) = ty {
                         variable_types.insert(name.clone(), resolved_type_to_string(ty));
                     }
                 }
                 if let Some(init) = init {
-                    collect_resolved_expr_vars(init, variable_defs, variable_types, source_len);
+                    collect_resolved_expr_vars(init, variable_defs, variable_types, file);
                 }
             }
             wisp_hir::ResolvedStmt::Expr(expr) => {
-                collect_resolved_expr_vars(expr, variable_defs, variable_types, source_len);
+                collect_resolved_expr_vars(expr, variable_defs, variable_types, file);
+            }
+            wisp_hir::ResolvedStmt::Defer { body, .. } => {
+                collect_resolved_block_vars(body, variable_defs, variable_types, file);
             }
         }
     }
@@ -2379,29 +2461,30 @@ fn collect_resolved_expr_vars(
     expr: &wisp_hir::ResolvedExpr,
     variable_defs: &mut HashMap<String, (usize, usize)>,
     variable_types: &mut HashMap<String, String>,
-    source_len: usize,
+    file: FileId,
 ) {
-    if expr.span.end > source_len { return; }
+    if expr.span.file != file { return; }
     
     match &expr.kind {
-        wisp_hir::ResolvedExprKind::Block(block) => {
-            collect_resolved_block_vars(block, variable_defs, variable_types, source_len);
+        wisp_hir::ResolvedExprKind::Block(block) | wisp_hir::ResolvedExprKind::TrailingBlock(block) => {
+            collect_resolved_block_vars(block, variable_defs, variable_types, file);
         }
         wisp_hir::ResolvedExprKind::If { then_block, else_block, .. } => {
-            collect_resolved_block_vars(then_block, variable_defs, variable_types, source_len);
+            collect_resolved_block_vars(then_block, variable_defs, variable_types, file);
             if let Some(else_branch) = else_block {
                 match else_branch {
                     wisp_hir::ResolvedElse::Block(block) => {
-                        collect_resolved_block_vars(block, variable_defs, variable_types, source_len);
+                        collect_resolved_block_vars(block, variable_defs, variable_types, file);
                     }
                     wisp_hir::ResolvedElse::If(if_expr) => {
-                        collect_resolved_expr_vars(if_expr, variable_defs, variable_types, source_len);
+                        collect_resolved_expr_vars(if_expr, variable_defs, variable_types, file);
                     }
                 }
             }
         }
-        wisp_hir::ResolvedExprKind::While { body, .. } => {
-            collect_resolved_block_vars(body, variable_defs, variable_types, source_len);
+        wisp_hir::ResolvedExprKind::While { body, .. }
+        | wisp_hir::ResolvedExprKind::Loop { body, .. } => {
+            collect_resolved_block_vars(body, variable_defs, variable_types, file);
         }
         _ => {}
     }
@@ -2427,6 +2510,17 @@ fn resolved_type_to_string(ty: &wisp_hir::ResolvedType) -> String {
         wisp_hir::ResolvedType::Slice { elem } => {
             format!("[{}]", resolved_type_to_string(elem))
         }
+        wisp_hir::ResolvedType::Array { elem, size } => {
+            format!("[{}; {}]", resolved_type_to_string(elem), size)
+        }
+        wisp_hir::ResolvedType::Function { params, ret } => {
+            let params: Vec<_> = params.iter().map(resolved_type_to_string).collect();
+            format!("fn({}) -> {}", params.join(", "), resolved_type_to_string(ret))
+        }
+        wisp_hir::ResolvedType::Tuple(elems) => {
+            let elems: Vec<_> = elems.iter().map(resolved_type_to_string).collect();
+            if elems.len() == 1 { format!("({},)", elems[0]) } else { format!("({})", elems.join(", ")) }
+        }
         wisp_hir::ResolvedType::Unit => "()".to_string(),
         wisp_hir::ResolvedType::SelfType => "Self".to_string(),
         wisp_hir::ResolvedType::Error => "?".to_string(),
@@ -2519,6 +2613,7 @@ fn is_item_public(item: &wisp_ast::Item) -> bool {
         wisp_ast::Item::Trait(t) => t.is_pub,
         wisp_ast::Item::Impl(_) => true, // Impl blocks are always visible if the type is visible
         wisp_ast::Item::Import(_) => false, // Imports are not items in the namespace
+        wisp_ast::Item::Cfg(c) => is_item_public(&c.item),
     }
 }
 
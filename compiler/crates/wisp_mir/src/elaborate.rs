@@ -0,0 +1,416 @@
+//! Drop elaboration
+//!
+//! Lowering emits a `Drop` at every exit from a variable's scope and before
+//! every assignment that overwrites it, plus a `Deinit` wherever the borrow
+//! checker saw the variable or one of its fields moved out of. This pass
+//! works out which of those drops can see a live value: the drop of a struct
+//! with a field moved out is split into drops of its fields, drops of values
+//! that were moved out on every path are removed, and values moved out on
+//! only some paths get a boolean drop flag that is checked at runtime.
+
+use crate::mir::*;
+use std::collections::{HashMap, HashSet};
+use wisp_hir::DefId;
+use wisp_lexer::Span;
+use wisp_types::Type;
+
+/// A local, or a field of one reached through fields only: what can be
+/// dropped or moved out of
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct MovePath {
+    local: u32,
+    fields: Vec<usize>,
+}
+
+impl MovePath {
+    fn of(place: &Place) -> Option<MovePath> {
+        let fields = place.projections.iter()
+            .map(|proj| match proj {
+                PlaceProjection::Field(idx, _) => Some(*idx),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(MovePath { local: place.local, fields })
+    }
+
+    /// Whether `other` is this path or a field of it
+    fn contains(&self, other: &MovePath) -> bool {
+        self.local == other.local && other.fields.starts_with(&self.fields)
+    }
+}
+
+/// Which dropped paths are initialized at a program point
+#[derive(Debug, Clone, PartialEq)]
+struct InitState {
+    /// Initialized on some path to this point
+    maybe_init: Vec<bool>,
+    /// Uninitialized (not yet assigned, moved out or dropped) on some path to this point
+    maybe_uninit: Vec<bool>,
+}
+
+impl InitState {
+    fn set_init(&mut self, idx: usize) {
+        self.maybe_init[idx] = true;
+        self.maybe_uninit[idx] = false;
+    }
+
+    fn set_uninit(&mut self, idx: usize) {
+        self.maybe_init[idx] = false;
+        self.maybe_uninit[idx] = true;
+    }
+
+    /// Merge the state of another path into this one. Returns true if anything changed.
+    fn join(&mut self, other: &InitState) -> bool {
+        let mut changed = false;
+        for (mine, theirs) in self.maybe_init.iter_mut().zip(&other.maybe_init)
+            .chain(self.maybe_uninit.iter_mut().zip(&other.maybe_uninit))
+        {
+            if *theirs && !*mine {
+                *mine = true;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Dropped paths and their index in the dataflow state
+struct Tracked(HashMap<MovePath, usize>);
+
+impl Tracked {
+    /// Indices of the tracked paths that `place` is or contains
+    fn within(&self, place: &Place) -> impl Iterator<Item = usize> + '_ {
+        let path = MovePath::of(place);
+        self.0.iter()
+            .filter(move |(tracked, _)| path.as_ref().is_some_and(|path| path.contains(tracked)))
+            .map(|(_, &idx)| idx)
+    }
+}
+
+/// How a `Drop` terminator is compiled after elaboration
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DropStyle {
+    /// The value is never live here: skip the drop
+    Dead,
+    /// The value is always live here: drop unconditionally
+    Static,
+    /// The value is live on some paths only: drop if the flag is set
+    Conditional,
+}
+
+/// Split drops of partly moved-out structs, remove drops of moved-out values
+/// and guard drops of maybe-moved values with drop flags
+pub fn elaborate_drops<F>(func: &mut MirFunction, structs: &HashMap<DefId, MirStruct>, enums: &HashMap<DefId, MirEnum>, drop_fns: &HashMap<DefId, F>) {
+    split_partial_drops(func, structs, enums, drop_fns);
+
+    // Paths that are dropped somewhere
+    let mut tracked = Tracked(HashMap::new());
+    for block in &func.blocks {
+        if let Terminator::Drop { place, .. } = &block.terminator
+            && let Some(path) = MovePath::of(place)
+        {
+            let next = tracked.0.len();
+            tracked.0.entry(path).or_insert(next);
+        }
+    }
+    if tracked.0.is_empty() {
+        return;
+    }
+
+    // Parameters are initialized on entry; everything else starts uninitialized
+    let mut entry = InitState {
+        maybe_init: vec![false; tracked.0.len()],
+        maybe_uninit: vec![true; tracked.0.len()],
+    };
+    for param in &func.params {
+        for idx in tracked.within(&Place::local(param.id)) {
+            entry.set_init(idx);
+        }
+    }
+
+    let states = block_entry_states(func, &tracked, entry);
+
+    // Decide how to compile each drop
+    let mut styles: HashMap<u32, DropStyle> = HashMap::new();
+    for block in &func.blocks {
+        let Terminator::Drop { place, .. } = &block.terminator else { continue };
+        let idx = tracked.0[&MovePath::of(place).unwrap()];
+        let style = match states.get(block.id as usize).and_then(|s| s.as_ref()) {
+            Some(state) => {
+                let state = transfer_statements(state.clone(), &block.statements, &tracked);
+                match (state.maybe_init[idx], state.maybe_uninit[idx]) {
+                    (false, _) => DropStyle::Dead,
+                    (true, false) => DropStyle::Static,
+                    (true, true) => DropStyle::Conditional,
+                }
+            }
+            // Unreachable block
+            None => DropStyle::Dead,
+        };
+        styles.insert(block.id, style);
+    }
+
+    // Paths with a conditional drop need a flag tracking whether they hold a value
+    let mut next_local = func.params.iter().chain(&func.locals).map(|l| l.id + 1).max().unwrap_or(0);
+    let mut flags: Vec<(Place, u32)> = Vec::new();
+    for block in &func.blocks {
+        if let Terminator::Drop { place, .. } = &block.terminator
+            && styles[&block.id] == DropStyle::Conditional
+            && !flags.iter().any(|(flagged, _)| MovePath::of(flagged) == MovePath::of(place))
+        {
+            let name = func.params.iter().chain(&func.locals)
+                .find(|l| l.id == place.local)
+                .map(|l| l.name.clone())
+                .unwrap_or_default();
+            let fields: String = place.projections.iter()
+                .filter_map(|proj| match proj {
+                    PlaceProjection::Field(_, field) => Some(format!(".{}", field)),
+                    _ => None,
+                })
+                .collect();
+            func.locals.push(MirLocal {
+                id: next_local,
+                name: format!("{}{}$drop_flag", name, fields),
+                ty: Type::Bool,
+                is_arg: false,
+            });
+            flags.push((place.clone(), next_local));
+            next_local += 1;
+        }
+    }
+    let flags_within = |place: &Place| -> Vec<u32> {
+        let Some(path) = MovePath::of(place) else { return Vec::new() };
+        flags.iter()
+            .filter(|(flagged, _)| MovePath::of(flagged).is_some_and(|flagged| path.contains(&flagged)))
+            .map(|&(_, flag)| flag)
+            .collect()
+    };
+
+    // Keep the flags up to date wherever a flagged path is initialized or moved out of
+    for block in &mut func.blocks {
+        let mut statements = Vec::with_capacity(block.statements.len());
+        for stmt in block.statements.drain(..) {
+            let updates = match &stmt.kind {
+                StatementKind::Assign { place, .. } => flags_within(place).into_iter().map(|flag| (flag, true)).collect(),
+                StatementKind::Deinit(place) => flags_within(place).into_iter().map(|flag| (flag, false)).collect(),
+                _ => Vec::new(),
+            };
+            let span = stmt.span;
+            statements.push(stmt);
+            for (flag, value) in updates {
+                statements.push(set_flag(flag, value, span));
+            }
+        }
+        block.statements = statements;
+    }
+    if let Some(entry_block) = func.blocks.first_mut() {
+        let mut initial: Vec<_> = flags.iter()
+            .map(|(place, flag)| set_flag(*flag, func.params.iter().any(|p| p.id == place.local), func.span))
+            .collect();
+        initial.append(&mut entry_block.statements);
+        entry_block.statements = initial;
+    }
+
+    // Rewrite the drops
+    let mut new_blocks = Vec::new();
+    let mut next_block = func.blocks.len() as u32;
+    for block in &mut func.blocks {
+        let Terminator::Drop { place, target } = block.terminator.clone() else { continue };
+        let flag = flags.iter()
+            .find(|(flagged, _)| MovePath::of(flagged) == MovePath::of(&place))
+            .map(|&(_, flag)| flag);
+        match styles[&block.id] {
+            DropStyle::Dead => {
+                block.terminator = Terminator::Goto { target };
+            }
+            DropStyle::Static => {
+                // The value is gone after the drop
+                if let Some(flag) = flag {
+                    block.statements.push(set_flag(flag, false, block.terminator_span));
+                }
+            }
+            DropStyle::Conditional => {
+                let Some(flag) = flag else { continue };
+                let drop_bb = next_block;
+                next_block += 1;
+                new_blocks.push(BasicBlock {
+                    id: drop_bb,
+                    statements: vec![set_flag(flag, false, block.terminator_span)],
+                    terminator: Terminator::Drop { place, target },
+                    terminator_span: block.terminator_span,
+                });
+                block.terminator = Terminator::SwitchInt {
+                    discr: Operand::Copy(Place::local(flag)),
+                    targets: vec![(1, drop_bb)],
+                    otherwise: target,
+                };
+            }
+        }
+    }
+    func.blocks.extend(new_blocks);
+}
+
+/// Replace each drop of a struct that has a field moved out of it somewhere
+/// in the function with drops of its fields, in declaration order, so that
+/// the fields left in it are still dropped. The borrow checker doesn't allow
+/// moving out of a struct with a `drop` method, so there is none to call.
+fn split_partial_drops<F>(func: &mut MirFunction, structs: &HashMap<DefId, MirStruct>, enums: &HashMap<DefId, MirEnum>, drop_fns: &HashMap<DefId, F>) {
+    let moved_fields: HashSet<MovePath> = func.blocks.iter()
+        .flat_map(|block| &block.statements)
+        .filter_map(|stmt| match &stmt.kind {
+            StatementKind::Deinit(place) => MovePath::of(place).filter(|path| !path.fields.is_empty()),
+            _ => None,
+        })
+        .collect();
+    if moved_fields.is_empty() {
+        return;
+    }
+
+    let mut next_block = func.blocks.len() as u32;
+    let mut new_blocks = Vec::new();
+    for block_idx in 0..func.blocks.len() {
+        let Terminator::Drop { place, target } = func.blocks[block_idx].terminator.clone() else { continue };
+        let Some(path) = MovePath::of(&place) else { continue };
+        if !moved_fields.iter().any(|moved| moved != &path && path.contains(moved)) {
+            continue;
+        }
+        let Some(ty) = func.params.iter().chain(&func.locals).find(|l| l.id == place.local).map(|l| l.ty.clone()) else { continue };
+
+        let mut field_drops = Vec::new();
+        split_drop(place, &ty, &moved_fields, structs, enums, drop_fns, &mut field_drops);
+
+        // Chain the field drops, each continuing to the next
+        let span = func.blocks[block_idx].terminator_span;
+        let mut next_target = target;
+        for field_place in field_drops.into_iter().rev() {
+            let id = next_block;
+            next_block += 1;
+            new_blocks.push(BasicBlock {
+                id,
+                statements: Vec::new(),
+                terminator: Terminator::Drop { place: field_place, target: next_target },
+                terminator_span: span,
+            });
+            next_target = id;
+        }
+        func.blocks[block_idx].terminator = Terminator::Goto { target: next_target };
+    }
+    func.blocks.extend(new_blocks);
+}
+
+/// The places dropping `place` comes down to: each of its fields that needs
+/// dropping, itself split if a field of it was moved out
+fn split_drop<F>(
+    place: Place,
+    ty: &Type,
+    moved_fields: &HashSet<MovePath>,
+    structs: &HashMap<DefId, MirStruct>,
+    enums: &HashMap<DefId, MirEnum>,
+    drop_fns: &HashMap<DefId, F>,
+    drops: &mut Vec<Place>,
+) {
+    let path = MovePath::of(&place);
+    let partly_moved = path.is_some_and(|path| moved_fields.iter().any(|moved| moved != &path && path.contains(moved)));
+    let mir_struct = match ty {
+        Type::Struct { def_id, .. } if partly_moved => structs.get(def_id),
+        _ => None,
+    };
+    let Some(mir_struct) = mir_struct else {
+        if needs_drop(ty, drop_fns, structs, enums) {
+            drops.push(place);
+        }
+        return;
+    };
+    let type_args = match ty {
+        Type::Struct { type_args, .. } => type_args.as_slice(),
+        _ => &[],
+    };
+    for (idx, (name, field_ty)) in mir_struct.fields.iter().enumerate() {
+        let field_ty = substitute_type(field_ty, type_args);
+        split_drop(place.clone().field(idx, name.clone()), &field_ty, moved_fields, structs, enums, drop_fns, drops);
+    }
+}
+
+/// Forward dataflow: the initialization state on entry to each block, or
+/// `None` for blocks that can't be reached
+fn block_entry_states(func: &MirFunction, tracked: &Tracked, entry: InitState) -> Vec<Option<InitState>> {
+    let mut states: Vec<Option<InitState>> = vec![None; func.blocks.len()];
+    if states.is_empty() {
+        return states;
+    }
+    states[0] = Some(entry);
+
+    let mut worklist = vec![0usize];
+    while let Some(idx) = worklist.pop() {
+        let Some(state) = states[idx].clone() else { continue };
+        let block = &func.blocks[idx];
+        let mut exit = transfer_statements(state, &block.statements, tracked);
+
+        // The value is gone once it has been dropped
+        if let Terminator::Drop { place, .. } = &block.terminator {
+            for idx in tracked.within(place) {
+                exit.set_uninit(idx);
+            }
+        }
+
+        for succ in successors(&block.terminator) {
+            let Some(succ_state) = states.get_mut(succ as usize) else { continue };
+            let changed = match succ_state {
+                Some(existing) => existing.join(&exit),
+                None => {
+                    *succ_state = Some(exit.clone());
+                    true
+                }
+            };
+            if changed && !worklist.contains(&(succ as usize)) {
+                worklist.push(succ as usize);
+            }
+        }
+    }
+
+    states
+}
+
+/// Apply a block's statements to the state on entry to the block. Assigning
+/// to a local or a field initializes every dropped path within it, and moving
+/// out of one uninitializes them.
+fn transfer_statements(mut state: InitState, statements: &[Statement], tracked: &Tracked) -> InitState {
+    for stmt in statements {
+        match &stmt.kind {
+            StatementKind::Assign { place, .. } => {
+                for idx in tracked.within(place) {
+                    state.set_init(idx);
+                }
+            }
+            StatementKind::Deinit(place) => {
+                for idx in tracked.within(place) {
+                    state.set_uninit(idx);
+                }
+            }
+            _ => {}
+        }
+    }
+    state
+}
+
+fn successors(terminator: &Terminator) -> Vec<u32> {
+    match terminator {
+        Terminator::Goto { target } |
+        Terminator::Call { target, .. } |
+        Terminator::Drop { target, .. } => vec![*target],
+        Terminator::SwitchInt { targets, otherwise, .. } => {
+            targets.iter().map(|(_, bb)| *bb).chain(std::iter::once(*otherwise)).collect()
+        }
+        Terminator::Return | Terminator::Unreachable => Vec::new(),
+    }
+}
+
+fn set_flag(flag: u32, value: bool, span: Span) -> Statement {
+    Statement {
+        kind: StatementKind::Assign {
+            place: Place::local(flag),
+            rvalue: Rvalue::Use(Operand::Constant(Constant::Bool(value))),
+        },
+        span,
+    }
+}
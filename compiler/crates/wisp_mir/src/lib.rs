@@ -8,6 +8,7 @@
 
 mod mir;
 mod lower;
+mod elaborate;
 
 pub use mir::*;
 pub use lower::lower_program;
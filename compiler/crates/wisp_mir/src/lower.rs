@@ -1,9 +1,13 @@
 //! Lower typed HIR to MIR
 
+use crate::elaborate::elaborate_drops;
 use crate::mir::*;
-use wisp_hir::DefId;
-use wisp_types::{Type, TypeContext, TypedBlock, TypedElse, TypedExpr, TypedExprKind, TypedFunction, TypedLambdaParam, TypedPattern, TypedProgram, TypedStmt};
-use std::collections::HashMap;
+use wisp_borrowck::{MoveData, MovePath};
+use wisp_hir::{DefId, LoopId};
+use wisp_lexer::Span;
+use wisp_types::{CaptureMode, Type, TypeContext, TypedBlock, TypedCapture, TypedCollectElem, TypedElse, TypedExpr, TypedForIter, TypedExprKind, TypedFunction, TypedLambdaParam, TypedPattern, TypedProgram, TypedStmt};
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 
 /// Generate a mangled name for a monomorphized generic function
 fn mangle_generic_name(base_name: &str, type_args: &[Type]) -> String {
@@ -11,6 +15,154 @@ fn mangle_generic_name(base_name: &str, type_args: &[Type]) -> String {
     format!("{}<{}>", base_name, type_strs.join(","))
 }
 
+/// A DefId for a function or struct created during lowering (lambdas, shims
+/// and closure environments), derived from its unique name so it doesn't
+/// collide with the resolver's ids
+fn synthetic_def_id(name: &str) -> DefId {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    name.hash(&mut hasher);
+    DefId::new(0x8000_0000 | (hasher.finish() as u32 & 0x7fff_ffff))
+}
+
+/// Whether a closure holds a reference to a captured variable rather than a
+/// copy of its value. Aggregates are always referenced: one moved into a
+/// closure stays in the creating function's frame and is dropped there.
+fn captured_by_ref(capture: &TypedCapture, ty: &Type) -> bool {
+    capture.mode == CaptureMode::MutRef || matches!(ty, Type::Struct { .. } | Type::Enum { .. } | Type::Array(..) | Type::Tuple(..))
+}
+
+/// The anonymous struct a tuple type is laid out as, named after its element types
+fn tuple_struct_id(elems: &[Type]) -> (DefId, String) {
+    let name = format!("({})", elems.iter().map(mangle_type).collect::<Vec<_>>().join(", "));
+    (synthetic_def_id(&name), name)
+}
+
+/// The struct an instance of a generic struct is laid out as, named after its type args
+fn struct_instance_id(name: &str, type_args: &[Type]) -> (DefId, String) {
+    let name = mangle_generic_name(name, type_args);
+    (synthetic_def_id(&name), name)
+}
+
+/// The struct a slice `&[elem]` is laid out as: a pointer to its first
+/// element and its length
+fn slice_struct(elem: &Type) -> (DefId, String, Vec<(String, Type)>) {
+    let name = format!("&[{}]", mangle_type(elem));
+    let fields = vec![
+        ("ptr".to_string(), Type::Ref { is_mut: false, inner: Box::new(Type::Array(Box::new(elem.clone()), 0)) }),
+        ("len".to_string(), Type::I32),
+    ];
+    (synthetic_def_id(&name), name, fields)
+}
+
+/// Lays out tuple types, slices and instances of generic structs as structs
+/// of their own, so that codegen sees the concrete types of their fields
+struct AggregateLayouts<'a> {
+    /// The program's structs, generic ones with their type params
+    templates: &'a HashMap<DefId, MirStruct>,
+    /// Structs created for tuples and instances
+    created: HashMap<DefId, MirStruct>,
+    /// The generic struct and type args of each instance
+    instances: HashMap<DefId, (DefId, Vec<Type>)>,
+    pointer_size: u32,
+}
+
+impl AggregateLayouts<'_> {
+    /// Replace tuple types and generic struct instances with the structs they
+    /// are laid out as. A tuple struct's fields are named "0", "1", ...
+    fn convert(&mut self, ty: &Type) -> Type {
+        match ty {
+            Type::Tuple(elems) => {
+                let (def_id, name) = tuple_struct_id(elems);
+                if !self.created.contains_key(&def_id) {
+                    let fields = elems.iter().enumerate()
+                        .map(|(i, elem)| (i.to_string(), self.convert(elem)))
+                        .collect();
+                    self.created.insert(def_id, MirStruct { def_id, name, fields, pointer_size: self.pointer_size });
+                }
+                Type::Struct { def_id, type_args: Vec::new() }
+            }
+            Type::Struct { def_id, type_args } if !type_args.is_empty() && !type_args.iter().any(has_type_param) => {
+                let Some(template) = self.templates.get(def_id) else {
+                    return ty.clone();
+                };
+                let (instance_id, name) = struct_instance_id(&template.name, type_args);
+                if !self.created.contains_key(&instance_id) {
+                    // Registered before its fields are converted, which may refer back to it
+                    self.created.insert(instance_id, MirStruct { def_id: instance_id, name: name.clone(), fields: Vec::new(), pointer_size: self.pointer_size });
+                    self.instances.insert(instance_id, (*def_id, type_args.clone()));
+                    let fields = template.fields.iter()
+                        .map(|(field, field_ty)| (field.clone(), self.convert(&substitute_type(field_ty, type_args))))
+                        .collect();
+                    self.created.insert(instance_id, MirStruct { def_id: instance_id, name, fields, pointer_size: self.pointer_size });
+                }
+                Type::Struct { def_id: instance_id, type_args: Vec::new() }
+            }
+            Type::Ref { is_mut, inner } => Type::Ref {
+                is_mut: *is_mut,
+                inner: Box::new(self.convert(inner)),
+            },
+            Type::Slice(elem) => {
+                let (def_id, name, fields) = slice_struct(&self.convert(elem));
+                self.created.entry(def_id).or_insert(MirStruct { def_id, name, fields, pointer_size: self.pointer_size });
+                Type::Struct { def_id, type_args: Vec::new() }
+            }
+            Type::Array(elem, size) => Type::Array(Box::new(self.convert(elem)), *size),
+            Type::Function { params, ret } => Type::Function {
+                params: params.iter().map(|p| self.convert(p)).collect(),
+                ret: Box::new(self.convert(ret)),
+            },
+            Type::Struct { def_id, type_args } => Type::Struct {
+                def_id: *def_id,
+                type_args: type_args.iter().map(|t| self.convert(t)).collect(),
+            },
+            Type::Enum { def_id, type_args } => Type::Enum {
+                def_id: *def_id,
+                type_args: type_args.iter().map(|t| self.convert(t)).collect(),
+            },
+            _ => ty.clone(),
+        }
+    }
+}
+
+/// Lay out every tuple type and generic struct instance in the program as a
+/// struct, so that codegen passes and returns them exactly like structs.
+/// Returns the generic struct and type args of each instance.
+fn lower_aggregate_types(mir: &mut MirProgram) -> HashMap<DefId, (DefId, Vec<Type>)> {
+    let templates = mir.structs.clone();
+    let mut layouts = AggregateLayouts {
+        templates: &templates,
+        created: HashMap::new(),
+        instances: HashMap::new(),
+        pointer_size: mir.pointer_size,
+    };
+    for func in &mut mir.functions {
+        func.return_type = layouts.convert(&func.return_type);
+        for local in func.params.iter_mut().chain(func.locals.iter_mut()) {
+            local.ty = layouts.convert(&local.ty);
+        }
+    }
+    for s in mir.structs.values_mut() {
+        for (_, ty) in &mut s.fields {
+            *ty = layouts.convert(ty);
+        }
+    }
+    for e in mir.enums.values_mut() {
+        for (_, _, fields) in &mut e.variants {
+            for ty in fields {
+                *ty = layouts.convert(ty);
+            }
+        }
+    }
+    for ext in &mut mir.extern_functions {
+        ext.return_type = layouts.convert(&ext.return_type);
+        for ty in &mut ext.params {
+            *ty = layouts.convert(ty);
+        }
+    }
+    mir.structs.extend(layouts.created);
+    layouts.instances
+}
+
 /// Check if a type contains any type parameters
 fn has_type_param(ty: &Type) -> bool {
     match ty {
@@ -29,44 +181,6 @@ fn has_type_param(ty: &Type) -> bool {
     }
 }
 
-/// Substitute type parameters with concrete types
-/// Uses index-based matching like Rust's ParamTy - the index is the position
-/// in the generics list, so T at index 0 in `Option<T>` matches T at index 0
-/// in `impl<T> Option<T>`, regardless of DefId.
-fn substitute_type(ty: &Type, type_args: &[Type]) -> Type {
-    match ty {
-        Type::TypeParam { index, .. } => {
-            // Use index-based substitution - the index tells us which type arg to use
-            type_args.get(*index as usize).cloned().unwrap_or(ty.clone())
-        }
-        Type::Ref { is_mut, inner } => Type::Ref {
-            is_mut: *is_mut,
-            inner: Box::new(substitute_type(inner, type_args)),
-        },
-        Type::Slice(elem) => Type::Slice(Box::new(substitute_type(elem, type_args))),
-        Type::Array(elem, size) => Type::Array(
-            Box::new(substitute_type(elem, type_args)),
-            *size,
-        ),
-        Type::Tuple(elems) => Type::Tuple(
-            elems.iter().map(|e| substitute_type(e, type_args)).collect()
-        ),
-        Type::Function { params, ret } => Type::Function {
-            params: params.iter().map(|p| substitute_type(p, type_args)).collect(),
-            ret: Box::new(substitute_type(ret, type_args)),
-        },
-        Type::Enum { def_id, type_args: enum_type_args } => Type::Enum {
-            def_id: *def_id,
-            type_args: enum_type_args.iter().map(|t| substitute_type(t, type_args)).collect(),
-        },
-        Type::Struct { def_id, type_args: struct_type_args } => Type::Struct {
-            def_id: *def_id,
-            type_args: struct_type_args.iter().map(|t| substitute_type(t, type_args)).collect(),
-        },
-        _ => ty.clone(),
-    }
-}
-
 /// Mangle a type into a string suitable for function names
 fn mangle_type(ty: &Type) -> String {
     match ty {
@@ -123,9 +237,11 @@ fn mangle_type(ty: &Type) -> String {
     }
 }
 
-/// Lower a typed program to MIR
-pub fn lower_program(program: &TypedProgram) -> MirProgram {
-    let mut mir = MirProgram::new();
+/// Lower a typed program to MIR, using the borrow checker's move data to
+/// skip dropping values that were moved out. Aggregates are laid out for a
+/// target whose pointers are `pointer_size` bytes.
+pub fn lower_program(program: &TypedProgram, moves: &MoveData, pointer_size: u32) -> MirProgram {
+    let mut mir = MirProgram::new(pointer_size);
 
     // Register structs
     for s in &program.structs {
@@ -136,31 +252,64 @@ pub fn lower_program(program: &TypedProgram) -> MirProgram {
             def_id: s.def_id,
             name: s.name.clone(),
             fields,
+            pointer_size,
         });
     }
     
     // Register enums
     for e in &program.enums {
         let variants: Vec<_> = e.variants.iter().map(|v| {
-            let field_types: Vec<Type> = v.fields.iter().map(|f| {
-                // Get the resolved type for this field
-                program.ctx.get_def_type(f.def_id).cloned().unwrap_or(Type::Unit)
-            }).collect();
+            // The resolved field types are the variant constructor's params
+            let field_types = match program.ctx.get_def_type(v.def_id) {
+                Some(Type::Function { params, .. }) => params.clone(),
+                _ => Vec::new(),
+            };
             (v.name.clone(), v.def_id, field_types)
         }).collect();
         mir.enums.insert(e.def_id, MirEnum {
             def_id: e.def_id,
             name: e.name.clone(),
             variants,
+            pointer_size,
         });
     }
 
+    // Impl methods are named after the type they're defined on
+    let mut method_owners: HashMap<DefId, String> = HashMap::new();
+    for imp in &program.impls {
+        let owner = match &imp.target_type {
+            Type::Struct { def_id, .. } | Type::Enum { def_id, .. } => program.ctx.get_type_name(*def_id),
+            _ => None,
+        };
+        let owner = owner.unwrap_or_else(|| get_type_name(&imp.target_type, &program.ctx));
+        for method in &imp.methods {
+            method_owners.insert(method.def_id, owner.clone());
+        }
+    }
+
+    let context = ProgramContext {
+        structs: &mir.structs,
+        enums: &mir.enums,
+        pointer_size,
+        drop_fns: &program.drop_impls,
+        method_owners: &method_owners,
+        moves,
+    };
+
     // Collect extern statics for lookup during lowering
     let mut extern_statics: HashMap<DefId, (String, Type)> = HashMap::new();
     for ext in &program.extern_statics {
         extern_statics.insert(ext.def_id, (ext.name.clone(), ext.ty.clone()));
     }
 
+    // Environment structs of closures, registered once lowering is done
+    let mut closure_envs = Vec::new();
+    // Instances of generic functions and methods to generate, starting with
+    // the ones the type checker saw called
+    let mut pending: Vec<(DefId, Vec<Type>)> = program.generic_instantiations.iter()
+        .map(|inst| (inst.func_def_id, inst.type_args.clone()))
+        .collect();
+
     // Build a map of generic functions by DefId
     let mut generic_funcs: HashMap<DefId, &TypedFunction> = HashMap::new();
     
@@ -173,25 +322,8 @@ pub fn lower_program(program: &TypedProgram) -> MirProgram {
         if is_generic {
             generic_funcs.insert(func.def_id, func);
         } else {
-            if let Some(result) = lower_function(func, &program.ctx, &extern_statics, None) {
-                mir.functions.push(result.main_function);
-                mir.functions.extend(result.lambda_functions);
-            }
-        }
-    }
-    
-    // Generate monomorphized versions for each instantiation
-    for inst in &program.generic_instantiations {
-        if let Some(func) = generic_funcs.get(&inst.func_def_id) {
-            // Create a specialized version of the function
-            if let Some(result) = lower_monomorphized_function(
-                func, 
-                &inst.type_args,
-                &program.ctx, 
-                &extern_statics
-            ) {
-                mir.functions.push(result.main_function);
-                mir.functions.extend(result.lambda_functions);
+            if let Some(result) = lower_function(func, &program.ctx, &extern_statics, &context, None) {
+                add_lowered(&mut mir.functions, &mut closure_envs, &mut pending, &context, result);
             }
         }
     }
@@ -205,35 +337,38 @@ pub fn lower_program(program: &TypedProgram) -> MirProgram {
         let impl_type_name = get_type_name(&imp.target_type, &program.ctx);
         
         for method in &imp.methods {
-            // Check if this method has type parameters (is generic)
+            // Check if this method has type parameters (is generic), its own or its impl's
             let is_generic = method.params.iter().any(|p| has_type_param(&p.ty)) 
-                || has_type_param(&method.return_type);
+                || has_type_param(&method.return_type)
+                || has_type_param(&imp.target_type);
             
             if is_generic {
                 // Store for potential monomorphization
                 generic_methods.insert(method.def_id, (method, impl_type_name.clone()));
             } else {
-                if let Some(result) = lower_function(method, &program.ctx, &extern_statics, Some(&impl_type_name)) {
-                    mir.functions.push(result.main_function);
-                    mir.functions.extend(result.lambda_functions);
+                if let Some(result) = lower_function(method, &program.ctx, &extern_statics, &context, Some(&impl_type_name)) {
+                    add_lowered(&mut mir.functions, &mut closure_envs, &mut pending, &context, result);
                 }
             }
         }
     }
     
-    // Monomorphize generic impl methods
-    for inst in &program.generic_instantiations {
-        if let Some((method, impl_type_name)) = generic_methods.get(&inst.func_def_id) {
-            if let Some(result) = lower_monomorphized_impl_method(
-                method, 
-                &inst.type_args,
-                impl_type_name,
-                &program.ctx, 
-                &extern_statics
-            ) {
-                mir.functions.push(result.main_function);
-                mir.functions.extend(result.lambda_functions);
-            }
+    // Generate a monomorphized version of each instance. Lowering one can
+    // reach more: generic calls in its body and drops of generic structs.
+    let mut generated: HashSet<(DefId, Vec<Type>)> = HashSet::new();
+    while let Some((def_id, type_args)) = pending.pop() {
+        if type_args.iter().any(has_type_param) || !generated.insert((def_id, type_args.clone())) {
+            continue;
+        }
+        let result = if let Some(func) = generic_funcs.get(&def_id) {
+            lower_monomorphized_function(func, &type_args, None, &program.ctx, &extern_statics, &context)
+        } else if let Some((method, impl_type_name)) = generic_methods.get(&def_id) {
+            lower_monomorphized_function(method, &type_args, Some(impl_type_name), &program.ctx, &extern_statics, &context)
+        } else {
+            None
+        };
+        if let Some(result) = result {
+            add_lowered(&mut mir.functions, &mut closure_envs, &mut pending, &context, result);
         }
     }
     
@@ -256,6 +391,27 @@ pub fn lower_program(program: &TypedProgram) -> MirProgram {
         });
     }
 
+    for env in closure_envs {
+        mir.structs.insert(env.def_id, env);
+    }
+    let instances = lower_aggregate_types(&mut mir);
+
+    for (def_id, drop_fn) in &program.drop_impls {
+        if let Some(func) = mir.functions.iter().find(|f| f.def_id == *drop_fn && !f.name.contains('<')) {
+            mir.drop_fns.insert(*def_id, func.name.clone());
+        }
+    }
+    for (instance_id, (def_id, type_args)) in instances {
+        if let Some(drop_fn) = program.drop_impls.get(&def_id) {
+            mir.drop_fns.insert(instance_id, mono_name(&method_owners, *drop_fn, "drop", &type_args));
+        }
+    }
+
+    // Resolve drops of values that may have been moved out
+    for func in &mut mir.functions {
+        elaborate_drops(func, &mir.structs, &mir.enums, &mir.drop_fns);
+    }
+
     mir
 }
 
@@ -301,29 +457,98 @@ fn get_type_name(ty: &Type, ctx: &TypeContext) -> String {
 struct LowerResult {
     main_function: MirFunction,
     lambda_functions: Vec<MirFunction>,
+    closure_envs: Vec<MirStruct>,
+    /// Instances of generic functions the function calls
+    instances: Vec<(DefId, Vec<Type>)>,
+}
+
+/// Add a lowered function and the closures it created to the program.
+/// Shims for functions used as values are generated at each use, so only the
+/// first copy is kept. The generic instances it uses are added to `pending`.
+fn add_lowered(
+    functions: &mut Vec<MirFunction>,
+    closure_envs: &mut Vec<MirStruct>,
+    pending: &mut Vec<(DefId, Vec<Type>)>,
+    context: &ProgramContext,
+    result: LowerResult,
+) {
+    pending.extend(result.instances);
+    for local in result.main_function.locals.iter().chain(&result.main_function.params) {
+        collect_drop_instances(&local.ty, context, pending);
+    }
+    functions.push(result.main_function);
+    for lambda in result.lambda_functions {
+        if !functions.iter().any(|f| f.name == lambda.name) {
+            for local in lambda.locals.iter().chain(&lambda.params) {
+                collect_drop_instances(&local.ty, context, pending);
+            }
+            functions.push(lambda);
+        }
+    }
+    closure_envs.extend(result.closure_envs);
+}
+
+/// The `drop` methods of generic structs that dropping a value of this type
+/// runs, with the type args to instantiate them with
+fn collect_drop_instances(ty: &Type, context: &ProgramContext, out: &mut Vec<(DefId, Vec<Type>)>) {
+    match ty {
+        Type::Struct { def_id, type_args } => {
+            if !type_args.is_empty()
+                && let Some(&drop_fn) = context.drop_fns.get(def_id)
+            {
+                out.push((drop_fn, type_args.clone()));
+            }
+            if let Some(s) = context.structs.get(def_id) {
+                for (_, field_ty) in &s.fields {
+                    collect_drop_instances(&substitute_type(field_ty, type_args), context, out);
+                }
+            }
+        }
+        Type::Enum { def_id, type_args } => {
+            if let Some(e) = context.enums.get(def_id) {
+                for field_ty in e.variants.iter().flat_map(|(_, _, fields)| fields) {
+                    collect_drop_instances(&substitute_type(field_ty, type_args), context, out);
+                }
+            }
+        }
+        Type::Array(elem, _) => collect_drop_instances(elem, context, out),
+        Type::Tuple(elems) => {
+            for elem in elems {
+                collect_drop_instances(elem, context, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The symbol name of a monomorphized function. Impl methods are qualified
+/// with their type, so `Vec::push<i32>` doesn't collide with another type's `push`.
+fn mono_name(method_owners: &HashMap<DefId, String>, def_id: DefId, name: &str, type_args: &[Type]) -> String {
+    let mangled = mangle_generic_name(name, type_args);
+    match method_owners.get(&def_id) {
+        Some(owner) => format!("{}::{}", owner, mangled),
+        None => mangled,
+    }
 }
 
 /// Lower a single function to MIR
 /// If `impl_type_name` is provided, the function name will be mangled as `TypeName::method_name`
-fn lower_function(func: &TypedFunction, ctx: &TypeContext, extern_statics: &HashMap<DefId, (String, Type)>, impl_type_name: Option<&str>) -> Option<LowerResult> {
+fn lower_function(func: &TypedFunction, ctx: &TypeContext, extern_statics: &HashMap<DefId, (String, Type)>, context: &ProgramContext, impl_type_name: Option<&str>) -> Option<LowerResult> {
     let body = func.body.as_ref()?;
 
-    let mut lowerer = FunctionLowerer::new(func, ctx, extern_statics, impl_type_name, None);
+    let mut lowerer = FunctionLowerer::new(func, ctx, extern_statics, context, impl_type_name, None);
     lowerer.lower_body(body);
-
-    let lambda_functions = std::mem::take(&mut lowerer.lambda_functions);
-    Some(LowerResult {
-        main_function: lowerer.finish(),
-        lambda_functions,
-    })
+    Some(lowerer.into_result())
 }
 
-/// Lower a monomorphized version of a generic function
+/// Lower a monomorphized version of a generic function or impl method
 fn lower_monomorphized_function(
     func: &TypedFunction, 
     type_args: &[Type],
+    impl_type_name: Option<&str>,
     ctx: &TypeContext, 
-    extern_statics: &HashMap<DefId, (String, Type)>
+    extern_statics: &HashMap<DefId, (String, Type)>,
+    context: &ProgramContext,
 ) -> Option<LowerResult> {
     let body = func.body.as_ref()?;
 
@@ -332,39 +557,9 @@ fn lower_monomorphized_function(
         type_args: type_args.to_vec(),
     };
 
-    let mut lowerer = FunctionLowerer::new(func, ctx, extern_statics, None, Some(subst));
+    let mut lowerer = FunctionLowerer::new(func, ctx, extern_statics, context, impl_type_name, Some(subst));
     lowerer.lower_body(body);
-
-    let lambda_functions = std::mem::take(&mut lowerer.lambda_functions);
-    Some(LowerResult {
-        main_function: lowerer.finish(),
-        lambda_functions,
-    })
-}
-
-/// Lower a monomorphized version of a generic impl method
-fn lower_monomorphized_impl_method(
-    method: &TypedFunction, 
-    type_args: &[Type],
-    impl_type_name: &str,
-    ctx: &TypeContext, 
-    extern_statics: &HashMap<DefId, (String, Type)>
-) -> Option<LowerResult> {
-    let body = method.body.as_ref()?;
-
-    // Create substitution info (just the concrete type args - index-based matching handles the rest)
-    let subst = TypeSubstitution {
-        type_args: type_args.to_vec(),
-    };
-
-    let mut lowerer = FunctionLowerer::new(method, ctx, extern_statics, Some(impl_type_name), Some(subst));
-    lowerer.lower_body(body);
-
-    let lambda_functions = std::mem::take(&mut lowerer.lambda_functions);
-    Some(LowerResult {
-        main_function: lowerer.finish(),
-        lambda_functions,
-    })
+    Some(lowerer.into_result())
 }
 
 /// Type substitution info for monomorphization
@@ -374,6 +569,50 @@ struct TypeSubstitution {
     type_args: Vec<Type>,
 }
 
+/// What lowering needs to know about the whole program: type layouts,
+/// drops, and the names of impl methods
+struct ProgramContext<'a> {
+    structs: &'a HashMap<DefId, MirStruct>,
+    enums: &'a HashMap<DefId, MirEnum>,
+    pointer_size: u32,
+    /// Types that implement Drop, mapped to their `drop` method
+    drop_fns: &'a HashMap<DefId, DefId>,
+    /// Impl methods, mapped to the name of the type they're defined on
+    method_owners: &'a HashMap<DefId, String>,
+    /// Moves out of variables, from the borrow checker
+    moves: &'a MoveData,
+}
+
+/// Work to do when control leaves a scope
+#[derive(Clone)]
+enum ScopeExit {
+    /// Run a `defer` block
+    Defer(TypedBlock),
+    /// Drop the value owned by a local
+    Drop(u32),
+}
+
+/// Jump targets of an enclosing loop
+struct LoopTarget {
+    id: LoopId,
+    /// Block that `continue` jumps to
+    continue_bb: u32,
+    /// Block that `break` jumps to
+    break_bb: u32,
+    /// Local receiving `break` values (only for `loop`)
+    result: Option<u32>,
+    /// Number of scopes outside the loop
+    scope_depth: usize,
+}
+
+/// Where the items of a list or map literal go
+enum CollectSink {
+    /// Consecutive slots of an array local
+    Array(u32),
+    /// `extend_one` calls on a collection local
+    Collection { local: u32, ty: Type, extend_one: Operand },
+}
+
 /// State for lowering a single function
 struct FunctionLowerer<'a> {
     func: &'a TypedFunction,
@@ -384,6 +623,8 @@ struct FunctionLowerer<'a> {
     impl_type_name: Option<String>,
     /// Optional type substitution for monomorphization
     type_subst: Option<TypeSubstitution>,
+    /// Layout, drop and naming information for the whole program
+    program: &'a ProgramContext<'a>,
     
     /// All locals (including params and temporaries)
     locals: Vec<MirLocal>,
@@ -398,14 +639,28 @@ struct FunctionLowerer<'a> {
     current_block: u32,
     /// Statements for current block
     current_stmts: Vec<Statement>,
+    /// Source of the statement or expression being lowered
+    span: Span,
     
     /// Return place (local 0)
     return_place: u32,
+    /// Enclosing loops, innermost last
+    loop_stack: Vec<LoopTarget>,
+    /// `defer` blocks and drops of each enclosing scope, innermost last
+    scope_exits: Vec<Vec<ScopeExit>>,
+    
+    /// Variables the lambda being lowered captures by reference, mapped to
+    /// the local holding the reference
+    captured_refs: HashMap<DefId, u32>,
     
     /// Lambda functions generated during lowering
     lambda_functions: Vec<MirFunction>,
+    /// Environment structs of the closures created during lowering
+    closure_envs: Vec<MirStruct>,
     /// Counter for generating unique lambda names
     lambda_counter: u32,
+    /// Instances of generic functions called, to be monomorphized
+    instances: Vec<(DefId, Vec<Type>)>,
 }
 
 impl<'a> FunctionLowerer<'a> {
@@ -413,6 +668,7 @@ impl<'a> FunctionLowerer<'a> {
         func: &'a TypedFunction, 
         ctx: &'a TypeContext, 
         extern_statics: &'a HashMap<DefId, (String, Type)>, 
+        program: &'a ProgramContext<'a>,
         impl_type_name: Option<&str>,
         type_subst: Option<TypeSubstitution>,
     ) -> Self {
@@ -429,15 +685,22 @@ impl<'a> FunctionLowerer<'a> {
             extern_statics,
             impl_type_name: impl_type_name.map(|s| s.to_string()),
             type_subst,
+            program,
             locals: Vec::new(),
             def_to_local: HashMap::new(),
             next_local: 0,
             blocks: Vec::new(),
             current_block: 0,
             current_stmts: Vec::new(),
+            span: func.span,
             return_place: 0,
+            loop_stack: Vec::new(),
+            scope_exits: Vec::new(),
+            captured_refs: HashMap::new(),
             lambda_functions: Vec::new(),
+            closure_envs: Vec::new(),
             lambda_counter: 0,
+            instances: Vec::new(),
         };
 
         // Local 0 is the return place
@@ -459,6 +722,7 @@ impl<'a> FunctionLowerer<'a> {
     fn new_local(&mut self, name: String, ty: Type, is_arg: bool) -> u32 {
         let id = self.next_local;
         self.next_local += 1;
+        let ty = self.subst_type(&ty);
         self.locals.push(MirLocal { id, name, ty, is_arg });
         id
     }
@@ -483,12 +747,13 @@ impl<'a> FunctionLowerer<'a> {
             id,
             statements: Vec::new(),
             terminator: Terminator::Unreachable, // Placeholder
+            terminator_span: self.span,
         });
         id
     }
 
     fn push_stmt(&mut self, kind: StatementKind) {
-        self.current_stmts.push(Statement { kind });
+        self.current_stmts.push(Statement { kind, span: self.span });
     }
 
     fn assign(&mut self, place: Place, rvalue: Rvalue) {
@@ -503,10 +768,12 @@ impl<'a> FunctionLowerer<'a> {
                 id: self.current_block,
                 statements: std::mem::take(&mut self.current_stmts),
                 terminator,
+                terminator_span: self.span,
             });
         } else {
             self.blocks[block_id].statements = std::mem::take(&mut self.current_stmts);
             self.blocks[block_id].terminator = terminator;
+            self.blocks[block_id].terminator_span = self.span;
         }
     }
 
@@ -515,34 +782,114 @@ impl<'a> FunctionLowerer<'a> {
         self.current_stmts.clear();
     }
 
+    /// Terminate the current block and continue lowering in a fresh block
+    /// with no predecessors (code after `return`, `break` or `continue`)
+    fn terminate_diverging(&mut self, terminator: Terminator) -> Operand {
+        self.terminate(terminator);
+        let dead_bb = self.new_block();
+        self.switch_to_block(dead_bb);
+        Operand::Constant(Constant::Unit)
+    }
+
+    fn loop_target(&self, id: LoopId) -> &LoopTarget {
+        self.loop_stack.iter().rev()
+            .find(|l| l.id == id)
+            .expect("break/continue target should be an enclosing loop")
+    }
+
+    /// Run the `defer` blocks and drops of every scope above `depth`, last declared first
+    fn emit_scope_exits(&mut self, depth: usize) {
+        let exits: Vec<ScopeExit> = self.scope_exits[depth..].iter().flatten().cloned().collect();
+        for exit in exits.iter().rev() {
+            match exit {
+                ScopeExit::Defer(body) => {
+                    self.lower_block(body);
+                }
+                ScopeExit::Drop(local) => self.emit_drop(*local),
+            }
+        }
+    }
+
+    /// Drop the value in a local and continue in a new block. Drop
+    /// elaboration later removes drops of values that were moved out.
+    fn emit_drop(&mut self, local: u32) {
+        let cont_block = self.new_block();
+        self.terminate(Terminator::Drop { place: Place::local(local), target: cont_block });
+        self.switch_to_block(cont_block);
+    }
+
+    /// Whether a local's value needs dropping when it goes out of scope
+    fn local_needs_drop(&self, local: u32) -> bool {
+        needs_drop(&self.locals[local as usize].ty, self.program.drop_fns, self.program.structs, self.program.enums)
+    }
+
+    /// Mark the local or field `expr` moves out of, if it moves out of one
+    fn note_move(&mut self, expr: &TypedExpr) {
+        if let Some(path) = MovePath::of(expr)
+            && self.program.moves.is_move(self.func.def_id, expr.span, &path)
+            && self.def_to_local.contains_key(&path.var)
+            && let Some(place) = self.expr_to_place(expr)
+        {
+            self.push_stmt(StatementKind::Deinit(place));
+        }
+    }
+
     fn lower_body(&mut self, body: &TypedBlock) {
         // Create the entry block
         self.new_block();
         self.switch_to_block(0);
 
+        // Parameters are owned by the function and dropped when it returns
+        let param_drops = self.locals.iter()
+            .filter(|l| l.is_arg && self.local_needs_drop(l.id))
+            .map(|l| ScopeExit::Drop(l.id))
+            .collect();
+        self.scope_exits.push(param_drops);
+
         // Lower the body
         let result = self.lower_block(body);
 
-        // Assign result to return place and return
+        // Assign result to return place and return, at the closing brace of
+        // the function
+        self.span = self.func.span.with_start(self.func.span.end.saturating_sub(1));
         if let Some(result) = result {
             self.assign(Place::local(self.return_place), Rvalue::Use(result));
         }
+        self.emit_scope_exits(0);
+        self.scope_exits.pop();
         self.terminate(Terminator::Return);
     }
 
     fn lower_block(&mut self, block: &TypedBlock) -> Option<Operand> {
         let mut last_value = None;
+        self.scope_exits.push(Vec::new());
 
         for stmt in &block.stmts {
             last_value = self.lower_stmt(stmt);
         }
 
+        // Run this scope's `defer` blocks and drops on fallthrough, after the block's value is computed
+        let depth = self.scope_exits.len() - 1;
+        if !self.scope_exits[depth].is_empty() {
+            if let Some(value) = last_value.take()
+                && !matches!(block.ty, Type::Unit | Type::Never)
+            {
+                let temp = self.new_temp(block.ty.clone());
+                self.assign(Place::local(temp), Rvalue::Use(value));
+                last_value = Some(Operand::Move(Place::local(temp)));
+            }
+            self.emit_scope_exits(depth);
+        }
+        self.scope_exits.pop();
+
         last_value
     }
 
     fn lower_stmt(&mut self, stmt: &TypedStmt) -> Option<Operand> {
         match stmt {
-            TypedStmt::Let { def_id, name, ty, init, .. } => {
+            TypedStmt::Let { def_id, name, ty, init, span, .. } => {
+                let outer_span = std::mem::replace(&mut self.span, *span);
+
                 let local = self.new_local(name.clone(), ty.clone(), false);
                 self.def_to_local.insert(*def_id, local);
 
@@ -551,15 +898,37 @@ impl<'a> FunctionLowerer<'a> {
                     self.assign(Place::local(local), Rvalue::Use(init_val));
                 }
 
+                // Dropped when the enclosing scope exits
+                if self.local_needs_drop(local)
+                    && let Some(scope) = self.scope_exits.last_mut()
+                {
+                    scope.push(ScopeExit::Drop(local));
+                }
+
+                self.span = outer_span;
                 None
             }
             TypedStmt::Expr(expr) => {
                 Some(self.lower_expr(expr))
             }
+            TypedStmt::Defer { body, .. } => {
+                // Lowered at each exit from the enclosing scope
+                if let Some(scope) = self.scope_exits.last_mut() {
+                    scope.push(ScopeExit::Defer(body.clone()));
+                }
+                None
+            }
         }
     }
 
     fn lower_expr(&mut self, expr: &TypedExpr) -> Operand {
+        let outer_span = std::mem::replace(&mut self.span, expr.span);
+        let operand = self.lower_expr_kind(expr);
+        self.span = outer_span;
+        operand
+    }
+
+    fn lower_expr_kind(&mut self, expr: &TypedExpr) -> Operand {
         match &expr.kind {
             TypedExprKind::IntLiteral(n) => {
                 Operand::Constant(Constant::Int(*n, expr.ty.clone()))
@@ -576,19 +945,41 @@ impl<'a> FunctionLowerer<'a> {
 
             TypedExprKind::Var { def_id, .. } => {
                 if let Some(&local) = self.def_to_local.get(def_id) {
+                    self.note_move(expr);
                     // Check if type is Copy
                     if self.is_copy_type(&expr.ty) {
                         Operand::Copy(Place::local(local))
                     } else {
                         Operand::Move(Place::local(local))
                     }
+                } else if let Some(&reference) = self.captured_refs.get(def_id) {
+                    // Captured by reference: reached through the closure's environment
+                    let place = Place::local(reference).deref();
+                    if self.is_copy_type(&expr.ty) {
+                        Operand::Copy(place)
+                    } else {
+                        Operand::Move(place)
+                    }
+                } else if let Some((enum_def_id, variant_idx)) = self.ctx.is_enum_variant(*def_id)
+                    && matches!(expr.ty, Type::Enum { .. })
+                {
+                    // Unit enum variant (e.g. `None`)
+                    let temp = self.new_temp(expr.ty.clone());
+                    self.assign(
+                        Place::local(temp),
+                        Rvalue::Aggregate {
+                            kind: AggregateKind::Enum(enum_def_id, variant_idx, *def_id),
+                            operands: Vec::new(),
+                        }
+                    );
+                    Operand::Copy(Place::local(temp))
                 } else if let Some((name, ty)) = self.extern_statics.get(def_id) {
                     // Extern static reference
                     Operand::Constant(Constant::ExternStatic(*def_id, name.clone(), ty.clone()))
                 } else {
-                    // Might be a function reference
+                    // Might be a function used as a value
                     if let Some(name) = self.ctx.get_type_name(*def_id) {
-                        Operand::Constant(Constant::FnPtr(*def_id, name))
+                        self.function_value(*def_id, name, &expr.ty)
                     } else {
                         Operand::Constant(Constant::Unit)
                     }
@@ -705,7 +1096,11 @@ impl<'a> FunctionLowerer<'a> {
                     }
                 }
                 
-                let func_op = self.lower_expr(callee);
+                // Named functions are called directly, anything else through a closure
+                let func_op = match self.function_item(callee) {
+                    Some(func_op) => func_op,
+                    None => self.lower_expr(callee),
+                };
                 let arg_ops: Vec<_> = args.iter().map(|a| self.lower_expr(a)).collect();
 
                 let temp = self.new_temp(expr.ty.clone());
@@ -739,10 +1134,6 @@ impl<'a> FunctionLowerer<'a> {
                     return Operand::Copy(Place::local(temp));
                 }
                 
-                // Generate a mangled name for the monomorphized function
-                let base_name = self.ctx.get_type_name(*func_def_id).unwrap_or_default();
-                let mangled_name = mangle_generic_name(&base_name, type_args);
-                
                 let arg_ops: Vec<_> = args.iter().map(|a| self.lower_expr(a)).collect();
 
                 let temp = self.new_temp(expr.ty.clone());
@@ -750,8 +1141,8 @@ impl<'a> FunctionLowerer<'a> {
                 // Create continuation block
                 let cont_block = self.new_block();
                 
-                // Use a special FnPtr constant with the mangled name
-                let func_op = Operand::Constant(Constant::MonomorphizedFn(*func_def_id, mangled_name, type_args.clone()));
+                // Call the monomorphized function
+                let func_op = self.function_operand(*func_def_id, type_args);
                 
                 self.terminate(Terminator::Call {
                     func: func_op,
@@ -764,7 +1155,7 @@ impl<'a> FunctionLowerer<'a> {
                 Operand::Copy(Place::local(temp))
             }
             
-            TypedExprKind::MethodCall { receiver, method_def_id, is_mut_self, args, .. } => {
+            TypedExprKind::MethodCall { receiver, method_def_id, is_mut_self, args, method_type_args, .. } => {
                 // Lower the receiver
                 let receiver_op = self.lower_expr(receiver);
                 
@@ -780,7 +1171,10 @@ impl<'a> FunctionLowerer<'a> {
                     .unwrap_or(false);
                 
                 // Create receiver argument based on method signature
-                let receiver_arg = if takes_ref_self {
+                let receiver_arg = if takes_ref_self && matches!(receiver.ty, Type::Ref { .. }) {
+                    // Already a reference (e.g. `self` in another method), pass it on
+                    receiver_op
+                } else if takes_ref_self {
                     // Method takes &self or &mut self - create a reference
                     match receiver_op {
                         Operand::Copy(place) | Operand::Move(place) => {
@@ -820,20 +1214,18 @@ impl<'a> FunctionLowerer<'a> {
                 let mut arg_ops: Vec<_> = vec![receiver_arg];
                 arg_ops.extend(args.iter().map(|a| self.lower_expr(a)));
                 
-                // Get method name for the function reference
-                let method_name = self.ctx.get_type_name(*method_def_id).unwrap_or_default();
-                
-                // Check if receiver has type arguments - if so, we need a monomorphized function call
-                let func_op = match &receiver.ty {
-                    Type::Enum { type_args, .. } | Type::Struct { type_args, .. } if !type_args.is_empty() => {
-                        // This is a generic type instantiation - use monomorphized function
-                        let mangled_name = mangle_generic_name(&method_name, type_args);
-                        Operand::Constant(Constant::MonomorphizedFn(*method_def_id, mangled_name, type_args.clone()))
-                    }
-                    _ => {
-                        Operand::Constant(Constant::FnPtr(*method_def_id, method_name))
-                    }
+                // Check if receiver or method has type arguments - if so, we need a monomorphized function call.
+                // The method's own type args come after the receiver's, matching their type param indices.
+                let receiver_ty = match &receiver.ty {
+                    Type::Ref { inner, .. } => inner.as_ref(),
+                    ty => ty,
                 };
+                let mut type_args = match receiver_ty {
+                    Type::Enum { type_args, .. } | Type::Struct { type_args, .. } => type_args.clone(),
+                    _ => Vec::new(),
+                };
+                type_args.extend(method_type_args.iter().cloned());
+                let func_op = self.function_operand(*method_def_id, &type_args);
 
                 let temp = self.new_temp(expr.ty.clone());
                 
@@ -851,13 +1243,12 @@ impl<'a> FunctionLowerer<'a> {
                 Operand::Copy(Place::local(temp))
             }
             
-            TypedExprKind::AssociatedFunctionCall { function_def_id, args, .. } => {
+            TypedExprKind::AssociatedFunctionCall { function_def_id, type_args, args, .. } => {
                 // Lower all arguments (no receiver/self for associated functions)
                 let arg_ops: Vec<_> = args.iter().map(|a| self.lower_expr(a)).collect();
                 
-                // Get function name
-                let fn_name = self.ctx.get_type_name(*function_def_id).unwrap_or_default();
-                let func_op = Operand::Constant(Constant::FnPtr(*function_def_id, fn_name));
+                // Functions of generic impls are monomorphized with the impl's type args
+                let func_op = self.function_operand(*function_def_id, type_args);
                 
                 let temp = self.new_temp(expr.ty.clone());
                 
@@ -1015,7 +1406,7 @@ impl<'a> FunctionLowerer<'a> {
             }
 
             TypedExprKind::Field { expr: base, field, .. } => {
-                let base_op = self.lower_expr(base);
+                let mut base_op = self.lower_expr(base);
                 
                 // Get field index
                 let field_idx = self.get_field_index(&base.ty, field).unwrap_or(0);
@@ -1023,8 +1414,19 @@ impl<'a> FunctionLowerer<'a> {
                 // Create a temp for the field access
                 let temp = self.new_temp(expr.ty.clone());
                 
+                // A field of a field (`pair.0.1`) is read from a copy of the inner aggregate
+                if let Operand::Copy(place) | Operand::Move(place) = &base_op
+                    && place.projections.iter().any(|p| matches!(p, PlaceProjection::Field(..)))
+                    && matches!(base.ty, Type::Struct { .. } | Type::Tuple(_))
+                {
+                    let inner = self.new_temp(base.ty.clone());
+                    self.assign(Place::local(inner), Rvalue::Use(base_op));
+                    base_op = Operand::Move(Place::local(inner));
+                }
+                
                 // If base is a place, we can do a field projection
                 if let Operand::Copy(place) | Operand::Move(place) = base_op {
+                    self.note_move(expr);
                     let field_place = place.field(field_idx, field.clone());
                     if self.is_copy_type(&expr.ty) {
                         return Operand::Copy(field_place);
@@ -1038,17 +1440,47 @@ impl<'a> FunctionLowerer<'a> {
                 Operand::Copy(Place::local(temp).field(field_idx, field.clone()))
             }
 
-            TypedExprKind::StructLit { struct_def, fields } => {
-                let operands: Vec<_> = fields.iter()
-                    .map(|(_, e)| self.lower_expr(e))
+            TypedExprKind::StructLit { struct_def, fields, base } => {
+                let mut values: Vec<(String, Operand)> = fields.iter()
+                    .map(|(name, e)| (name.clone(), self.lower_expr(e)))
                     .collect();
+                let struct_fields = self.ctx.get_struct_fields(*struct_def).unwrap_or_default().to_vec();
+                
+                // The fields not listed are moved or copied out of the base
+                if let Some(base) = base {
+                    let base_op = self.lower_expr(base);
+                    let base_local = self.new_temp(base.ty.clone());
+                    self.assign(Place::local(base_local), Rvalue::Use(base_op));
+                    for (i, (name, ty)) in struct_fields.iter().enumerate() {
+                        if values.iter().any(|(n, _)| n == name) {
+                            continue;
+                        }
+                        let place = Place::local(base_local).field(i, name.clone());
+                        let operand = if self.is_copy_type(ty) { Operand::Copy(place) } else { Operand::Move(place) };
+                        values.push((name.clone(), operand));
+                    }
+                }
                 
-                let name = self.ctx.get_type_name(*struct_def).unwrap_or_default();
+                // Operands follow the struct's field order, not the literal's
+                let operands: Vec<_> = struct_fields.iter()
+                    .filter_map(|(name, _)| values.iter().position(|(n, _)| n == name))
+                    .map(|i| values[i].1.clone())
+                    .collect();
+                
+                // An instance of a generic struct is built as the struct it's laid out as
+                let mut name = self.ctx.get_type_name(*struct_def).unwrap_or_default();
+                let mut def_id = *struct_def;
+                if let Type::Struct { type_args, .. } = self.subst_type(&expr.ty)
+                    && !type_args.is_empty()
+                    && !type_args.iter().any(has_type_param)
+                {
+                    (def_id, name) = struct_instance_id(&name, &type_args);
+                }
                 let temp = self.new_temp(expr.ty.clone());
                 self.assign(
                     Place::local(temp),
                     Rvalue::Aggregate {
-                        kind: AggregateKind::Struct(*struct_def, name),
+                        kind: AggregateKind::Struct(def_id, name),
                         operands,
                     }
                 );
@@ -1095,7 +1527,7 @@ impl<'a> FunctionLowerer<'a> {
                 Operand::Copy(Place::local(result))
             }
 
-            TypedExprKind::While { cond, body } => {
+            TypedExprKind::While { loop_id, cond, body } => {
                 let cond_bb = self.new_block();
                 let body_bb = self.new_block();
                 let exit_bb = self.new_block();
@@ -1114,7 +1546,9 @@ impl<'a> FunctionLowerer<'a> {
 
                 // Body block
                 self.switch_to_block(body_bb);
+                self.loop_stack.push(LoopTarget { id: *loop_id, continue_bb: cond_bb, break_bb: exit_bb, result: None, scope_depth: self.scope_exits.len() });
                 self.lower_block(body);
+                self.loop_stack.pop();
                 self.terminate(Terminator::Goto { target: cond_bb });
 
                 // Exit block
@@ -1122,61 +1556,59 @@ impl<'a> FunctionLowerer<'a> {
                 Operand::Constant(Constant::Unit)
             }
 
-            TypedExprKind::For { binding, start, end, body, .. } => {
-                // Lower: for i in start..end { body }
-                // To:    let i = start; while i < end { body; i = i + 1; }
-                
-                let cond_bb = self.new_block();
+            TypedExprKind::Loop { loop_id, body } => {
                 let body_bb = self.new_block();
                 let exit_bb = self.new_block();
+                let result = self.new_temp(expr.ty.clone());
                 
-                // Initialize loop variable
-                let loop_var = self.new_temp(Type::I32);
-                self.def_to_local.insert(*binding, loop_var);
-                let start_op = self.lower_expr(start);
-                self.assign(Place::local(loop_var), Rvalue::Use(start_op));
-                
-                // Jump to condition check
-                self.terminate(Terminator::Goto { target: cond_bb });
-                
-                // Condition block: i < end
-                self.switch_to_block(cond_bb);
-                let end_op = self.lower_expr(end);
-                let cond_temp = self.new_temp(Type::Bool);
-                self.assign(
-                    Place::local(cond_temp),
-                    Rvalue::BinaryOp {
-                        op: BinOp::Lt,
-                        left: Operand::Copy(Place::local(loop_var)),
-                        right: end_op,
-                    },
-                );
-                self.terminate(Terminator::SwitchInt {
-                    discr: Operand::Copy(Place::local(cond_temp)),
-                    targets: vec![(1, body_bb)],
-                    otherwise: exit_bb,
-                });
+                self.terminate(Terminator::Goto { target: body_bb });
                 
-                // Body block
+                // Body block loops back to itself; only `break` reaches the exit
                 self.switch_to_block(body_bb);
+                self.loop_stack.push(LoopTarget { id: *loop_id, continue_bb: body_bb, break_bb: exit_bb, result: Some(result), scope_depth: self.scope_exits.len() });
                 self.lower_block(body);
+                self.loop_stack.pop();
+                self.terminate(Terminator::Goto { target: body_bb });
                 
-                // Increment: i = i + 1
-                let inc_temp = self.new_temp(Type::I32);
-                self.assign(
-                    Place::local(inc_temp),
-                    Rvalue::BinaryOp {
-                        op: BinOp::Add,
-                        left: Operand::Copy(Place::local(loop_var)),
-                        right: Operand::Constant(Constant::Int(1, Type::I32)),
-                    },
-                );
-                self.assign(Place::local(loop_var), Rvalue::Use(Operand::Copy(Place::local(inc_temp))));
-                
-                self.terminate(Terminator::Goto { target: cond_bb });
-                
-                // Exit block
                 self.switch_to_block(exit_bb);
+                Operand::Copy(Place::local(result))
+            }
+
+            TypedExprKind::Return(value) => {
+                if let Some(value) = value {
+                    let value_op = self.lower_expr(value);
+                    self.assign(Place::local(self.return_place), Rvalue::Use(value_op));
+                }
+                self.emit_scope_exits(0);
+                self.terminate_diverging(Terminator::Return)
+            }
+
+            TypedExprKind::Break { target, value } => {
+                let value_op = value.as_ref().map(|v| self.lower_expr(v));
+                let (break_bb, result, scope_depth) = {
+                    let target = self.loop_target(*target);
+                    (target.break_bb, target.result, target.scope_depth)
+                };
+                if let (Some(result), Some(value_op)) = (result, value_op) {
+                    self.assign(Place::local(result), Rvalue::Use(value_op));
+                }
+                self.emit_scope_exits(scope_depth);
+                self.terminate_diverging(Terminator::Goto { target: break_bb })
+            }
+
+            TypedExprKind::Continue { target } => {
+                let (continue_bb, scope_depth) = {
+                    let target = self.loop_target(*target);
+                    (target.continue_bb, target.scope_depth)
+                };
+                self.emit_scope_exits(scope_depth);
+                self.terminate_diverging(Terminator::Goto { target: continue_bb })
+            }
+
+            TypedExprKind::For { loop_id, binding, iter, body, .. } => {
+                self.lower_for_loop(*loop_id, *binding, iter, |this| {
+                    this.lower_block(body);
+                });
                 Operand::Constant(Constant::Unit)
             }
 
@@ -1188,6 +1620,10 @@ impl<'a> FunctionLowerer<'a> {
                 let value_op = self.lower_expr(value);
                 
                 if let Some(place) = self.expr_to_place(target) {
+                    // Overwriting a whole variable drops its old value
+                    if place.projections.is_empty() && self.local_needs_drop(place.local) {
+                        self.emit_drop(place.local);
+                    }
                     self.assign(place, Rvalue::Use(value_op));
                 }
                 
@@ -1240,6 +1676,13 @@ impl<'a> FunctionLowerer<'a> {
                 let index_op = self.lower_expr(index);
                 
                 if let Operand::Copy(place) | Operand::Move(place) = base_op {
+                    // Indexing through `&[T; N]` reads the array behind it,
+                    // and indexing a slice the elements it points to
+                    let place = match self.subst_type(&base.ty) {
+                        Type::Slice(elem) => self.slice_parts(place, &elem).0,
+                        ty if ty.is_ref() => place.deref(),
+                        _ => place,
+                    };
                     let indexed = place.index(index_op);
                     if self.is_copy_type(&expr.ty) {
                         Operand::Copy(indexed)
@@ -1253,50 +1696,147 @@ impl<'a> FunctionLowerer<'a> {
                 }
             }
 
-            TypedExprKind::ArrayLit(elements) => {
+            TypedExprKind::ArrayLit(elems) => {
                 // Create a temporary for the array
                 let array_temp = self.new_temp(expr.ty.clone());
                 
                 // Initialize each element
-                for (i, elem) in elements.iter().enumerate() {
-                    let elem_op = self.lower_expr(elem);
-                    let index_op = Operand::Constant(Constant::Int(i as i64, Type::I32));
-                    let place = Place::local(array_temp).index(index_op);
-                    self.assign(place, Rvalue::Use(elem_op));
+                let sink = CollectSink::Array(array_temp);
+                let mut index = 0;
+                for elem in elems {
+                    index = self.lower_collect_elem(elem, &sink, index);
                 }
                 
                 Operand::Copy(Place::local(array_temp))
             }
 
-            TypedExprKind::Lambda { params, body } => {
+            TypedExprKind::Collect { with_capacity, extend_one, type_args, capacity, elems } => {
+                // Lower: let c = C.with_capacity(n); c.extend_one(item); ...
+                let ty = self.subst_type(&expr.ty);
+                let type_args: Vec<_> = type_args.iter().map(|t| self.subst_type(t)).collect();
+                let local = self.new_temp(ty.clone());
+                
+                let cont_block = self.new_block();
+                let with_capacity = self.function_operand(*with_capacity, &type_args);
+                self.terminate(Terminator::Call {
+                    func: with_capacity,
+                    args: vec![Operand::Constant(Constant::Int(*capacity as i64, Type::I64))],
+                    destination: Place::local(local),
+                    target: cont_block,
+                });
+                self.switch_to_block(cont_block);
+                
+                let sink = CollectSink::Collection { local, ty, extend_one: self.function_operand(*extend_one, &type_args) };
+                for elem in elems {
+                    self.lower_collect_elem(elem, &sink, 0);
+                }
+                
+                Operand::Move(Place::local(local))
+            }
+
+            TypedExprKind::Tuple(elements) => {
+                let operands: Vec<_> = elements.iter().map(|e| self.lower_expr(e)).collect();
+                let tuple_ty = self.subst_type(&expr.ty);
+                let Type::Tuple(elem_tys) = &tuple_ty else {
+                    return Operand::Constant(Constant::Unit);
+                };
+                let (def_id, name) = tuple_struct_id(elem_tys);
+                let temp = self.new_temp(tuple_ty.clone());
+                self.assign(Place::local(temp), Rvalue::Aggregate {
+                    kind: AggregateKind::Struct(def_id, name),
+                    operands,
+                });
+                Operand::Move(Place::local(temp))
+            }
+
+            TypedExprKind::Lambda { params, captures, body } => {
                 // Generate a unique name for this lambda
-                let lambda_name = format!("{}$lambda{}", self.func.name, self.lambda_counter);
+                let lambda_name = format!("{}$lambda{}", self.function_name(), self.lambda_counter);
                 self.lambda_counter += 1;
-                
-                // Create a synthetic DefId for the lambda (use a hash of the name)
-                let lambda_def_id = DefId::new(
-                    (std::collections::hash_map::DefaultHasher::new(), &lambda_name)
-                        .1.len() as u32 + self.lambda_counter * 1000000
-                );
+
+                // The environment holds the code pointer followed by the captures
+                let mut fields = vec![("$fn".to_string(), Type::I64)];
+                for capture in captures {
+                    let ty = self.subst_type(&capture.ty);
+                    let field_ty = if captured_by_ref(capture, &ty) {
+                        Type::Ref { is_mut: capture.mode != CaptureMode::Ref, inner: Box::new(ty) }
+                    } else {
+                        ty
+                    };
+                    fields.push((capture.name.clone(), field_ty));
+                }
+                let env_name = format!("{}$env", lambda_name);
+                let env = MirStruct {
+                    def_id: synthetic_def_id(&env_name),
+                    name: env_name,
+                    fields,
+                    pointer_size: self.program.pointer_size,
+                };
                 
                 // Build the lambda function's MIR
-                let lambda_mir = self.lower_lambda(&lambda_name, lambda_def_id, params, body);
+                let return_type = match &expr.ty {
+                    Type::Function { ret, .. } => ret.as_ref().clone(),
+                    _ => body.ty.clone(),
+                };
+                let lambda_mir = self.lower_lambda(&lambda_name, &env, captures, params, body, return_type);
+                let mut operands = vec![Operand::Constant(Constant::FnPtr(lambda_mir.def_id, lambda_name))];
                 self.lambda_functions.push(lambda_mir);
-                
-                // Return a function pointer to the lambda
-                Operand::Constant(Constant::FnPtr(lambda_def_id, lambda_name))
+                for (capture, (_, field_ty)) in captures.iter().zip(&env.fields[1..]) {
+                    operands.push(self.capture_operand(capture.def_id, field_ty));
+                }
+                self.make_closure(env, operands)
             }
 
             TypedExprKind::Cast { expr: inner, target_type } => {
                 let operand = self.lower_expr(inner);
+                let target_type = self.subst_type(target_type);
+                // A reference to an array becomes a slice of all its elements
+                if let Type::Slice(elem) = &target_type
+                    && let Type::Ref { inner: array, .. } = self.subst_type(&inner.ty)
+                    && let Type::Array(_, len) = *array
+                {
+                    let (def_id, name, _) = slice_struct(elem);
+                    let slice = self.new_temp(target_type.clone());
+                    self.assign(Place::local(slice), Rvalue::Aggregate {
+                        kind: AggregateKind::Struct(def_id, name),
+                        operands: vec![operand, Operand::Constant(Constant::Int(len as i64, Type::I32))],
+                    });
+                    return Operand::Move(Place::local(slice));
+                }
                 let result = self.new_temp(target_type.clone());
                 self.assign(Place::local(result), Rvalue::Cast {
                     operand,
-                    ty: target_type.clone(),
+                    ty: target_type,
                 });
                 Operand::Copy(Place::local(result))
             }
 
+            TypedExprKind::Intrinsic { intrinsic, ty, args } => {
+                let ty = self.subst_type(ty);
+                let (size, align) = self.layout_of(&ty);
+                match intrinsic {
+                    wisp_ast::Intrinsic::SizeOf => Operand::Constant(Constant::Int(size as i64, Type::I64)),
+                    wisp_ast::Intrinsic::AlignOf => Operand::Constant(Constant::Int(align as i64, Type::I64)),
+                    wisp_ast::Intrinsic::Read | wisp_ast::Intrinsic::Write => {
+                        // Access the memory through a reference made from the address
+                        let addr = self.lower_expr(&args[0]);
+                        let ref_ty = Type::Ref { is_mut: true, inner: Box::new(ty.clone()) };
+                        let ptr = self.new_temp(ref_ty.clone());
+                        self.assign(Place::local(ptr), Rvalue::Cast { operand: addr, ty: ref_ty });
+                        if *intrinsic == wisp_ast::Intrinsic::Read {
+                            let result = self.new_temp(ty);
+                            self.assign(Place::local(result), Rvalue::Use(Operand::Copy(Place::local(ptr).deref())));
+                            Operand::Move(Place::local(result))
+                        } else {
+                            // The old value is overwritten without being dropped
+                            let value = self.lower_expr(&args[1]);
+                            self.assign(Place::local(ptr).deref(), Rvalue::Use(value));
+                            Operand::Constant(Constant::Unit)
+                        }
+                    }
+                }
+            }
+
             TypedExprKind::Match { scrutinee, arms } => {
                 // Lower scrutinee and store it in a temp so we can extract fields
                 let scrut_op = self.lower_expr(scrutinee);
@@ -1307,59 +1847,20 @@ impl<'a> FunctionLowerer<'a> {
                 let result = self.new_temp(expr.ty.clone());
                 let merge_bb = self.new_block();
                 
-                // For now, simple pattern matching on enum discriminants
-                let mut targets = Vec::new();
-                let mut arm_blocks = Vec::new();
-                
-                for (i, _arm) in arms.iter().enumerate() {
-                    let arm_bb = self.new_block();
-                    targets.push((i as i64, arm_bb));
-                    arm_blocks.push(arm_bb);
-                }
-                
-                let otherwise = arm_blocks.last().copied().unwrap_or(merge_bb);
-                
-                // Extract discriminant explicitly - SwitchInt needs the tag value, not the whole enum
-                let discr_temp = self.new_temp(Type::I64);
-                self.assign(
-                    Place::local(discr_temp),
-                    Rvalue::Discriminant(Place::local(scrut_local))
-                );
-                
-                self.terminate(Terminator::SwitchInt {
-                    discr: Operand::Copy(Place::local(discr_temp)),
-                    targets: targets[..targets.len().saturating_sub(1)].to_vec(),
-                    otherwise,
-                });
-                
-                for (i, arm) in arms.iter().enumerate() {
-                    self.switch_to_block(arm_blocks[i]);
-                    
-                    // Handle pattern bindings - extract fields from variant
-                    if let TypedPattern::Variant { fields, .. } = &arm.pattern {
-                        for (field_idx, field_pattern) in fields.iter().enumerate() {
-                            if let TypedPattern::Binding { def_id, ty, .. } = field_pattern {
-                                // Create a local for the binding
-                                let binding_local = self.new_temp(self.subst_type(ty));
-                                self.def_to_local.insert(*def_id, binding_local);
-                                
-                                // Extract the field from the scrutinee
-                                // For enums, field 0 is the discriminant, so payload starts at field 1
-                                let field_place = Place::local(scrut_local)
-                                    .field(field_idx + 1, format!("_{}", field_idx));
-                                self.assign(
-                                    Place::local(binding_local),
-                                    Rvalue::Use(Operand::Copy(field_place))
-                                );
-                            }
-                        }
-                    }
+                // Arms are tried in order: each one's tests jump to the next arm on failure
+                for arm in arms {
+                    let next_arm = self.new_block();
+                    self.lower_pattern(&arm.pattern, scrut_local, &scrut_ty, next_arm);
                     
                     let arm_val = self.lower_expr(&arm.body);
                     self.assign(Place::local(result), Rvalue::Use(arm_val));
                     self.terminate(Terminator::Goto { target: merge_bb });
+                    self.switch_to_block(next_arm);
                 }
                 
+                // The type checker rejects matches that don't cover every value
+                self.terminate(Terminator::Unreachable);
+                
                 self.switch_to_block(merge_bb);
                 Operand::Copy(Place::local(result))
             }
@@ -1377,6 +1878,397 @@ impl<'a> FunctionLowerer<'a> {
         }
     }
 
+    /// Test the value in `local` against a pattern, continuing in the current
+    /// block if it matches and jumping to `fail` if it doesn't. Bindings are
+    /// assigned as soon as the tests guarding them have passed.
+    fn lower_pattern(&mut self, pattern: &TypedPattern, local: u32, ty: &Type, fail: u32) {
+        match pattern {
+            TypedPattern::Wildcard => {}
+            TypedPattern::Binding { def_id, .. } => {
+                self.def_to_local.insert(*def_id, local);
+            }
+            TypedPattern::Literal(literal) => {
+                let value = match &literal.kind {
+                    TypedExprKind::IntLiteral(n) => Some(*n),
+                    TypedExprKind::BoolLiteral(b) => Some(*b as i64),
+                    TypedExprKind::Unary { op: wisp_ast::UnaryOp::Neg, expr } => match &expr.kind {
+                        TypedExprKind::IntLiteral(n) => Some(-*n),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let matched = self.new_block();
+                match value {
+                    Some(value) => self.terminate(Terminator::SwitchInt {
+                        discr: Operand::Copy(Place::local(local)),
+                        targets: vec![(value, matched)],
+                        otherwise: fail,
+                    }),
+                    None => {
+                        // Anything else is compared with `==`
+                        let literal = self.lower_expr(literal);
+                        let is_equal = self.new_temp(Type::Bool);
+                        self.assign(Place::local(is_equal), Rvalue::BinaryOp {
+                            op: BinOp::Eq,
+                            left: Operand::Copy(Place::local(local)),
+                            right: literal,
+                        });
+                        self.terminate(Terminator::SwitchInt {
+                            discr: Operand::Copy(Place::local(is_equal)),
+                            targets: vec![(1, matched)],
+                            otherwise: fail,
+                        });
+                    }
+                }
+                self.switch_to_block(matched);
+            }
+            TypedPattern::Variant { variant_def, fields } => {
+                let Some((enum_id, variant_idx)) = self.ctx.is_enum_variant(*variant_def) else { return };
+                
+                // SwitchInt needs the tag value, not the whole enum
+                let discr_temp = self.new_temp(Type::I64);
+                self.assign(Place::local(discr_temp), Rvalue::Discriminant(Place::local(local)));
+                let matched = self.new_block();
+                self.terminate(Terminator::SwitchInt {
+                    discr: Operand::Copy(Place::local(discr_temp)),
+                    targets: vec![(variant_idx as i64, matched)],
+                    otherwise: fail,
+                });
+                self.switch_to_block(matched);
+                
+                let type_args = match ty {
+                    Type::Enum { type_args, .. } => type_args.clone(),
+                    _ => Vec::new(),
+                };
+                let field_types = self.ctx.get_enum_variants(enum_id)
+                    .and_then(|variants| variants.get(variant_idx))
+                    .map(|(_, _, field_types)| field_types.clone())
+                    .unwrap_or_default();
+                for (field_idx, field_pattern) in fields.iter().enumerate() {
+                    let field_ty = field_types.get(field_idx)
+                        .map(|t| substitute_type(t, &type_args))
+                        .unwrap_or(Type::I64);
+                    // For enums, field 0 is the discriminant, so payload starts at field 1
+                    let field_place = Place::local(local).field(field_idx + 1, format!("_{}", field_idx));
+                    self.lower_subpattern(field_pattern, field_place, field_ty, fail);
+                }
+            }
+            TypedPattern::Tuple(elems) => {
+                let elem_types = match ty {
+                    Type::Tuple(elem_types) => elem_types.clone(),
+                    _ => Vec::new(),
+                };
+                for (i, elem) in elems.iter().enumerate() {
+                    let elem_ty = elem_types.get(i).cloned().unwrap_or(Type::I64);
+                    let elem_place = Place::local(local).field(i, i.to_string());
+                    self.lower_subpattern(elem, elem_place, elem_ty, fail);
+                }
+            }
+        }
+    }
+    
+    /// Match a field of the value being tested: it's copied into its own local first
+    fn lower_subpattern(&mut self, pattern: &TypedPattern, place: Place, ty: Type, fail: u32) {
+        if matches!(pattern, TypedPattern::Wildcard) {
+            return;
+        }
+        let local = self.new_temp(ty.clone());
+        self.assign(Place::local(local), Rvalue::Use(Operand::Copy(place)));
+        self.lower_pattern(pattern, local, &ty, fail);
+    }
+
+    /// Lower a `for` loop, with `body` lowering each iteration after the item
+    /// has been bound to `binding`
+    fn lower_for_loop(&mut self, loop_id: LoopId, binding: DefId, iter: &TypedForIter, body: impl FnOnce(&mut Self)) {
+        match iter {
+            TypedForIter::Range { start, end } => {
+                // The counter is the binding itself
+                let start_op = self.lower_expr(start);
+                let end_op = self.lower_expr(end);
+                let counter = self.new_temp(Type::I32);
+                self.def_to_local.insert(binding, counter);
+                self.lower_counted_loop(loop_id, counter, start_op, end_op, body);
+            }
+            TypedForIter::Array(array) => {
+                let array_ty = self.subst_type(&array.ty);
+                let place = match self.lower_expr(array) {
+                    Operand::Copy(place) | Operand::Move(place) => place,
+                    op => {
+                        let temp = self.new_temp(array_ty.clone());
+                        self.assign(Place::local(temp), Rvalue::Use(op));
+                        Place::local(temp)
+                    }
+                };
+                // Through a reference or a slice, each item is a reference to the element
+                let len = |len: usize| Operand::Constant(Constant::Int(len as i64, Type::I32));
+                let (elements, end, by_ref) = match &array_ty {
+                    Type::Array(_, n) => (place, len(*n), None),
+                    Type::Ref { is_mut, inner } => match inner.as_ref() {
+                        Type::Array(_, n) => (place.deref(), len(*n), Some(*is_mut)),
+                        _ => return,
+                    },
+                    Type::Slice(elem) => {
+                        let (elements, end) = self.slice_parts(place, elem);
+                        (elements, end, Some(false))
+                    }
+                    _ => return,
+                };
+                let item_ty = self.ctx.get_def_type(binding).map(|ty| self.subst_type(ty)).unwrap_or(Type::Unit);
+                let counter = self.new_temp(Type::I32);
+                let start = Operand::Constant(Constant::Int(0, Type::I32));
+                self.lower_counted_loop(loop_id, counter, start, end, |this| {
+                    let element = elements.index(Operand::Copy(Place::local(counter)));
+                    let value = match by_ref {
+                        Some(is_mut) => Rvalue::Ref { is_mut, place: element },
+                        None if this.is_copy_type(&item_ty) => Rvalue::Use(Operand::Copy(element)),
+                        None => Rvalue::Use(Operand::Move(element)),
+                    };
+                    let item = this.new_temp(item_ty);
+                    this.assign(Place::local(item), value);
+                    this.def_to_local.insert(binding, item);
+                    body(this);
+                });
+            }
+            TypedForIter::Next { iter, iter_def, next } => {
+                let iter_op = self.lower_expr(iter);
+                let iter_local = self.new_temp(self.subst_type(&iter.ty));
+                self.assign(Place::local(iter_local), Rvalue::Use(iter_op));
+                self.def_to_local.insert(*iter_def, iter_local);
+                
+                let head_bb = self.new_block();
+                let body_bb = self.new_block();
+                let exit_bb = self.new_block();
+                self.terminate(Terminator::Goto { target: head_bb });
+                
+                // Head: match iter.next() { Some(item) => body, None => exit }
+                self.switch_to_block(head_bb);
+                let next_op = self.lower_expr(next);
+                let option_ty = self.subst_type(&next.ty);
+                let option = self.new_temp(option_ty.clone());
+                self.assign(Place::local(option), Rvalue::Use(next_op));
+                let Type::Enum { def_id: option_id, type_args } = &option_ty else { return };
+                let some_idx = self.ctx.get_enum_variants(*option_id)
+                    .and_then(|variants| variants.iter().position(|(name, _, _)| name == "Some"))
+                    .unwrap_or(0);
+                let discr = self.new_temp(Type::I64);
+                self.assign(Place::local(discr), Rvalue::Discriminant(Place::local(option)));
+                self.terminate(Terminator::SwitchInt {
+                    discr: Operand::Copy(Place::local(discr)),
+                    targets: vec![(some_idx as i64, body_bb)],
+                    otherwise: exit_bb,
+                });
+                
+                self.switch_to_block(body_bb);
+                let item_ty = type_args.first().cloned().unwrap_or(Type::Unit);
+                let payload = Place::local(option).field(1, "_0".to_string());
+                let payload = if self.is_copy_type(&item_ty) { Operand::Copy(payload) } else { Operand::Move(payload) };
+                let item = self.new_temp(item_ty);
+                self.assign(Place::local(item), Rvalue::Use(payload));
+                self.def_to_local.insert(binding, item);
+                
+                self.loop_stack.push(LoopTarget { id: loop_id, continue_bb: head_bb, break_bb: exit_bb, result: None, scope_depth: self.scope_exits.len() });
+                body(self);
+                self.loop_stack.pop();
+                self.terminate(Terminator::Goto { target: head_bb });
+                
+                self.switch_to_block(exit_bb);
+            }
+        }
+    }
+
+    /// Lower `while counter < end { body; counter = counter + 1 }`, with
+    /// `counter` starting at `start` and `end` evaluated once
+    fn lower_counted_loop(&mut self, loop_id: LoopId, counter: u32, start: Operand, end: Operand, body: impl FnOnce(&mut Self)) {
+        let cond_bb = self.new_block();
+        let body_bb = self.new_block();
+        let step_bb = self.new_block();
+        let exit_bb = self.new_block();
+        
+        self.assign(Place::local(counter), Rvalue::Use(start));
+        let end_local = self.new_temp(Type::I32);
+        self.assign(Place::local(end_local), Rvalue::Use(end));
+        self.terminate(Terminator::Goto { target: cond_bb });
+        
+        // Condition block: counter < end
+        self.switch_to_block(cond_bb);
+        let cond_temp = self.new_temp(Type::Bool);
+        self.assign(
+            Place::local(cond_temp),
+            Rvalue::BinaryOp {
+                op: BinOp::Lt,
+                left: Operand::Copy(Place::local(counter)),
+                right: Operand::Copy(Place::local(end_local)),
+            },
+        );
+        self.terminate(Terminator::SwitchInt {
+            discr: Operand::Copy(Place::local(cond_temp)),
+            targets: vec![(1, body_bb)],
+            otherwise: exit_bb,
+        });
+        
+        // Body block
+        self.switch_to_block(body_bb);
+        self.loop_stack.push(LoopTarget { id: loop_id, continue_bb: step_bb, break_bb: exit_bb, result: None, scope_depth: self.scope_exits.len() });
+        body(self);
+        self.loop_stack.pop();
+        self.terminate(Terminator::Goto { target: step_bb });
+        
+        // Increment: counter = counter + 1
+        self.switch_to_block(step_bb);
+        let inc_temp = self.new_temp(Type::I32);
+        self.assign(
+            Place::local(inc_temp),
+            Rvalue::BinaryOp {
+                op: BinOp::Add,
+                left: Operand::Copy(Place::local(counter)),
+                right: Operand::Constant(Constant::Int(1, Type::I32)),
+            },
+        );
+        self.assign(Place::local(counter), Rvalue::Use(Operand::Copy(Place::local(inc_temp))));
+        self.terminate(Terminator::Goto { target: cond_bb });
+        
+        // Exit block
+        self.switch_to_block(exit_bb);
+    }
+
+    /// Lower an element of a list or map literal into `sink`. For an array,
+    /// `index` is the slot of its first item; returns the slot after its last.
+    fn lower_collect_elem(&mut self, elem: &TypedCollectElem, sink: &CollectSink, index: usize) -> usize {
+        match elem {
+            TypedCollectElem::Item(value) => {
+                let op = self.lower_expr(value);
+                self.push_collect_item(sink, index, op);
+                index + 1
+            }
+            TypedCollectElem::If { cond, then_elem, else_elem } => {
+                let cond_op = self.lower_expr(cond);
+                let then_bb = self.new_block();
+                let else_bb = self.new_block();
+                let merge_bb = self.new_block();
+                self.terminate(Terminator::SwitchInt {
+                    discr: cond_op,
+                    targets: vec![(1, then_bb)],
+                    otherwise: else_bb,
+                });
+                
+                // An array's `if` adds as many items either way
+                self.switch_to_block(then_bb);
+                let next = self.lower_collect_elem(then_elem, sink, index);
+                self.terminate(Terminator::Goto { target: merge_bb });
+                
+                self.switch_to_block(else_bb);
+                if let Some(else_elem) = else_elem {
+                    self.lower_collect_elem(else_elem, sink, index);
+                }
+                self.terminate(Terminator::Goto { target: merge_bb });
+                
+                self.switch_to_block(merge_bb);
+                next
+            }
+            TypedCollectElem::For { loop_id, binding, iter, bindings, elem, .. } => {
+                self.lower_for_loop(*loop_id, *binding, iter, |this| {
+                    // The destructured item lives for one iteration
+                    this.scope_exits.push(Vec::new());
+                    for stmt in bindings {
+                        this.lower_stmt(stmt);
+                    }
+                    this.lower_collect_elem(elem, sink, index);
+                    this.emit_scope_exits(this.scope_exits.len() - 1);
+                    this.scope_exits.pop();
+                });
+                index
+            }
+            TypedCollectElem::Spread(base) => {
+                let Type::Array(elem_ty, len) = self.subst_type(&base.ty) else {
+                    return index;
+                };
+                let base_place = match self.lower_expr(base) {
+                    Operand::Copy(place) | Operand::Move(place) => place,
+                    op => {
+                        let temp = self.new_temp(base.ty.clone());
+                        self.assign(Place::local(temp), Rvalue::Use(op));
+                        Place::local(temp)
+                    }
+                };
+                for i in 0..len {
+                    let place = base_place.clone().index(Operand::Constant(Constant::Int(i as i64, Type::I32)));
+                    let op = if self.is_copy_type(&elem_ty) { Operand::Copy(place) } else { Operand::Move(place) };
+                    self.push_collect_item(sink, index + i, op);
+                }
+                index + len
+            }
+        }
+    }
+
+    fn push_collect_item(&mut self, sink: &CollectSink, index: usize, item: Operand) {
+        match sink {
+            CollectSink::Array(local) => {
+                let index_op = Operand::Constant(Constant::Int(index as i64, Type::I32));
+                self.assign(Place::local(*local).index(index_op), Rvalue::Use(item));
+            }
+            CollectSink::Collection { local, ty, extend_one } => {
+                let ref_temp = self.new_temp(Type::Ref { is_mut: true, inner: Box::new(ty.clone()) });
+                self.assign(Place::local(ref_temp), Rvalue::Ref { is_mut: true, place: Place::local(*local) });
+                let result = self.new_temp(Type::Unit);
+                let cont_block = self.new_block();
+                self.terminate(Terminator::Call {
+                    func: extend_one.clone(),
+                    args: vec![Operand::Copy(Place::local(ref_temp)), item],
+                    destination: Place::local(result),
+                    target: cont_block,
+                });
+                self.switch_to_block(cont_block);
+            }
+        }
+    }
+
+    /// Size and alignment in bytes of a value of this type stored in memory,
+    /// as `#size_of` and `#align_of` report them
+    fn layout_of(&self, ty: &Type) -> (u32, u32) {
+        let struct_size = |fields: Vec<(String, Type)>| {
+            MirStruct { def_id: DefId::new(0), name: String::new(), fields, pointer_size: self.program.pointer_size }.compute_total_size()
+        };
+        match ty {
+            Type::Unit | Type::Never => (0, 1),
+            Type::Struct { def_id, type_args } => {
+                let Some(s) = self.program.structs.get(def_id) else {
+                    return (8, 8);
+                };
+                let fields = s.fields.iter()
+                    .map(|(name, field_ty)| (name.clone(), substitute_type(field_ty, type_args)))
+                    .collect();
+                (struct_size(fields), 8)
+            }
+            Type::Tuple(elems) => {
+                let fields = elems.iter().enumerate().map(|(i, elem)| (i.to_string(), elem.clone())).collect();
+                (struct_size(fields), 8)
+            }
+            Type::Enum { def_id, .. } => (self.program.enums.get(def_id).map_or(8, |e| e.compute_total_size()), 8),
+            Type::Array(elem, len) => {
+                let (size, align) = self.layout_of(elem);
+                (size * *len as u32, align)
+            }
+            Type::Slice(elem) => (struct_size(slice_struct(elem).2), 8),
+            _ => {
+                let size = type_size(ty, self.program.pointer_size);
+                (size, size)
+            }
+        }
+    }
+
+    /// The function to call for `def_id`, monomorphized when it has type args
+    fn function_operand(&mut self, def_id: DefId, type_args: &[Type]) -> Operand {
+        let name = self.ctx.get_type_name(def_id).unwrap_or_default();
+        if type_args.is_empty() {
+            Operand::Constant(Constant::FnPtr(def_id, name))
+        } else {
+            let type_args: Vec<Type> = type_args.iter().map(|t| self.subst_type(t)).collect();
+            let mangled_name = mono_name(self.program.method_owners, def_id, &name, &type_args);
+            self.instances.push((def_id, type_args.clone()));
+            Operand::Constant(Constant::MonomorphizedFn(def_id, mangled_name, type_args))
+        }
+    }
+
     fn lower_else(&mut self, else_branch: &TypedElse) -> Option<Operand> {
         match else_branch {
             TypedElse::Block(block) => self.lower_block(block),
@@ -1386,9 +2278,7 @@ impl<'a> FunctionLowerer<'a> {
 
     fn expr_to_place(&self, expr: &TypedExpr) -> Option<Place> {
         match &expr.kind {
-            TypedExprKind::Var { def_id, .. } => {
-                self.def_to_local.get(def_id).map(|&local| Place::local(local))
-            }
+            TypedExprKind::Var { def_id, .. } => self.var_place(*def_id),
             TypedExprKind::Field { expr: base, field, .. } => {
                 let base_place = self.expr_to_place(base)?;
                 let field_idx = self.get_field_index(&base.ty, field)?;
@@ -1400,6 +2290,7 @@ impl<'a> FunctionLowerer<'a> {
             }
             TypedExprKind::Index { expr: base, index } => {
                 let base_place = self.expr_to_place(base)?;
+                let base_place = if base.ty.is_ref() { base_place.deref() } else { base_place };
                 let index_op = self.lower_expr_pure(index)?;
                 Some(base_place.index(index_op))
             }
@@ -1407,6 +2298,114 @@ impl<'a> FunctionLowerer<'a> {
         }
     }
 
+    /// Where a variable of the function being lowered lives
+    fn var_place(&self, def_id: DefId) -> Option<Place> {
+        if let Some(&local) = self.def_to_local.get(&def_id) {
+            Some(Place::local(local))
+        } else {
+            self.captured_refs.get(&def_id).map(|&reference| Place::local(reference).deref())
+        }
+    }
+
+    /// A callee naming a function item, which can be called directly
+    fn function_item(&self, callee: &TypedExpr) -> Option<Operand> {
+        let TypedExprKind::Var { def_id, .. } = &callee.kind else { return None };
+        if self.var_place(*def_id).is_some() || self.extern_statics.contains_key(def_id) {
+            return None;
+        }
+        let name = self.ctx.get_type_name(*def_id)?;
+        Some(Operand::Constant(Constant::FnPtr(*def_id, name)))
+    }
+
+    /// A captured variable as stored in a closure's environment field of type `field_ty`
+    fn capture_operand(&mut self, def_id: DefId, field_ty: &Type) -> Operand {
+        let Some(place) = self.var_place(def_id) else {
+            return Operand::Constant(Constant::Unit);
+        };
+        let Type::Ref { is_mut, .. } = field_ty else {
+            return Operand::Copy(place);
+        };
+        // A variable this function itself captured by reference passes the same reference on
+        if let Some(&reference) = self.captured_refs.get(&def_id) {
+            return Operand::Copy(Place::local(reference));
+        }
+        let temp = self.new_temp(field_ty.clone());
+        self.assign(Place::local(temp), Rvalue::Ref { is_mut: *is_mut, place });
+        Operand::Copy(Place::local(temp))
+    }
+
+    /// Build a closure from its environment's fields. The closure value is
+    /// the environment's address, so it must not outlive this function.
+    fn make_closure(&mut self, env: MirStruct, operands: Vec<Operand>) -> Operand {
+        let temp = self.new_temp(Type::Struct { def_id: env.def_id, type_args: Vec::new() });
+        self.assign(
+            Place::local(temp),
+            Rvalue::Aggregate {
+                kind: AggregateKind::Struct(env.def_id, env.name.clone()),
+                operands,
+            }
+        );
+        self.closure_envs.push(env);
+        Operand::Copy(Place::local(temp))
+    }
+
+    /// A function item used as a value: a closure without captures whose
+    /// code is a shim that drops the environment argument and calls the function
+    fn function_value(&mut self, def_id: DefId, name: String, ty: &Type) -> Operand {
+        let Type::Function { params, ret } = self.subst_type(ty) else {
+            return Operand::Constant(Constant::FnPtr(def_id, name));
+        };
+        let shim_name = format!("{}$shim", name);
+        let shim_def_id = synthetic_def_id(&shim_name);
+        let env_name = format!("{}$env", shim_name);
+        let env = MirStruct {
+            def_id: synthetic_def_id(&env_name),
+            name: env_name,
+            fields: vec![("$fn".to_string(), Type::I64)],
+            pointer_size: self.program.pointer_size,
+        };
+
+        let env_ty = Type::Ref { is_mut: false, inner: Box::new(Type::Struct { def_id: env.def_id, type_args: Vec::new() }) };
+        let shim_params: Vec<_> = std::iter::once(env_ty).chain(params)
+            .enumerate()
+            .map(|(i, ty)| MirLocal { id: i as u32 + 1, name: format!("_{}", i), ty, is_arg: true })
+            .collect();
+        let args = shim_params[1..].iter().map(|p| Operand::Move(Place::local(p.id))).collect();
+        self.lambda_functions.push(MirFunction {
+            def_id: shim_def_id,
+            name: shim_name.clone(),
+            params: shim_params,
+            return_type: (*ret).clone(),
+            locals: vec![MirLocal { id: 0, name: "_return".to_string(), ty: *ret, is_arg: false }],
+            blocks: vec![
+                BasicBlock {
+                    id: 0,
+                    statements: Vec::new(),
+                    terminator: Terminator::Call {
+                        func: Operand::Constant(Constant::FnPtr(def_id, name)),
+                        args,
+                        destination: Place::local(0),
+                        target: 1,
+                    },
+                    terminator_span: self.span,
+                },
+                BasicBlock { id: 1, statements: Vec::new(), terminator: Terminator::Return, terminator_span: self.span },
+            ],
+            span: self.span,
+        });
+
+        self.make_closure(env, vec![Operand::Constant(Constant::FnPtr(shim_def_id, shim_name))])
+    }
+
+    /// The elements a slice points to, as a place that can be indexed, and
+    /// its length
+    fn slice_parts(&mut self, slice: Place, elem: &Type) -> (Place, Operand) {
+        let ptr_ty = slice_struct(elem).2.swap_remove(0).1;
+        let ptr = self.new_temp(ptr_ty);
+        self.assign(Place::local(ptr), Rvalue::Use(Operand::Copy(slice.clone().field(0, "ptr".to_string()))));
+        (Place::local(ptr).deref(), Operand::Copy(slice.field(1, "len".to_string())))
+    }
+
     fn lower_expr_pure(&self, _expr: &TypedExpr) -> Option<Operand> {
         // For pure expressions that don't have side effects
         // Used for index expressions in places
@@ -1415,6 +2414,16 @@ impl<'a> FunctionLowerer<'a> {
     }
 
     fn get_field_index(&self, ty: &Type, field_name: &str) -> Option<usize> {
+        // Tuple elements are numbered
+        if let Type::Tuple(_) = ty {
+            return field_name.parse().ok();
+        }
+        if let Type::Ref { inner, .. } = ty
+            && let Type::Tuple(_) = inner.as_ref()
+        {
+            return field_name.parse().ok();
+        }
+
         // Handle both direct struct types and references to structs
         let struct_def_id = match ty {
             Type::Struct { def_id, .. } => Some(*def_id),
@@ -1441,65 +2450,91 @@ impl<'a> FunctionLowerer<'a> {
             Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::I128 |
             Type::U8 | Type::U16 | Type::U32 | Type::U64 | Type::U128 |
             Type::F32 | Type::F64 | Type::Bool | Type::Char | Type::Unit |
-            Type::Ref { .. }
+            Type::Ref { .. } | Type::Slice(_) | Type::Str
         )
     }
 
-    /// Lower a lambda expression into a separate MIR function
+    /// Lower a lambda expression into a separate MIR function. The closure's
+    /// environment is passed as the first parameter.
     fn lower_lambda(
         &mut self,
         name: &str,
-        def_id: DefId,
+        env: &MirStruct,
+        captures: &[TypedCapture],
         params: &[TypedLambdaParam],
         body: &TypedExpr,
+        return_type: Type,
     ) -> MirFunction {
         // Create a new lowerer state for the lambda
         let mut locals = Vec::new();
         let mut def_to_local = HashMap::new();
-        let mut next_local = 0u32;
         
         // Local 0 is the return place
-        let return_type = body.ty.clone();
         locals.push(MirLocal {
             id: 0,
             name: "_return".to_string(),
             ty: return_type.clone(),
             is_arg: false,
         });
-        next_local = 1;
+        
+        // Local 1 is the environment
+        locals.push(MirLocal {
+            id: 1,
+            name: "_env".to_string(),
+            ty: Type::Ref { is_mut: false, inner: Box::new(Type::Struct { def_id: env.def_id, type_args: Vec::new() }) },
+            is_arg: true,
+        });
         
         // Add parameters as locals
-        let mut mir_params = Vec::new();
         for param in params {
-            let local_id = next_local;
-            next_local += 1;
+            let local_id = locals.len() as u32;
             locals.push(MirLocal {
                 id: local_id,
                 name: param.name.clone(),
                 ty: param.ty.clone(),
                 is_arg: true,
             });
-            mir_params.push(MirLocal {
-                id: local_id,
-                name: param.name.clone(),
-                ty: param.ty.clone(),
-                is_arg: true,
-            });
             def_to_local.insert(param.def_id, local_id);
         }
+        let mir_params: Vec<_> = locals.iter().filter(|l| l.is_arg).cloned().collect();
+        let next_local = locals.len() as u32;
         
         // Save current lowerer state
         let saved_locals = std::mem::replace(&mut self.locals, locals);
         let saved_def_to_local = std::mem::replace(&mut self.def_to_local, def_to_local);
+        let saved_captured_refs = std::mem::take(&mut self.captured_refs);
         let saved_next_local = std::mem::replace(&mut self.next_local, next_local);
         let saved_blocks = std::mem::take(&mut self.blocks);
         let saved_current_block = self.current_block;
         let saved_current_stmts = std::mem::take(&mut self.current_stmts);
         let saved_return_place = self.return_place;
+        let saved_loop_stack = std::mem::take(&mut self.loop_stack);
+        let saved_scope_exits = std::mem::take(&mut self.scope_exits);
         
         // Reset for lambda
-        self.current_block = 0;
         self.return_place = 0;
+        self.new_block();
+        self.switch_to_block(0);
+        
+        // Copy the captures out of the environment. A value moved into it is
+        // used in place, so changes to it last from one call to the next.
+        for (i, (capture, (field, field_ty))) in captures.iter().zip(&env.fields[1..]).enumerate() {
+            let field_place = Place::local(1).field(i + 1, field.clone());
+            if matches!(field_ty, Type::Ref { .. }) && captured_by_ref(capture, &self.subst_type(&capture.ty)) {
+                let local = self.new_local(capture.name.clone(), field_ty.clone(), false);
+                self.assign(Place::local(local), Rvalue::Use(Operand::Copy(field_place)));
+                self.captured_refs.insert(capture.def_id, local);
+            } else if capture.mode == CaptureMode::Move {
+                let ref_ty = Type::Ref { is_mut: true, inner: Box::new(field_ty.clone()) };
+                let local = self.new_local(capture.name.clone(), ref_ty, false);
+                self.assign(Place::local(local), Rvalue::Ref { is_mut: true, place: field_place });
+                self.captured_refs.insert(capture.def_id, local);
+            } else {
+                let local = self.new_local(capture.name.clone(), field_ty.clone(), false);
+                self.assign(Place::local(local), Rvalue::Use(Operand::Copy(field_place)));
+                self.def_to_local.insert(capture.def_id, local);
+            }
+        }
         
         // Lower the lambda body
         let result = self.lower_expr(body);
@@ -1518,23 +2553,54 @@ impl<'a> FunctionLowerer<'a> {
         // Restore parent lowerer state
         self.locals = saved_locals;
         self.def_to_local = saved_def_to_local;
+        self.captured_refs = saved_captured_refs;
         self.next_local = saved_next_local;
         self.blocks = saved_blocks;
         self.current_block = saved_current_block;
         self.current_stmts = saved_current_stmts;
         self.return_place = saved_return_place;
+        self.loop_stack = saved_loop_stack;
+        self.scope_exits = saved_scope_exits;
         
         MirFunction {
-            def_id,
+            def_id: synthetic_def_id(name),
             name: name.to_string(),
             params: mir_params,
             locals: lambda_locals,
             blocks: lambda_blocks,
             return_type,
+            span: body.span,
         }
     }
 
-    fn finish(mut self) -> MirFunction {
+    /// The symbol name of the function being lowered
+    fn function_name(&self) -> String {
+        if let Some(ref subst) = self.type_subst {
+            // Monomorphized generic function - use mangled name
+            mono_name(self.program.method_owners, self.func.def_id, &self.func.name, &subst.type_args)
+        } else if let Some(ref type_name) = self.impl_type_name {
+            // Impl method - mangle with type name
+            format!("{}::{}", type_name, self.func.name)
+        } else {
+            self.func.name.clone()
+        }
+    }
+
+    /// The lowered function with the lambdas, closure environments and
+    /// generic instances it created
+    fn into_result(mut self) -> LowerResult {
+        let lambda_functions = std::mem::take(&mut self.lambda_functions);
+        let closure_envs = std::mem::take(&mut self.closure_envs);
+        let instances = std::mem::take(&mut self.instances);
+        LowerResult {
+            main_function: self.finish(),
+            lambda_functions,
+            closure_envs,
+            instances,
+        }
+    }
+
+    fn finish(self) -> MirFunction {
         // Separate params from other locals
         let params: Vec<_> = self.locals.iter()
             .filter(|l| l.is_arg)
@@ -1545,16 +2611,7 @@ impl<'a> FunctionLowerer<'a> {
             .cloned()
             .collect();
 
-        // Determine the function name
-        let name = if let Some(ref subst) = self.type_subst {
-            // Monomorphized generic function - use mangled name
-            mangle_generic_name(&self.func.name, &subst.type_args)
-        } else if let Some(ref type_name) = self.impl_type_name {
-            // Impl method - mangle with type name
-            format!("{}::{}", type_name, self.func.name)
-        } else {
-            self.func.name.clone()
-        };
+        let name = self.function_name();
         
         // Get the return type (possibly substituted)
         let return_type = self.subst_type(&self.func.return_type);
@@ -1566,6 +2623,7 @@ impl<'a> FunctionLowerer<'a> {
             return_type,
             locals,
             blocks: self.blocks,
+            span: self.func.span,
         }
     }
 }
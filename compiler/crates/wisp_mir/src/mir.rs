@@ -1,6 +1,7 @@
 //! MIR data structures
 
 use wisp_hir::DefId;
+use wisp_lexer::Span;
 use wisp_types::Type;
 use std::collections::HashMap;
 
@@ -12,8 +13,15 @@ pub struct MirProgram {
     pub extern_statics: Vec<MirExternStatic>,
     pub structs: HashMap<DefId, MirStruct>,
     pub enums: HashMap<DefId, MirEnum>,
+    /// Types that implement Drop, mapped to the name of their `drop` method
+    pub drop_fns: HashMap<DefId, String>,
+    /// Size in bytes of a pointer on the target the program is lowered for
+    pub pointer_size: u32,
 }
 
+/// Pointer size of the machine the compiler runs on
+pub const HOST_POINTER_SIZE: u32 = std::mem::size_of::<usize>() as u32;
+
 /// A MIR extern function declaration
 #[derive(Debug, Clone)]
 pub struct MirExternFunction {
@@ -32,13 +40,15 @@ pub struct MirExternStatic {
 }
 
 impl MirProgram {
-    pub fn new() -> Self {
+    pub fn new(pointer_size: u32) -> Self {
         Self {
             functions: Vec::new(),
             extern_functions: Vec::new(),
             extern_statics: Vec::new(),
             structs: HashMap::new(),
             enums: HashMap::new(),
+            drop_fns: HashMap::new(),
+            pointer_size,
         }
     }
 
@@ -77,7 +87,7 @@ impl MirProgram {
 
 impl Default for MirProgram {
     fn default() -> Self {
-        Self::new()
+        Self::new(HOST_POINTER_SIZE)
     }
 }
 
@@ -104,6 +114,8 @@ pub struct MirStruct {
     pub def_id: DefId,
     pub name: String,
     pub fields: Vec<(String, Type)>,
+    /// Target pointer size, which pointer-sized fields are laid out with
+    pub pointer_size: u32,
 }
 
 impl MirStruct {
@@ -112,7 +124,7 @@ impl MirStruct {
         let mut offset = 0u32;
         for i in 0..field_idx {
             if i < self.fields.len() {
-                let field_size = type_size(&self.fields[i].1);
+                let field_size = type_size(&self.fields[i].1, self.pointer_size);
                 // Align to natural alignment (min of size and 8)
                 let align = field_size.min(8);
                 offset = (offset + align - 1) / align * align;
@@ -121,7 +133,7 @@ impl MirStruct {
         }
         // Align final offset
         if field_idx < self.fields.len() {
-            let field_size = type_size(&self.fields[field_idx].1);
+            let field_size = type_size(&self.fields[field_idx].1, self.pointer_size);
             let align = field_size.min(8);
             offset = (offset + align - 1) / align * align;
         }
@@ -135,7 +147,7 @@ impl MirStruct {
         }
         let last_idx = self.fields.len() - 1;
         let last_offset = self.compute_field_offset(last_idx);
-        let last_size = type_size(&self.fields[last_idx].1);
+        let last_size = type_size(&self.fields[last_idx].1, self.pointer_size);
         // Round up to 8-byte alignment
         let total = last_offset + last_size;
         (total + 7) / 8 * 8
@@ -171,6 +183,8 @@ pub struct MirEnum {
     pub name: String,
     /// Variants: (name, variant_def_id, field_types)
     pub variants: Vec<(String, DefId, Vec<Type>)>,
+    /// Target pointer size, which pointer-sized fields are laid out with
+    pub pointer_size: u32,
 }
 
 impl MirEnum {
@@ -179,12 +193,11 @@ impl MirEnum {
         8 // Use i64 for discriminant
     }
     
-    /// Get the size of the largest variant's payload
+    /// Get the size of the largest variant's payload, whose fields are laid
+    /// out 8 bytes apart
     pub fn max_payload_size(&self) -> u32 {
         self.variants.iter()
-            .map(|(_, _, fields)| {
-                fields.iter().map(|ty| type_size(ty)).sum::<u32>()
-            })
+            .map(|(_, _, fields)| fields.len() as u32 * 8)
             .max()
             .unwrap_or(0)
     }
@@ -240,22 +253,83 @@ impl Aggregate for MirEnum {
     }
 }
 
-/// Helper to get type size
-pub fn type_size(ty: &Type) -> u32 {
+/// Helper to get type size on a target with `pointer_size`-byte pointers
+pub fn type_size(ty: &Type, pointer_size: u32) -> u32 {
     match ty {
         Type::I8 | Type::U8 | Type::Bool => 1,
         Type::I16 | Type::U16 => 2,
         Type::I32 | Type::U32 | Type::Char => 4,
-        Type::I64 | Type::U64 | Type::Str => 8,
+        Type::I64 | Type::U64 => 8,
         Type::F32 => 4,
         Type::F64 => 8,
-        Type::Ref { .. } => 8, // Pointers are 8 bytes
-        Type::Struct { .. } | Type::Enum { .. } => 8, // Passed as pointers
-        Type::TypeParam { .. } => 8, // Assume pointer-sized for generics
+        Type::Str | Type::Ref { .. } => pointer_size,
+        Type::Struct { .. } | Type::Enum { .. } | Type::Tuple(_) => pointer_size, // Passed as pointers
+        Type::TypeParam { .. } => pointer_size, // Assume pointer-sized for generics
         _ => 8, // Default to 8
     }
 }
 
+/// Whether dropping a value of this type runs a destructor: it implements
+/// Drop, or it's a struct, enum or array holding something that needs dropping
+pub fn needs_drop<F>(ty: &Type, drop_fns: &HashMap<DefId, F>, structs: &HashMap<DefId, MirStruct>, enums: &HashMap<DefId, MirEnum>) -> bool {
+    match ty {
+        Type::Struct { def_id, type_args } => {
+            drop_fns.contains_key(def_id)
+                || structs.get(def_id).is_some_and(|s| {
+                    s.fields.iter().any(|(_, field_ty)| needs_drop(&substitute_type(field_ty, type_args), drop_fns, structs, enums))
+                })
+        }
+        Type::Enum { def_id, type_args } => {
+            drop_fns.contains_key(def_id)
+                || enums.get(def_id).is_some_and(|e| {
+                    e.variants.iter().flat_map(|(_, _, fields)| fields)
+                        .any(|field_ty| needs_drop(&substitute_type(field_ty, type_args), drop_fns, structs, enums))
+                })
+        }
+        Type::Array(elem, len) => *len > 0 && needs_drop(elem, drop_fns, structs, enums),
+        Type::Tuple(elems) => elems.iter().any(|ty| needs_drop(ty, drop_fns, structs, enums)),
+        _ => false,
+    }
+}
+
+/// Substitute type parameters with concrete types
+/// Uses index-based matching like Rust's ParamTy - the index is the position
+/// in the generics list, so T at index 0 in `Option<T>` matches T at index 0
+/// in `impl<T> Option<T>`, regardless of DefId.
+pub fn substitute_type(ty: &Type, type_args: &[Type]) -> Type {
+    match ty {
+        Type::TypeParam { index, .. } => {
+            // Use index-based substitution - the index tells us which type arg to use
+            type_args.get(*index as usize).cloned().unwrap_or(ty.clone())
+        }
+        Type::Ref { is_mut, inner } => Type::Ref {
+            is_mut: *is_mut,
+            inner: Box::new(substitute_type(inner, type_args)),
+        },
+        Type::Slice(elem) => Type::Slice(Box::new(substitute_type(elem, type_args))),
+        Type::Array(elem, size) => Type::Array(
+            Box::new(substitute_type(elem, type_args)),
+            *size,
+        ),
+        Type::Tuple(elems) => Type::Tuple(
+            elems.iter().map(|e| substitute_type(e, type_args)).collect()
+        ),
+        Type::Function { params, ret } => Type::Function {
+            params: params.iter().map(|p| substitute_type(p, type_args)).collect(),
+            ret: Box::new(substitute_type(ret, type_args)),
+        },
+        Type::Enum { def_id, type_args: enum_type_args } => Type::Enum {
+            def_id: *def_id,
+            type_args: enum_type_args.iter().map(|t| substitute_type(t, type_args)).collect(),
+        },
+        Type::Struct { def_id, type_args: struct_type_args } => Type::Struct {
+            def_id: *def_id,
+            type_args: struct_type_args.iter().map(|t| substitute_type(t, type_args)).collect(),
+        },
+        _ => ty.clone(),
+    }
+}
+
 /// A MIR function
 #[derive(Debug)]
 pub struct MirFunction {
@@ -265,6 +339,8 @@ pub struct MirFunction {
     pub return_type: Type,
     pub locals: Vec<MirLocal>,
     pub blocks: Vec<BasicBlock>,
+    /// Source of the function definition; spans of its statements are in the same file
+    pub span: Span,
 }
 
 impl MirFunction {
@@ -312,18 +388,37 @@ pub struct MirLocal {
     pub is_arg: bool,
 }
 
+impl MirLocal {
+    /// Whether the local is a variable or parameter named in the source, as
+    /// opposed to a temporary, return place or drop flag made up by lowering
+    pub fn is_user_variable(&self) -> bool {
+        let numbered = |prefix: &str| self.name.strip_prefix(prefix)
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+        let generated = self.name == "_return"
+            || self.name == "_env"
+            || self.name.contains('$')
+            || numbered("_t")
+            || numbered("_");
+        !generated
+    }
+}
+
 /// A basic block
 #[derive(Debug)]
 pub struct BasicBlock {
     pub id: u32,
     pub statements: Vec<Statement>,
     pub terminator: Terminator,
+    /// Source the terminator was lowered from
+    pub terminator_span: Span,
 }
 
 /// A MIR statement
 #[derive(Debug, Clone)]
 pub struct Statement {
     pub kind: StatementKind,
+    /// Source the statement was lowered from
+    pub span: Span,
 }
 
 impl Statement {
@@ -334,6 +429,7 @@ impl Statement {
             }
             StatementKind::StorageLive(local) => format!("StorageLive(_{local})"),
             StatementKind::StorageDead(local) => format!("StorageDead(_{local})"),
+            StatementKind::Deinit(place) => format!("Deinit({})", place.pretty_print()),
             StatementKind::Nop => "nop".to_string(),
         }
     }
@@ -347,6 +443,9 @@ pub enum StatementKind {
     StorageLive(u32),
     /// Mark a local as dead
     StorageDead(u32),
+    /// Mark a local or a field of one as moved out of, so its value isn't
+    /// dropped
+    Deinit(Place),
     /// No operation
     Nop,
 }
@@ -545,6 +644,11 @@ pub enum Terminator {
         destination: Place,
         target: u32,
     },
+    /// Run the destructor of the value in a place, then jump to target
+    Drop {
+        place: Place,
+        target: u32,
+    },
     /// Unreachable code
     Unreachable,
 }
@@ -569,6 +673,9 @@ impl Terminator {
                     args_str.join(", "),
                     target)
             }
+            Terminator::Drop { place, target } => {
+                format!("drop({}) -> bb{}", place.pretty_print(), target)
+            }
             Terminator::Unreachable => "unreachable".to_string(),
         }
     }
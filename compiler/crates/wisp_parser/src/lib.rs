@@ -1,5 +1,6 @@
 use wisp_ast::*;
-use wisp_lexer::{Lexer, Span, SpannedToken, Token};
+use wisp_diagnostics::{codes, Diagnostic};
+use wisp_lexer::{FileId, Lexer, SourceMap, Span, SpannedToken, Token};
 use std::collections::{HashSet, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -25,6 +26,12 @@ impl std::fmt::Display for ParseError {
 
 impl std::error::Error for ParseError {}
 
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Self {
+        Diagnostic::error(codes::SYNTAX_ERROR, e.message, e.span)
+    }
+}
+
 pub type ParseResult<T> = Result<T, ParseError>;
 
 /// Result type for error-recovering parsing that returns both the AST and any errors
@@ -36,10 +43,10 @@ pub struct ParseResultWithErrors<T> {
 
 /// Parse a file and recursively resolve imports
 /// 
-/// This function takes the source text and the file path, parses the source,
-/// and recursively parses and includes any imported files.
-pub fn parse_with_imports(source: &str, file_path: &Path) -> Result<SourceFile, String> {
-    let base_dir = file_path.parent().unwrap_or(Path::new("."));
+/// This function takes a file of the source map, parses its source,
+/// and recursively parses and includes any imported files, adding them to the map.
+pub fn parse_with_imports(sources: &mut SourceMap, file: FileId) -> Result<SourceFile, Vec<Diagnostic>> {
+    let file_path = sources.path(file);
     let mut visited = HashSet::new();
     if let Ok(canonical) = file_path.canonicalize() {
         visited.insert(canonical);
@@ -47,7 +54,7 @@ pub fn parse_with_imports(source: &str, file_path: &Path) -> Result<SourceFile,
         visited.insert(file_path.to_path_buf());
     }
     
-    parse_with_imports_recursive(source, base_dir, &mut visited)
+    parse_with_imports_recursive(sources, file, &mut visited, &TargetCfg::host())
 }
 
 /// Configuration for import resolution
@@ -77,7 +84,7 @@ impl ImportConfig {
 }
 
 /// Find project root by walking up looking for wisp.toml
-fn find_project_root(start: &Path) -> Option<PathBuf> {
+pub fn find_project_root(start: &Path) -> Option<PathBuf> {
     let mut current = start.to_path_buf();
     loop {
         if current.join("wisp.toml").exists() {
@@ -90,12 +97,13 @@ fn find_project_root(start: &Path) -> Option<PathBuf> {
 }
 
 fn parse_with_imports_recursive(
-    source: &str,
-    base_dir: &Path,
+    sources: &mut SourceMap,
+    file: FileId,
     visited: &mut HashSet<PathBuf>,
-) -> Result<SourceFile, String> {
+    target: &TargetCfg,
+) -> Result<SourceFile, Vec<Diagnostic>> {
     // For backwards compatibility, flatten the imports into a single SourceFile
-    let with_imports = parse_with_imports_structured(source, base_dir, visited)?;
+    let with_imports = parse_with_imports_structured(sources, file, visited, target)?;
     
     let mut all_items = Vec::new();
     
@@ -119,54 +127,62 @@ pub type ModuleCache = HashMap<PathBuf, Vec<Item>>;
 /// Parse a file with imports, preserving namespace structure
 /// Uses a module cache to ensure each module is only parsed once
 /// but its items can be referenced by multiple namespaces
+/// Items whose `@cfg` doesn't hold for `target` are left out of every module
+/// Imported files are added to `sources` under their canonical paths, unless already there
 pub fn parse_with_imports_structured(
-    source: &str,
-    base_dir: &Path,
+    sources: &mut SourceMap,
+    file: FileId,
     visited: &mut HashSet<PathBuf>,
-) -> Result<SourceFileWithImports, String> {
+    target: &TargetCfg,
+) -> Result<SourceFileWithImports, Vec<Diagnostic>> {
     let mut module_cache: ModuleCache = HashMap::new();
-    parse_with_imports_structured_cached(source, base_dir, visited, &mut module_cache)
+    parse_with_imports_structured_cached(sources, file, visited, &mut module_cache, target)
 }
 
-/// Cache entry includes both items and the module's own imports
-type ModuleCacheEntry = (Vec<Item>, Vec<ImportDecl>);
+/// Cache entry includes the module's file, its items and its own imports
+type ModuleCacheEntry = (FileId, Vec<Item>, Vec<ImportDecl>);
 type ModuleCacheWithImports = HashMap<PathBuf, ModuleCacheEntry>;
 
 fn parse_with_imports_structured_cached(
-    source: &str,
-    base_dir: &Path,
+    sources: &mut SourceMap,
+    file: FileId,
     visited: &mut HashSet<PathBuf>,
     module_cache: &mut ModuleCache,
-) -> Result<SourceFileWithImports, String> {
+    target: &TargetCfg,
+) -> Result<SourceFileWithImports, Vec<Diagnostic>> {
     // Use a separate cache that includes import info
     let mut imports_cache: ModuleCacheWithImports = HashMap::new();
-    parse_with_imports_impl(source, base_dir, visited, module_cache, &mut imports_cache)
+    parse_with_imports_impl(sources, file, visited, module_cache, &mut imports_cache, target)
 }
 
 fn parse_with_imports_impl(
-    source: &str,
-    base_dir: &Path,
+    sources: &mut SourceMap,
+    file: FileId,
     visited: &mut HashSet<PathBuf>,
     module_cache: &mut ModuleCache,
     imports_cache: &mut ModuleCacheWithImports,
-) -> Result<SourceFileWithImports, String> {
-    let ast = Parser::parse(source).map_err(|e| format!("Parse error: {}", e))?;
+    target: &TargetCfg,
+) -> Result<SourceFileWithImports, Vec<Diagnostic>> {
+    let ast = Parser::parse_file(sources.text(file), file).map_err(into_diagnostics)?;
+    let items = configure_items(ast.items, target);
     
-    // Detect import config from base_dir
-    let config = ImportConfig::detect(base_dir);
+    // Detect import config from the file's directory
+    let base_dir = sources.path(file).parent().unwrap_or(Path::new(".")).to_path_buf();
+    let config = ImportConfig::detect(&base_dir);
     
     let mut local_items = Vec::new();
     let mut local_imports = Vec::new();  // Track this module's imports
     let mut imported_modules = Vec::new();
     
-    for item in ast.items {
+    for item in items {
         match item {
             Item::Import(mut import) => {
                 // Track this import for scope resolution
                 local_imports.push(import.clone());
                 
                 // Resolve import path based on type
-                let import_path = resolve_import_path(&import.path, base_dir, &config)?;
+                let import_path = resolve_import_path(&import.path, &base_dir, &config)
+                    .map_err(|e| vec![Diagnostic::error(codes::UNRESOLVED_IMPORT, e, import.span)])?;
                 
                 // Check if we resolved to a parent module (item import case)
                 // e.g., `import std.io.print` resolved to `std/io.ws`
@@ -208,14 +224,19 @@ fn parse_with_imports_impl(
                 
                 let canonical = match import_path.canonicalize() {
                     Ok(c) => c,
-                    Err(e) => return Err(format!("Cannot find import '{}': {}", format_import_path(&import.path), e)),
+                    Err(e) => return Err(vec![Diagnostic::error(
+                        codes::UNRESOLVED_IMPORT,
+                        format!("cannot find import '{}': {}", format_import_path(&import.path), e),
+                        import.span,
+                    )]),
                 };
                 
                 // Check if we've already parsed this module
-                if let Some((cached_items, cached_imports)) = imports_cache.get(&canonical) {
+                if let Some((cached_file, cached_items, cached_imports)) = imports_cache.get(&canonical) {
                     // Use cached items for this namespace
                     imported_modules.push(ImportedModule {
                         import,
+                        file: *cached_file,
                         items: cached_items.clone(),
                         module_imports: cached_imports.clone(),
                         is_transitive: false,
@@ -227,6 +248,7 @@ fn parse_with_imports_impl(
                 if let Some(cached_items) = module_cache.get(&canonical) {
                     imported_modules.push(ImportedModule {
                         import,
+                        file: module_file(sources, &canonical),
                         items: cached_items.clone(),
                         module_imports: vec![],
                         is_transitive: false,
@@ -239,6 +261,7 @@ fn parse_with_imports_impl(
                     // Cycle detected - add empty module to break the cycle
                     imported_modules.push(ImportedModule {
                         import,
+                        file: module_file(sources, &canonical),
                         items: vec![],
                         module_imports: vec![],
                         is_transitive: false,
@@ -247,14 +270,21 @@ fn parse_with_imports_impl(
                 }
                 visited.insert(canonical.clone());
                 
-                // Read and parse the imported file
-                let import_source = match fs::read_to_string(&import_path) {
-                    Ok(s) => s,
-                    Err(e) => return Err(format!("Cannot read import '{}': {}", format_import_path(&import.path), e)),
+                // Read the imported file, unless an earlier parse with the same
+                // source map did, and parse it
+                let import_file = match sources.find(&canonical) {
+                    Some(file) => file,
+                    None => match fs::read_to_string(&import_path) {
+                        Ok(s) => sources.add(canonical.clone(), s),
+                        Err(e) => return Err(vec![Diagnostic::error(
+                            codes::UNRESOLVED_IMPORT,
+                            format!("cannot read import '{}': {}", format_import_path(&import.path), e),
+                            import.span,
+                        )]),
+                    },
                 };
                 
-                let import_dir = import_path.parent().unwrap_or(Path::new("."));
-                let imported_ast = parse_with_imports_impl(&import_source, import_dir, visited, module_cache, imports_cache)?;
+                let imported_ast = parse_with_imports_impl(sources, import_file, visited, module_cache, imports_cache, target)?;
                 
                 // Get the module's local items and its own imports
                 let module_items = imported_ast.local_items.clone();
@@ -267,10 +297,11 @@ fn parse_with_imports_impl(
                 
                 // Re-parse just to get imports (inefficient but correct for now)
                 let module_own_imports: Vec<ImportDecl> = {
-                    let temp_ast = Parser::parse(&import_source).map_err(|e| format!("Parse error: {}", e))?;
-                    temp_ast.items.iter().filter_map(|item| {
+                    let temp_ast = Parser::parse_file(sources.text(import_file), import_file)
+                        .map_err(into_diagnostics)?;
+                    configure_items(temp_ast.items, target).into_iter().filter_map(|item| {
                         if let Item::Import(imp) = item {
-                            Some(imp.clone())
+                            Some(imp)
                         } else {
                             None
                         }
@@ -279,7 +310,7 @@ fn parse_with_imports_impl(
                 
                 // Cache this module's items and imports
                 module_cache.insert(canonical.clone(), module_items.clone());
-                imports_cache.insert(canonical.clone(), (module_items.clone(), module_own_imports.clone()));
+                imports_cache.insert(canonical.clone(), (import_file, module_items.clone(), module_own_imports.clone()));
                 
                 // First, add the transitive imports as separate modules
                 // Mark them as transitive so they don't create top-level namespaces
@@ -291,6 +322,7 @@ fn parse_with_imports_impl(
                 // Then add this module with its local items and its own imports
                 imported_modules.push(ImportedModule {
                     import,
+                    file: import_file,
                     items: module_items,
                     module_imports: module_own_imports,
                     is_transitive: false,
@@ -306,6 +338,17 @@ fn parse_with_imports_impl(
     })
 }
 
+fn into_diagnostics(errors: Vec<ParseError>) -> Vec<Diagnostic> {
+    errors.into_iter().map(Into::into).collect()
+}
+
+/// File of a module that has already been parsed or is being parsed
+fn module_file(sources: &SourceMap, canonical: &Path) -> FileId {
+    // Imports are added under their canonical paths; only the root file,
+    // which is in `visited` from the start, may be missing
+    sources.find(canonical).unwrap_or(FileId::ROOT)
+}
+
 /// Resolve an import path to a file system path
 /// Detect if an import path was resolved to a parent module, indicating an item import
 /// e.g., `import std.io.print` where path has ["std", "io", "print"] but resolved to `std/io.ws`
@@ -464,20 +507,32 @@ fn format_import_path(path: &ImportPath) -> String {
 
 impl<'src> Parser<'src> {
     pub fn new(source: &'src str) -> ParseResult<Self> {
-        let tokens = Lexer::tokenize(source)
+        Self::with_file(source, FileId::ROOT)
+    }
+
+    /// Parser whose spans point into `file`
+    pub fn with_file(source: &'src str, file: FileId) -> ParseResult<Self> {
+        let tokens = Lexer::tokenize_file(source, file)
             .map_err(|e| ParseError { message: e.message, span: e.span })?;
         Ok(Self { tokens, pos: 0, source, errors: Vec::new() })
     }
 
-    pub fn parse(source: &str) -> ParseResult<SourceFile> {
-        let mut parser = Parser::new(source)?;
-        parser.parse_source_file()
+    pub fn parse(source: &str) -> Result<SourceFile, Vec<ParseError>> {
+        Self::parse_file(source, FileId::ROOT)
+    }
+
+    /// Parse the source of `file`, failing with every error found in it
+    pub fn parse_file(source: &str, file: FileId) -> Result<SourceFile, Vec<ParseError>> {
+        let mut parser = Parser::with_file(source, file).map_err(|e| vec![e])?;
+        let ast = parser.parse_source_file();
+        if parser.errors.is_empty() { Ok(ast) } else { Err(parser.errors) }
     }
     
-    /// Parse with error recovery - returns partial AST and all parse errors
+    /// Parse with error recovery - returns partial AST and all parse errors;
+    /// fails only if the source can't be lexed
     pub fn parse_with_recovery(source: &str) -> Result<ParseResultWithErrors<SourceFile>, ParseError> {
         let mut parser = Parser::new(source)?;
-        let ast = parser.parse_source_file()?;
+        let ast = parser.parse_source_file();
         Ok(ParseResultWithErrors {
             ast,
             errors: parser.errors,
@@ -551,22 +606,27 @@ impl<'src> Parser<'src> {
 
     // === Parsing ===
 
-    fn parse_source_file(&mut self) -> ParseResult<SourceFile> {
+    /// Parse every item, recording the errors of those that don't parse in
+    /// `self.errors` and skipping ahead to the next item
+    fn parse_source_file(&mut self) -> SourceFile {
         let mut items = Vec::new();
-        let mut errors = Vec::new();
         
         while !self.is_at_end() {
+            let start = self.pos;
             match self.parse_item() {
                 Ok(item) => {
                     items.push(item);
                 }
                 Err(err) => {
                     // Record the error and try to continue parsing
-                    errors.push(err);
+                    self.errors.push(err);
                     
-                    // Skip to next likely item start (next line with 'import', 'fn', 'struct', etc.)
-                    // or advance at least one token to avoid infinite loop
-                    self.advance();
+                    // Skip to next likely item start (next line with 'import', 'fn', 'struct', etc.),
+                    // advancing at least one token to avoid an infinite loop unless the
+                    // item got as far as the start of the next one
+                    if self.pos == start {
+                        self.advance();
+                    }
                     
                     // Try to recover by finding the next top-level keyword
                     while !self.is_at_end() {
@@ -584,18 +644,138 @@ impl<'src> Parser<'src> {
             }
         }
         
-        // If we couldn't parse anything, return the first error as a hard failure
-        if items.is_empty() && !errors.is_empty() {
-            return Err(errors.into_iter().next().unwrap());
+        SourceFile { items }
+    }
+
+    fn parse_item(&mut self) -> ParseResult<Item> {
+        // Item attributes: @non_exhaustive, @defaults, @test, @cfg(...). A
+        // misused attribute is recorded in `self.errors` and the item kept,
+        // so the mistake doesn't take the item or the one after it with it
+        let start = self.peek_span();
+        let mut attributes = Vec::new();
+        let mut cfgs = Vec::new();
+        while self.check(&Token::At) {
+            self.advance();
+            let attr = self.expect_ident()?;
+            if attr.name == "cfg" {
+                self.expect(Token::LParen)?;
+                let predicate_start = self.pos;
+                match self.parse_cfg_predicate() {
+                    Ok(predicate) => {
+                        cfgs.push(predicate);
+                        self.expect(Token::RParen)?;
+                    }
+                    Err(err) => {
+                        self.errors.push(err);
+                        self.pos = predicate_start;
+                        self.skip_past_cfg_predicate();
+                    }
+                }
+            } else {
+                attributes.push(attr);
+            }
         }
         
-        // Store errors in the parser for retrieval
-        self.errors = errors;
-        
-        Ok(SourceFile { items })
+        let mut item = self.parse_item_inner()?;
+        for attr in attributes {
+            let message = match (attr.name.as_str(), &mut item) {
+                ("non_exhaustive", Item::Enum(e)) => {
+                    e.is_non_exhaustive = true;
+                    continue;
+                }
+                ("non_exhaustive", _) => "'@non_exhaustive' can only be applied to enums".to_string(),
+                ("defaults", Item::Struct(s)) => {
+                    s.has_defaults = true;
+                    continue;
+                }
+                ("defaults", _) => "'@defaults' can only be applied to structs".to_string(),
+                ("test", Item::Function(f)) if f.params.is_empty() && f.return_type.is_none() => {
+                    f.is_test = true;
+                    continue;
+                }
+                ("test", Item::Function(_)) => "'@test' functions can't take parameters or return a value".to_string(),
+                ("test", _) => "'@test' can only be applied to functions".to_string(),
+                _ => format!("unknown attribute '@{}'", attr.name),
+            };
+            self.errors.push(ParseError { message, span: attr.span });
+        }
+        if cfgs.is_empty() {
+            return Ok(item);
+        }
+        let predicate = if cfgs.len() == 1 { cfgs.pop().unwrap() } else { CfgPredicate::All(cfgs) };
+        let span = start.to(self.tokens[self.pos - 1].span);
+        Ok(Item::Cfg(CfgItem { predicate, item: Box::new(item), span }))
     }
 
-    fn parse_item(&mut self) -> ParseResult<Item> {
+    /// Skip the predicate of a `@cfg(...)` that didn't parse, up to and
+    /// including the parenthesis closing it
+    fn skip_past_cfg_predicate(&mut self) {
+        let mut depth = 1;
+        while !self.is_at_end() {
+            match self.peek() {
+                Token::LParen => depth += 1,
+                Token::RParen => depth -= 1,
+                // An unclosed predicate: leave the item to be parsed
+                Token::Fn | Token::Extern | Token::Struct | Token::Enum | Token::Trait |
+                Token::Impl | Token::Pub | Token::Import | Token::At => return,
+                _ => {}
+            }
+            self.advance();
+            if depth == 0 {
+                return;
+            }
+        }
+    }
+
+    /// `target_os = "linux"`, `not(p)`, `all(p, ...)` or `any(p, ...)`
+    fn parse_cfg_predicate(&mut self) -> ParseResult<CfgPredicate> {
+        let name = self.expect_ident()?;
+        match name.name.as_str() {
+            "not" | "all" | "any" => {
+                self.expect(Token::LParen)?;
+                let mut predicates = Vec::new();
+                while !self.check(&Token::RParen) {
+                    predicates.push(self.parse_cfg_predicate()?);
+                    if !self.check(&Token::RParen) {
+                        self.expect(Token::Comma)?;
+                    }
+                }
+                let end = self.expect(Token::RParen)?.span;
+                match name.name.as_str() {
+                    "not" if predicates.len() == 1 => Ok(CfgPredicate::Not(Box::new(predicates.remove(0)))),
+                    "not" => Err(ParseError {
+                        message: "'not' takes exactly one cfg predicate".to_string(),
+                        span: name.span.to(end),
+                    }),
+                    "all" => Ok(CfgPredicate::All(predicates)),
+                    _ => Ok(CfgPredicate::Any(predicates)),
+                }
+            }
+            key if CfgPredicate::KEYS.contains(&key) => {
+                self.expect(Token::Eq)?;
+                match self.peek().clone() {
+                    Token::StringLiteral(value) => {
+                        self.advance();
+                        Ok(CfgPredicate::KeyValue(name, value))
+                    }
+                    _ => Err(ParseError {
+                        message: format!("expected a string after '{} =', found '{}'", key, self.peek()),
+                        span: self.peek_span(),
+                    }),
+                }
+            }
+            _ => Err(ParseError {
+                message: format!(
+                    "unknown cfg predicate '{}' (expected {}, not, all or any)",
+                    name.name,
+                    CfgPredicate::KEYS.join(", "),
+                ),
+                span: name.span,
+            }),
+        }
+    }
+
+    fn parse_item_inner(&mut self) -> ParseResult<Item> {
         // Check for optional pub keyword
         let is_pub = if self.check(&Token::Pub) {
             self.advance();
@@ -648,7 +828,7 @@ impl<'src> Parser<'src> {
                 };
                 
                 let end_span = return_type.as_ref().map(|t| t.span).unwrap_or(start);
-                let span = Span::new(start.start, end_span.end);
+                let span = start.to(end_span);
                 
                 Ok(Item::ExternFunction(ExternFnDef { is_pub, name, params, return_type, span }))
             }
@@ -659,7 +839,7 @@ impl<'src> Parser<'src> {
                 self.expect(Token::Colon)?;
                 let ty = self.parse_type()?;
                 
-                let span = Span::new(start.start, ty.span.end);
+                let span = start.to(ty.span);
                 
                 Ok(Item::ExternStatic(ExternStaticDef { is_pub, name, ty, span }))
             }
@@ -713,7 +893,7 @@ impl<'src> Parser<'src> {
             alias,
             items,
             destructure_only: false,
-            span: Span::new(start.start, end.start),
+            span: start.until(end),
         })
     }
     
@@ -747,7 +927,7 @@ impl<'src> Parser<'src> {
             alias: None,
             items: Some(items),
             destructure_only: true,
-            span: Span::new(start.start, end.start),
+            span: start.until(end),
         })
     }
     
@@ -840,7 +1020,7 @@ impl<'src> Parser<'src> {
             items.push(ImportItem {
                 name,
                 alias,
-                span: Span::new(start.start, end.start),
+                span: start.until(end),
             });
             
             if !self.check(&Token::RBrace) {
@@ -886,9 +1066,9 @@ impl<'src> Parser<'src> {
         let end_span = body.as_ref().map(|b| b.span)
             .or(return_type.as_ref().map(|t| t.span))
             .unwrap_or(start);
-        let span = Span::new(start.start, end_span.end);
+        let span = start.to(end_span);
         
-        Ok(FnDef { is_pub, name, type_params, params, return_type, body, span })
+        Ok(FnDef { is_pub, name, type_params, params, return_type, body, is_test: false, span })
     }
     
     /// Parse generic parameters: <T, U: Clone + Debug, V = i32>
@@ -917,7 +1097,7 @@ impl<'src> Parser<'src> {
                 None
             };
             
-            let span = Span::new(start.start, self.peek_span().end);
+            let span = start.to(self.peek_span());
             params.push(GenericParam { name, bounds, default, span });
             
             if !self.check(&Token::Gt) {
@@ -978,9 +1158,9 @@ impl<'src> Parser<'src> {
                         kind: TypeKind::Named(Ident::new("Self".to_string(), span), Vec::new()),
                         span,
                     })),
-                    span: Span::new(start.start, span.end),
+                    span: start.to(span),
                 };
-                return Ok(Param { name, is_mut: false, ty, span: Span::new(start.start, span.end) });
+                return Ok(Param { name, is_mut: false, ty, span: start.to(span) });
             }
         }
         
@@ -1007,7 +1187,7 @@ impl<'src> Parser<'src> {
         self.expect(Token::Colon)?;
         let ty = self.parse_type()?;
         
-        let span = Span::new(start.start, ty.span.end);
+        let span = start.to(ty.span);
         
         Ok(Param { name, is_mut, ty, span })
     }
@@ -1029,9 +1209,9 @@ impl<'src> Parser<'src> {
         let fields = self.parse_struct_fields()?;
         let end = self.expect(Token::RBrace)?;
         
-        let span = Span::new(start.start, end.span.end);
+        let span = start.to(end.span);
         
-        Ok(StructDef { is_pub, name, type_params, fields, span })
+        Ok(StructDef { is_pub, name, type_params, fields, has_defaults: false, span })
     }
 
     fn parse_struct_fields(&mut self) -> ParseResult<Vec<StructField>> {
@@ -1043,7 +1223,7 @@ impl<'src> Parser<'src> {
             self.expect(Token::Colon)?;
             let ty = self.parse_type()?;
             
-            let span = Span::new(start.start, ty.span.end);
+            let span = start.to(ty.span);
             fields.push(StructField { name, ty, span });
             
             // Optional trailing comma
@@ -1072,9 +1252,9 @@ impl<'src> Parser<'src> {
         let variants = self.parse_enum_variants()?;
         let end = self.expect(Token::RBrace)?;
         
-        let span = Span::new(start.start, end.span.end);
+        let span = start.to(end.span);
         
-        Ok(EnumDef { is_pub, name, type_params, variants, span })
+        Ok(EnumDef { is_pub, name, type_params, variants, is_non_exhaustive: false, span })
     }
 
     fn parse_enum_variants(&mut self) -> ParseResult<Vec<EnumVariant>> {
@@ -1094,7 +1274,7 @@ impl<'src> Parser<'src> {
             };
             
             let end_span = if fields.is_empty() { name.span } else { self.tokens[self.pos - 1].span };
-            let span = Span::new(start.start, end_span.end);
+            let span = start.to(end_span);
             variants.push(EnumVariant { name, fields, span });
             
             // Optional trailing comma
@@ -1146,7 +1326,7 @@ impl<'src> Parser<'src> {
                 (name, ty)
             };
             
-            let span = Span::new(start.start, ty.span.end);
+            let span = start.to(ty.span);
             fields.push(StructField { name, ty, span });
             field_index += 1;
             
@@ -1186,7 +1366,7 @@ impl<'src> Parser<'src> {
         }
         
         let end = self.expect(Token::RBrace)?;
-        let span = Span::new(start.start, end.span.end);
+        let span = start.to(end.span);
         
         Ok(TraitDef { is_pub, name, type_params, methods, span })
     }
@@ -1236,7 +1416,7 @@ impl<'src> Parser<'src> {
         }
         
         let end = self.expect(Token::RBrace)?;
-        let span = Span::new(start.start, end.span.end);
+        let span = start.to(end.span);
         
         Ok(ImplBlock { type_params, trait_name, trait_type_args, target_type, methods, span })
     }
@@ -1254,12 +1434,26 @@ impl<'src> Parser<'src> {
                 false
             };
             
-            // Check for slice: &[T]
+            // Check for slice: &[T], as opposed to a reference to an array: &[T; N]
             if self.check(&Token::LBracket) {
+                let bracket = self.peek_span();
                 self.advance();
                 let elem = self.parse_type()?;
+                if self.check(&Token::Semi) {
+                    self.advance();
+                    let size = self.parse_expr()?;
+                    let end = self.expect(Token::RBracket)?;
+                    let array = TypeExpr {
+                        kind: TypeKind::Array(Box::new(elem), Box::new(size)),
+                        span: bracket.to(end.span),
+                    };
+                    return Ok(TypeExpr {
+                        kind: TypeKind::Ref(is_mut, Box::new(array)),
+                        span: start.to(end.span),
+                    });
+                }
                 let end = self.expect(Token::RBracket)?;
-                let span = Span::new(start.start, end.span.end);
+                let span = start.to(end.span);
                 return Ok(TypeExpr {
                     kind: TypeKind::Slice(Box::new(elem)),
                     span,
@@ -1267,28 +1461,54 @@ impl<'src> Parser<'src> {
             }
             
             let inner = self.parse_type()?;
-            let span = Span::new(start.start, inner.span.end);
+            let span = start.to(inner.span);
             return Ok(TypeExpr {
                 kind: TypeKind::Ref(is_mut, Box::new(inner)),
                 span,
             });
         }
         
-        // Unit type or tuple: ()
-        if self.check(&Token::LParen) {
+        // Function type: fn(T, U) -> R, with the return type defaulting to ()
+        if self.check(&Token::Fn) {
             self.advance();
-            if self.check(&Token::RParen) {
-                let end = self.advance();
+            let (params, params_end) = self.parse_type_list()?;
+            let ret = if self.check(&Token::Arrow) {
+                self.advance();
+                self.parse_type()?
+            } else {
+                TypeExpr { kind: TypeKind::Unit, span: params_end }
+            };
+            let span = start.to(ret.span);
+            return Ok(TypeExpr {
+                kind: TypeKind::Function(params, Box::new(ret)),
+                span,
+            });
+        }
+        
+        // Unit type, tuple or arrow function type: (), (T, U) -> R
+        if self.check(&Token::LParen) {
+            let (params, end) = self.parse_type_list()?;
+            if self.check(&Token::Arrow) {
+                self.advance();
+                let ret = self.parse_type()?;
+                let span = start.to(ret.span);
                 return Ok(TypeExpr {
-                    kind: TypeKind::Unit,
-                    span: Span::new(start.start, end.span.end),
+                    kind: TypeKind::Function(params, Box::new(ret)),
+                    span,
                 });
             }
-            // TODO: Tuple types
-            return Err(ParseError {
-                message: "tuple types not yet supported".to_string(),
-                span: self.peek_span(),
-            });
+            let span = start.to(end);
+            if params.is_empty() {
+                return Ok(TypeExpr { kind: TypeKind::Unit, span });
+            }
+            // A single type without a trailing comma is just parenthesized: (T) vs (T,)
+            let trailing_comma = matches!(self.tokens[self.pos - 2].token, Token::Comma);
+            if params.len() == 1 && !trailing_comma {
+                let mut inner = params.into_iter().next().unwrap();
+                inner.span = span;
+                return Ok(inner);
+            }
+            return Ok(TypeExpr { kind: TypeKind::Tuple(params), span });
         }
         
         // Array type: [T; N]
@@ -1298,7 +1518,7 @@ impl<'src> Parser<'src> {
             self.expect(Token::Semi)?;
             let size = self.parse_expr()?;
             let end = self.expect(Token::RBracket)?;
-            let span = Span::new(start.start, end.span.end);
+            let span = start.to(end.span);
             return Ok(TypeExpr {
                 kind: TypeKind::Array(Box::new(elem), Box::new(size)),
                 span,
@@ -1316,7 +1536,7 @@ impl<'src> Parser<'src> {
         };
         
         let end_span = if type_args.is_empty() { name.span } else { self.tokens[self.pos - 1].span };
-        let span = Span::new(name.span.start, end_span.end);
+        let span = name.span.to(end_span);
         
         Ok(TypeExpr {
             kind: TypeKind::Named(name, type_args),
@@ -1324,6 +1544,24 @@ impl<'src> Parser<'src> {
         })
     }
     
+    /// Parse a parenthesized list of types: (i32, &str). Returns the types and the span of the `)`
+    fn parse_type_list(&mut self) -> ParseResult<(Vec<TypeExpr>, Span)> {
+        self.expect(Token::LParen)?;
+        
+        let mut types = Vec::new();
+        
+        while !self.check(&Token::RParen) && !self.is_at_end() {
+            types.push(self.parse_type()?);
+            
+            if !self.check(&Token::RParen) {
+                self.expect(Token::Comma)?;
+            }
+        }
+        
+        let end = self.expect(Token::RParen)?;
+        Ok((types, end.span))
+    }
+    
     /// Parse type arguments: <i32, String>
     fn parse_type_args(&mut self) -> ParseResult<Vec<TypeExpr>> {
         self.expect(Token::Lt)?;
@@ -1353,7 +1591,7 @@ impl<'src> Parser<'src> {
         }
         
         let end = self.expect(Token::RBrace)?;
-        let span = Span::new(start.start, end.span.end);
+        let span = start.to(end.span);
         
         Ok(Block { stmts, span })
     }
@@ -1361,6 +1599,7 @@ impl<'src> Parser<'src> {
     fn parse_stmt(&mut self) -> ParseResult<Stmt> {
         let stmt = match self.peek() {
             Token::Let => self.parse_let_stmt(),
+            Token::Defer => self.parse_defer_stmt(),
             _ => self.parse_expr_stmt(),
         }?;
         
@@ -1383,7 +1622,7 @@ impl<'src> Parser<'src> {
             false
         };
         
-        let name = self.expect_ident()?;
+        let pattern = self.parse_binding_pattern("let")?;
         
         let ty = if self.check(&Token::Colon) {
             self.advance();
@@ -1401,11 +1640,21 @@ impl<'src> Parser<'src> {
         
         let end_span = init.as_ref().map(|e| e.span)
             .or(ty.as_ref().map(|t| t.span))
-            .unwrap_or(name.span);
+            .unwrap_or(pattern.span);
+        
+        let span = start.to(end_span);
+        
+        Ok(Stmt::Let(LetStmt { pattern, is_mut, ty, init, span }))
+    }
+
+    fn parse_defer_stmt(&mut self) -> ParseResult<Stmt> {
+        let start = self.peek_span();
+        self.expect(Token::Defer)?;
         
-        let span = Span::new(start.start, end_span.end);
+        let body = self.parse_block()?;
+        let span = start.to(body.span);
         
-        Ok(Stmt::Let(LetStmt { name, is_mut, ty, init, span }))
+        Ok(Stmt::Defer(DeferStmt { body, span }))
     }
 
     fn parse_expr_stmt(&mut self) -> ParseResult<Stmt> {
@@ -1447,7 +1696,7 @@ impl<'src> Parser<'src> {
         if let Some(op) = compound_op {
             self.advance();
             let rhs = self.parse_assignment(allow_struct_lit)?;
-            let span = Span::new(expr.span.start, rhs.span.end);
+            let span = expr.span.to(rhs.span);
             
             // Create: expr = expr op rhs
             let binary = Expr {
@@ -1463,7 +1712,7 @@ impl<'src> Parser<'src> {
         if self.check(&Token::Eq) {
             self.advance();
             let rhs = self.parse_assignment(allow_struct_lit)?;
-            let span = Span::new(expr.span.start, rhs.span.end);
+            let span = expr.span.to(rhs.span);
             return Ok(Expr {
                 kind: ExprKind::Assign(Box::new(expr), Box::new(rhs)),
                 span,
@@ -1485,7 +1734,7 @@ impl<'src> Parser<'src> {
             self.advance(); // consume operator
             let right = self.parse_binary_inner(prec + 1, allow_struct_lit)?;
             
-            let span = Span::new(left.span.start, right.span.end);
+            let span = left.span.to(right.span);
             left = Expr {
                 kind: ExprKind::Binary(Box::new(left), op, Box::new(right)),
                 span,
@@ -1521,7 +1770,7 @@ impl<'src> Parser<'src> {
         if self.check(&Token::Minus) {
             self.advance();
             let expr = self.parse_unary_inner(allow_struct_lit)?;
-            let span = Span::new(start.start, expr.span.end);
+            let span = start.to(expr.span);
             return Ok(Expr {
                 kind: ExprKind::Unary(UnaryOp::Neg, Box::new(expr)),
                 span,
@@ -1531,7 +1780,7 @@ impl<'src> Parser<'src> {
         if self.check(&Token::Not) {
             self.advance();
             let expr = self.parse_unary_inner(allow_struct_lit)?;
-            let span = Span::new(start.start, expr.span.end);
+            let span = start.to(expr.span);
             return Ok(Expr {
                 kind: ExprKind::Unary(UnaryOp::Not, Box::new(expr)),
                 span,
@@ -1547,7 +1796,7 @@ impl<'src> Parser<'src> {
                 false
             };
             let expr = self.parse_unary_inner(allow_struct_lit)?;
-            let span = Span::new(start.start, expr.span.end);
+            let span = start.to(expr.span);
             return Ok(Expr {
                 kind: ExprKind::Ref(is_mut, Box::new(expr)),
                 span,
@@ -1557,7 +1806,7 @@ impl<'src> Parser<'src> {
         if self.check(&Token::Star) {
             self.advance();
             let expr = self.parse_unary_inner(allow_struct_lit)?;
-            let span = Span::new(start.start, expr.span.end);
+            let span = start.to(expr.span);
             return Ok(Expr {
                 kind: ExprKind::Deref(Box::new(expr)),
                 span,
@@ -1574,28 +1823,33 @@ impl<'src> Parser<'src> {
             if self.check(&Token::LParen) {
                 // Function call
                 self.advance();
-                let args = self.parse_arg_list()?;
+                let mut args = self.parse_arg_list()?;
                 let end = self.expect(Token::RParen)?;
-                let span = Span::new(expr.span.start, end.span.end);
+                let mut span = expr.span.to(end.span);
+                if allow_struct_lit && let Some(block) = self.parse_trailing_block(end.span)? {
+                    span = span.to(block.span);
+                    args.push(block);
+                }
                 expr = Expr {
                     kind: ExprKind::Call(Box::new(expr), args),
                     span,
                 };
             } else if self.check(&Token::Dot) {
-                // Field access
+                // Field access, or a tuple element: pair.0
                 self.advance();
-                let field = self.expect_ident()?;
-                let span = Span::new(expr.span.start, field.span.end);
-                expr = Expr {
-                    kind: ExprKind::Field(Box::new(expr), field),
-                    span,
-                };
+                for field in self.parse_field_names()? {
+                    let span = expr.span.to(field.span);
+                    expr = Expr {
+                        kind: ExprKind::Field(Box::new(expr), field),
+                        span,
+                    };
+                }
             } else if self.check(&Token::LBracket) {
                 // Index
                 self.advance();
                 let index = self.parse_expr()?;
                 let end = self.expect(Token::RBracket)?;
-                let span = Span::new(expr.span.start, end.span.end);
+                let span = expr.span.to(end.span);
                 expr = Expr {
                     kind: ExprKind::Index(Box::new(expr), Box::new(index)),
                     span,
@@ -1604,11 +1858,19 @@ impl<'src> Parser<'src> {
                 // Type cast: expr as Type
                 self.advance();
                 let ty = self.parse_type()?;
-                let span = Span::new(expr.span.start, ty.span.end);
+                let span = expr.span.to(ty.span);
                 expr = Expr {
                     kind: ExprKind::Cast(Box::new(expr), ty),
                     span,
                 };
+            } else if self.check(&Token::Question) {
+                // Error propagation: expr?
+                let end = self.advance().span;
+                let span = expr.span.to(end);
+                expr = Expr {
+                    kind: ExprKind::Try(Box::new(expr)),
+                    span,
+                };
             } else {
                 break;
             }
@@ -1667,7 +1929,7 @@ impl<'src> Parser<'src> {
             args.push(CallArg {
                 name,
                 value,
-                span: Span::new(start.start, end),
+                span: start.with_end(end),
             });
             
             if !self.check(&Token::RParen) {
@@ -1678,6 +1940,45 @@ impl<'src> Parser<'src> {
         Ok(args)
     }
 
+    /// Parse a compiler intrinsic: `#name(Type, args...)`
+    fn parse_intrinsic(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(Token::Hash)?.span;
+        let name = self.expect_ident()?;
+        let Some(intrinsic) = Intrinsic::from_name(&name.name) else {
+            return Err(ParseError {
+                message: format!("unknown intrinsic '#{}'", name.name),
+                span: name.span,
+            });
+        };
+        self.expect(Token::LParen)?;
+        let ty = self.parse_type()?;
+        let mut args = Vec::new();
+        while self.check(&Token::Comma) {
+            self.advance();
+            if self.check(&Token::RParen) {
+                break;
+            }
+            args.push(self.parse_expr()?);
+        }
+        let end = self.expect(Token::RParen)?.span;
+        let span = start.to(end);
+        if args.len() != intrinsic.arity() {
+            let expected = match intrinsic.arity() {
+                0 => "only a type".to_string(),
+                1 => "a type and 1 argument".to_string(),
+                n => format!("a type and {} arguments", n),
+            };
+            return Err(ParseError {
+                message: format!("'{}' takes {}, found {} argument(s)", intrinsic, expected, args.len()),
+                span,
+            });
+        }
+        Ok(Expr {
+            kind: ExprKind::Intrinsic(intrinsic, ty, args),
+            span,
+        })
+    }
+
     fn parse_primary_inner(&mut self, allow_struct_lit: bool) -> ParseResult<Expr> {
         let start = self.peek_span();
         
@@ -1733,10 +2034,21 @@ impl<'src> Parser<'src> {
                 self.advance();
                 let ident = Ident::new(name, start);
                 
-                // Check for struct literal: Ident { ... }
+                // Check for struct literal: Ident { ... }, or a call with only
+                // a trailing block: Text { "hello" }
                 // Only allowed when allow_struct_lit is true (not in if/while conditions)
                 if allow_struct_lit && self.check(&Token::LBrace) {
-                    return self.parse_struct_literal(ident);
+                    if self.at_record_literal() || matches!(self.tokens.get(self.pos + 1).map(|t| &t.token), Some(Token::RBrace)) {
+                        return self.parse_struct_literal(Some(ident));
+                    }
+                    if let Some(block) = self.parse_trailing_block(start)? {
+                        let span = start.to(block.span);
+                        let callee = Expr { kind: ExprKind::Ident(ident), span: start };
+                        return Ok(Expr {
+                            kind: ExprKind::Call(Box::new(callee), vec![block]),
+                            span,
+                        });
+                    }
                 }
                 
                 Ok(Expr {
@@ -1747,7 +2059,60 @@ impl<'src> Parser<'src> {
             Token::If => self.parse_if_expr(),
             Token::While => self.parse_while_expr(),
             Token::For => self.parse_for_expr(),
+            Token::Loop => self.parse_loop_expr(),
+            Token::Return => {
+                self.advance();
+                let value = if self.at_expr_terminator() {
+                    None
+                } else {
+                    Some(Box::new(self.parse_expr()?))
+                };
+                let end = value.as_ref().map(|v| v.span).unwrap_or(start);
+                Ok(Expr {
+                    kind: ExprKind::Return(value),
+                    span: start.to(end),
+                })
+            }
+            Token::Break => {
+                self.advance();
+                let label = self.parse_loop_label()?;
+                let value = if self.at_expr_terminator() {
+                    None
+                } else {
+                    Some(Box::new(self.parse_expr()?))
+                };
+                let end = value.as_ref().map(|v| v.span)
+                    .or(label.as_ref().map(|l| l.span))
+                    .unwrap_or(start);
+                Ok(Expr {
+                    kind: ExprKind::Break(label, value),
+                    span: start.to(end),
+                })
+            }
+            Token::Continue => {
+                self.advance();
+                let label = self.parse_loop_label()?;
+                let end = label.as_ref().map(|l| l.span).unwrap_or(start);
+                Ok(Expr {
+                    kind: ExprKind::Continue(label),
+                    span: start.to(end),
+                })
+            }
             Token::Match => self.parse_match_expr(),
+            // `{ name: ...` or `{ ..base` is a record literal typed by context
+            Token::LBrace if allow_struct_lit && self.at_record_literal() => {
+                self.parse_struct_literal(None)
+            }
+            // `{ "key": ...` is a map literal
+            Token::LBrace if allow_struct_lit && self.at_map_literal() => {
+                self.advance();
+                let elements = self.parse_collection_elems(&Token::RBrace, true)?;
+                let end = self.expect(Token::RBrace)?;
+                Ok(Expr {
+                    kind: ExprKind::MapLit(elements),
+                    span: start.to(end.span),
+                })
+            }
             Token::LBrace => {
                 let block = self.parse_block()?;
                 let span = block.span;
@@ -1756,33 +2121,49 @@ impl<'src> Parser<'src> {
                     span,
                 })
             }
+            Token::Hash => self.parse_intrinsic(),
+            Token::Move => {
+                self.advance();
+                let lambda = if self.check(&Token::LParen) { self.try_parse_lambda(start, true)? } else { None };
+                lambda.ok_or_else(|| ParseError {
+                    message: format!("expected a closure after 'move', found '{}'", self.peek()),
+                    span: self.peek_span(),
+                })
+            }
             Token::LParen => {
                 // Could be grouped expression or lambda
                 // Try to parse as lambda first by looking ahead
-                if let Some(lambda) = self.try_parse_lambda(start)? {
-                    Ok(lambda)
-                } else {
-                    self.advance();
-                    let expr = self.parse_expr()?;
+                if let Some(lambda) = self.try_parse_lambda(start, false)? {
+                    return Ok(lambda);
+                }
+                self.advance();
+                let expr = self.parse_expr()?;
+                if !self.check(&Token::Comma) {
                     self.expect(Token::RParen)?;
-                    Ok(expr)
+                    return Ok(expr);
+                }
+
+                // Tuple literal: (a, b), or (a,) for a single element
+                let mut elements = vec![expr];
+                while self.check(&Token::Comma) {
+                    self.advance();
+                    if self.check(&Token::RParen) {
+                        break;
+                    }
+                    elements.push(self.parse_expr()?);
                 }
+                let end = self.expect(Token::RParen)?;
+                Ok(Expr {
+                    kind: ExprKind::Tuple(elements),
+                    span: start.to(end.span),
+                })
             }
             Token::LBracket => {
-                // Array literal: [1, 2, 3]
+                // List literal: [1, 2, 3], [..xs, if c { y }, for z in zs { z }]
                 self.advance();
-                let mut elements = Vec::new();
-                
-                while !self.check(&Token::RBracket) && !self.is_at_end() {
-                    elements.push(self.parse_expr()?);
-                    
-                    if !self.check(&Token::RBracket) {
-                        self.expect(Token::Comma)?;
-                    }
-                }
-                
+                let elements = self.parse_collection_elems(&Token::RBracket, false)?;
                 let end = self.expect(Token::RBracket)?;
-                let span = Span::new(start.start, end.span.end);
+                let span = start.to(end.span);
                 
                 Ok(Expr {
                     kind: ExprKind::ArrayLit(elements),
@@ -1797,8 +2178,8 @@ impl<'src> Parser<'src> {
     }
 
     /// Try to parse a lambda expression. Returns None if not a lambda.
-    /// Lambda syntax: (params) -> body
-    fn try_parse_lambda(&mut self, start: Span) -> ParseResult<Option<Expr>> {
+    /// Lambda syntax: (params) -> body, after `move` if `is_move`
+    fn try_parse_lambda(&mut self, start: Span, is_move: bool) -> ParseResult<Option<Expr>> {
         // Save position for backtracking
         let saved_pos = self.pos;
         
@@ -1822,7 +2203,7 @@ impl<'src> Parser<'src> {
                     None
                 };
                 
-                let span = Span::new(param_start.start, self.peek_span().start);
+                let span = param_start.until(self.peek_span());
                 params.push(LambdaParam { name: name_ident, ty, span });
                 
                 if !self.check(&Token::RParen) {
@@ -1855,28 +2236,170 @@ impl<'src> Parser<'src> {
         
         // Parse body
         let body = self.parse_expr()?;
-        let span = Span::new(start.start, body.span.end);
+        let span = start.to(body.span);
         
         Ok(Some(Expr {
-            kind: ExprKind::Lambda(params, Box::new(body)),
+            kind: ExprKind::Lambda(is_move, params, Box::new(body)),
+            span,
+        }))
+    }
+
+    /// Parse the block after a call, which is passed as its last argument.
+    /// It has to start on the line the call ends on, so a block statement on
+    /// the next line isn't taken as one. `f(x) { a: 1 }` passes a record literal.
+    fn parse_trailing_block(&mut self, call_end: Span) -> ParseResult<Option<CallArg>> {
+        if !self.check(&Token::LBrace) || self.source[call_end.end..self.peek_span().start].contains('\n') {
+            return Ok(None);
+        }
+        let block = if self.at_record_literal() {
+            let record = self.parse_struct_literal(None)?;
+            let span = record.span;
+            Block { stmts: vec![Stmt::Expr(ExprStmt { expr: record, span })], span }
+        } else {
+            self.parse_block()?
+        };
+        let span = block.span;
+        Ok(Some(CallArg {
+            name: None,
+            value: Expr { kind: ExprKind::TrailingBlock(block), span },
             span,
         }))
     }
 
-    fn parse_struct_literal(&mut self, name: Ident) -> ParseResult<Expr> {
-        let start = name.span;
+    /// Whether the `{` at the current position opens a record literal rather than a block
+    fn at_record_literal(&self) -> bool {
+        match self.tokens.get(self.pos + 1).map(|t| &t.token) {
+            Some(Token::DotDot) => true,
+            Some(Token::Ident(_)) => matches!(self.tokens.get(self.pos + 2).map(|t| &t.token), Some(Token::Colon)),
+            _ => false,
+        }
+    }
+
+    /// Whether the `{` at the current position opens a map literal: its first key is a literal
+    fn at_map_literal(&self) -> bool {
+        matches!(self.tokens.get(self.pos + 1).map(|t| &t.token), Some(Token::StringLiteral(_) | Token::IntLiteral(_)))
+            && matches!(self.tokens.get(self.pos + 2).map(|t| &t.token), Some(Token::Colon))
+    }
+
+    /// Parse the comma-separated elements of a list or map literal, up to `close`
+    fn parse_collection_elems(&mut self, close: &Token, is_map: bool) -> ParseResult<Vec<CollectionElem>> {
+        let mut elements = Vec::new();
+        while !self.check(close) && !self.is_at_end() {
+            elements.push(self.parse_collection_elem(close, is_map)?);
+            if !self.check(close) {
+                self.expect(Token::Comma)?;
+            }
+        }
+        Ok(elements)
+    }
+
+    /// Parse one element of a list or map literal. In a list, an `if` or `for`
+    /// whose body isn't a single element is an ordinary expression.
+    fn parse_collection_elem(&mut self, close: &Token, is_map: bool) -> ParseResult<CollectionElem> {
+        let start = self.peek_span();
+        if self.check(&Token::DotDot) {
+            self.advance();
+            let base = self.parse_expr()?;
+            let span = start.to(base.span);
+            return Ok(CollectionElem { kind: CollectionElemKind::Spread(Box::new(base)), span });
+        }
+        if self.check(&Token::If) || self.check(&Token::For) {
+            let saved_pos = self.pos;
+            match self.parse_collection_control(is_map) {
+                Ok(elem) if is_map || self.check(&Token::Comma) || self.check(close) => return Ok(elem),
+                Err(e) if is_map => return Err(e),
+                _ => self.pos = saved_pos,
+            }
+        }
+        let value = self.parse_expr()?;
+        if !is_map {
+            let span = value.span;
+            return Ok(CollectionElem { kind: CollectionElemKind::Item(value), span });
+        }
+        self.expect(Token::Colon)?;
+        let entry_value = self.parse_expr()?;
+        let span = start.to(entry_value.span);
+        Ok(CollectionElem { kind: CollectionElemKind::Entry(Box::new(value), Box::new(entry_value)), span })
+    }
+
+    /// Parse an `if` or `for` element, whose bodies hold a single element
+    fn parse_collection_control(&mut self, is_map: bool) -> ParseResult<CollectionElem> {
+        let start = self.peek_span();
+        if self.check(&Token::For) {
+            self.advance();
+            let pattern = self.parse_binding_pattern("for")?;
+            self.expect(Token::In)?;
+            let iter = self.parse_expr_no_struct()?;
+            let (elem, end) = self.parse_collection_body(is_map)?;
+            let span = start.to(end);
+            return Ok(CollectionElem { kind: CollectionElemKind::For(pattern, Box::new(iter), Box::new(elem)), span });
+        }
+        self.expect(Token::If)?;
+        let cond = self.parse_expr_no_struct()?;
+        let (then_elem, mut end) = self.parse_collection_body(is_map)?;
+        let else_elem = if self.check(&Token::Else) {
+            self.advance();
+            let elem = if self.check(&Token::If) {
+                let elem = self.parse_collection_control(is_map)?;
+                end = elem.span;
+                elem
+            } else {
+                let (elem, body_end) = self.parse_collection_body(is_map)?;
+                end = body_end;
+                elem
+            };
+            Some(Box::new(elem))
+        } else {
+            None
+        };
+        let span = start.to(end);
+        Ok(CollectionElem { kind: CollectionElemKind::If(Box::new(cond), Box::new(then_elem), else_elem), span })
+    }
+
+    /// Parse `{ elem }`, returning the element and the span of the closing brace
+    fn parse_collection_body(&mut self, is_map: bool) -> ParseResult<(CollectionElem, Span)> {
+        self.expect(Token::LBrace)?;
+        let elem = self.parse_collection_elem(&Token::RBrace, is_map)?;
+        if self.check(&Token::Comma) {
+            self.advance();
+        }
+        let end = self.expect(Token::RBrace)?;
+        Ok((elem, end.span))
+    }
+
+    fn parse_struct_literal(&mut self, name: Option<Ident>) -> ParseResult<Expr> {
+        let start = match &name {
+            Some(name) => name.span,
+            None => self.peek_span(),
+        };
         self.expect(Token::LBrace)?;
         
         let mut fields = Vec::new();
+        let mut spread = None;
         
         while !self.check(&Token::RBrace) && !self.is_at_end() {
-            let field_start = self.peek_span();
-            let field_name = self.expect_ident()?;
-            self.expect(Token::Colon)?;
-            let value = self.parse_expr()?;
-            
-            let span = Span::new(field_start.start, value.span.end);
-            fields.push(FieldInit { name: field_name, value, span });
+            // `..base` can stand anywhere among the fields, which are given
+            // in order with the last value of each winning
+            if self.check(&Token::DotDot) {
+                let dots = self.advance().span;
+                let base = self.parse_expr()?;
+                if spread.is_some() {
+                    self.errors.push(ParseError {
+                        message: "a record literal can only have one '..' spread".to_string(),
+                        span: dots.to(base.span),
+                    });
+                } else {
+                    spread = Some(Box::new(base));
+                }
+            } else {
+                let field_start = self.peek_span();
+                let field_name = self.expect_ident()?;
+                self.expect(Token::Colon)?;
+                let value = self.parse_expr()?;
+                
+                let span = field_start.to(value.span);
+                fields.push(FieldInit { name: field_name, value, span });
+            }
             
             if !self.check(&Token::RBrace) {
                 self.expect(Token::Comma)?;
@@ -1884,10 +2407,10 @@ impl<'src> Parser<'src> {
         }
         
         let end = self.expect(Token::RBrace)?;
-        let span = Span::new(start.start, end.span.end);
+        let span = start.to(end.span);
         
         Ok(Expr {
-            kind: ExprKind::StructLit(name, fields),
+            kind: ExprKind::StructLit(name, fields, spread),
             span,
         })
     }
@@ -1917,7 +2440,7 @@ impl<'src> Parser<'src> {
             Some(ElseBranch::If(e)) => e.span,
             None => then_block.span,
         };
-        let span = Span::new(start.start, end_span.end);
+        let span = start.to(end_span);
         
         Ok(Expr {
             kind: ExprKind::If(Box::new(cond), then_block, else_branch),
@@ -1925,17 +2448,37 @@ impl<'src> Parser<'src> {
         })
     }
 
+    /// Parse an optional `@label` after a loop keyword, `break` or `continue`
+    fn parse_loop_label(&mut self) -> ParseResult<Option<Ident>> {
+        if self.check(&Token::At) {
+            self.advance();
+            Ok(Some(self.expect_ident()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Check whether the next token ends an expression, so that
+    /// `return` and `break` carry no value
+    fn at_expr_terminator(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::RBrace | Token::RParen | Token::RBracket | Token::Semi | Token::Comma | Token::Eof
+        )
+    }
+
     fn parse_while_expr(&mut self) -> ParseResult<Expr> {
         let start = self.peek_span();
         self.expect(Token::While)?;
+        let label = self.parse_loop_label()?;
         
         let cond = self.parse_expr_no_struct()?;
         let body = self.parse_block()?;
         
-        let span = Span::new(start.start, body.span.end);
+        let span = start.to(body.span);
         
         Ok(Expr {
-            kind: ExprKind::While(Box::new(cond), body),
+            kind: ExprKind::While(label, Box::new(cond), body),
             span,
         })
     }
@@ -1943,16 +2486,32 @@ impl<'src> Parser<'src> {
     fn parse_for_expr(&mut self) -> ParseResult<Expr> {
         let start = self.peek_span();
         self.expect(Token::For)?;
+        let label = self.parse_loop_label()?;
         
-        let binding = self.expect_ident()?;
+        let pattern = self.parse_binding_pattern("for")?;
         self.expect(Token::In)?;
         let iter = self.parse_expr_no_struct()?;
         let body = self.parse_block()?;
         
-        let span = Span::new(start.start, body.span.end);
+        let span = start.to(body.span);
         
         Ok(Expr {
-            kind: ExprKind::For(binding, Box::new(iter), body),
+            kind: ExprKind::For(label, pattern, Box::new(iter), body),
+            span,
+        })
+    }
+
+    fn parse_loop_expr(&mut self) -> ParseResult<Expr> {
+        let start = self.peek_span();
+        self.expect(Token::Loop)?;
+        let label = self.parse_loop_label()?;
+        
+        let body = self.parse_block()?;
+        
+        let span = start.to(body.span);
+        
+        Ok(Expr {
+            kind: ExprKind::Loop(label, body),
             span,
         })
     }
@@ -1975,7 +2534,7 @@ impl<'src> Parser<'src> {
         }
         
         let end = self.expect(Token::RBrace)?;
-        let span = Span::new(start.start, end.span.end);
+        let span = start.to(end.span);
         
         Ok(Expr {
             kind: ExprKind::Match(Box::new(scrutinee), arms),
@@ -1989,7 +2548,7 @@ impl<'src> Parser<'src> {
         self.expect(Token::Arrow)?;
         let body = self.parse_expr()?;
         
-        let span = Span::new(start.start, body.span.end);
+        let span = start.to(body.span);
         Ok(MatchArm { pattern, body, span })
     }
 
@@ -2019,7 +2578,7 @@ impl<'src> Parser<'src> {
                         }
                     }
                     let end = self.expect(Token::RParen)?;
-                    let span = Span::new(start.start, end.span.end);
+                    let span = start.to(end.span);
                     return Ok(Pattern {
                         kind: PatternKind::Variant(ident, fields),
                         span,
@@ -2031,33 +2590,58 @@ impl<'src> Parser<'src> {
                     span: start,
                 })
             }
+            Token::LParen => {
+                // Tuple pattern: (x, _), or (x,) for a single element
+                self.advance();
+                let mut elems = Vec::new();
+                let mut trailing_comma = false;
+                while !self.check(&Token::RParen) && !self.is_at_end() {
+                    elems.push(self.parse_pattern()?);
+                    trailing_comma = false;
+                    if !self.check(&Token::RParen) {
+                        self.expect(Token::Comma)?;
+                        trailing_comma = true;
+                    }
+                }
+                let end = self.expect(Token::RParen)?;
+                let span = start.to(end.span);
+                if elems.len() == 1 && !trailing_comma {
+                    let mut inner = elems.pop().unwrap();
+                    inner.span = span;
+                    return Ok(inner);
+                }
+                Ok(Pattern {
+                    kind: PatternKind::Tuple(elems),
+                    span,
+                })
+            }
             Token::IntLiteral(n) => {
                 self.advance();
                 Ok(Pattern {
-                    kind: PatternKind::Literal(Expr {
+                    kind: PatternKind::Literal(Box::new(Expr {
                         kind: ExprKind::IntLiteral(n),
                         span: start,
-                    }),
+                    })),
                     span: start,
                 })
             }
             Token::True => {
                 self.advance();
                 Ok(Pattern {
-                    kind: PatternKind::Literal(Expr {
+                    kind: PatternKind::Literal(Box::new(Expr {
                         kind: ExprKind::BoolLiteral(true),
                         span: start,
-                    }),
+                    })),
                     span: start,
                 })
             }
             Token::False => {
                 self.advance();
                 Ok(Pattern {
-                    kind: PatternKind::Literal(Expr {
+                    kind: PatternKind::Literal(Box::new(Expr {
                         kind: ExprKind::BoolLiteral(false),
                         span: start,
-                    }),
+                    })),
                     span: start,
                 })
             }
@@ -2068,6 +2652,60 @@ impl<'src> Parser<'src> {
         }
     }
     
+    /// Parse the pattern of a `let` or `for` binding: a name, `_` or a tuple of those
+    fn parse_binding_pattern(&mut self, keyword: &str) -> ParseResult<Pattern> {
+        let pattern = self.parse_pattern()?;
+        let error = |span| ParseError {
+            message: format!("only names, `_` and tuples can be destructured by `{}`", keyword),
+            span,
+        };
+        match &pattern.kind {
+            PatternKind::Ident(_) | PatternKind::Wildcard => Ok(pattern),
+            PatternKind::Tuple(elems) => {
+                for elem in elems {
+                    if !matches!(elem.kind, PatternKind::Ident(_) | PatternKind::Wildcard | PatternKind::Tuple(_)) {
+                        return Err(error(elem.span));
+                    }
+                }
+                Ok(pattern)
+            }
+            _ => Err(error(pattern.span)),
+        }
+    }
+
+    /// Parse what follows a `.`: a field name or a tuple index. The lexer reads
+    /// `pair.0.1` as `pair`, `.`, `0.1`, so a float splits into two indices.
+    fn parse_field_names(&mut self) -> ParseResult<Vec<Ident>> {
+        let span = self.peek_span();
+        match self.peek().clone() {
+            Token::IntLiteral(n) => {
+                self.advance();
+                Ok(vec![Ident::new(n.to_string(), span)])
+            }
+            Token::FloatLiteral(_) => {
+                self.advance();
+                let text = &self.source[span.start..span.end];
+                match text.split_once('.') {
+                    Some((first, second))
+                        if !first.is_empty() && !second.is_empty()
+                            && first.chars().chain(second.chars()).all(|c| c.is_ascii_digit()) =>
+                    {
+                        let mid = span.start + first.len();
+                        Ok(vec![
+                            Ident::new(first.to_string(), span.with_end(mid)),
+                            Ident::new(second.to_string(), span.with_start(mid + 1)),
+                        ])
+                    }
+                    _ => Err(ParseError {
+                        message: format!("invalid tuple index '{}'", text),
+                        span,
+                    }),
+                }
+            }
+            _ => Ok(vec![self.expect_ident()?]),
+        }
+    }
+    
     /// Parse an interpolated string like "hello {name}!"
     /// The string content has already been extracted from quotes
     fn parse_interpolated_string(&mut self, s: &str, span: Span) -> ParseResult<Expr> {
@@ -2117,7 +2755,7 @@ impl<'src> Parser<'src> {
                 }
                 
                 // Parse the expression
-                let expr = Parser::parse_interpolation_expr(&expr_str).map_err(|e| ParseError {
+                let expr = Parser::parse_interpolation_expr(&expr_str, span.file).map_err(|e| ParseError {
                     message: format!("error in interpolated expression: {}", e.message),
                     span,
                 })?;
@@ -2160,9 +2798,9 @@ impl<'src> Parser<'src> {
         })
     }
     
-    /// Parse a single expression from a string (for interpolation)
-    pub fn parse_interpolation_expr(source: &str) -> ParseResult<Expr> {
-        let mut parser = Parser::new(source)?;
+    /// Parse a single expression from a string (for interpolation) in `file`
+    pub fn parse_interpolation_expr(source: &str, file: FileId) -> ParseResult<Expr> {
+        let mut parser = Parser::with_file(source, file)?;
         parser.parse_expr()
     }
 }
@@ -2191,4 +2829,219 @@ mod tests {
         let ast = Parser::parse(source).unwrap();
         assert_eq!(ast.items.len(), 1);
     }
+
+    #[test]
+    fn test_parse_non_exhaustive_enum() {
+        let source = "@non_exhaustive pub enum Mode { Read, Write }";
+        let ast = Parser::parse(source).unwrap();
+        let Item::Enum(e) = &ast.items[0] else { panic!("expected enum") };
+        assert!(e.is_non_exhaustive && e.is_pub);
+        assert!(Parser::parse("@non_exhaustive struct S { x: i32 }").is_err());
+    }
+
+    #[test]
+    fn test_parse_test_fn() {
+        let ast = Parser::parse("@test fn adds() { let x = 1 + 1 }").unwrap();
+        let Item::Function(f) = &ast.items[0] else { panic!("expected function") };
+        assert!(f.is_test);
+        assert!(Parser::parse("@test fn takes(x: i32) {}").is_err());
+        assert!(Parser::parse("@test struct S { x: i32 }").is_err());
+    }
+
+    #[test]
+    fn test_parse_cfg_items() {
+        let source = r#"
+            @cfg(target_os = "linux")
+            extern static stdout: i64
+            @cfg(not(any(target_os = "linux", target_arch = "riscv64")))
+            fn other() {}
+        "#;
+        let ast = Parser::parse(source).unwrap();
+        let Item::Cfg(c) = &ast.items[1] else { panic!("expected cfg item") };
+        assert!(matches!(&c.predicate, CfgPredicate::Not(p) if matches!(p.as_ref(), CfgPredicate::Any(ps) if ps.len() == 2)));
+        let linux = configure_items(ast.items.clone(), &TargetCfg::from_triple("x86_64-unknown-linux-gnu"));
+        assert!(matches!(linux.as_slice(), [Item::ExternStatic(_)]));
+        let macos = configure_items(ast.items, &TargetCfg::from_triple("aarch64-apple-darwin"));
+        assert!(matches!(macos.as_slice(), [Item::Function(_)]));
+        let bad_key = Parser::parse_with_recovery("@cfg(target_oss = \"linux\") fn f() {}").unwrap();
+        assert!(bad_key.errors[0].message.contains("unknown cfg predicate 'target_oss'"));
+        assert!(matches!(bad_key.ast.items.as_slice(), [Item::Function(_)]));
+    }
+
+    #[test]
+    fn test_misused_attributes_keep_their_items() {
+        let source = r#"
+            @cfg(any(target_os = "linux", bogus(1)))
+            fn a() {}
+            @bogus
+            fn b() {}
+            @test
+            fn c(x: i32) {}
+            @cfg(not(target_os = "linux", target_os = "macos"))
+            fn main() {}
+        "#;
+        let result = Parser::parse_with_recovery(source).unwrap();
+        let names: Vec<_> = result.ast.items.iter().map(|item| match item {
+            Item::Function(f) => f.name.name.as_str(),
+            _ => panic!("expected function, found {:?}", item),
+        }).collect();
+        assert_eq!(names, ["a", "b", "c", "main"]);
+        assert_eq!(result.errors.len(), 4, "{:?}", result.errors);
+    }
+
+    #[test]
+    fn test_parse_control_flow() {
+        let source = "fn main() -> i32 {
+            let x = loop@outer {
+                for i in 0..10 {
+                    if i == 3 { continue }
+                    if i == 7 { break@outer i }
+                }
+                break 0
+            }
+            defer { x }
+            while true { return x }
+            return
+        }";
+        let ast = Parser::parse(source).unwrap();
+        let Item::Function(f) = &ast.items[0] else { panic!("expected function") };
+        let body = f.body.as_ref().unwrap();
+        assert_eq!(body.stmts.len(), 4);
+        let Stmt::Let(LetStmt { init: Some(init), .. }) = &body.stmts[0] else { panic!("expected let") };
+        assert!(matches!(&init.kind, ExprKind::Loop(Some(label), _) if label.name == "outer"));
+        assert!(matches!(&body.stmts[1], Stmt::Defer(d) if d.body.stmts.len() == 1));
+        let Stmt::Expr(ret) = &body.stmts[3] else { panic!("expected return") };
+        assert!(matches!(ret.expr.kind, ExprKind::Return(None)));
+    }
+
+    #[test]
+    fn test_parse_try() {
+        let source = "fn load() -> Result<i32, i32> { Ok(parse(read()?)?.value) }";
+        let ast = Parser::parse(source).unwrap();
+        let Item::Function(f) = &ast.items[0] else { panic!("expected function") };
+        let Stmt::Expr(body) = &f.body.as_ref().unwrap().stmts[0] else { panic!("expected expression") };
+        let ExprKind::Call(_, args) = &body.expr.kind else { panic!("expected call") };
+        let ExprKind::Field(inner, _) = &args[0].value.kind else { panic!("expected field access") };
+        let ExprKind::Try(call) = &inner.kind else { panic!("expected try") };
+        let ExprKind::Call(_, args) = &call.kind else { panic!("expected call") };
+        assert!(matches!(args[0].value.kind, ExprKind::Try(_)));
+    }
+
+    #[test]
+    fn test_parse_function_type() {
+        let source = "fn compose(f: (i32) -> i32, g: fn(i32, bool), h: () -> () -> i32) -> () {}";
+        let ast = Parser::parse(source).unwrap();
+        let Item::Function(f) = &ast.items[0] else { panic!("expected function") };
+        assert!(matches!(&f.params[0].ty.kind, TypeKind::Function(params, ret)
+            if params.len() == 1 && matches!(ret.kind, TypeKind::Named(..))));
+        assert!(matches!(&f.params[1].ty.kind, TypeKind::Function(params, ret)
+            if params.len() == 2 && matches!(ret.kind, TypeKind::Unit)));
+        assert!(matches!(&f.params[2].ty.kind, TypeKind::Function(params, ret)
+            if params.is_empty() && matches!(ret.kind, TypeKind::Function(..))));
+        assert!(matches!(f.return_type.as_ref().unwrap().kind, TypeKind::Unit));
+    }
+
+    #[test]
+    fn test_parse_tuples() {
+        let source = "fn split(p: (i32, (bool,)), q: (i32)) -> (i32, bool) { let (a, (b,)) = p; (a, p.1.0) }";
+        let ast = Parser::parse(source).unwrap();
+        let Item::Function(f) = &ast.items[0] else { panic!("expected function") };
+        assert!(matches!(&f.params[0].ty.kind, TypeKind::Tuple(elems)
+            if elems.len() == 2 && matches!(&elems[1].kind, TypeKind::Tuple(inner) if inner.len() == 1)));
+        assert!(matches!(f.params[1].ty.kind, TypeKind::Named(..)));
+        assert!(matches!(&f.return_type.as_ref().unwrap().kind, TypeKind::Tuple(elems) if elems.len() == 2));
+
+        let body = f.body.as_ref().unwrap();
+        let Stmt::Let(l) = &body.stmts[0] else { panic!("expected let") };
+        assert!(matches!(&l.pattern.kind, PatternKind::Tuple(elems)
+            if matches!(&elems[1].kind, PatternKind::Tuple(inner) if inner.len() == 1)));
+        let Stmt::Expr(e) = &body.stmts[1] else { panic!("expected expression") };
+        let ExprKind::Tuple(elems) = &e.expr.kind else { panic!("expected tuple") };
+        let ExprKind::Field(base, index) = &elems[1].kind else { panic!("expected field") };
+        assert_eq!(index.name, "0");
+        assert!(matches!(&base.kind, ExprKind::Field(_, index) if index.name == "1"));
+    }
+
+    #[test]
+    fn test_parse_record_literals() {
+        let source = "@defaults struct S { a: i32 } fn f(b: S) -> S { { a: 1, ..b } } fn g() { { let x = 1; } if c == d { } }";
+        let ast = Parser::parse(source).unwrap();
+        assert!(matches!(&ast.items[0], Item::Struct(s) if s.has_defaults));
+
+        let Item::Function(f) = &ast.items[1] else { panic!("expected function") };
+        let Stmt::Expr(e) = &f.body.as_ref().unwrap().stmts[0] else { panic!("expected expression") };
+        assert!(matches!(&e.expr.kind, ExprKind::StructLit(None, fields, Some(_)) if fields.len() == 1));
+
+        // The spread can stand anywhere, but only once
+        let middle = Parser::parse("fn f(b: S) -> S { S { a: 1, ..b, c: 2 } }").unwrap();
+        let Item::Function(m) = &middle.items[0] else { panic!("expected function") };
+        let Stmt::Expr(e) = &m.body.as_ref().unwrap().stmts[0] else { panic!("expected expression") };
+        assert!(matches!(&e.expr.kind, ExprKind::StructLit(Some(_), fields, Some(_)) if fields.len() == 2));
+        let twice = Parser::parse_with_recovery("fn f(b: S) -> S { S { ..b, a: 1, ..b } }\nfn main() {}").unwrap();
+        assert_eq!(twice.ast.items.len(), 2);
+        assert!(matches!(twice.errors.as_slice(), [e] if e.message.contains("only have one '..' spread")), "{:?}", twice.errors);
+
+        // A block starting with a statement and an `if` body stay blocks
+        let Item::Function(g) = &ast.items[2] else { panic!("expected function") };
+        let body = g.body.as_ref().unwrap();
+        assert!(matches!(&body.stmts[0], Stmt::Expr(e) if matches!(e.expr.kind, ExprKind::Block(_))));
+        assert!(matches!(&body.stmts[1], Stmt::Expr(e) if matches!(e.expr.kind, ExprKind::If(..))));
+    }
+
+    #[test]
+    fn test_parse_trailing_blocks() {
+        let source = "fn f() { row(gap: 2) { text { 1 } } p(1) { x: 2 } q(3)\n{ 4 } Point { x: 1 } }";
+        let ast = Parser::parse(source).unwrap();
+        let Item::Function(f) = &ast.items[0] else { panic!("expected function") };
+        let stmts = &f.body.as_ref().unwrap().stmts;
+        let call = |i: usize| match &stmts[i] {
+            Stmt::Expr(e) => match &e.expr.kind {
+                ExprKind::Call(_, args) => args.clone(),
+                other => panic!("expected call, found {:?}", other),
+            },
+            _ => panic!("expected expression"),
+        };
+
+        // The block is the last argument, after any named ones
+        let args = call(0);
+        assert_eq!(args.len(), 2);
+        let ExprKind::TrailingBlock(block) = &args[1].value.kind else { panic!("expected trailing block") };
+        assert!(matches!(&block.stmts[0], Stmt::Expr(e) if matches!(&e.expr.kind, ExprKind::Call(_, inner) if inner.len() == 1)));
+
+        // A record literal can follow the parentheses
+        let args = call(1);
+        let ExprKind::TrailingBlock(block) = &args[1].value.kind else { panic!("expected trailing block") };
+        assert!(matches!(&block.stmts[0], Stmt::Expr(e) if matches!(e.expr.kind, ExprKind::StructLit(None, ..))));
+
+        // A block on the next line is a statement of its own
+        assert_eq!(call(2).len(), 1);
+        assert!(matches!(&stmts[3], Stmt::Expr(e) if matches!(e.expr.kind, ExprKind::Block(_))));
+        assert!(matches!(&stmts[4], Stmt::Expr(e) if matches!(e.expr.kind, ExprKind::StructLit(Some(_), ..))));
+    }
+
+    #[test]
+    fn test_parse_collection_literals() {
+        let source = r#"fn f() {
+            [..xs, 3, if c { 4 } else { 5 }, for i in 0..n { i }, if c { let t = 1; t } else { 2 }];
+            { "k": 1, if d { "x": 2 }, ..extra };
+        }"#;
+        let ast = Parser::parse(source).unwrap();
+        let Item::Function(f) = &ast.items[0] else { panic!("expected function") };
+        let stmts = &f.body.as_ref().unwrap().stmts;
+
+        let Stmt::Expr(e) = &stmts[0] else { panic!("expected expression") };
+        let ExprKind::ArrayLit(elems) = &e.expr.kind else { panic!("expected list literal") };
+        assert!(matches!(elems[0].kind, CollectionElemKind::Spread(_)));
+        assert!(matches!(elems[1].kind, CollectionElemKind::Item(_)));
+        assert!(matches!(elems[2].kind, CollectionElemKind::If(_, _, Some(_))));
+        assert!(matches!(elems[3].kind, CollectionElemKind::For(..)));
+        // An `if` whose body isn't a single element is an ordinary expression
+        assert!(matches!(&elems[4].kind, CollectionElemKind::Item(e) if matches!(e.kind, ExprKind::If(..))));
+
+        let Stmt::Expr(e) = &stmts[1] else { panic!("expected expression") };
+        let ExprKind::MapLit(elems) = &e.expr.kind else { panic!("expected map literal") };
+        assert!(matches!(elems[0].kind, CollectionElemKind::Entry(..)));
+        assert!(matches!(&elems[1].kind, CollectionElemKind::If(_, then, None) if matches!(then.kind, CollectionElemKind::Entry(..))));
+        assert!(matches!(elems[2].kind, CollectionElemKind::Spread(_)));
+    }
 }
@@ -0,0 +1,314 @@
+//! Capture analysis for closures
+//!
+//! Works out which local variables of the enclosing function a lambda body
+//! uses, and how: a variable that is only read is captured by reference, one
+//! that is assigned, mutably borrowed or used as a `&mut self` receiver is
+//! captured by mutable reference, and one whose non-Copy value is moved out
+//! is moved into the closure.
+
+use crate::check::{TypedBlock, TypedCollectElem, TypedElse, TypedExpr, TypedExprKind, TypedForIter, TypedPattern, TypedStmt, TypedStringInterpPart};
+use crate::types::{Type, TypeContext};
+use std::collections::HashSet;
+use wisp_hir::DefId;
+
+/// How a closure captures a variable, weakest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CaptureMode {
+    /// The closure holds a shared reference to the variable
+    Ref,
+    /// The closure holds a mutable reference to the variable
+    MutRef,
+    /// The variable's value is moved into the closure
+    Move,
+}
+
+/// A variable captured by a closure
+#[derive(Debug, Clone)]
+pub struct TypedCapture {
+    pub def_id: DefId,
+    pub name: String,
+    pub ty: Type,
+    pub mode: CaptureMode,
+}
+
+/// Walks a lambda body collecting the outer variables it captures
+pub struct CaptureCollector<'a> {
+    ctx: &'a TypeContext,
+    /// Parameters and locals of every function in the program
+    local_defs: &'a HashSet<DefId>,
+    is_copy: &'a dyn Fn(&Type) -> bool,
+    /// Variables declared inside the lambda, which aren't captures
+    declared: HashSet<DefId>,
+    /// Captures in order of first use
+    captures: Vec<TypedCapture>,
+}
+
+impl<'a> CaptureCollector<'a> {
+    pub fn new(ctx: &'a TypeContext, local_defs: &'a HashSet<DefId>, is_copy: &'a dyn Fn(&Type) -> bool) -> Self {
+        Self {
+            ctx,
+            local_defs,
+            is_copy,
+            declared: HashSet::new(),
+            captures: Vec::new(),
+        }
+    }
+
+    /// Collect the captures of a lambda with the given parameters and body
+    pub fn collect(mut self, params: impl IntoIterator<Item = DefId>, body: &TypedExpr) -> Vec<TypedCapture> {
+        self.declared.extend(params);
+        self.expr(body);
+        self.captures
+    }
+
+    fn note(&mut self, def_id: DefId, name: &str, ty: &Type, mode: CaptureMode) {
+        if !self.local_defs.contains(&def_id) || self.declared.contains(&def_id) {
+            return;
+        }
+        match self.captures.iter_mut().find(|c| c.def_id == def_id) {
+            Some(capture) => capture.mode = capture.mode.max(mode),
+            None => self.captures.push(TypedCapture {
+                def_id,
+                name: name.to_string(),
+                ty: self.ctx.apply(ty),
+                mode,
+            }),
+        }
+    }
+
+    /// An expression whose value is used: non-Copy variables are moved
+    fn expr(&mut self, expr: &TypedExpr) {
+        match &expr.kind {
+            TypedExprKind::IntLiteral(_) |
+            TypedExprKind::FloatLiteral(_) |
+            TypedExprKind::BoolLiteral(_) |
+            TypedExprKind::StringLiteral(_) |
+            TypedExprKind::Continue { .. } |
+            TypedExprKind::Error => {}
+
+            TypedExprKind::Var { .. } | TypedExprKind::Field { .. } => {
+                let mode = if (self.is_copy)(&self.ctx.apply(&expr.ty)) { CaptureMode::Ref } else { CaptureMode::Move };
+                self.place(expr, mode);
+            }
+            TypedExprKind::Deref(_) | TypedExprKind::Index { .. } => self.place(expr, CaptureMode::Ref),
+            TypedExprKind::Ref { is_mut, expr: inner } => {
+                self.place(inner, if *is_mut { CaptureMode::MutRef } else { CaptureMode::Ref });
+            }
+            TypedExprKind::Assign { target, value } => {
+                self.expr(value);
+                self.place(target, CaptureMode::MutRef);
+            }
+
+            TypedExprKind::Binary { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            TypedExprKind::OperatorCall { method_def_id, left, right, .. } => {
+                let params = self.method_params(*method_def_id);
+                for (i, operand) in [left, right].into_iter().enumerate() {
+                    if matches!(params.get(i), Some(Type::Ref { .. })) {
+                        self.place(operand, CaptureMode::Ref);
+                    } else {
+                        self.expr(operand);
+                    }
+                }
+            }
+            TypedExprKind::Unary { expr: inner, .. } |
+            TypedExprKind::Cast { expr: inner, .. } => self.expr(inner),
+
+            TypedExprKind::Call { callee, args } => {
+                // Calling a closure only reads it
+                self.place(callee, CaptureMode::Ref);
+                self.exprs(args);
+            }
+            TypedExprKind::GenericCall { args, .. } |
+            TypedExprKind::AssociatedFunctionCall { args, .. } |
+            TypedExprKind::Intrinsic { args, .. } => self.exprs(args),
+            TypedExprKind::MethodCall { receiver, method_def_id, is_mut_self, args, .. } => {
+                let by_value = self.method_params(*method_def_id).first()
+                    .is_some_and(|p| !matches!(p, Type::Ref { .. }));
+                if by_value {
+                    self.expr(receiver);
+                } else {
+                    self.receiver(receiver, *is_mut_self);
+                }
+                self.exprs(args);
+            }
+            TypedExprKind::TraitMethodCall { receiver, is_mut_self, args, .. } |
+            TypedExprKind::PrimitiveMethodCall { receiver, is_mut_self, args, .. } => {
+                self.receiver(receiver, *is_mut_self);
+                self.exprs(args);
+            }
+
+            TypedExprKind::StructLit { fields, base, .. } => {
+                for (_, value) in fields {
+                    self.expr(value);
+                }
+                if let Some(base) = base {
+                    self.expr(base);
+                }
+            }
+            TypedExprKind::ArrayLit(elems) | TypedExprKind::Collect { elems, .. } => {
+                for elem in elems {
+                    self.collect_elem(elem);
+                }
+            }
+            TypedExprKind::Tuple(elements) => self.exprs(elements),
+            TypedExprKind::StringInterp { parts } => {
+                for part in parts {
+                    if let TypedStringInterpPart::Expr(e) = part {
+                        self.expr(e);
+                    }
+                }
+            }
+
+            TypedExprKind::If { cond, then_block, else_block } => {
+                self.expr(cond);
+                self.block(then_block);
+                match else_block {
+                    Some(TypedElse::Block(block)) => self.block(block),
+                    Some(TypedElse::If(if_expr)) => self.expr(if_expr),
+                    None => {}
+                }
+            }
+            TypedExprKind::While { cond, body, .. } => {
+                self.expr(cond);
+                self.block(body);
+            }
+            TypedExprKind::Loop { body, .. } => self.block(body),
+            TypedExprKind::For { binding, iter, body, .. } => {
+                self.for_iter(iter, *binding);
+                self.block(body);
+            }
+            TypedExprKind::Block(block) => self.block(block),
+            TypedExprKind::Return(value) | TypedExprKind::Break { value, .. } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            TypedExprKind::Match { scrutinee, arms } => {
+                self.place(scrutinee, CaptureMode::Ref);
+                for arm in arms {
+                    self.declare_pattern(&arm.pattern);
+                    self.expr(&arm.body);
+                }
+            }
+
+            TypedExprKind::Lambda { captures, .. } => {
+                // A nested closure's captures from further out are captured here too
+                for capture in captures {
+                    self.note(capture.def_id, &capture.name, &capture.ty, capture.mode);
+                }
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[TypedExpr]) {
+        for e in exprs {
+            self.expr(e);
+        }
+    }
+
+    /// An expression used as a place: the root variable is captured with `mode`
+    fn place(&mut self, expr: &TypedExpr, mode: CaptureMode) {
+        match &expr.kind {
+            TypedExprKind::Var { def_id, name } => self.note(*def_id, name, &expr.ty, mode),
+            TypedExprKind::Field { expr: base, .. } => self.place(base, mode),
+            // Going through a reference only reads the variable holding it
+            TypedExprKind::Deref(inner) => self.place(inner, CaptureMode::Ref),
+            TypedExprKind::Index { expr: base, index } => {
+                self.place(base, mode);
+                self.expr(index);
+            }
+            _ => self.expr(expr),
+        }
+    }
+
+    fn receiver(&mut self, receiver: &TypedExpr, is_mut_self: bool) {
+        // A receiver that is already a reference is only read
+        let mode = if is_mut_self && !matches!(self.ctx.apply(&receiver.ty), Type::Ref { .. }) {
+            CaptureMode::MutRef
+        } else {
+            CaptureMode::Ref
+        };
+        self.place(receiver, mode);
+    }
+
+    fn block(&mut self, block: &TypedBlock) {
+        for stmt in &block.stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &TypedStmt) {
+        match stmt {
+            TypedStmt::Let { def_id, init, .. } => {
+                if let Some(init) = init {
+                    self.expr(init);
+                }
+                self.declared.insert(*def_id);
+            }
+            TypedStmt::Expr(e) => self.expr(e),
+            TypedStmt::Defer { body, .. } => self.block(body),
+        }
+    }
+
+    /// What a `for` loop iterates is used before its binding is declared
+    fn for_iter(&mut self, iter: &TypedForIter, binding: DefId) {
+        match iter {
+            TypedForIter::Range { start, end } => {
+                self.expr(start);
+                self.expr(end);
+            }
+            TypedForIter::Array(array) => self.expr(array),
+            TypedForIter::Next { iter, iter_def, next } => {
+                self.expr(iter);
+                self.declared.insert(*iter_def);
+                self.expr(next);
+            }
+        }
+        self.declared.insert(binding);
+    }
+
+    fn collect_elem(&mut self, elem: &TypedCollectElem) {
+        match elem {
+            TypedCollectElem::Item(value) | TypedCollectElem::Spread(value) => self.expr(value),
+            TypedCollectElem::If { cond, then_elem, else_elem } => {
+                self.expr(cond);
+                self.collect_elem(then_elem);
+                if let Some(else_elem) = else_elem {
+                    self.collect_elem(else_elem);
+                }
+            }
+            TypedCollectElem::For { binding, iter, bindings, elem, .. } => {
+                self.for_iter(iter, *binding);
+                for stmt in bindings {
+                    self.stmt(stmt);
+                }
+                self.collect_elem(elem);
+            }
+        }
+    }
+
+    fn declare_pattern(&mut self, pattern: &TypedPattern) {
+        match pattern {
+            TypedPattern::Binding { def_id, .. } => {
+                self.declared.insert(*def_id);
+            }
+            TypedPattern::Variant { fields, .. } | TypedPattern::Tuple(fields) => {
+                for field in fields {
+                    self.declare_pattern(field);
+                }
+            }
+            TypedPattern::Wildcard | TypedPattern::Literal(_) => {}
+        }
+    }
+
+    /// Parameter types of a method, including `self`
+    fn method_params(&self, method_def_id: DefId) -> Vec<Type> {
+        match self.ctx.get_def_type(method_def_id) {
+            Some(Type::Function { params, .. }) => params.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
@@ -1,15 +1,21 @@
 //! Type checking pass
 
+use wisp_diagnostics::{codes, Code, Diagnostic};
 use wisp_hir::*;
-use wisp_lexer::Span;
+use wisp_lexer::{FileId, Span};
 use crate::types::*;
+use crate::exhaustive::{MatchChecker, describe_missing};
+use crate::capture::{CaptureCollector, CaptureMode, TypedCapture};
 use std::collections::{HashMap, HashSet};
 
 /// Type error
 #[derive(Debug, Clone)]
 pub struct TypeError {
+    pub code: Code,
     pub message: String,
     pub span: Span,
+    /// How to fix it, shown after the error
+    pub help: Option<String>,
 }
 
 impl std::fmt::Display for TypeError {
@@ -20,6 +26,36 @@ impl std::fmt::Display for TypeError {
 
 impl std::error::Error for TypeError {}
 
+impl From<TypeError> for Diagnostic {
+    fn from(e: TypeError) -> Self {
+        let diagnostic = Diagnostic::error(e.code, e.message, e.span);
+        match e.help {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic,
+        }
+    }
+}
+
+/// A problem worth reporting that doesn't stop compilation
+#[derive(Debug, Clone)]
+pub struct TypeWarning {
+    pub code: Code,
+    pub message: String,
+    pub span: Span,
+}
+
+impl std::fmt::Display for TypeWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}..{}", self.message, self.span.start, self.span.end)
+    }
+}
+
+impl From<TypeWarning> for Diagnostic {
+    fn from(w: TypeWarning) -> Self {
+        Diagnostic::warning(w.code, w.message, w.span)
+    }
+}
+
 /// A specific instantiation of a generic function
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct GenericInstantiation {
@@ -37,12 +73,24 @@ pub struct TypeParamInfo {
     pub bounds: Vec<DefId>,  // Trait DefIds
 }
 
+/// An enclosing loop while checking its body
+struct LoopContext {
+    id: LoopId,
+    /// Type of `break` values (only `loop` can break with a value)
+    break_type: Option<Type>,
+    /// Whether any `break` targets this loop
+    has_break: bool,
+}
+
 /// Type checker
 pub struct TypeChecker {
     ctx: TypeContext,
     errors: Vec<TypeError>,
+    warnings: Vec<TypeWarning>,
     /// Current function's return type
     current_return_type: Option<Type>,
+    /// Enclosing loops, innermost last
+    loop_stack: Vec<LoopContext>,
     /// Expression types (by span for now, could use NodeId)
     expr_types: HashMap<(usize, usize), Type>,
     /// Method lookup: (struct DefId, method name) -> (method DefId, method type)
@@ -71,10 +119,28 @@ pub struct TypeChecker {
     trait_by_name: HashMap<String, DefId>,
     /// Type parameters for structs and enums: DefId -> [(param DefId, param name)]
     type_type_params: HashMap<DefId, Vec<(DefId, String)>>,
+    /// Type parameters of generic structs, which their field types refer to:
+    /// struct DefId -> [(param DefId, param name)]
+    struct_type_params: HashMap<DefId, Vec<(DefId, String)>>,
+    /// Type parameters of the impl block each method was defined in:
+    /// method DefId -> [(param DefId, param name)]
+    impl_type_params: HashMap<DefId, Vec<(DefId, String)>>,
     /// Types that implement the Copy trait (can be implicitly copied)
     copy_types: HashSet<DefId>,
     /// The Copy trait's DefId (if found)
     copy_trait_id: Option<DefId>,
+    /// Types that implement the Drop trait, mapped to their `drop` method
+    drop_impls: HashMap<DefId, DefId>,
+    /// The Drop trait's DefId (if found)
+    drop_trait_id: Option<DefId>,
+    /// Enums marked `@non_exhaustive`
+    non_exhaustive_enums: HashSet<DefId>,
+    /// Structs marked `@defaults`
+    defaults_structs: HashSet<DefId>,
+    /// Parameters and local variables, the things a closure can capture
+    local_defs: HashSet<DefId>,
+    /// Parameters of lambdas, which are assigned through when they're `&mut`
+    lambda_params: HashSet<DefId>,
 }
 
 impl TypeChecker {
@@ -82,7 +148,9 @@ impl TypeChecker {
         Self {
             ctx: TypeContext::new(),
             errors: Vec::new(),
+            warnings: Vec::new(),
             current_return_type: None,
+            loop_stack: Vec::new(),
             expr_types: HashMap::new(),
             methods: HashMap::new(),
             current_self_type: None,
@@ -97,8 +165,16 @@ impl TypeChecker {
             primitive_trait_impls: HashSet::new(),
             trait_by_name: HashMap::new(),
             type_type_params: HashMap::new(),
+            struct_type_params: HashMap::new(),
+            impl_type_params: HashMap::new(),
             copy_types: HashSet::new(),
             copy_trait_id: None,
+            drop_impls: HashMap::new(),
+            drop_trait_id: None,
+            non_exhaustive_enums: HashSet::new(),
+            defaults_structs: HashSet::new(),
+            local_defs: HashSet::new(),
+            lambda_params: HashSet::new(),
         }
     }
 
@@ -122,8 +198,16 @@ impl TypeChecker {
         (result, checker.errors)
     }
 
-    fn error(&mut self, message: String, span: Span) {
-        self.errors.push(TypeError { message, span });
+    fn error(&mut self, code: Code, message: String, span: Span) {
+        self.errors.push(TypeError { code, message, span, help: None });
+    }
+
+    fn error_with_help(&mut self, code: Code, message: String, span: Span, help: String) {
+        self.errors.push(TypeError { code, message, span, help: Some(help) });
+    }
+
+    fn warning(&mut self, code: Code, message: String, span: Span) {
+        self.warnings.push(TypeWarning { code, message, span });
     }
     
     fn argument_count_error(&mut self, def_id: Option<DefId>, expected: usize, got: usize, span: Span) {
@@ -156,23 +240,44 @@ impl TypeChecker {
             // No def_id available
             format!("expected {} arguments, got {}", expected, got)
         };
-        self.error(message, span);
+        self.error(codes::WRONG_ARGUMENTS, message, span);
     }
 
     fn check_program(&mut self, program: &ResolvedProgram) -> TypedProgram {
+        self.local_defs = program.defs.values()
+            .filter(|d| matches!(d.kind, DefKind::Parameter | DefKind::Local))
+            .map(|d| d.id)
+            .collect();
+        
         // First pass: register all type names and struct/enum info
         for s in &program.structs {
             self.ctx.register_type_name(s.def_id, s.name.clone());
             self.ctx.register_def_type(s.def_id, Type::Struct { def_id: s.def_id, type_args: vec![] });
+            if s.has_defaults {
+                self.defaults_structs.insert(s.def_id);
+            }
         }
         
         for e in &program.enums {
             self.ctx.register_type_name(e.def_id, e.name.clone());
             self.ctx.register_def_type(e.def_id, Type::Enum { def_id: e.def_id, type_args: vec![] });
+            if e.is_non_exhaustive {
+                self.non_exhaustive_enums.insert(e.def_id);
+            }
         }
 
         // Second pass: register struct fields and enum variants
         for s in &program.structs {
+            // Register type parameters first so field types can refer to them
+            if !s.type_params.is_empty() {
+                let params = s.type_params.iter()
+                    .map(|tp| {
+                        self.ctx.register_type_param(tp.def_id, tp.index, tp.name.clone());
+                        (tp.def_id, tp.name.clone())
+                    })
+                    .collect();
+                self.struct_type_params.insert(s.def_id, params);
+            }
             let fields: Vec<_> = s.fields.iter()
                 .map(|f| (f.name.clone(), self.resolve_type(&f.ty)))
                 .collect();
@@ -181,8 +286,8 @@ impl TypeChecker {
             // Record struct field spans for LSP
             for (i, f) in s.fields.iter().enumerate() {
                 let field_type = &fields[i].1;
-                self.ctx.record_span_type(f.span.start, f.span.end, format!("{}: {}", f.name, field_type.display(&self.ctx)));
-                self.ctx.record_span_definition(f.span.start, f.span.end, f.def_id);
+                self.ctx.record_span_type(f.span, format!("{}: {}", f.name, field_type.display(&self.ctx)));
+                self.ctx.record_span_definition(f.span, f.def_id);
             }
         }
 
@@ -241,6 +346,9 @@ impl TypeChecker {
             if t.name == "Copy" {
                 self.copy_trait_id = Some(t.def_id);
             }
+            if t.name == "Drop" {
+                self.drop_trait_id = Some(t.def_id);
+            }
             
             // Collect trait method signatures
             let mut methods = Vec::new();
@@ -271,26 +379,7 @@ impl TypeChecker {
             
             // Track generic functions with their bounds
             if !f.type_params.is_empty() {
-                let type_params: Vec<_> = f.type_params.iter()
-                    .map(|tp| {
-                        // Extract trait DefIds from bounds
-                        let bounds: Vec<DefId> = tp.bounds.iter()
-                            .filter_map(|b| {
-                                if let ResolvedType::Named { def_id: Some(id), .. } = b {
-                                    Some(*id)
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect();
-                        TypeParamInfo {
-                            def_id: tp.def_id,
-                            name: tp.name.clone(),
-                            bounds,
-                        }
-                    })
-                    .collect();
-                self.generic_functions.insert(f.def_id, type_params);
+                self.generic_functions.insert(f.def_id, type_param_infos(&f.type_params));
             }
         }
         
@@ -324,6 +413,17 @@ impl TypeChecker {
             let mut impl_methods = Vec::new();
             
             for m in &imp.methods {
+                // Register the method's own type parameters (e.g., the U in fn map<U>)
+                for tp in &m.type_params {
+                    self.ctx.register_type_param(tp.def_id, tp.index, tp.name.clone());
+                }
+                if !m.type_params.is_empty() {
+                    self.generic_functions.insert(m.def_id, type_param_infos(&m.type_params));
+                }
+                if !impl_type_params.is_empty() {
+                    self.impl_type_params.insert(m.def_id, impl_type_params.clone());
+                }
+                
                 let fn_type = self.function_type(m);
                 self.ctx.register_def_type(m.def_id, fn_type.clone());
                 self.ctx.register_type_name(m.def_id, m.name.clone());
@@ -364,11 +464,13 @@ impl TypeChecker {
                             for (field_name, field_type) in fields {
                                 if !self.is_copy_type(&field_type) {
                                     self.errors.push(TypeError {
+                                        code: codes::INVALID_COPY_IMPL,
                                         message: format!(
                                             "the trait `Copy` cannot be implemented for struct because field `{}` is not `Copy`",
                                             field_name
                                         ),
                                         span: imp.span,
+                                        help: None,
                                     });
                                 }
                             }
@@ -378,6 +480,23 @@ impl TypeChecker {
                             }
                         }
                     }
+                    
+                    // If this is impl Drop for T, remember the destructor for drop elaboration
+                    if Some(trait_def) == self.drop_trait_id
+                        && let Some(m) = imp.methods.iter().find(|m| m.name == "drop")
+                    {
+                        self.drop_impls.insert(struct_id, m.def_id);
+                    }
+                    
+                    // A Copy value is duplicated implicitly, so it can't own anything to drop
+                    if self.copy_types.contains(&struct_id) && self.drop_impls.contains_key(&struct_id) {
+                        self.errors.push(TypeError {
+                            code: codes::INVALID_COPY_IMPL,
+                            message: "the traits `Copy` and `Drop` cannot both be implemented for the same type".to_string(),
+                            span: imp.span,
+                            help: None,
+                        });
+                    }
                 } else if let Some(ref prim_name) = primitive_name {
                     // Primitive trait impl
                     self.primitive_trait_impls.insert((prim_name.clone(), trait_def));
@@ -475,6 +594,8 @@ impl TypeChecker {
             impls: typed_impls,
             generic_instantiations: std::mem::take(&mut self.generic_instantiations),
             copy_types: std::mem::take(&mut self.copy_types),
+            drop_impls: std::mem::take(&mut self.drop_impls),
+            warnings: std::mem::take(&mut self.warnings),
         }
     }
 
@@ -511,8 +632,10 @@ impl TypeChecker {
             Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::I128 |
             Type::U8 | Type::U16 | Type::U32 | Type::U64 | Type::U128 |
             Type::F32 | Type::F64 | Type::Bool | Type::Char | Type::Unit => true,
-            // References are Copy (they're just pointers)
-            Type::Ref { .. } => true,
+            // References, slices and `str` are Copy (they're just pointers)
+            Type::Ref { .. } | Type::Slice(_) | Type::Str => true,
+            // Tuples are Copy if all their elements are
+            Type::Tuple(elems) => elems.iter().all(|t| self.is_copy_type(t)),
             // Structs are Copy if they have impl Copy (checked via copy_types set)
             Type::Struct { def_id, .. } => self.copy_types.contains(def_id),
             // Enums are Copy if they have impl Copy
@@ -568,6 +691,12 @@ impl TypeChecker {
                 inner: Box::new(self.resolve_type(inner)),
             },
             ResolvedType::Slice { elem } => Type::Slice(Box::new(self.resolve_type(elem))),
+            ResolvedType::Array { elem, size } => Type::Array(Box::new(self.resolve_type(elem)), *size),
+            ResolvedType::Function { params, ret } => Type::Function {
+                params: params.iter().map(|p| self.resolve_type(p)).collect(),
+                ret: Box::new(self.resolve_type(ret)),
+            },
+            ResolvedType::Tuple(elems) => Type::Tuple(elems.iter().map(|t| self.resolve_type(t)).collect()),
             ResolvedType::Unit => Type::Unit,
             ResolvedType::SelfType => {
                 self.current_self_type.clone().unwrap_or(Type::Error)
@@ -633,9 +762,9 @@ impl TypeChecker {
             let ty = self.resolve_type(&p.ty);
             self.ctx.register_def_type(p.def_id, ty.clone());
             // Record parameter span for hover
-            self.ctx.record_span_type(p.span.start, p.span.end, format!("{}: {}", p.name, ty.display(&self.ctx)));
+            self.ctx.record_span_type(p.span, format!("{}: {}", p.name, ty.display(&self.ctx)));
             // Record definition for go-to-definition
-            self.ctx.record_span_definition(p.span.start, p.span.end, p.def_id);
+            self.ctx.record_span_definition(p.span, p.def_id);
             param_types.push((p.name.clone(), ty));
         }
         
@@ -653,8 +782,8 @@ impl TypeChecker {
             .map(|(name, ty)| format!("{}: {}", name, ty.display(&self.ctx)))
             .collect();
         let sig = format!("fn {}({}) -> {}", f.name, params_str.join(", "), return_type.display(&self.ctx));
-        self.ctx.record_span_type(f.name_span.start, f.name_span.end, sig);
-        self.ctx.record_span_definition(f.name_span.start, f.name_span.end, f.def_id);
+        self.ctx.record_span_type(f.name_span, sig);
+        self.ctx.record_span_definition(f.name_span, f.def_id);
 
         // Check body
         let body = f.body.as_ref().map(|b| self.check_block(b, Some(&return_type)));
@@ -681,12 +810,45 @@ impl TypeChecker {
     }
 
     fn check_block(&mut self, block: &ResolvedBlock, expected: Option<&Type>) -> TypedBlock {
+        let typed = self.check_block_stmts(block, expected);
+
+        // Check that block type matches expected
+        if let Some(expected) = expected {
+            if let Err(_) = self.ctx.unify(&typed.ty, expected) {
+                let expected_str = expected.display(&self.ctx);
+                let found_str = typed.ty.display(&self.ctx);
+                
+                let message = format!("expected `{}`, found `{}`", expected_str, found_str);
+                // Suggest a return type if the function has none
+                if matches!(expected, Type::Unit) && !matches!(typed.ty, Type::Unit) {
+                    let help = format!("add `-> {}` to the function signature if you want to return a value", found_str);
+                    self.error_with_help(codes::MISMATCHED_TYPES, message, block.span, help);
+                } else {
+                    self.error(codes::MISMATCHED_TYPES, message, block.span);
+                }
+            }
+        }
+
+        typed
+    }
+
+    /// Check a block's statements, giving its last expression the expected
+    /// type as a hint without requiring it
+    fn check_block_stmts(&mut self, block: &ResolvedBlock, expected: Option<&Type>) -> TypedBlock {
         let mut stmts = Vec::new();
         let mut last_type = Type::Unit;
 
         for (i, stmt) in block.stmts.iter().enumerate() {
             let is_last = i == block.stmts.len() - 1;
-            let (typed_stmt, stmt_type) = self.check_stmt(stmt);
+            // The block's value is its last expression, which gets the expected type
+            let (typed_stmt, stmt_type) = match stmt {
+                ResolvedStmt::Expr(expr) if is_last => {
+                    let typed = self.check_expr_with_expected(expr, expected);
+                    let ty = typed.ty.clone();
+                    (TypedStmt::Expr(typed), ty)
+                }
+                _ => self.check_stmt(stmt),
+            };
             stmts.push(typed_stmt);
             
             if is_last {
@@ -694,26 +856,6 @@ impl TypeChecker {
             }
         }
 
-        // Check that block type matches expected
-        if let Some(expected) = expected {
-            if let Err(_) = self.ctx.unify(&last_type, expected) {
-                let expected_str = expected.display(&self.ctx);
-                let found_str = last_type.display(&self.ctx);
-                
-                // Provide helpful hint if function has no return type
-                let hint = if matches!(expected, Type::Unit) && !matches!(last_type, Type::Unit) {
-                    format!("\nhint: add `-> {}` to the function signature if you want to return a value", found_str)
-                } else {
-                    String::new()
-                };
-                
-                self.error(
-                    format!("expected `{}`, found `{}`{}", expected_str, found_str, hint),
-                    block.span
-                );
-            }
-        }
-
         TypedBlock {
             stmts,
             ty: self.ctx.apply(&last_type),
@@ -725,6 +867,14 @@ impl TypeChecker {
             ResolvedStmt::Let { def_id, name, is_mut, ty, init, span } => {
                 let declared_type = ty.as_ref().map(|t| self.resolve_type(t));
                 
+                // Without a type, `let x = {}` could be an empty map or an empty block
+                if declared_type.is_none()
+                    && let Some(init) = init
+                    && matches!(&init.kind, ResolvedExprKind::Block(block) if block.stmts.is_empty())
+                {
+                    self.error(codes::TYPE_ANNOTATIONS_NEEDED, "cannot infer the type of an empty `{}`; add a type annotation".to_string(), init.span);
+                }
+                
                 // Type check the initializer with the expected type (if declared)
                 let typed_init = init.as_ref().map(|e| {
                     self.check_expr_with_expected(e, declared_type.as_ref())
@@ -734,14 +884,14 @@ impl TypeChecker {
                 let var_type = match (&declared_type, &typed_init) {
                     (Some(d), Some(init_expr)) => {
                         if let Err(e) = self.ctx.unify(d, &init_expr.ty) {
-                            self.error(format!("type mismatch in let: {}", e), *span);
+                            self.error(codes::MISMATCHED_TYPES, format!("type mismatch in let: {}", e), *span);
                         }
                         self.ctx.apply(d)
                     }
                     (Some(d), None) => d.clone(),
                     (None, Some(init_expr)) => init_expr.ty.clone(),
                     (None, None) => {
-                        self.error("cannot infer type without initializer".to_string(), *span);
+                        self.error(codes::TYPE_ANNOTATIONS_NEEDED, "cannot infer type without initializer".to_string(), *span);
                         Type::Error
                     }
                 };
@@ -749,9 +899,9 @@ impl TypeChecker {
                 self.ctx.register_def_type(*def_id, var_type.clone());
                 
                 // Record span→type for LSP hover
-                self.ctx.record_span_type(span.start, span.end, format!("{}: {}", name, var_type.display(&self.ctx)));
+                self.ctx.record_span_type(*span, format!("{}: {}", name, var_type.display(&self.ctx)));
                 // Record definition for go-to-definition
-                self.ctx.record_span_definition(span.start, span.end, *def_id);
+                self.ctx.record_span_definition(*span, *def_id);
 
                 (TypedStmt::Let {
                     def_id: *def_id,
@@ -767,6 +917,10 @@ impl TypeChecker {
                 let ty = typed.ty.clone();
                 (TypedStmt::Expr(typed), ty)
             }
+            ResolvedStmt::Defer { body, span } => {
+                let typed_body = self.check_block(body, None);
+                (TypedStmt::Defer { body: typed_body, span: *span }, Type::Unit)
+            }
         }
     }
 
@@ -805,10 +959,11 @@ impl TypeChecker {
             }
         };
         
-        // Check if all args are named (no mixing allowed)
-        let has_positional = args.iter().any(|a| a.name.is_none());
+        // Check if all args are named (no mixing allowed), apart from a trailing block
+        let is_trailing_block = |a: &ResolvedCallArg| matches!(a.value.kind, ResolvedExprKind::TrailingBlock(_));
+        let has_positional = args.iter().any(|a| a.name.is_none() && !is_trailing_block(a));
         if has_positional {
-            self.error("cannot mix positional and named arguments".to_string(), span);
+            self.error(codes::WRONG_ARGUMENTS, "cannot mix positional and named arguments".to_string(), span);
             return args.iter().map(|a| &a.value).collect();
         }
         
@@ -817,17 +972,21 @@ impl TypeChecker {
         let mut used_params: HashSet<String> = HashSet::new();
         
         for arg in args {
-            let name = arg.name.as_ref().unwrap();
+            // A trailing block is passed as the last parameter
+            let Some(name) = arg.name.as_ref().or(param_names.last()) else {
+                self.error(codes::WRONG_ARGUMENTS, "too many arguments: expected 0, got 1".to_string(), arg.span);
+                continue;
+            };
             
             if let Some(idx) = param_names.iter().position(|p| p == name) {
                 if used_params.contains(name) {
-                    self.error(format!("argument '{}' specified more than once", name), arg.span);
+                    self.error(codes::WRONG_ARGUMENTS, format!("argument '{}' specified more than once", name), arg.span);
                     continue;
                 }
                 used_params.insert(name.clone());
                 result[idx] = Some(&arg.value);
             } else {
-                self.error(format!("unknown parameter '{}'", name), arg.span);
+                self.error(codes::WRONG_ARGUMENTS, format!("unknown parameter '{}'", name), arg.span);
             }
         }
         
@@ -843,7 +1002,7 @@ impl TypeChecker {
             } else {
                 format!("missing arguments: {}", missing.join(", "))
             };
-            self.error(message, span);
+            self.error(codes::WRONG_ARGUMENTS, message, span);
         }
         
         // Return only the provided arguments in order (filter out None)
@@ -864,7 +1023,7 @@ impl TypeChecker {
         let has_positional = args.iter().any(|a| a.name.is_none());
         
         if has_named && has_positional {
-            self.error("cannot mix positional and named arguments".to_string(), span);
+            self.error(codes::WRONG_ARGUMENTS, "cannot mix positional and named arguments".to_string(), span);
             return args.iter().map(|a| self.check_expr(&a.value)).collect();
         }
         
@@ -879,7 +1038,7 @@ impl TypeChecker {
                 // Find the parameter index
                 if let Some(idx) = param_names.iter().position(|p| p == name) {
                     if used_params.contains(name.as_str()) {
-                        self.error(format!("argument '{}' specified more than once", name), arg.span);
+                        self.error(codes::WRONG_ARGUMENTS, format!("argument '{}' specified more than once", name), arg.span);
                         continue;
                     }
                     used_params.insert(name);
@@ -889,6 +1048,7 @@ impl TypeChecker {
                     // Check type
                     if let Err(e) = self.ctx.unify(&typed.ty, &param_types[idx]) {
                         self.error(
+                            codes::MISMATCHED_TYPES,
                             format!("argument '{}' type mismatch: {}", name, e),
                             arg.span
                         );
@@ -896,7 +1056,7 @@ impl TypeChecker {
                     
                     result[idx] = Some(typed);
                 } else {
-                    self.error(format!("unknown parameter '{}'", name), arg.span);
+                    self.error(codes::WRONG_ARGUMENTS, format!("unknown parameter '{}'", name), arg.span);
                 }
             }
             
@@ -912,7 +1072,7 @@ impl TypeChecker {
                 } else {
                     format!("missing arguments: {}", missing.join(", "))
                 };
-                self.error(message, span);
+                self.error(codes::WRONG_ARGUMENTS, message, span);
                 
                 // Create error placeholders
                 for i in 0..param_names.len() {
@@ -945,7 +1105,7 @@ impl TypeChecker {
                     // Too many arguments
                     format!("too many arguments: expected {}, got {}", param_types.len(), args.len())
                 };
-                self.error(message, span);
+                self.error(codes::WRONG_ARGUMENTS, message, span);
             }
             
             args.iter().enumerate().map(|(i, arg)| {
@@ -953,6 +1113,7 @@ impl TypeChecker {
                 if i < param_types.len() {
                     if let Err(e) = self.ctx.unify(&typed.ty, &param_types[i]) {
                         self.error(
+                            codes::MISMATCHED_TYPES,
                             format!("argument {} type mismatch: {}", i + 1, e),
                             arg.span
                         );
@@ -1006,12 +1167,16 @@ impl TypeChecker {
                 let ty = self.ctx.get_def_type(*def_id)
                     .cloned()
                     .unwrap_or_else(|| {
-                        self.error(format!("no type for variable '{}'", name), expr.span);
+                        self.error(codes::UNRESOLVED_NAME, format!("no type for variable '{}'", name), expr.span);
                         Type::Error
                     });
                 (TypedExprKind::Var { name: name.clone(), def_id: *def_id }, ty)
             }
             
+            ResolvedExprKind::Binary { left, op: wisp_ast::BinOp::Range, right } => {
+                self.check_range_value(left, right, expr.span)
+            }
+            
             ResolvedExprKind::Binary { left, op, right } => {
                 let left_typed = self.check_expr(left);
                 let right_typed = self.check_expr(right);
@@ -1126,7 +1291,7 @@ impl TypeChecker {
                             let result_type = if let Type::Function { ret, .. } = &method_type {
                                 (**ret).clone()
                             } else {
-                                self.error(format!("operator {} method has wrong type", method_name), expr.span);
+                                self.error(codes::INCOMPATIBLE_SIGNATURE, format!("operator {} method has wrong type", method_name), expr.span);
                                 Type::Error
                             };
                             
@@ -1211,6 +1376,7 @@ impl TypeChecker {
                                 _ => "?",
                             };
                             self.error(
+                                codes::TRAIT_NOT_IMPLEMENTED,
                                 format!("cannot apply `{}` to type `{}`: `{}` does not implement `{}`",
                                     op_symbol, type_name, type_name, trait_name),
                                 expr.span
@@ -1260,7 +1426,28 @@ impl TypeChecker {
                             // This is potentially an associated function call
                             if let Some((fn_def_id, fn_type)) = self.associated_functions.get(&(struct_id, method_name.clone())).cloned() {
                                 // This is an associated function call!
-                                let args_typed: Vec<_> = args.iter().map(|a| self.check_expr(&a.value)).collect();
+                                // The impl's type params (the T in Vec<T>.new) and the function's own
+                                // are inferred from the arguments and the expected result type
+                                let mut type_params = self.impl_type_params.get(&fn_def_id).cloned().unwrap_or_default();
+                                type_params.extend(self.generic_functions.get(&fn_def_id).into_iter().flatten()
+                                    .map(|tp| (tp.def_id, tp.name.clone())));
+                                let type_vars: Vec<Type> = type_params.iter().map(|_| self.ctx.fresh_var()).collect();
+                                let fn_type = if type_params.is_empty() {
+                                    fn_type
+                                } else {
+                                    let fn_type = self.substitute_type_params(&fn_type, &type_params, &type_vars);
+                                    if let (Some(expected), Type::Function { ret, .. }) = (expected, &fn_type) {
+                                        let _ = self.ctx.unify(ret, expected);
+                                    }
+                                    fn_type
+                                };
+                                let param_types = match &fn_type {
+                                    Type::Function { params, .. } => params.clone(),
+                                    _ => Vec::new(),
+                                };
+                                let args_typed: Vec<_> = args.iter().enumerate()
+                                    .map(|(i, a)| self.check_expr_with_expected(&a.value, param_types.get(i)))
+                                    .collect();
                                 
                                 let result_type = if let Type::Function { params, ret } = &fn_type {
                                     // Check argument count and types (no self parameter)
@@ -1271,25 +1458,43 @@ impl TypeChecker {
                                         for (i, (arg, param)) in args_typed.iter().zip(params.iter()).enumerate() {
                                             if let Err(e) = self.ctx.unify(&arg.ty, param) {
                                                 self.error(
+                                                    codes::MISMATCHED_TYPES,
                                                     format!("argument {} type mismatch: {}", i + 1, e),
                                                     expr.span
                                                 );
                                             }
                                         }
-                                        (**ret).clone()
+                                        self.ctx.apply(ret)
                                     }
                                 } else {
                                     Type::Error
                                 };
                                 
+                                let type_args: Vec<Type> = type_vars.iter().map(|v| self.ctx.apply(v)).collect();
+                                if !type_args.is_empty() {
+                                    if type_args.iter().any(|t| matches!(t, Type::Var(_))) {
+                                        let type_name = self.ctx.get_type_name(struct_id).unwrap_or_default();
+                                        self.error(
+                                            codes::TYPE_ANNOTATIONS_NEEDED,
+                                            format!("cannot infer type arguments for '{}.{}'; add a type annotation", type_name, method_name),
+                                            expr.span
+                                        );
+                                    } else {
+                                        self.generic_instantiations.insert(GenericInstantiation {
+                                            func_def_id: fn_def_id,
+                                            type_args: type_args.clone(),
+                                        });
+                                    }
+                                }
+                                
                                 // Record function signature at function span for hover
-                                if let Type::Function { params, ret } = &fn_type {
+                                if let Type::Function { params, ret } = &self.ctx.apply(&fn_type) {
                                     let params_str: Vec<String> = params.iter()
                                         .map(|p| p.display(&self.ctx))
                                         .collect();
                                     let sig = format!("fn {}({}) -> {}", method_name, params_str.join(", "), ret.display(&self.ctx));
-                                    self.ctx.record_span_type(method_span.start, method_span.end, sig);
-                                    self.ctx.record_span_definition(method_span.start, method_span.end, fn_def_id);
+                                    self.ctx.record_span_type(method_span, sig);
+                                    self.ctx.record_span_definition(method_span, fn_def_id);
                                 }
                                 
                                 return TypedExpr {
@@ -1298,6 +1503,7 @@ impl TypeChecker {
                                         function: method_name.clone(),
                                         function_def_id: fn_def_id,
                                         function_span: method_span,
+                                        type_args,
                                         args: args_typed,
                                     },
                                     ty: result_type,
@@ -1344,12 +1550,9 @@ impl TypeChecker {
                     if let Some(struct_id) = struct_id {
                         if let Some((method_def_id, method_type)) = self.methods.get(&(struct_id, method_name.clone())).cloned() {
                             // This is a method call!
-                            // TODO: Handle named arguments for methods
-                            let args_typed: Vec<_> = args.iter().map(|a| self.check_expr(&a.value)).collect();
-                            
                             // Apply type parameter substitution if the receiver has type args
                             let substituted_method_type = if !receiver_type_args.is_empty() {
-                                if let Some(type_params) = self.type_type_params.get(&struct_id).cloned() {
+                                if let Some(type_params) = self.impl_type_params.get(&method_def_id).cloned() {
                                     self.substitute_type_params(&method_type, &type_params, &receiver_type_args)
                                 } else {
                                     method_type.clone()
@@ -1358,6 +1561,27 @@ impl TypeChecker {
                                 method_type.clone()
                             };
                             
+                            // The method's own type params (e.g., the U in fn map<U>) are inferred from the arguments
+                            let method_type_params: Vec<(DefId, String)> = self.generic_functions.get(&method_def_id)
+                                .map(|tps| tps.iter().map(|tp| (tp.def_id, tp.name.clone())).collect())
+                                .unwrap_or_default();
+                            let method_type_vars: Vec<Type> = method_type_params.iter().map(|_| self.ctx.fresh_var()).collect();
+                            let substituted_method_type = if method_type_params.is_empty() {
+                                substituted_method_type
+                            } else {
+                                self.substitute_type_params(&substituted_method_type, &method_type_params, &method_type_vars)
+                            };
+                            
+                            // Check arguments against the parameter types so lambdas can infer theirs
+                            // TODO: Handle named arguments for methods
+                            let param_types = match &substituted_method_type {
+                                Type::Function { params, .. } => params.get(1..).unwrap_or_default().to_vec(),
+                                _ => Vec::new(),
+                            };
+                            let args_typed: Vec<_> = args.iter().enumerate()
+                                .map(|(i, a)| self.check_expr_with_expected(&a.value, param_types.get(i)))
+                                .collect();
+                            
                             let (result_type, is_mut_self) = if let Type::Function { params, ret } = &substituted_method_type {
                                 // Method's first param is &self or &mut self
                                 // Check remaining args against remaining params
@@ -1370,6 +1594,7 @@ impl TypeChecker {
                                     for (i, (arg, param)) in args_typed.iter().zip(method_params.iter()).enumerate() {
                                         if let Err(e) = self.ctx.unify(&arg.ty, param) {
                                             self.error(
+                                                codes::MISMATCHED_TYPES,
                                                 format!("argument {} type mismatch: {}", i + 1, e),
                                                 expr.span
                                             );
@@ -1377,14 +1602,14 @@ impl TypeChecker {
                                     }
                                     // Check if self param is &mut
                                     let is_mut = params.first().map(|p| matches!(p, Type::Ref { is_mut: true, .. })).unwrap_or(false);
-                                    ((**ret).clone(), is_mut)
+                                    (self.ctx.apply(ret), is_mut)
                                 }
                             } else {
                                 (Type::Error, false)
                             };
                             
                             // Record method signature at method span for hover (use substituted type for concrete signature)
-                            if let Type::Function { params, ret } = &substituted_method_type {
+                            if let Type::Function { params, ret } = &self.ctx.apply(&substituted_method_type) {
                                 let params_str: Vec<String> = params.iter().enumerate()
                                     .map(|(i, p)| {
                                         if i == 0 {
@@ -1400,15 +1625,19 @@ impl TypeChecker {
                                     })
                                     .collect();
                                 let sig = format!("fn {}({}) -> {}", method_name, params_str.join(", "), ret.display(&self.ctx));
-                                self.ctx.record_span_type(method_span.start, method_span.end, sig);
-                                self.ctx.record_span_definition(method_span.start, method_span.end, method_def_id);
+                                self.ctx.record_span_type(method_span, sig);
+                                self.ctx.record_span_definition(method_span, method_def_id);
                             }
                             
-                            // Record generic instantiation for method if receiver has type args
-                            if !receiver_type_args.is_empty() {
+                            // Record generic instantiation for method if receiver or method has type args
+                            let method_type_args: Vec<Type> = method_type_vars.iter().map(|v| self.ctx.apply(v)).collect();
+                            if !receiver_type_args.is_empty() || !method_type_args.is_empty() {
+                                let type_args = receiver_type_args.iter().map(|t| self.ctx.apply(t))
+                                    .chain(method_type_args.iter().cloned())
+                                    .collect();
                                 self.generic_instantiations.insert(GenericInstantiation {
                                     func_def_id: method_def_id,
-                                    type_args: receiver_type_args.clone(),
+                                    type_args,
                                 });
                             }
                             
@@ -1420,6 +1649,7 @@ impl TypeChecker {
                                     method_span,
                                     is_mut_self,
                                     args: args_typed,
+                                    method_type_args,
                                 },
                                 ty: result_type,
                                 span: expr.span,
@@ -1526,8 +1756,8 @@ impl TypeChecker {
                                     })
                                     .collect();
                                 let sig = format!("fn {}({}) -> {}", method_name, params_str.join(", "), ret.display(&self.ctx));
-                                self.ctx.record_span_type(method_span.start, method_span.end, sig);
-                                self.ctx.record_span_definition(method_span.start, method_span.end, method_def_id);
+                                self.ctx.record_span_type(method_span, sig);
+                                self.ctx.record_span_definition(method_span, method_def_id);
                             }
                             
                             return TypedExpr {
@@ -1569,7 +1799,39 @@ impl TypeChecker {
                     args.iter().map(|a| &a.value).collect()
                 };
                 
-                let args_typed: Vec<_> = reordered_args.iter().map(|a| self.check_expr(a)).collect();
+                // Check arguments against the callee's parameter types so lambdas
+                // can infer theirs. A generic callee's type params become
+                // inference variables, which the other arguments fix before
+                // the lambdas are checked.
+                let generic_params = callee_def_id.and_then(|id| {
+                    self.generic_functions.get(&id)
+                        .map(|tps| tps.iter().map(|tp| (tp.def_id, tp.name.clone())).collect::<Vec<_>>())
+                        .or_else(|| self.type_type_params.get(&id).cloned())
+                });
+                let expected_params = match (&callee_typed.ty, &generic_params) {
+                    (Type::Function { params, .. }, None) => params.clone(),
+                    (Type::Function { params, .. }, Some(tp_pairs)) => {
+                        let vars: Vec<_> = tp_pairs.iter().map(|_| self.ctx.fresh_var()).collect();
+                        params.iter().map(|p| self.substitute_type_params(p, tp_pairs, &vars)).collect()
+                    }
+                    _ => Vec::new(),
+                };
+                let mut checked: Vec<Option<TypedExpr>> = vec![None; reordered_args.len()];
+                for lambdas in [false, true] {
+                    for (i, a) in reordered_args.iter().enumerate() {
+                        if matches!(a.kind, ResolvedExprKind::Lambda { .. }) != lambdas {
+                            continue;
+                        }
+                        let expected = expected_params.get(i).map(|p| self.ctx.apply(p));
+                        let typed = self.check_expr_with_expected(a, expected.as_ref());
+                        // Mismatches are reported against the declared parameter types below
+                        if let (Some(expected), Some(_)) = (&expected, &generic_params) {
+                            let _ = self.ctx.unify(&typed.ty, expected);
+                        }
+                        checked[i] = Some(typed);
+                    }
+                }
+                let args_typed: Vec<TypedExpr> = checked.into_iter().flatten().collect();
                 
                 let (result_type, type_args) = match &callee_typed.ty {
                     Type::Function { params, ret } => {
@@ -1608,6 +1870,7 @@ impl TypeChecker {
                                                     let trait_name = self.ctx.get_type_name(trait_def_id)
                                                         .unwrap_or_else(|| format!("trait#{}", trait_def_id.0));
                                                     self.error(
+                                                        codes::TRAIT_NOT_IMPLEMENTED,
                                                         format!("type {} does not implement trait {}", 
                                                             concrete_type.display(&self.ctx), trait_name),
                                                         expr.span
@@ -1637,6 +1900,7 @@ impl TypeChecker {
                             for (i, (arg, param)) in args_typed.iter().zip(params.iter()).enumerate() {
                                 if let Err(e) = self.ctx.unify(&arg.ty, param) {
                                     self.error(
+                                        codes::MISMATCHED_TYPES,
                                         format!("argument {} type mismatch: {}", i + 1, e),
                                         expr.span
                                     );
@@ -1668,7 +1932,7 @@ impl TypeChecker {
                     }
                     Type::Error => (Type::Error, None),
                     _ => {
-                        self.error(format!("cannot call non-function type"), expr.span);
+                        self.error(codes::NOT_CALLABLE, format!("cannot call non-function type"), expr.span);
                         (Type::Error, None)
                     }
                 };
@@ -1700,32 +1964,33 @@ impl TypeChecker {
             ResolvedExprKind::Field { expr: base, field, field_span, .. } => {
                 let base_typed = self.check_expr(base);
                 
-                let field_type = match &base_typed.ty {
-                    Type::Struct { def_id: struct_id, .. } => {
-                        self.ctx.get_struct_field(*struct_id, field)
-                            .cloned()
+                let field_type = match &self.ctx.apply(&base_typed.ty) {
+                    Type::Tuple(elems) => self.tuple_element(elems, field, expr.span),
+                    Type::Struct { def_id: struct_id, type_args } => {
+                        self.struct_field_type(*struct_id, type_args, field)
                             .unwrap_or_else(|| {
-                                self.error(format!("no field '{}' on struct", field), expr.span);
+                                self.error(codes::NO_FIELD, format!("no field '{}' on struct", field), expr.span);
                                 Type::Error
                             })
                     }
                     Type::Ref { inner, .. } => {
                         // Auto-deref for field access
-                        if let Type::Struct { def_id: struct_id, .. } = inner.as_ref() {
-                            self.ctx.get_struct_field(*struct_id, field)
-                                .cloned()
+                        if let Type::Struct { def_id: struct_id, type_args } = inner.as_ref() {
+                            self.struct_field_type(*struct_id, type_args, field)
                                 .unwrap_or_else(|| {
-                                    self.error(format!("no field '{}' on struct", field), expr.span);
+                                    self.error(codes::NO_FIELD, format!("no field '{}' on struct", field), expr.span);
                                     Type::Error
                                 })
+                        } else if let Type::Tuple(elems) = inner.as_ref() {
+                            self.tuple_element(elems, field, expr.span)
                         } else {
-                            self.error(format!("cannot access field on non-struct type"), expr.span);
+                            self.error(codes::NO_FIELD, format!("cannot access field on non-struct type"), expr.span);
                             Type::Error
                         }
                     }
                     Type::Error => Type::Error,
                     _ => {
-                        self.error(format!("cannot access field on non-struct type"), expr.span);
+                        self.error(codes::NO_FIELD, format!("cannot access field on non-struct type"), expr.span);
                         Type::Error
                     }
                 };
@@ -1737,29 +2002,8 @@ impl TypeChecker {
                 }, field_type)
             }
             
-            ResolvedExprKind::StructLit { struct_def, fields } => {
-                let struct_type = Type::Struct { def_id: *struct_def, type_args: vec![] };
-                
-                // Check field types
-                let mut typed_fields = Vec::new();
-                for (name, name_span, field_expr) in fields {
-                    let typed = self.check_expr(field_expr);
-                    
-                    if let Some(expected) = self.ctx.get_struct_field(*struct_def, name).cloned() {
-                        if let Err(e) = self.ctx.unify(&typed.ty, &expected) {
-                            self.error(format!("field '{}' type mismatch: {}", name, e), field_expr.span);
-                        }
-                        // Record the field type at the field name span for hover
-                        self.ctx.record_span_type(name_span.start, name_span.end, format!("{}: {}", name, expected.display(&self.ctx)));
-                    }
-                    
-                    typed_fields.push((name.clone(), typed));
-                }
-                
-                (TypedExprKind::StructLit {
-                    struct_def: *struct_def,
-                    fields: typed_fields,
-                }, struct_type)
+            ResolvedExprKind::StructLit { struct_def, fields, spread } => {
+                self.check_struct_lit(*struct_def, fields, spread.as_deref(), expected, expr.span)
             }
             
             ResolvedExprKind::If { cond, then_block, else_block } => {
@@ -1767,7 +2011,7 @@ impl TypeChecker {
                 
                 // Condition must be bool
                 if let Err(e) = self.ctx.unify(&cond_typed.ty, &Type::Bool) {
-                    self.error(format!("if condition must be bool: {}", e), expr.span);
+                    self.error(codes::MISMATCHED_TYPES, format!("if condition must be bool: {}", e), expr.span);
                 }
                 
                 let then_typed = self.check_block(then_block, None);
@@ -1787,14 +2031,21 @@ impl TypeChecker {
                     None => (None, Type::Unit),
                 };
                 
-                // Unify then and else types
-                if else_typed.is_some() {
+                // Unify then and else types. Without an `else` the `if` can
+                // skip its block, so its value is `()` even if the block diverges.
+                let then_ty = self.ctx.apply(&then_ty);
+                if else_typed.is_some() || then_ty != Type::Never {
                     if let Err(e) = self.ctx.unify(&then_ty, &result_type) {
-                        self.error(format!("if/else type mismatch: {}", e), expr.span);
+                        let message = if else_typed.is_some() { "if/else type mismatch" } else { "if without else must have type ()" };
+                        self.error(codes::MISMATCHED_TYPES, format!("{}: {}", message, e), expr.span);
                     }
                 }
                 
-                let final_ty = self.ctx.apply(&then_ty);
+                // A diverging branch takes the type of the other one
+                let final_ty = match then_ty {
+                    Type::Never => self.ctx.apply(&result_type),
+                    ty => self.ctx.apply(&ty),
+                };
                 
                 (TypedExprKind::If {
                     cond: Box::new(cond_typed),
@@ -1803,69 +2054,170 @@ impl TypeChecker {
                 }, final_ty)
             }
             
-            ResolvedExprKind::While { cond, body } => {
+            ResolvedExprKind::While { loop_id, cond, body } => {
                 let cond_typed = self.check_expr(cond);
                 
                 if let Err(e) = self.ctx.unify(&cond_typed.ty, &Type::Bool) {
-                    self.error(format!("while condition must be bool: {}", e), expr.span);
+                    self.error(codes::MISMATCHED_TYPES, format!("while condition must be bool: {}", e), expr.span);
                 }
                 
+                self.loop_stack.push(LoopContext { id: *loop_id, break_type: None, has_break: false });
                 let body_typed = self.check_block(body, None);
+                self.loop_stack.pop();
                 
                 (TypedExprKind::While {
+                    loop_id: *loop_id,
                     cond: Box::new(cond_typed),
                     body: body_typed,
                 }, Type::Unit)
             }
             
-            ResolvedExprKind::For { binding, binding_name, iter, body } => {
-                // Check that iter is a range expression (Binary with Range operator)
-                // For now, we only support `for x in start..end` syntax
-                match &iter.kind {
-                    ResolvedExprKind::Binary { left, op: wisp_ast::BinOp::Range, right } => {
-                        let start_typed = self.check_expr(left);
-                        let end_typed = self.check_expr(right);
-                        
-                        // Both start and end must be i32 (for now)
-                        if let Err(e) = self.ctx.unify(&start_typed.ty, &Type::I32) {
-                            self.error(format!("for loop range start must be i32: {}", e), left.span);
-                        }
-                        if let Err(e) = self.ctx.unify(&end_typed.ty, &Type::I32) {
-                            self.error(format!("for loop range end must be i32: {}", e), right.span);
+            ResolvedExprKind::Loop { loop_id, body } => {
+                let break_type = expected.cloned().unwrap_or_else(|| self.ctx.fresh_var());
+                self.loop_stack.push(LoopContext { id: *loop_id, break_type: Some(break_type), has_break: false });
+                let body_typed = self.check_block(body, None);
+                let ctx = self.loop_stack.pop().unwrap();
+                
+                // A loop without a `break` never finishes
+                let ty = match (ctx.has_break, ctx.break_type) {
+                    (true, Some(break_type)) => self.ctx.apply(&break_type),
+                    _ => Type::Never,
+                };
+                
+                (TypedExprKind::Loop {
+                    loop_id: *loop_id,
+                    body: body_typed,
+                }, ty)
+            }
+            
+            ResolvedExprKind::Return(value) => {
+                let return_type = self.current_return_type.clone().unwrap_or(Type::Unit);
+                let typed_value = value.as_ref().map(|v| Box::new(self.check_expr_with_expected(v, Some(&return_type))));
+                let value_type = typed_value.as_ref().map(|v| v.ty.clone()).unwrap_or(Type::Unit);
+                
+                if self.ctx.unify(&value_type, &return_type).is_err() {
+                    let expected_str = self.ctx.apply(&return_type).display(&self.ctx);
+                    let found_str = self.ctx.apply(&value_type).display(&self.ctx);
+                    self.error(
+                        codes::MISMATCHED_TYPES,
+                        format!("mismatched return type: expected `{}`, found `{}`", expected_str, found_str),
+                        expr.span,
+                    );
+                }
+                
+                (TypedExprKind::Return(typed_value), Type::Never)
+            }
+            
+            ResolvedExprKind::Break { target, value } => {
+                let break_type = self.loop_stack.iter_mut().rev()
+                    .find(|l| l.id == *target)
+                    .map(|l| {
+                        l.has_break = true;
+                        l.break_type.clone()
+                    });
+                let typed_value = match break_type.clone().flatten() {
+                    Some(break_type) => value.as_ref().map(|v| Box::new(self.check_expr_with_expected(v, Some(&break_type)))),
+                    None => value.as_ref().map(|v| Box::new(self.check_expr(v))),
+                };
+                
+                match break_type {
+                    Some(Some(break_type)) => {
+                        let value_type = typed_value.as_ref().map(|v| v.ty.clone()).unwrap_or(Type::Unit);
+                        if let Err(e) = self.ctx.unify(&value_type, &break_type) {
+                            self.error(codes::MISMATCHED_TYPES, format!("break value type mismatch: {}", e), expr.span);
                         }
-                        
-                        // The binding has type i32
-                        self.ctx.register_def_type(*binding, Type::I32);
-                        
-                        let body_typed = self.check_block(body, None);
-                        
-                        (TypedExprKind::For {
-                            binding: *binding,
-                            binding_name: binding_name.clone(),
-                            start: Box::new(start_typed),
-                            end: Box::new(end_typed),
-                            body: body_typed,
-                        }, Type::Unit)
                     }
-                    _ => {
-                        self.error("for loop currently only supports range expressions (start..end)".to_string(), iter.span);
-                        (TypedExprKind::Error, Type::Error)
+                    Some(None) => {
+                        if let Some(v) = &typed_value {
+                            self.error(codes::LOOP_CONTROL, "`break` with a value is only allowed inside `loop`".to_string(), v.span);
+                        }
                     }
+                    None => {}
+                }
+                
+                (TypedExprKind::Break { target: *target, value: typed_value }, Type::Never)
+            }
+            
+            ResolvedExprKind::Continue { target } => {
+                (TypedExprKind::Continue { target: *target }, Type::Never)
+            }
+            
+            ResolvedExprKind::For { loop_id, binding, binding_name, iter, iter_def, body } => {
+                let for_iter = self.check_for_iter(iter, *binding, *iter_def);
+                
+                self.loop_stack.push(LoopContext { id: *loop_id, break_type: None, has_break: false });
+                let body_typed = self.check_block(body, None);
+                self.loop_stack.pop();
+                
+                match for_iter {
+                    Some(for_iter) => (TypedExprKind::For {
+                        loop_id: *loop_id,
+                        binding: *binding,
+                        binding_name: binding_name.clone(),
+                        iter: for_iter,
+                        body: body_typed,
+                    }, Type::Unit),
+                    None => (TypedExprKind::Error, Type::Error),
                 }
             }
             
+            // `{}` where a map is expected is an empty map literal
+            ResolvedExprKind::Block(block)
+                if block.stmts.is_empty()
+                    && expected.is_some_and(|t| self.find_from_iterator_impl(&self.ctx.apply(t)).is_some()) =>
+            {
+                self.check_collection_lit(&[], true, expected, expr.span)
+            }
+            
             ResolvedExprKind::Block(block) => {
                 let typed = self.check_block(block, None);
                 let ty = typed.ty.clone();
                 (TypedExprKind::Block(typed), ty)
             }
             
+            ResolvedExprKind::TrailingBlock(block) => {
+                match expected.map(|t| self.ctx.apply(t)) {
+                    // For a collection parameter, each expression in the block is an element
+                    Some(collection) if matches!(collection, Type::Array(..)) || self.find_from_iterator_impl(&collection).is_some() => {
+                        let mut elements = Vec::new();
+                        for stmt in &block.stmts {
+                            match stmt {
+                                ResolvedStmt::Expr(e) => elements.push(ResolvedCollectionElem {
+                                    kind: ResolvedCollectionElemKind::Item(e.clone()),
+                                    span: e.span,
+                                }),
+                                ResolvedStmt::Let { span, .. } | ResolvedStmt::Defer { span, .. } => {
+                                    self.error(codes::SYNTAX_ERROR, "a trailing block that builds a collection can only list elements".to_string(), *span);
+                                    self.check_stmt(stmt);
+                                }
+                            }
+                        }
+                        self.check_collection_lit(&elements, false, Some(&collection), expr.span)
+                    }
+                    // Otherwise the block's value is the argument
+                    _ => {
+                        let typed = self.check_block_stmts(block, expected);
+                        let ty = typed.ty.clone();
+                        (TypedExprKind::Block(typed), ty)
+                    }
+                }
+            }
+            
             ResolvedExprKind::Assign { target, value } => {
+                let through_param = self.assign_through_param(target, value);
+                let (target, value) = match &through_param {
+                    Some((target, value)) => (target, value),
+                    None => (&**target, &**value),
+                };
                 let target_typed = self.check_expr(target);
                 let value_typed = self.check_expr(value);
                 
+                if matches!(target.kind, ResolvedExprKind::Index { .. }) && matches!(target_typed.kind, TypedExprKind::Deref(_)) {
+                    self.error(codes::CANNOT_INDEX, "cannot assign through `Index`: indexing a struct is read-only".to_string(), target.span);
+                }
+                
                 if let Err(e) = self.ctx.unify(&target_typed.ty, &value_typed.ty) {
-                    self.error(format!("assignment type mismatch: {}", e), expr.span);
+                    self.error(codes::MISMATCHED_TYPES, format!("assignment type mismatch: {}", e), expr.span);
                 }
                 
                 (TypedExprKind::Assign {
@@ -1894,7 +2246,7 @@ impl TypeChecker {
                     Type::Ref { inner, .. } => (**inner).clone(),
                     Type::Error => Type::Error,
                     _ => {
-                        self.error("cannot dereference non-reference type".to_string(), expr.span);
+                        self.error(codes::CANNOT_DEREF, "cannot dereference non-reference type".to_string(), expr.span);
                         Type::Error
                     }
                 };
@@ -1910,13 +2262,23 @@ impl TypeChecker {
                 
                 for arm in arms {
                     let typed_arm = self.check_match_arm(arm, &scrutinee_typed.ty);
-                    if let Err(e) = self.ctx.unify(&typed_arm.body.ty, &result_type) {
-                        self.error(format!("match arm type mismatch: {}", e), arm.span);
+                    // Diverging arms don't contribute to the match type
+                    if !matches!(self.ctx.apply(&typed_arm.body.ty), Type::Never)
+                        && let Err(e) = self.ctx.unify(&typed_arm.body.ty, &result_type)
+                    {
+                        self.error(codes::MISMATCHED_TYPES, format!("match arm type mismatch: {}", e), arm.span);
                     }
                     result_type = self.ctx.apply(&result_type);
                     typed_arms.push(typed_arm);
                 }
                 
+                // Every arm diverges
+                if !arms.is_empty() && matches!(result_type, Type::Var(_)) {
+                    result_type = Type::Never;
+                }
+                
+                self.check_match_exhaustiveness(arms, &typed_arms, &scrutinee_typed.ty, scrutinee.span);
+                
                 (TypedExprKind::Match {
                     scrutinee: Box::new(scrutinee_typed),
                     arms: typed_arms,
@@ -1925,11 +2287,21 @@ impl TypeChecker {
             
             ResolvedExprKind::Index { expr: base, index } => {
                 let base_typed = self.check_expr(base);
+                
+                // Structs and enums are indexed through their Index impl
+                let indexed_ty = match &base_typed.ty {
+                    Type::Ref { inner, .. } => (**inner).clone(),
+                    ty => ty.clone(),
+                };
+                if let Some((method_def_id, method_type)) = self.find_op_trait_method(&indexed_ty, "Index", "index") {
+                    return self.check_index_call(base_typed, indexed_ty, index, method_def_id, method_type, expr.span);
+                }
+                
                 let index_typed = self.check_expr(index);
                 
                 // Index must be integer
                 if !index_typed.ty.is_integer() && !matches!(index_typed.ty, Type::Error) {
-                    self.error("index must be an integer".to_string(), expr.span);
+                    self.error(codes::MISMATCHED_TYPES, "index must be an integer".to_string(), expr.span);
                 }
                 
                 let elem_type = match &base_typed.ty {
@@ -1940,14 +2312,14 @@ impl TypeChecker {
                             Type::Slice(elem) => (**elem).clone(),
                             Type::Array(elem, _) => (**elem).clone(),
                             _ => {
-                                self.error("cannot index non-array type".to_string(), expr.span);
+                                self.error(codes::CANNOT_INDEX, "cannot index non-array type".to_string(), expr.span);
                                 Type::Error
                             }
                         }
                     }
                     Type::Error => Type::Error,
                     _ => {
-                        self.error("cannot index non-array type".to_string(), expr.span);
+                        self.error(codes::CANNOT_INDEX, "cannot index non-array type".to_string(), expr.span);
                         Type::Error
                     }
                 };
@@ -1959,43 +2331,48 @@ impl TypeChecker {
             }
             
             ResolvedExprKind::ArrayLit(elements) => {
-                if elements.is_empty() {
-                    self.error("cannot infer type of empty array literal".to_string(), expr.span);
-                    (TypedExprKind::ArrayLit(vec![]), Type::Error)
-                } else {
-                    // Type check all elements
-                    let typed_elements: Vec<_> = elements.iter().map(|e| self.check_expr(e)).collect();
-                    
-                    // All elements must have the same type
-                    let elem_type = typed_elements[0].ty.clone();
-                    for (i, elem) in typed_elements.iter().enumerate().skip(1) {
-                        if let Err(e) = self.ctx.unify(&elem_type, &elem.ty) {
-                            self.error(format!("array element {} has wrong type: {}", i, e), elem.span);
-                        }
-                    }
-                    
-                    let len = typed_elements.len();
-                    let array_type = Type::Array(Box::new(self.ctx.apply(&elem_type)), len);
-                    
-                    (TypedExprKind::ArrayLit(typed_elements), array_type)
-                }
+                self.check_collection_lit(elements, false, expected, expr.span)
+            }
+            
+            ResolvedExprKind::MapLit(elements) => {
+                self.check_collection_lit(elements, true, expected, expr.span)
+            }
+            
+            ResolvedExprKind::Tuple(elements) => {
+                // Elements take their expected types from an expected tuple of the same size
+                let expected_elems = match expected.map(|t| self.ctx.apply(t)) {
+                    Some(Type::Tuple(elems)) if elems.len() == elements.len() => elems,
+                    _ => Vec::new(),
+                };
+                let typed_elements: Vec<_> = elements.iter().enumerate()
+                    .map(|(i, e)| self.check_expr_with_expected(e, expected_elems.get(i)))
+                    .collect();
+                let tuple_type = Type::Tuple(typed_elements.iter().map(|e| e.ty.clone()).collect());
+                (TypedExprKind::Tuple(typed_elements), tuple_type)
             }
             
-            ResolvedExprKind::Lambda { params, body } => {
+            ResolvedExprKind::Lambda { is_move, params, body } => {
                 // Type check the lambda
-                // For now, we require type annotations on parameters (no inference)
+                // Unannotated parameters take their types from the expected function type, if any
+                let expected_params = match expected.map(|t| self.ctx.apply(t)) {
+                    Some(Type::Function { params: expected_params, .. }) if expected_params.len() == params.len() => expected_params,
+                    _ => Vec::new(),
+                };
                 let mut param_types = Vec::new();
                 let mut typed_params = Vec::new();
                 
-                for p in params {
+                for (i, p) in params.iter().enumerate() {
                     let param_ty = if let Some(ty) = &p.ty {
                         self.resolve_type(ty)
+                    } else if let Some(ty) = expected_params.get(i) {
+                        ty.clone()
                     } else {
                         // No type annotation - use a fresh type variable for inference
                         self.ctx.fresh_var()
                     };
                     
                     self.ctx.register_def_type(p.def_id, param_ty.clone());
+                    self.lambda_params.insert(p.def_id);
                     param_types.push(param_ty.clone());
                     typed_params.push(TypedLambdaParam {
                         def_id: p.def_id,
@@ -2005,16 +2382,37 @@ impl TypeChecker {
                     });
                 }
                 
+                // `return` inside the body returns from the lambda
+                let lambda_return = self.ctx.fresh_var();
+                let outer_return = self.current_return_type.replace(lambda_return.clone());
+                let outer_loops = std::mem::take(&mut self.loop_stack);
                 let body_typed = self.check_expr(body);
-                let ret_type = body_typed.ty.clone();
+                self.current_return_type = outer_return;
+                self.loop_stack = outer_loops;
+                
+                if let Err(e) = self.ctx.unify(&body_typed.ty, &lambda_return) {
+                    self.error(codes::MISMATCHED_TYPES, format!("lambda return type mismatch: {}", e), body.span);
+                }
+                let ret_type = self.ctx.apply(&lambda_return);
                 
                 let fn_type = Type::Function {
                     params: param_types,
                     ret: Box::new(ret_type),
                 };
                 
+                // Outer variables the body uses, and how
+                let is_copy = |ty: &Type| self.is_copy_type(ty);
+                let mut captures = CaptureCollector::new(&self.ctx, &self.local_defs, &is_copy)
+                    .collect(params.iter().map(|p| p.def_id), &body_typed);
+                if *is_move {
+                    for capture in &mut captures {
+                        capture.mode = CaptureMode::Move;
+                    }
+                }
+                
                 (TypedExprKind::Lambda {
                     params: typed_params,
+                    captures,
                     body: Box::new(body_typed),
                 }, fn_type)
             }
@@ -2030,7 +2428,7 @@ impl TypeChecker {
                 
                 let valid = self.is_valid_cast(&from_ty, &to_ty);
                 if !valid {
-                    self.error(format!("cannot cast {} to {}", from_ty.display(&self.ctx), to_ty.display(&self.ctx)), expr.span);
+                    self.error(codes::INVALID_CAST, format!("cannot cast {} to {}", from_ty.display(&self.ctx), to_ty.display(&self.ctx)), expr.span);
                 }
                 
                 (TypedExprKind::Cast {
@@ -2039,10 +2437,39 @@ impl TypeChecker {
                 }, target)
             }
             
+            ResolvedExprKind::Intrinsic { intrinsic, ty, args } => {
+                let ty = self.resolve_type(ty);
+                // #read and #write take the address as an i64, #write the value as the type itself
+                let mut args_typed = Vec::new();
+                for (i, arg) in args.iter().enumerate() {
+                    let param = if i == 0 { Type::I64 } else { ty.clone() };
+                    let typed = self.check_expr_with_expected(arg, Some(&param));
+                    if let Err(e) = self.ctx.unify(&param, &typed.ty) {
+                        self.error(codes::MISMATCHED_TYPES, format!("argument {} of '{}' has wrong type: {}", i + 1, intrinsic, e), typed.span);
+                    }
+                    args_typed.push(typed);
+                }
+                let result_type = match intrinsic {
+                    wisp_ast::Intrinsic::SizeOf | wisp_ast::Intrinsic::AlignOf => Type::I64,
+                    wisp_ast::Intrinsic::Read => ty.clone(),
+                    wisp_ast::Intrinsic::Write => Type::Unit,
+                };
+                (TypedExprKind::Intrinsic {
+                    intrinsic: *intrinsic,
+                    ty,
+                    args: args_typed,
+                }, result_type)
+            }
+            
+            ResolvedExprKind::Try { expr: inner, value_binding, error_binding } => {
+                self.check_try(inner, *value_binding, *error_binding, expr.span)
+            }
+            
             ResolvedExprKind::NamespacePath(path) => {
                 // This is an intermediate state that should be resolved during field access
                 // If we get here, it means we have something like `std.io` without a final member access
                 self.error(
+                    codes::NOT_A_VALUE,
                     format!("namespace path '{}' cannot be used as a value", path.join(".")),
                     expr.span
                 );
@@ -2122,6 +2549,7 @@ impl TypeChecker {
                                     // No Display trait found, just use the expression
                                     // This will likely fail at runtime
                                     self.error(
+                                        codes::TRAIT_NOT_IMPLEMENTED,
                                         format!("type {} does not implement Display for string interpolation", 
                                             typed_expr.ty.display(&self.ctx)),
                                         expr.span
@@ -2176,6 +2604,7 @@ impl TypeChecker {
                                         method_span: expr.span,
                                         is_mut_self: false,
                                         args: vec![rhs],
+                                        method_type_args: Vec::new(),
                                     },
                                     ty: string_type.clone(),
                                     span: expr.span,
@@ -2217,9 +2646,34 @@ impl TypeChecker {
             TypedExprKind::Var { name, .. } => format!("{}: {}", name, ty.display(&self.ctx)),
             _ => ty.display(&self.ctx),
         };
-        self.ctx.record_span_type(expr.span.start, expr.span.end, type_str);
+        self.ctx.record_span_type(expr.span, type_str);
 
-        TypedExpr { kind, ty, span: expr.span }
+        let typed = TypedExpr { kind, ty, span: expr.span };
+        match expected {
+            Some(expected) => self.coerce_to_slice(typed, expected),
+            None => typed,
+        }
+    }
+
+    /// A reference to an array given where a slice is expected becomes a
+    /// slice of the whole array
+    fn coerce_to_slice(&mut self, typed: TypedExpr, expected: &Type) -> TypedExpr {
+        let Type::Slice(_) = self.ctx.apply(expected) else {
+            return typed;
+        };
+        let Type::Ref { inner, .. } = self.ctx.apply(&typed.ty) else {
+            return typed;
+        };
+        let Type::Array(elem, _) = self.ctx.apply(&inner) else {
+            return typed;
+        };
+        let span = typed.span;
+        let target_type = Type::Slice(elem);
+        TypedExpr {
+            kind: TypedExprKind::Cast { expr: Box::new(typed), target_type: target_type.clone() },
+            ty: target_type,
+            span,
+        }
     }
     
     /// Map binary operators to (trait name, method name) for operator overloading
@@ -2244,6 +2698,77 @@ impl TypeChecker {
         }
     }
     
+    /// Indexing through an `Index` impl: `base[index]` becomes `*base.index(index)`
+    fn check_index_call(
+        &mut self,
+        base_typed: TypedExpr,
+        indexed_ty: Type,
+        index: &ResolvedExpr,
+        method_def_id: DefId,
+        method_type: Type,
+        span: Span,
+    ) -> TypedExpr {
+        let receiver_type_args = match &indexed_ty {
+            Type::Struct { type_args, .. } | Type::Enum { type_args, .. } => type_args.clone(),
+            _ => Vec::new(),
+        };
+        let method_type = match self.impl_type_params.get(&method_def_id).cloned() {
+            Some(type_params) if !receiver_type_args.is_empty() => {
+                self.substitute_type_params(&method_type, &type_params, &receiver_type_args)
+            }
+            _ => method_type,
+        };
+        let Type::Function { params, ret } = &method_type else {
+            return TypedExpr { kind: TypedExprKind::Error, ty: Type::Error, span };
+        };
+        
+        let index_typed = match params.get(1) {
+            Some(param) => {
+                let typed = self.check_expr_with_expected(index, Some(param));
+                if let Err(e) = self.ctx.unify(param, &typed.ty) {
+                    self.error(codes::MISMATCHED_TYPES, format!("index has wrong type: {}", e), typed.span);
+                }
+                typed
+            }
+            None => self.check_expr(index),
+        };
+        let elem_type = match self.ctx.apply(ret) {
+            Type::Ref { inner, .. } => *inner,
+            _ => {
+                self.error(codes::INCOMPATIBLE_SIGNATURE, "`index` must return a reference".to_string(), span);
+                Type::Error
+            }
+        };
+        
+        if !receiver_type_args.is_empty() {
+            self.generic_instantiations.insert(GenericInstantiation {
+                func_def_id: method_def_id,
+                type_args: receiver_type_args.iter().map(|t| self.ctx.apply(t)).collect(),
+            });
+        }
+        
+        // The receiver is passed by reference, so look through one that already is
+        let receiver = if matches!(base_typed.ty, Type::Ref { .. }) {
+            TypedExpr { span: base_typed.span, ty: indexed_ty, kind: TypedExprKind::Deref(Box::new(base_typed)) }
+        } else {
+            base_typed
+        };
+        let call = TypedExpr {
+            kind: TypedExprKind::MethodCall {
+                receiver: Box::new(receiver),
+                method: "index".to_string(),
+                method_def_id,
+                method_span: span,
+                is_mut_self: false,
+                args: vec![index_typed],
+                method_type_args: Vec::new(),
+            },
+            ty: self.ctx.apply(ret),
+            span,
+        };
+        TypedExpr { kind: TypedExprKind::Deref(Box::new(call)), ty: elem_type, span }
+    }
+    
     /// Try to find an operator trait implementation for the given type
     fn find_op_trait_method(&self, ty: &Type, trait_name: &str, method_name: &str) -> Option<(DefId, Type)> {
         // Get the trait DefId
@@ -2311,6 +2836,7 @@ impl TypeChecker {
                         function: "from".to_string(),
                         function_def_id: *fn_def_id,
                         function_span: span,
+                        type_args: Vec::new(),
                         args: vec![str_expr],
                     },
                     ty: string_type,
@@ -2330,12 +2856,12 @@ impl TypeChecker {
             // Arithmetic
             BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod => {
                 if let Err(e) = self.ctx.unify(left, right) {
-                    self.error(format!("arithmetic type mismatch: {}", e), span);
+                    self.error(codes::MISMATCHED_TYPES, format!("arithmetic type mismatch: {}", e), span);
                     return Type::Error;
                 }
                 // Allow numeric types, type variables, type parameters (which will be checked via trait bounds), and error types
                 if !left.is_numeric() && !matches!(left, Type::Var(_) | Type::TypeParam { .. } | Type::Error) {
-                    self.error("arithmetic requires numeric types".to_string(), span);
+                    self.error(codes::MISMATCHED_TYPES, "arithmetic requires numeric types".to_string(), span);
                     return Type::Error;
                 }
                 self.ctx.apply(left)
@@ -2343,27 +2869,21 @@ impl TypeChecker {
             // Comparison
             BinOp::Eq | BinOp::NotEq | BinOp::Lt | BinOp::Gt | BinOp::LtEq | BinOp::GtEq => {
                 if let Err(e) = self.ctx.unify(left, right) {
-                    self.error(format!("comparison type mismatch: {}", e), span);
+                    self.error(codes::MISMATCHED_TYPES, format!("comparison type mismatch: {}", e), span);
                 }
                 Type::Bool
             }
             // Logical
             BinOp::And | BinOp::Or => {
                 if let Err(e) = self.ctx.unify(left, &Type::Bool) {
-                    self.error(format!("logical operator requires bool: {}", e), span);
+                    self.error(codes::MISMATCHED_TYPES, format!("logical operator requires bool: {}", e), span);
                 }
                 if let Err(e) = self.ctx.unify(right, &Type::Bool) {
-                    self.error(format!("logical operator requires bool: {}", e), span);
+                    self.error(codes::MISMATCHED_TYPES, format!("logical operator requires bool: {}", e), span);
                 }
                 Type::Bool
             }
-            // Range (used in for loops)
-            BinOp::Range => {
-                // Range is only valid in for loop context, which is handled specially
-                // If we get here, it's an error
-                self.error("range operator (..) can only be used in for loops".to_string(), span);
-                Type::Error
-            }
+            BinOp::Range => unreachable!("ranges are checked as `Range` values"),
         }
     }
 
@@ -2373,7 +2893,7 @@ impl TypeChecker {
         match op {
             UnaryOp::Neg => {
                 if !inner.is_numeric() && !matches!(inner, Type::Var(_) | Type::Error) {
-                    self.error("negation requires numeric type".to_string(), span);
+                    self.error(codes::MISMATCHED_TYPES, "negation requires numeric type".to_string(), span);
                     Type::Error
                 } else {
                     inner.clone()
@@ -2381,7 +2901,7 @@ impl TypeChecker {
             }
             UnaryOp::Not => {
                 if let Err(e) = self.ctx.unify(inner, &Type::Bool) {
-                    self.error(format!("logical not requires bool: {}", e), span);
+                    self.error(codes::MISMATCHED_TYPES, format!("logical not requires bool: {}", e), span);
                 }
                 Type::Bool
             }
@@ -2389,7 +2909,6 @@ impl TypeChecker {
     }
 
     fn check_match_arm(&mut self, arm: &ResolvedMatchArm, scrutinee_type: &Type) -> TypedMatchArm {
-        // TODO: proper pattern type checking
         let pattern = self.check_pattern(&arm.pattern, scrutinee_type);
         let body = self.check_expr(&arm.body);
         
@@ -2407,37 +2926,52 @@ impl TypeChecker {
                 TypedPattern::Binding { def_id: *def_id, name: name.clone(), ty: expected.clone() }
             }
             ResolvedPatternKind::Literal(expr) => {
-                let typed = self.check_expr(expr);
+                let expected = self.ctx.apply(expected);
+                let typed = self.check_expr_with_expected(expr, Some(&expected));
+                if !matches!(expected, Type::Error)
+                    && let Err(e) = self.ctx.unify(&typed.ty, &expected)
+                {
+                    self.error(codes::MISMATCHED_TYPES, format!("mismatched types in pattern: {}", e), pattern.span);
+                }
                 TypedPattern::Literal(typed)
             }
             ResolvedPatternKind::Variant { variant_def, fields } => {
-                // Get the expected enum's type args for substitution
-                let (enum_def_id, type_args) = match expected {
-                    Type::Enum { def_id, type_args } => (Some(*def_id), type_args.clone()),
-                    _ => (None, vec![]),
-                };
-                
-                // Get the variant's field types and substitute type params
-                let variant_field_types: Vec<Type> = if let Some(enum_id) = enum_def_id {
-                    if let Some(type_params) = self.type_type_params.get(&enum_id).cloned() {
-                        // Get variant info from the enum
-                        if let Some(variants) = self.ctx.get_enum_variants(enum_id) {
-                            if let Some((_, _, field_types)) = variants.iter().find(|(_, vid, _)| *vid == *variant_def) {
-                                // Substitute type params in field types
-                                field_types.iter()
-                                    .map(|ft| self.substitute_type_params(ft, &type_params, &type_args))
-                                    .collect()
-                            } else {
-                                vec![]
+                let variant_field_types: Vec<Type> = match self.ctx.is_enum_variant(*variant_def) {
+                    Some((enum_id, variant_idx)) => {
+                        // Take the type args from the scrutinee, or infer them from the pattern
+                        let expected = self.ctx.apply(expected);
+                        // The variant's entry holds the enum's own type params; an impl replaces the enum's entry with its own
+                        let type_params = self.type_type_params.get(variant_def).cloned().unwrap_or_default();
+                        let type_args = match &expected {
+                            Type::Enum { def_id, type_args } if *def_id == enum_id => type_args.clone(),
+                            _ => {
+                                let type_args: Vec<_> = type_params.iter().map(|_| self.ctx.fresh_var()).collect();
+                                let enum_type = Type::Enum { def_id: enum_id, type_args: type_args.clone() };
+                                if !matches!(expected, Type::Error)
+                                    && let Err(e) = self.ctx.unify(&enum_type, &expected)
+                                {
+                                    self.error(codes::MISMATCHED_TYPES, format!("mismatched types in pattern: {}", e), pattern.span);
+                                }
+                                type_args
                             }
-                        } else {
-                            vec![]
+                        };
+                        
+                        let (variant_name, field_types) = self.ctx.get_enum_variants(enum_id)
+                            .and_then(|variants| variants.get(variant_idx))
+                            .map(|(name, _, field_types)| (name.clone(), field_types.clone()))
+                            .unwrap_or_default();
+                        if fields.len() != field_types.len() {
+                            self.error(
+                                codes::PATTERN_FIELD_COUNT,
+                                format!("wrong number of fields in pattern for `{}`: expected {}, got {}", variant_name, field_types.len(), fields.len()),
+                                pattern.span,
+                            );
                         }
-                    } else {
-                        vec![]
+                        field_types.iter()
+                            .map(|ft| self.substitute_type_params(ft, &type_params, &type_args))
+                            .collect()
                     }
-                } else {
-                    vec![]
+                    None => vec![],
                 };
                 
                 let mut typed_fields = Vec::new();
@@ -2450,7 +2984,659 @@ impl TypeChecker {
                     fields: typed_fields,
                 }
             }
+            ResolvedPatternKind::Tuple(elems) => {
+                let expected = self.ctx.apply(expected);
+                let elem_types = match &expected {
+                    Type::Tuple(types) if types.len() == elems.len() => types.clone(),
+                    _ => {
+                        let types: Vec<_> = elems.iter().map(|_| self.ctx.fresh_var()).collect();
+                        if !matches!(expected, Type::Error)
+                            && let Err(e) = self.ctx.unify(&Type::Tuple(types.clone()), &expected)
+                        {
+                            self.error(codes::MISMATCHED_TYPES, format!("mismatched types in pattern: {}", e), pattern.span);
+                        }
+                        types
+                    }
+                };
+                TypedPattern::Tuple(elems.iter().zip(&elem_types).map(|(p, ty)| self.check_pattern(p, ty)).collect())
+            }
+        }
+    }
+
+    /// Check the elements of an array literal, or of a trailing block passed
+    /// for an array parameter
+    /// Check a list or map literal. The expected type decides what it builds:
+    /// an array, or any type implementing `FromIterator` (of `(key, value)`
+    /// pairs for a map literal). Without one, a list literal is an array.
+    fn check_collection_lit(&mut self, elems: &[ResolvedCollectionElem], is_map: bool, expected: Option<&Type>, span: Span) -> (TypedExprKind, Type) {
+        let expected = expected.map(|t| self.ctx.apply(t));
+        let literal = if is_map { "map" } else { "list" };
+        
+        if let Some(collection) = &expected
+            && let Some((with_capacity, extend_one, type_args, item)) = self.find_from_iterator_impl(collection)
+        {
+            if is_map && !matches!(self.ctx.apply(&item), Type::Tuple(ref kv) if kv.len() == 2) {
+                self.error(codes::MISMATCHED_TYPES, format!(
+                    "`{}` collects `{}`, not `(key, value)` pairs, so a map literal can't build it",
+                    collection.display(&self.ctx), item.display(&self.ctx)
+                ), span);
+                return self.check_collection_recovery(elems);
+            }
+            let elems: Vec<_> = elems.iter().map(|e| self.check_collection_elem(e, &item)).collect();
+            let capacity = elems.iter().filter_map(|e| e.fixed_len()).sum();
+            let type_args: Vec<Type> = type_args.iter().map(|t| self.ctx.apply(t)).collect();
+            if !type_args.is_empty() {
+                for func_def_id in [with_capacity, extend_one] {
+                    self.generic_instantiations.insert(GenericInstantiation { func_def_id, type_args: type_args.clone() });
+                }
+            }
+            return (TypedExprKind::Collect { with_capacity, extend_one, type_args, capacity, elems }, collection.clone());
+        }
+        
+        let elem_type = match expected {
+            Some(Type::Array(elem, _)) if !is_map => *elem,
+            Some(Type::Error) => return self.check_collection_recovery(elems),
+            Some(ref ty @ (Type::Struct { .. } | Type::Enum { .. })) => {
+                self.error(codes::TRAIT_NOT_IMPLEMENTED, format!(
+                    "`{}` doesn't implement `FromIterator`, so a {} literal can't build it",
+                    ty.display(&self.ctx), literal
+                ), span);
+                return self.check_collection_recovery(elems);
+            }
+            Some(Type::Var(_)) | None if is_map => {
+                self.error(codes::TYPE_ANNOTATIONS_NEEDED, "cannot infer the type of this map literal; add a type annotation".to_string(), span);
+                return self.check_collection_recovery(elems);
+            }
+            Some(ref ty) if is_map => {
+                self.error(codes::MISMATCHED_TYPES, format!("a map literal can't have type `{}`", ty.display(&self.ctx)), span);
+                return self.check_collection_recovery(elems);
+            }
+            _ if elems.is_empty() => {
+                self.error(codes::TYPE_ANNOTATIONS_NEEDED, "cannot infer type of empty array literal".to_string(), span);
+                return (TypedExprKind::ArrayLit(vec![]), Type::Error);
+            }
+            _ => self.ctx.fresh_var(),
+        };
+        
+        // Every element of an array has to add a known number of items
+        let mut typed_elems = Vec::new();
+        let mut len = Some(0);
+        for elem in elems {
+            let typed = self.check_collection_elem(elem, &elem_type);
+            match typed.fixed_len() {
+                Some(n) => len = len.map(|len| len + n),
+                // A spread of something other than an array is already reported
+                None if matches!(typed, TypedCollectElem::Spread(_)) => len = None,
+                None => {
+                    self.error(
+                        codes::MISMATCHED_TYPES,
+                        "this element adds a varying number of items, so it can't be part of an array; \
+                         annotate a type that implements `FromIterator`".to_string(),
+                        elem.span,
+                    );
+                    len = None;
+                }
+            }
+            typed_elems.push(typed);
+        }
+        
+        let Some(len) = len else {
+            return (TypedExprKind::Error, Type::Error);
+        };
+        let array_type = Type::Array(Box::new(self.ctx.apply(&elem_type)), len);
+        (TypedExprKind::ArrayLit(typed_elems), array_type)
+    }
+    
+    /// The `FromIterator` impl of a struct or enum type: its `with_capacity`
+    /// and `extend_one` functions, the type's type args and the item type
+    fn find_from_iterator_impl(&self, ty: &Type) -> Option<(DefId, DefId, Vec<Type>, Type)> {
+        let (Type::Struct { def_id, type_args } | Type::Enum { def_id, type_args }) = ty else {
+            return None;
+        };
+        let trait_id = self.trait_by_name.get("FromIterator")?;
+        let impl_methods = self.trait_impls.get(&(*def_id, *trait_id))?;
+        let find = |name: &str| impl_methods.iter().find(|(n, _, _)| n == name);
+        let (_, with_capacity, _) = find("with_capacity")?;
+        let (_, extend_one, extend_type) = find("extend_one")?;
+        let Type::Function { params, .. } = extend_type else {
+            return None;
+        };
+        let item = params.get(1)?;
+        let item = match self.impl_type_params.get(extend_one) {
+            Some(type_params) if !type_args.is_empty() => self.substitute_type_params(item, type_params, type_args),
+            _ => item.clone(),
+        };
+        Some((*with_capacity, *extend_one, type_args.clone(), item))
+    }
+    
+    /// Check the elements of a literal whose type is unknown, for their own errors
+    fn check_collection_recovery(&mut self, elems: &[ResolvedCollectionElem]) -> (TypedExprKind, Type) {
+        for elem in elems {
+            let item = self.ctx.fresh_var();
+            self.check_collection_elem(elem, &item);
+        }
+        (TypedExprKind::Error, Type::Error)
+    }
+    
+    fn check_collection_elem(&mut self, elem: &ResolvedCollectionElem, item: &Type) -> TypedCollectElem {
+        match &elem.kind {
+            ResolvedCollectionElemKind::Item(value) => {
+                let typed = self.check_expr_with_expected(value, Some(item));
+                if let Err(e) = self.ctx.unify(item, &typed.ty) {
+                    self.error(codes::MISMATCHED_TYPES, format!("element has wrong type: {}", e), typed.span);
+                }
+                TypedCollectElem::Item(typed)
+            }
+            ResolvedCollectionElemKind::Entry(key, value) => {
+                let (key_type, value_type) = match self.ctx.apply(item) {
+                    Type::Tuple(kv) if kv.len() == 2 => (kv[0].clone(), kv[1].clone()),
+                    _ => (self.ctx.fresh_var(), self.ctx.fresh_var()),
+                };
+                let key_typed = self.check_expr_with_expected(key, Some(&key_type));
+                if let Err(e) = self.ctx.unify(&key_type, &key_typed.ty) {
+                    self.error(codes::MISMATCHED_TYPES, format!("map key has wrong type: {}", e), key_typed.span);
+                }
+                let value_typed = self.check_expr_with_expected(value, Some(&value_type));
+                if let Err(e) = self.ctx.unify(&value_type, &value_typed.ty) {
+                    self.error(codes::MISMATCHED_TYPES, format!("map value has wrong type: {}", e), value_typed.span);
+                }
+                TypedCollectElem::Item(TypedExpr {
+                    kind: TypedExprKind::Tuple(vec![key_typed, value_typed]),
+                    ty: Type::Tuple(vec![self.ctx.apply(&key_type), self.ctx.apply(&value_type)]),
+                    span: elem.span,
+                })
+            }
+            ResolvedCollectionElemKind::If { cond, then_elem, else_elem } => {
+                let cond_typed = self.check_expr_with_expected(cond, Some(&Type::Bool));
+                if let Err(e) = self.ctx.unify(&cond_typed.ty, &Type::Bool) {
+                    self.error(codes::MISMATCHED_TYPES, format!("if condition must be bool: {}", e), cond.span);
+                }
+                TypedCollectElem::If {
+                    cond: cond_typed,
+                    then_elem: Box::new(self.check_collection_elem(then_elem, item)),
+                    else_elem: else_elem.as_ref().map(|e| Box::new(self.check_collection_elem(e, item))),
+                }
+            }
+            ResolvedCollectionElemKind::For { loop_id, binding, binding_name, iter, iter_def, bindings, elem: inner } => {
+                let for_iter = self.check_for_iter(iter, *binding, *iter_def);
+                let bindings = bindings.iter().map(|stmt| self.check_stmt(stmt).0).collect();
+                let inner_typed = self.check_collection_elem(inner, item);
+                let Some(for_iter) = for_iter else {
+                    return inner_typed;
+                };
+                TypedCollectElem::For {
+                    loop_id: *loop_id,
+                    binding: *binding,
+                    binding_name: binding_name.clone(),
+                    iter: for_iter,
+                    bindings,
+                    elem: Box::new(inner_typed),
+                    span: elem.span,
+                }
+            }
+            ResolvedCollectionElemKind::Spread { base, loop_id, item: item_def, iter_def } => {
+                let base_typed = self.check_expr(base);
+                match self.ctx.apply(&base_typed.ty) {
+                    Type::Array(base_elem, _) => {
+                        if let Err(e) = self.ctx.unify(item, &base_elem) {
+                            self.error(codes::MISMATCHED_TYPES, format!("spread element type mismatch: {}", e), base.span);
+                        }
+                    }
+                    Type::Error => {}
+                    _ => return self.check_iterable_spread(base_typed, *loop_id, *item_def, *iter_def, item, elem.span),
+                }
+                TypedCollectElem::Spread(base_typed)
+            }
+        }
+    }
+
+    /// `v = x` and `v += x` on a lambda's `&mut` parameter assign through
+    /// it, as `*v = x` and `*v = *v + x`
+    fn assign_through_param(&self, target: &ResolvedExpr, value: &ResolvedExpr) -> Option<(ResolvedExpr, ResolvedExpr)> {
+        let ResolvedExprKind::Var { def_id, .. } = &target.kind else {
+            return None;
+        };
+        if !self.lambda_params.contains(def_id) {
+            return None;
+        }
+        let param_ty = self.ctx.get_def_type(*def_id).map(|ty| self.ctx.apply(ty));
+        if !matches!(param_ty, Some(Type::Ref { is_mut: true, .. })) {
+            return None;
+        }
+        let deref = |e: &ResolvedExpr| ResolvedExpr { kind: ResolvedExprKind::Deref(Box::new(e.clone())), span: e.span };
+        let value = match &value.kind {
+            ResolvedExprKind::Binary { left, op, right }
+                if matches!(&left.kind, ResolvedExprKind::Var { def_id: id, .. } if id == def_id) =>
+            {
+                ResolvedExpr {
+                    kind: ResolvedExprKind::Binary { left: Box::new(deref(left)), op: *op, right: right.clone() },
+                    span: value.span,
+                }
+            }
+            _ => value.clone(),
+        };
+        Some((deref(target), value))
+    }
+
+    /// Anything `for` can walk spreads one item at a time; items it only lends out are copied
+    fn check_iterable_spread(&mut self, base: TypedExpr, loop_id: LoopId, item_def: DefId, iter_def: DefId, item: &Type, span: Span) -> TypedCollectElem {
+        let base_span = base.span;
+        let Some(for_iter) = self.check_iterable(base.clone(), item_def, iter_def) else {
+            return TypedCollectElem::Spread(TypedExpr { ty: Type::Error, ..base });
+        };
+        let item_type = self.ctx.apply(&self.ctx.get_def_type(item_def).cloned().unwrap_or(Type::Error));
+        let mut value = TypedExpr {
+            kind: TypedExprKind::Var { name: "item".to_string(), def_id: item_def },
+            ty: item_type.clone(),
+            span: base_span,
+        };
+        if let Type::Ref { inner, .. } = &item_type
+            && !matches!(self.ctx.apply(item), Type::Ref { .. })
+        {
+            let inner = self.ctx.apply(inner);
+            if !self.is_copy_type(&inner) {
+                self.error(codes::MISMATCHED_TYPES, format!(
+                    "this spread lends out its items as `{}`, and `{}` isn't Copy, so they can't be copied into the collection",
+                    item_type.display(&self.ctx), inner.display(&self.ctx)
+                ), base_span);
+            }
+            value = TypedExpr { ty: inner, kind: TypedExprKind::Deref(Box::new(value)), span: base_span };
+        }
+        if let Err(e) = self.ctx.unify(item, &value.ty) {
+            self.error(codes::MISMATCHED_TYPES, format!("spread element type mismatch: {}", e), base_span);
+        }
+        TypedCollectElem::For {
+            loop_id,
+            binding: item_def,
+            binding_name: "item".to_string(),
+            iter: for_iter,
+            bindings: Vec::new(),
+            elem: Box::new(TypedCollectElem::Item(value)),
+            span,
+        }
+    }
+
+    /// Check the bounds of a range, which are i32 for now
+    fn check_range_bounds(&mut self, left: &ResolvedExpr, right: &ResolvedExpr) -> (TypedExpr, TypedExpr) {
+        let start_typed = self.check_expr(left);
+        let end_typed = self.check_expr(right);
+        if let Err(e) = self.ctx.unify(&start_typed.ty, &Type::I32) {
+            self.error(codes::MISMATCHED_TYPES, format!("range start must be i32: {}", e), left.span);
+        }
+        if let Err(e) = self.ctx.unify(&end_typed.ty, &Type::I32) {
+            self.error(codes::MISMATCHED_TYPES, format!("range end must be i32: {}", e), right.span);
+        }
+        (start_typed, end_typed)
+    }
+
+    /// A range used as a value builds a `std.iter.Range`
+    fn check_range_value(&mut self, left: &ResolvedExpr, right: &ResolvedExpr, span: Span) -> (TypedExprKind, Type) {
+        let (start_typed, end_typed) = self.check_range_bounds(left, right);
+        let Some(range_type @ Type::Struct { def_id, .. }) = self.ctx.lookup_type_by_name("Range") else {
+            self.error(codes::UNRESOLVED_NAME, "a range used as a value is a `std.iter.Range`; import std.iter".to_string(), span);
+            return (TypedExprKind::Error, Type::Error);
+        };
+        let fields = vec![("start".to_string(), start_typed), ("end".to_string(), end_typed)];
+        (TypedExprKind::StructLit { struct_def: def_id, fields, base: None }, range_type)
+    }
+
+    /// Check what a `for` loop iterates, and give its binding the type of the
+    /// items. Returns None after reporting an error.
+    fn check_for_iter(&mut self, iter: &ResolvedExpr, binding: DefId, iter_def: DefId) -> Option<TypedForIter> {
+        // A range written in the loop is counted in place
+        if let ResolvedExprKind::Binary { left, op: wisp_ast::BinOp::Range, right } = &iter.kind {
+            let (start, end) = self.check_range_bounds(left, right);
+            self.ctx.register_def_type(binding, Type::I32);
+            return Some(TypedForIter::Range { start: Box::new(start), end: Box::new(end) });
+        }
+        
+        let typed = self.check_expr(iter);
+        self.check_iterable(typed, binding, iter_def)
+    }
+    
+    /// How a `for` walks an already checked value, registering the type of its items
+    fn check_iterable(&mut self, typed: TypedExpr, binding: DefId, iter_def: DefId) -> Option<TypedForIter> {
+        let span = typed.span;
+        let ty = self.ctx.apply(&typed.ty);
+        let array_item = match &ty {
+            Type::Array(elem, _) => Some((**elem).clone()),
+            Type::Slice(elem) => Some(Type::Ref { is_mut: false, inner: elem.clone() }),
+            Type::Ref { is_mut, inner } => match self.ctx.apply(inner) {
+                Type::Array(elem, _) => Some(Type::Ref { is_mut: *is_mut, inner: elem }),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(item) = array_item {
+            self.ctx.register_def_type(binding, item);
+            return Some(TypedForIter::Array(Box::new(typed)));
+        }
+        self.ctx.register_def_type(binding, Type::Error);
+        if matches!(ty, Type::Error) {
+            return None;
+        }
+        
+        // Collections that aren't iterators are iterated through their `iter()` method
+        let iter_typed = match self.find_op_trait_method(&ty, "Iterator", "next") {
+            Some(_) => typed,
+            None => match self.call_iter_method(typed, &ty, span) {
+                Some(call) => call,
+                None => {
+                    self.error(codes::TRAIT_NOT_IMPLEMENTED, format!(
+                        "`{}` is not iterable; `for` needs a range, an array, an `Iterator` or a type with an `iter()` method",
+                        ty.display(&self.ctx)
+                    ), span);
+                    return None;
+                }
+            },
+        };
+        let iter_type = self.ctx.apply(&iter_typed.ty);
+        let Some((next_def_id, next_type)) = self.find_op_trait_method(&iter_type, "Iterator", "next") else {
+            self.error(codes::TRAIT_NOT_IMPLEMENTED, format!(
+                "`iter()` of `{}` returns `{}`, which doesn't implement `Iterator`",
+                ty.display(&self.ctx), iter_type.display(&self.ctx)
+            ), span);
+            return None;
+        };
+        let Type::Function { ret, .. } = self.receiver_method_type(next_def_id, next_type, &iter_type) else {
+            return None;
+        };
+        let ret = self.ctx.apply(&ret);
+        let item = match &ret {
+            Type::Enum { type_args, .. } if type_args.len() == 1 => type_args[0].clone(),
+            _ => {
+                self.error(codes::INCOMPATIBLE_SIGNATURE, "`next` must return an `Option`".to_string(), span);
+                return None;
+            }
+        };
+        self.ctx.register_def_type(binding, item);
+        
+        // The loop calls `next` on the iterator, held in a hidden local
+        self.ctx.register_def_type(iter_def, iter_type.clone());
+        let receiver = TypedExpr {
+            kind: TypedExprKind::Var { name: "iter".to_string(), def_id: iter_def },
+            ty: iter_type,
+            span,
+        };
+        let next = TypedExpr {
+            kind: TypedExprKind::MethodCall {
+                receiver: Box::new(receiver),
+                method: "next".to_string(),
+                method_def_id: next_def_id,
+                method_span: span,
+                is_mut_self: true,
+                args: Vec::new(),
+                method_type_args: Vec::new(),
+            },
+            ty: ret,
+            span,
+        };
+        Some(TypedForIter::Next { iter: Box::new(iter_typed), iter_def, next: Box::new(next) })
+    }
+
+    /// `receiver.iter()`, if the receiver's type (or the one it references) has that method
+    fn call_iter_method(&mut self, receiver: TypedExpr, ty: &Type, span: Span) -> Option<TypedExpr> {
+        let target = match ty {
+            Type::Ref { inner, .. } => self.ctx.apply(inner),
+            ty => ty.clone(),
+        };
+        let (Type::Struct { def_id, .. } | Type::Enum { def_id, .. }) = &target else {
+            return None;
+        };
+        let (method_def_id, method_type) = self.methods.get(&(*def_id, "iter".to_string())).cloned()?;
+        let Type::Function { ret, .. } = self.receiver_method_type(method_def_id, method_type, &target) else {
+            return None;
+        };
+        Some(TypedExpr {
+            kind: TypedExprKind::MethodCall {
+                receiver: Box::new(receiver),
+                method: "iter".to_string(),
+                method_def_id,
+                method_span: span,
+                is_mut_self: false,
+                args: Vec::new(),
+                method_type_args: Vec::new(),
+            },
+            ty: *ret,
+            span,
+        })
+    }
+
+    /// Type of a method called on a value of type `receiver`, with the impl's
+    /// type params substituted by the receiver's type args. The instance is recorded.
+    fn receiver_method_type(&mut self, method_def_id: DefId, method_type: Type, receiver: &Type) -> Type {
+        let type_args = match receiver {
+            Type::Struct { type_args, .. } | Type::Enum { type_args, .. } => type_args.clone(),
+            _ => Vec::new(),
+        };
+        let Some(type_params) = self.impl_type_params.get(&method_def_id).cloned().filter(|_| !type_args.is_empty()) else {
+            return method_type;
+        };
+        self.generic_instantiations.insert(GenericInstantiation { func_def_id: method_def_id, type_args: type_args.clone() });
+        self.substitute_type_params(&method_type, &type_params, &type_args)
+    }
+
+    /// Type of a struct's field, with the struct's type arguments substituted
+    fn struct_field_type(&self, struct_id: DefId, type_args: &[Type], field: &str) -> Option<Type> {
+        let ty = self.ctx.get_struct_field(struct_id, field)?;
+        Some(match self.struct_type_params.get(&struct_id) {
+            Some(params) if !type_args.is_empty() => self.substitute_type_params(ty, params, type_args),
+            _ => ty.clone(),
+        })
+    }
+
+    /// Check a struct literal. A `{ ... }` literal without a name builds the
+    /// expected struct type. Fields it leaves out come from its `..base`, or
+    /// from `Type.defaults()` for a `@defaults` struct.
+    fn check_struct_lit(
+        &mut self,
+        struct_def: Option<DefId>,
+        fields: &[(String, Span, ResolvedExpr)],
+        spread: Option<&ResolvedExpr>,
+        expected: Option<&Type>,
+        span: Span,
+    ) -> (TypedExprKind, Type) {
+        let struct_def = match struct_def {
+            Some(def_id) => Some(def_id),
+            None => match expected.map(|t| self.ctx.apply(t)) {
+                Some(Type::Struct { def_id, .. }) => Some(def_id),
+                Some(Type::Error) => None,
+                Some(Type::Var(_)) | None => {
+                    self.error(codes::TYPE_ANNOTATIONS_NEEDED, "cannot infer the type of this record literal; add a type annotation".to_string(), span);
+                    None
+                }
+                Some(ty) => {
+                    self.error(codes::MISMATCHED_TYPES, format!("a record literal can't have type `{}`", ty.display(&self.ctx)), span);
+                    None
+                }
+            },
+        };
+        let Some(struct_def) = struct_def else {
+            for (_, _, field_expr) in fields {
+                self.check_expr(field_expr);
+            }
+            if let Some(spread) = spread {
+                self.check_expr(spread);
+            }
+            return (TypedExprKind::Error, Type::Error);
+        };
+        // A generic struct's type arguments come from the expected type, or
+        // are inferred from the field values
+        let type_args: Vec<Type> = match self.struct_type_params.get(&struct_def).map(|params| params.len()) {
+            Some(count) => match expected.map(|t| self.ctx.apply(t)) {
+                Some(Type::Struct { def_id, type_args }) if def_id == struct_def && type_args.len() == count => type_args,
+                _ => (0..count).map(|_| self.ctx.fresh_var()).collect(),
+            },
+            None => Vec::new(),
+        };
+        let struct_type = Type::Struct { def_id: struct_def, type_args: type_args.clone() };
+        let struct_name = self.ctx.get_type_name(struct_def).unwrap_or_default();
+        
+        // Check field types
+        let mut typed_fields: Vec<(String, TypedExpr)> = Vec::new();
+        for (name, name_span, field_expr) in fields {
+            let field_type = self.struct_field_type(struct_def, &type_args, name);
+            let typed = self.check_expr_with_expected(field_expr, field_type.as_ref());
+            
+            let Some(field_type) = field_type else {
+                self.error(codes::NO_FIELD, format!("struct '{}' has no field '{}'", struct_name, name), *name_span);
+                continue;
+            };
+            if let Err(e) = self.ctx.unify(&typed.ty, &field_type) {
+                self.error(codes::MISMATCHED_TYPES, format!("field '{}' type mismatch: {}", name, e), field_expr.span);
+            }
+            // Record the field type at the field name span for hover
+            self.ctx.record_span_type(*name_span, format!("{}: {}", name, field_type.display(&self.ctx)));
+            
+            // The last value given for a field wins, and `..base` gives
+            // every field where it stands
+            let earlier = typed_fields.iter().position(|(n, _)| n == name);
+            if let Some(i) = earlier {
+                self.warning(codes::DUPLICATE_FIELD, format!("field '{}' is set more than once; the earlier value is ignored", name), *name_span);
+                typed_fields.remove(i);
+            }
+            match spread {
+                Some(spread) if name_span.start < spread.span.start => {
+                    self.warning(codes::DUPLICATE_FIELD, format!("field '{}' is overridden by the spread after it; this value is ignored", name), *name_span);
+                    continue;
+                }
+                Some(_) if earlier.is_none() => {
+                    self.warning(codes::DUPLICATE_FIELD, format!("field '{}' overrides the value from the spread before it", name), *name_span);
+                }
+                _ => {}
+            }
+            typed_fields.push((name.clone(), typed));
+        }
+        
+        let missing: Vec<(String, Type)> = self.ctx.get_struct_fields(struct_def)
+            .unwrap_or_default()
+            .iter()
+            .filter(|(name, _)| !typed_fields.iter().any(|(n, _)| n == name))
+            .map(|(name, _)| (name.clone(), self.struct_field_type(struct_def, &type_args, name).unwrap_or(Type::Error)))
+            .collect();
+        let base = match spread {
+            Some(spread) => {
+                let typed = self.check_expr_with_expected(spread, Some(&struct_type));
+                if let Err(e) = self.ctx.unify(&typed.ty, &struct_type) {
+                    self.error(codes::MISMATCHED_TYPES, format!("spread base type mismatch: {}", e), spread.span);
+                }
+                Some(typed)
+            }
+            None if missing.is_empty() => None,
+            None if self.defaults_structs.contains(&struct_def) => self.defaults_call(struct_def, span),
+            None => {
+                let names: Vec<_> = missing.iter().map(|(name, _)| format!("'{}'", name)).collect();
+                let noun = if names.len() == 1 { "field" } else { "fields" };
+                self.error(codes::MISSING_FIELDS, format!("missing {} {} in '{}' literal", noun, names.join(", "), struct_name), span);
+                None
+            }
+        };
+        
+        // Fields spread from a variable are read from it one by one, so
+        // only those fields are moved out of it
+        let base = match base {
+            Some(base) if Self::is_field_path(&base) => {
+                for (name, ty) in missing {
+                    let field = TypedExprKind::Field {
+                        expr: Box::new(base.clone()),
+                        field: name.clone(),
+                        field_span: base.span,
+                    };
+                    typed_fields.push((name, TypedExpr { kind: field, ty, span: base.span }));
+                }
+                None
+            }
+            base => base.map(Box::new),
+        };
+        
+        (TypedExprKind::StructLit {
+            struct_def,
+            fields: typed_fields,
+            base,
+        }, self.ctx.apply(&struct_type))
+    }
+
+    /// A call to `Type.defaults()` filling in the fields a literal of a
+    /// `@defaults` struct leaves out
+    fn defaults_call(&mut self, struct_def: DefId, span: Span) -> Option<TypedExpr> {
+        let struct_type = Type::Struct { def_id: struct_def, type_args: vec![] };
+        let struct_name = self.ctx.get_type_name(struct_def).unwrap_or_default();
+        let Some((fn_def_id, fn_type)) = self.associated_functions.get(&(struct_def, "defaults".to_string())).cloned() else {
+            self.error(codes::MISSING_ASSOCIATED_FUNCTION, format!("struct '{}' is marked @defaults but has no `{}.defaults()` function", struct_name, struct_name), span);
+            return None;
+        };
+        let valid = match &fn_type {
+            Type::Function { params, ret } => params.is_empty() && self.ctx.unify(ret, &struct_type).is_ok(),
+            _ => false,
+        };
+        if !valid {
+            self.error(codes::INCOMPATIBLE_SIGNATURE, format!("`{}.defaults()` must take no arguments and return `{}`", struct_name, struct_name), span);
+            return None;
+        }
+        Some(TypedExpr {
+            kind: TypedExprKind::AssociatedFunctionCall {
+                type_id: struct_def,
+                function: "defaults".to_string(),
+                function_def_id: fn_def_id,
+                function_span: span,
+                type_args: Vec::new(),
+                args: Vec::new(),
+            },
+            ty: struct_type,
+            span,
+        })
+    }
+
+    /// Whether an expression is a variable or a chain of fields of one
+    fn is_field_path(expr: &TypedExpr) -> bool {
+        match &expr.kind {
+            TypedExprKind::Var { .. } => true,
+            TypedExprKind::Field { expr: base, .. } => Self::is_field_path(base),
+            _ => false,
+        }
+    }
+
+    /// The type of element `field` of a tuple, e.g. `pair.1`
+    fn tuple_element(&mut self, elems: &[Type], field: &str, span: Span) -> Type {
+        match field.parse::<usize>().ok().and_then(|i| elems.get(i)) {
+            Some(ty) => ty.clone(),
+            None => {
+                self.error(codes::NO_FIELD, format!("no field '{}' on a tuple with {} elements", field, elems.len()), span);
+                Type::Error
+            }
+        }
+    }
+
+    /// Report arms that can never match and values that no arm covers
+    fn check_match_exhaustiveness(&mut self, arms: &[ResolvedMatchArm], typed_arms: &[TypedMatchArm], scrutinee_type: &Type, span: Span) {
+        let scrutinee_type = self.ctx.apply(scrutinee_type);
+        if matches!(scrutinee_type, Type::Error) {
+            return;
+        }
+        
+        let report = MatchChecker::new(&self.ctx, &self.non_exhaustive_enums)
+            .check(typed_arms.iter().map(|arm| &arm.pattern));
+        for &i in &report.unreachable_arms {
+            self.warning(codes::UNREACHABLE_PATTERN, "unreachable pattern".to_string(), arms[i].pattern.span);
+        }
+        if report.missing.is_empty() {
+            return;
+        }
+        
+        // An enum without variants has no values to cover
+        if let Type::Enum { def_id, .. } = &scrutinee_type
+            && !self.non_exhaustive_enums.contains(def_id)
+            && self.ctx.get_enum_variants(*def_id).is_some_and(|variants| variants.is_empty())
+        {
+            return;
+        }
+        
+        let mut message = format!("non-exhaustive patterns: {} not covered", describe_missing(&report.missing));
+        if let Some(enum_id) = report.non_exhaustive_enum {
+            let name = self.ctx.get_type_name(enum_id).unwrap_or_default();
+            message.push_str(&format!(" (`{}` is `@non_exhaustive`, so a `_` arm is required)", name));
         }
+        self.error(codes::NON_EXHAUSTIVE_PATTERNS, message, span);
     }
     
     /// Infer type arguments by matching a parameter type with an argument type
@@ -2496,6 +3682,134 @@ impl TypeChecker {
         }
     }
     
+    /// Desugar `expr?` into a match that yields the `Some`/`Ok` payload and
+    /// returns `None`/`Err(e)` early from the enclosing function
+    fn check_try(&mut self, inner: &ResolvedExpr, value_binding: DefId, error_binding: DefId, span: Span) -> (TypedExprKind, Type) {
+        let typed_inner = self.check_expr(inner);
+        let inner_ty = self.ctx.apply(&typed_inner.ty);
+        let (enum_id, type_args) = match &inner_ty {
+            Type::Enum { def_id, type_args }
+                if matches!(self.ctx.get_type_name(*def_id).as_deref(), Some("Option" | "Result")) =>
+            {
+                (*def_id, type_args.clone())
+            }
+            Type::Error => return (TypedExprKind::Error, Type::Error),
+            _ => {
+                self.error(
+                    codes::TRAIT_NOT_IMPLEMENTED,
+                    format!("the `?` operator can only be applied to `Option` or `Result`, found `{}`", inner_ty.display(&self.ctx)),
+                    span,
+                );
+                return (TypedExprKind::Error, Type::Error);
+            }
+        };
+        let enum_name = self.ctx.get_type_name(enum_id).unwrap_or_default();
+
+        // The enclosing function must return the same kind of enum
+        let declared_return = self.current_return_type.clone().unwrap_or(Type::Unit);
+        let declared_return = self.ctx.apply(&declared_return);
+        let return_args = match &declared_return {
+            Type::Enum { def_id, type_args } if *def_id == enum_id => type_args.clone(),
+            Type::Var(_) => {
+                // Lambda with an inferred return type
+                let args: Vec<_> = type_args.iter().map(|_| self.ctx.fresh_var()).collect();
+                let _ = self.ctx.unify(&declared_return, &Type::Enum { def_id: enum_id, type_args: args.clone() });
+                args
+            }
+            Type::Error => return (TypedExprKind::Error, Type::Error),
+            _ => {
+                self.error(
+                    codes::TRAIT_NOT_IMPLEMENTED,
+                    format!(
+                        "the `?` operator on `{}` can only be used in a function that returns `{}`, but this function returns `{}`",
+                        inner_ty.display(&self.ctx), enum_name, declared_return.display(&self.ctx)
+                    ),
+                    span,
+                );
+                return (TypedExprKind::Error, Type::Error);
+            }
+        };
+
+        // Errors propagate unchanged, so the error types must agree
+        if let (Some(err), Some(return_err)) = (type_args.get(1), return_args.get(1))
+            && self.ctx.unify(err, return_err).is_err()
+        {
+            self.error(
+                codes::MISMATCHED_TYPES,
+                format!(
+                    "mismatched error type for `?`: the function returns `{}` errors, found `{}`",
+                    self.ctx.apply(return_err).display(&self.ctx), self.ctx.apply(err).display(&self.ctx)
+                ),
+                span,
+            );
+        }
+        let return_args: Vec<_> = return_args.iter().map(|t| self.ctx.apply(t)).collect();
+        let return_type = Type::Enum { def_id: enum_id, type_args: return_args.clone() };
+
+        // One arm per variant, in declaration order
+        let variants = self.ctx.get_enum_variants(enum_id).map(|v| v.to_vec()).unwrap_or_default();
+        let mut value_type = Type::Error;
+        let mut arms = Vec::new();
+        for (name, variant_def, fields) in variants {
+            // The variant keeps the enum's own type params (an impl block may replace the enum's entry)
+            let type_params = self.type_type_params.get(&variant_def).cloned().unwrap_or_default();
+            let payload = fields.first().map(|t| self.substitute_type_params(t, &type_params, &type_args));
+            let arm = match (name.as_str(), payload) {
+                ("Some" | "Ok", Some(ty)) => {
+                    self.ctx.register_def_type(value_binding, ty.clone());
+                    value_type = ty.clone();
+                    TypedMatchArm {
+                        pattern: TypedPattern::Variant {
+                            variant_def,
+                            fields: vec![TypedPattern::Binding { def_id: value_binding, name: "value".to_string(), ty: ty.clone() }],
+                        },
+                        body: TypedExpr {
+                            kind: TypedExprKind::Var { name: "value".to_string(), def_id: value_binding },
+                            ty,
+                            span,
+                        },
+                    }
+                }
+                (_, Some(ty)) => {
+                    // Err(error) -> return Err(error)
+                    self.ctx.register_def_type(error_binding, ty.clone());
+                    let error = TypedExpr {
+                        kind: TypedExprKind::Var { name: "error".to_string(), def_id: error_binding },
+                        ty: ty.clone(),
+                        span,
+                    };
+                    let rewrapped = TypedExpr {
+                        kind: TypedExprKind::GenericCall { func_def_id: variant_def, type_args: return_args.clone(), args: vec![error] },
+                        ty: return_type.clone(),
+                        span,
+                    };
+                    TypedMatchArm {
+                        pattern: TypedPattern::Variant {
+                            variant_def,
+                            fields: vec![TypedPattern::Binding { def_id: error_binding, name: "error".to_string(), ty }],
+                        },
+                        body: TypedExpr { kind: TypedExprKind::Return(Some(Box::new(rewrapped))), ty: Type::Never, span },
+                    }
+                }
+                (_, None) => {
+                    // None -> return None
+                    let none = TypedExpr {
+                        kind: TypedExprKind::Var { name, def_id: variant_def },
+                        ty: return_type.clone(),
+                        span,
+                    };
+                    TypedMatchArm {
+                        pattern: TypedPattern::Variant { variant_def, fields: Vec::new() },
+                        body: TypedExpr { kind: TypedExprKind::Return(Some(Box::new(none))), ty: Type::Never, span },
+                    }
+                }
+            };
+            arms.push(arm);
+        }
+
+        (TypedExprKind::Match { scrutinee: Box::new(typed_inner), arms }, value_type)
+    }
+
     /// Substitute type parameters with concrete types
     fn substitute_type_params(&self, ty: &Type, type_params: &[(DefId, String)], type_args: &[Type]) -> Type {
         match ty {
@@ -2616,6 +3930,29 @@ impl TypeChecker {
     }
 }
 
+/// Type parameters with the trait DefIds of their bounds
+fn type_param_infos(type_params: &[ResolvedTypeParam]) -> Vec<TypeParamInfo> {
+    type_params.iter()
+        .map(|tp| {
+            // Extract trait DefIds from bounds
+            let bounds: Vec<DefId> = tp.bounds.iter()
+                .filter_map(|b| {
+                    if let ResolvedType::Named { def_id: Some(id), .. } = b {
+                        Some(*id)
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            TypeParamInfo {
+                def_id: tp.def_id,
+                name: tp.name.clone(),
+                bounds,
+            }
+        })
+        .collect()
+}
+
 impl Default for TypeChecker {
     fn default() -> Self {
         Self::new()
@@ -2637,6 +3974,10 @@ pub struct TypedProgram {
     pub generic_instantiations: HashSet<GenericInstantiation>,
     /// Types that implement Copy (can be implicitly copied)
     pub copy_types: HashSet<DefId>,
+    /// Types that implement Drop, mapped to their `drop` method
+    pub drop_impls: HashMap<DefId, DefId>,
+    /// Warnings found while checking
+    pub warnings: Vec<TypeWarning>,
 }
 
 /// Typed extern function declaration
@@ -2659,14 +4000,14 @@ pub struct TypedExternStatic {
 impl TypedProgram {
     // === LSP Query Methods ===
     
-    /// Get type string at a given offset (for hover)
-    pub fn type_at_offset(&self, offset: usize) -> Option<&String> {
-        self.ctx.type_at_offset(offset)
+    /// Get type string at a given offset into `file` (for hover)
+    pub fn type_at_offset(&self, file: FileId, offset: usize) -> Option<&String> {
+        self.ctx.type_at_offset(file, offset)
     }
     
-    /// Get definition DefId at a given offset (for go-to-definition)
-    pub fn definition_at_offset(&self, offset: usize) -> Option<DefId> {
-        self.ctx.definition_at_offset(offset)
+    /// Get definition DefId at a given offset into `file` (for go-to-definition)
+    pub fn definition_at_offset(&self, file: FileId, offset: usize) -> Option<DefId> {
+        self.ctx.definition_at_offset(file, offset)
     }
     
     /// Get the span of a definition by DefId
@@ -2761,7 +4102,7 @@ impl TypedProgram {
     }
     
     /// Get all span types (for iteration)
-    pub fn all_span_types(&self) -> &std::collections::HashMap<(usize, usize), String> {
+    pub fn all_span_types(&self) -> &std::collections::HashMap<Span, String> {
         self.ctx.all_span_types()
     }
     
@@ -2873,6 +4214,8 @@ pub enum TypedStmt {
         span: Span,
     },
     Expr(TypedExpr),
+    /// Block run when the enclosing scope exits
+    Defer { body: TypedBlock, span: Span },
 }
 
 #[derive(Debug, Clone)]
@@ -2896,31 +4239,89 @@ pub enum TypedExprKind {
     Call { callee: Box<TypedExpr>, args: Vec<TypedExpr> },
     /// Call to a generic function with inferred type arguments
     GenericCall { func_def_id: DefId, type_args: Vec<Type>, args: Vec<TypedExpr> },
-    MethodCall { receiver: Box<TypedExpr>, method: String, method_def_id: DefId, method_span: Span, is_mut_self: bool, args: Vec<TypedExpr> },
+    /// Method call on a struct or enum. `method_type_args` instantiate the method's own type params.
+    MethodCall { receiver: Box<TypedExpr>, method: String, method_def_id: DefId, method_span: Span, is_mut_self: bool, args: Vec<TypedExpr>, method_type_args: Vec<Type> },
     /// Method call on a type parameter via trait bounds
     TraitMethodCall { receiver: Box<TypedExpr>, method: String, method_span: Span, is_mut_self: bool, trait_bounds: Vec<DefId>, args: Vec<TypedExpr> },
     /// Associated function call: Type.function(args) where function has no self
-    AssociatedFunctionCall { type_id: DefId, function: String, function_def_id: DefId, function_span: Span, args: Vec<TypedExpr> },
+    AssociatedFunctionCall { type_id: DefId, function: String, function_def_id: DefId, function_span: Span, type_args: Vec<Type>, args: Vec<TypedExpr> },
     /// Method call on a primitive type (i32, bool, str, etc.)
     PrimitiveMethodCall { receiver: Box<TypedExpr>, method: String, method_def_id: DefId, method_span: Span, is_mut_self: bool, args: Vec<TypedExpr> },
     Field { expr: Box<TypedExpr>, field: String, field_span: Span },
-    StructLit { struct_def: DefId, fields: Vec<(String, TypedExpr)> },
+    /// Fields missing from `fields` are taken from `base`, a `..base` spread
+    /// or `defaults()` call evaluated after them
+    StructLit { struct_def: DefId, fields: Vec<(String, TypedExpr)>, base: Option<Box<TypedExpr>> },
     If { cond: Box<TypedExpr>, then_block: TypedBlock, else_block: Option<TypedElse> },
-    While { cond: Box<TypedExpr>, body: TypedBlock },
-    For { binding: DefId, binding_name: String, start: Box<TypedExpr>, end: Box<TypedExpr>, body: TypedBlock },
+    While { loop_id: LoopId, cond: Box<TypedExpr>, body: TypedBlock },
+    Loop { loop_id: LoopId, body: TypedBlock },
+    Return(Option<Box<TypedExpr>>),
+    Break { target: LoopId, value: Option<Box<TypedExpr>> },
+    Continue { target: LoopId },
+    For { loop_id: LoopId, binding: DefId, binding_name: String, iter: TypedForIter, body: TypedBlock },
     Block(TypedBlock),
     Assign { target: Box<TypedExpr>, value: Box<TypedExpr> },
     Ref { is_mut: bool, expr: Box<TypedExpr> },
     Deref(Box<TypedExpr>),
     Match { scrutinee: Box<TypedExpr>, arms: Vec<TypedMatchArm> },
     Index { expr: Box<TypedExpr>, index: Box<TypedExpr> },
-    ArrayLit(Vec<TypedExpr>),
-    Lambda { params: Vec<TypedLambdaParam>, body: Box<TypedExpr> },
+    /// Fixed-size array; every element adds a known number of items
+    ArrayLit(Vec<TypedCollectElem>),
+    /// List or map literal building a `FromIterator` collection: `with_capacity`
+    /// creates it, then `extend_one` adds the items in order. `type_args` are
+    /// the collection's, for calling a generic impl.
+    Collect { with_capacity: DefId, extend_one: DefId, type_args: Vec<Type>, capacity: usize, elems: Vec<TypedCollectElem> },
+    Tuple(Vec<TypedExpr>),
+    /// Closure; `captures` are the variables it uses from enclosing scopes
+    Lambda { params: Vec<TypedLambdaParam>, captures: Vec<TypedCapture>, body: Box<TypedExpr> },
     Cast { expr: Box<TypedExpr>, target_type: Type },
+    /// Compiler intrinsic: #size_of(T), #read(T, addr), #write(T, addr, value)
+    Intrinsic { intrinsic: wisp_ast::Intrinsic, ty: Type, args: Vec<TypedExpr> },
     StringInterp { parts: Vec<TypedStringInterpPart> },
     Error,
 }
 
+/// What a `for` loop iterates (typed)
+#[derive(Debug, Clone)]
+pub enum TypedForIter {
+    /// `start..end` written in the loop, counted in place
+    Range { start: Box<TypedExpr>, end: Box<TypedExpr> },
+    /// Each element of an array, or a reference to each one when iterating
+    /// through a reference to the array or a slice
+    Array(Box<TypedExpr>),
+    /// `iter` is moved into the hidden local `iter_def`, then `next` (its
+    /// `Iterator::next` call) runs until it returns `None`
+    Next { iter: Box<TypedExpr>, iter_def: DefId, next: Box<TypedExpr> },
+}
+
+/// Element of a list or map literal (typed)
+#[derive(Debug, Clone)]
+pub enum TypedCollectElem {
+    /// One item; a map entry is a `(key, value)` tuple
+    Item(TypedExpr),
+    If { cond: TypedExpr, then_elem: Box<TypedCollectElem>, else_elem: Option<Box<TypedCollectElem>> },
+    /// `bindings` destructure each item before the element runs
+    For { loop_id: LoopId, binding: DefId, binding_name: String, iter: TypedForIter, bindings: Vec<TypedStmt>, elem: Box<TypedCollectElem>, span: Span },
+    /// Every item of a fixed-size array
+    Spread(TypedExpr),
+}
+
+impl TypedCollectElem {
+    /// Number of items this element adds, if it doesn't depend on conditions or loops
+    pub fn fixed_len(&self) -> Option<usize> {
+        match self {
+            TypedCollectElem::Item(_) => Some(1),
+            TypedCollectElem::If { then_elem, else_elem: Some(else_elem), .. } => {
+                then_elem.fixed_len().filter(|&n| else_elem.fixed_len() == Some(n))
+            }
+            TypedCollectElem::If { else_elem: None, .. } | TypedCollectElem::For { .. } => None,
+            TypedCollectElem::Spread(base) => match &base.ty {
+                Type::Array(_, n) => Some(*n),
+                _ => None,
+            },
+        }
+    }
+}
+
 /// Part of an interpolated string (typed)
 #[derive(Debug, Clone)]
 pub enum TypedStringInterpPart {
@@ -2954,5 +4355,6 @@ pub enum TypedPattern {
     Binding { def_id: DefId, name: String, ty: Type },
     Literal(TypedExpr),
     Variant { variant_def: DefId, fields: Vec<TypedPattern> },
+    Tuple(Vec<TypedPattern>),
 }
 
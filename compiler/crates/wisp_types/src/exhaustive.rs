@@ -0,0 +1,302 @@
+//! Exhaustiveness and reachability checking for `match`
+//!
+//! Uses the pattern-matrix usefulness algorithm from Maranget's "Warnings for
+//! pattern matching". A pattern is useful with respect to the rows above it if
+//! some value matches it but none of those rows. An arm whose pattern isn't
+//! useful can never be reached, and a match is exhaustive when a `_` after the
+//! last arm wouldn't be useful. The values that make that `_` useful are
+//! reported back as the missing patterns.
+
+use crate::check::{TypedExprKind, TypedPattern};
+use crate::types::TypeContext;
+use std::collections::HashSet;
+use wisp_hir::DefId;
+
+/// Number of missing patterns spelled out before summarizing the rest
+const MAX_REPORTED: usize = 3;
+
+/// A pattern reduced to constructors and wildcards
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+/// A value constructor a pattern can test for
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Variant(DefId),
+    /// The only constructor of a tuple type, with its size
+    Tuple(usize),
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    /// A literal we can't compare against others; never covers anything but wildcards
+    Opaque,
+}
+
+impl Ctor {
+    fn same(&self, other: &Ctor) -> bool {
+        !matches!(self, Ctor::Opaque) && self == other
+    }
+}
+
+/// The outcome of checking a match's arms
+pub struct MatchReport {
+    /// Indices of arms that can never match
+    pub unreachable_arms: Vec<usize>,
+    /// Example patterns not covered by any arm, empty if the match is exhaustive
+    pub missing: Vec<String>,
+    /// The enum whose `@non_exhaustive` marker leaves the match incomplete
+    pub non_exhaustive_enum: Option<DefId>,
+}
+
+/// Checks the arms of one `match` against the enums known to the type checker
+pub struct MatchChecker<'a> {
+    ctx: &'a TypeContext,
+    non_exhaustive_enums: &'a HashSet<DefId>,
+}
+
+impl<'a> MatchChecker<'a> {
+    pub fn new(ctx: &'a TypeContext, non_exhaustive_enums: &'a HashSet<DefId>) -> Self {
+        Self { ctx, non_exhaustive_enums }
+    }
+
+    /// Check `patterns`, the arms of a match in source order
+    pub fn check<'p>(&self, patterns: impl IntoIterator<Item = &'p TypedPattern>) -> MatchReport {
+        let mut rows: Vec<Vec<Pat>> = Vec::new();
+        let mut unreachable_arms = Vec::new();
+        for (i, pattern) in patterns.into_iter().enumerate() {
+            let row = vec![self.lower(pattern)];
+            if !self.is_useful(&rows, &row) {
+                unreachable_arms.push(i);
+            }
+            rows.push(row);
+        }
+
+        let witnesses = self.witnesses(&rows, &[Pat::Wild]);
+        let non_exhaustive_enum = witnesses.iter()
+            .flat_map(|w| w.first())
+            .find_map(|p| self.hidden_variants_of(p, &rows));
+        let missing = witnesses.iter()
+            .map(|w| self.display(&w[0]))
+            .collect();
+
+        MatchReport { unreachable_arms, missing, non_exhaustive_enum }
+    }
+
+    /// Reduce a typed pattern to constructors and wildcards
+    fn lower(&self, pattern: &TypedPattern) -> Pat {
+        match pattern {
+            TypedPattern::Wildcard | TypedPattern::Binding { .. } => Pat::Wild,
+            TypedPattern::Literal(expr) => {
+                let ctor = match &expr.kind {
+                    TypedExprKind::BoolLiteral(b) => Ctor::Bool(*b),
+                    TypedExprKind::IntLiteral(n) => Ctor::Int(*n),
+                    TypedExprKind::FloatLiteral(f) => Ctor::Float(*f),
+                    TypedExprKind::StringLiteral(s) => Ctor::Str(s.clone()),
+                    TypedExprKind::Unary { op: wisp_ast::UnaryOp::Neg, expr } => match &expr.kind {
+                        TypedExprKind::IntLiteral(n) => Ctor::Int(-*n),
+                        TypedExprKind::FloatLiteral(f) => Ctor::Float(-*f),
+                        _ => Ctor::Opaque,
+                    },
+                    _ => Ctor::Opaque,
+                };
+                Pat::Ctor(ctor, Vec::new())
+            }
+            TypedPattern::Variant { variant_def, fields } => {
+                // Arity errors are reported by the type checker; pad so every row lines up
+                let arity = self.arity(&Ctor::Variant(*variant_def));
+                let mut args: Vec<Pat> = fields.iter().take(arity).map(|f| self.lower(f)).collect();
+                args.resize(arity, Pat::Wild);
+                Pat::Ctor(Ctor::Variant(*variant_def), args)
+            }
+            TypedPattern::Tuple(elems) => {
+                Pat::Ctor(Ctor::Tuple(elems.len()), elems.iter().map(|p| self.lower(p)).collect())
+            }
+        }
+    }
+
+    fn is_useful(&self, rows: &[Vec<Pat>], v: &[Pat]) -> bool {
+        !self.witnesses(rows, v).is_empty()
+    }
+
+    /// Values matched by `v` but by none of `rows`, as pattern vectors of
+    /// the same width. Empty if `v` is useless.
+    fn witnesses(&self, rows: &[Vec<Pat>], v: &[Pat]) -> Vec<Vec<Pat>> {
+        let Some((head, tail)) = v.split_first() else {
+            return if rows.is_empty() { vec![Vec::new()] } else { Vec::new() };
+        };
+
+        match head {
+            Pat::Ctor(ctor, args) => {
+                let specialized = self.specialize(rows, ctor, args.len());
+                let v: Vec<Pat> = args.iter().chain(tail).cloned().collect();
+                self.witnesses(&specialized, &v).into_iter()
+                    .map(|w| rebuild(ctor, args.len(), w))
+                    .collect()
+            }
+            Pat::Wild => {
+                let mut seen: Vec<Ctor> = Vec::new();
+                for row in rows {
+                    if let Pat::Ctor(ctor, _) = &row[0]
+                        && !seen.iter().any(|c| c.same(ctor))
+                    {
+                        seen.push(ctor.clone());
+                    }
+                }
+                let (listed, open) = self.signature(&seen);
+
+                // Every constructor of the type has an arm: the value must be one of them
+                if !seen.is_empty() && !open && listed.iter().all(|c| seen.iter().any(|s| s.same(c))) {
+                    let mut result = Vec::new();
+                    for ctor in &listed {
+                        let arity = self.arity(ctor);
+                        let specialized = self.specialize(rows, ctor, arity);
+                        let v: Vec<Pat> = std::iter::repeat_n(Pat::Wild, arity).chain(tail.iter().cloned()).collect();
+                        result.extend(self.witnesses(&specialized, &v).into_iter().map(|w| rebuild(ctor, arity, w)));
+                    }
+                    return result;
+                }
+
+                // Some constructor has no arm of its own, so only the wildcard rows can match it
+                let defaults: Vec<Vec<Pat>> = rows.iter()
+                    .filter(|row| matches!(row[0], Pat::Wild))
+                    .map(|row| row[1..].to_vec())
+                    .collect();
+                let rest = self.witnesses(&defaults, tail);
+                if rest.is_empty() {
+                    return rest;
+                }
+
+                let mut heads: Vec<Pat> = Vec::new();
+                if !seen.is_empty() {
+                    for ctor in listed.iter().filter(|c| !seen.iter().any(|s| s.same(c))) {
+                        heads.push(Pat::Ctor(ctor.clone(), vec![Pat::Wild; self.arity(ctor)]));
+                    }
+                }
+                if seen.is_empty() || open {
+                    heads.push(Pat::Wild);
+                }
+
+                let mut result = Vec::new();
+                for head in &heads {
+                    for w in &rest {
+                        let mut witness = vec![head.clone()];
+                        witness.extend(w.iter().cloned());
+                        result.push(witness);
+                    }
+                }
+                result
+            }
+        }
+    }
+
+    /// Rows whose first column can match `ctor`, with that column replaced by the
+    /// constructor's arguments
+    fn specialize(&self, rows: &[Vec<Pat>], ctor: &Ctor, arity: usize) -> Vec<Vec<Pat>> {
+        rows.iter()
+            .filter_map(|row| {
+                let args = match &row[0] {
+                    Pat::Ctor(c, args) if c.same(ctor) => args.clone(),
+                    Pat::Ctor(..) => return None,
+                    Pat::Wild => vec![Pat::Wild; arity],
+                };
+                Some(args.into_iter().chain(row[1..].iter().cloned()).collect())
+            })
+            .collect()
+    }
+
+    /// The constructors of the type the `seen` constructors belong to, and
+    /// whether the type has values beyond those listed
+    fn signature(&self, seen: &[Ctor]) -> (Vec<Ctor>, bool) {
+        match seen.iter().find(|c| !matches!(c, Ctor::Opaque)) {
+            Some(Ctor::Bool(_)) => (vec![Ctor::Bool(true), Ctor::Bool(false)], false),
+            Some(Ctor::Tuple(n)) => (vec![Ctor::Tuple(*n)], false),
+            Some(Ctor::Variant(variant)) => match self.ctx.is_enum_variant(*variant) {
+                Some((enum_id, _)) => {
+                    let listed = self.ctx.get_enum_variants(enum_id)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|(_, def_id, _)| Ctor::Variant(*def_id))
+                        .collect();
+                    (listed, self.non_exhaustive_enums.contains(&enum_id))
+                }
+                None => (Vec::new(), true),
+            },
+            _ => (Vec::new(), true),
+        }
+    }
+
+    fn arity(&self, ctor: &Ctor) -> usize {
+        match ctor {
+            Ctor::Variant(variant) => self.ctx.is_enum_variant(*variant)
+                .and_then(|(enum_id, idx)| self.ctx.get_enum_variants(enum_id)?.get(idx).map(|(_, _, fields)| fields.len()))
+                .unwrap_or(0),
+            Ctor::Tuple(n) => *n,
+            _ => 0,
+        }
+    }
+
+    /// If a missing top-level `_` is only needed because the scrutinee is a
+    /// `@non_exhaustive` enum, return that enum
+    fn hidden_variants_of(&self, witness: &Pat, rows: &[Vec<Pat>]) -> Option<DefId> {
+        if !matches!(witness, Pat::Wild) {
+            return None;
+        }
+        rows.iter().find_map(|row| match &row[0] {
+            Pat::Ctor(Ctor::Variant(variant), _) => self.ctx.is_enum_variant(*variant)
+                .map(|(enum_id, _)| enum_id)
+                .filter(|enum_id| self.non_exhaustive_enums.contains(enum_id)),
+            _ => None,
+        })
+    }
+
+    fn display(&self, pat: &Pat) -> String {
+        match pat {
+            Pat::Wild => "_".to_string(),
+            Pat::Ctor(ctor, args) => {
+                let name = match ctor {
+                    Ctor::Variant(variant) => self.ctx.is_enum_variant(*variant)
+                        .and_then(|(enum_id, idx)| self.ctx.get_enum_variants(enum_id)?.get(idx).map(|(name, _, _)| name.clone()))
+                        .unwrap_or_else(|| "_".to_string()),
+                    Ctor::Bool(b) => b.to_string(),
+                    Ctor::Int(n) => n.to_string(),
+                    Ctor::Float(f) => f.to_string(),
+                    Ctor::Str(s) => format!("{:?}", s),
+                    Ctor::Opaque => "_".to_string(),
+                    Ctor::Tuple(_) => {
+                        let args: Vec<_> = args.iter().map(|a| self.display(a)).collect();
+                        return if args.len() == 1 { format!("({},)", args[0]) } else { format!("({})", args.join(", ")) };
+                    }
+                };
+                if args.is_empty() {
+                    name
+                } else {
+                    let args: Vec<_> = args.iter().map(|a| self.display(a)).collect();
+                    format!("{}({})", name, args.join(", "))
+                }
+            }
+        }
+    }
+}
+
+/// Fold the first `arity` patterns of a witness back into `ctor`
+fn rebuild(ctor: &Ctor, arity: usize, mut witness: Vec<Pat>) -> Vec<Pat> {
+    let rest = witness.split_off(arity);
+    let mut result = vec![Pat::Ctor(ctor.clone(), witness)];
+    result.extend(rest);
+    result
+}
+
+/// Format missing patterns the way they're reported: "`A`, `B` and 2 more"
+pub fn describe_missing(missing: &[String]) -> String {
+    let quoted: Vec<_> = missing.iter().map(|m| format!("`{}`", m)).collect();
+    match quoted.len() {
+        0 => String::new(),
+        1 => quoted[0].clone(),
+        n if n <= MAX_REPORTED => format!("{} and {}", quoted[..n - 1].join(", "), quoted[n - 1]),
+        n => format!("{} and {} more", quoted[..MAX_REPORTED].join(", "), n - MAX_REPORTED),
+    }
+}
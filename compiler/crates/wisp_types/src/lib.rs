@@ -4,12 +4,15 @@
 
 mod types;
 mod check;
+mod exhaustive;
+mod capture;
 
 pub use types::*;
+pub use capture::{CaptureMode, TypedCapture};
 pub use check::{
-    TypeChecker, TypeError,
+    TypeChecker, TypeError, TypeWarning,
     TypedProgram, TypedImpl, TypedFunction, TypedExternFunction, TypedExternStatic, TypedParam, TypedBlock, TypedStmt,
-    TypedExpr, TypedExprKind, TypedElse, TypedMatchArm, TypedPattern, TypedLambdaParam, TypedStringInterpPart,
+    TypedExpr, TypedExprKind, TypedCollectElem, TypedForIter, TypedElse, TypedMatchArm, TypedPattern, TypedLambdaParam, TypedStringInterpPart,
     GenericInstantiation,
 };
 
@@ -1,6 +1,7 @@
 //! Type representation for Wisp
 
 use wisp_hir::DefId;
+use wisp_lexer::{FileId, Span};
 use std::collections::HashMap;
 
 /// Interned type ID
@@ -155,7 +156,7 @@ impl Type {
             Type::Array(elem, size) => format!("[{}; {}]", elem.display(ctx), size),
             Type::Tuple(elems) => {
                 let parts: Vec<_> = elems.iter().map(|t| t.display(ctx)).collect();
-                format!("({})", parts.join(", "))
+                if parts.len() == 1 { format!("({},)", parts[0]) } else { format!("({})", parts.join(", ")) }
             }
             Type::Function { params, ret } => {
                 let params_str: Vec<_> = params.iter().map(|t| t.display(ctx)).collect();
@@ -189,10 +190,10 @@ pub struct TypeContext {
     substitutions: HashMap<u32, Type>,
     
     // === LSP Query Support ===
-    /// Span -> type string for hover (only for current file, filtered by source_len)
-    span_types: HashMap<(usize, usize), String>,
+    /// Span -> type string for hover
+    span_types: HashMap<Span, String>,
     /// Span -> definition DefId for go-to-definition
-    span_definitions: HashMap<(usize, usize), DefId>,
+    span_definitions: HashMap<Span, DefId>,
 }
 
 impl TypeContext {
@@ -214,60 +215,42 @@ impl TypeContext {
     // === LSP Query Methods ===
     
     /// Record a span -> type mapping for hover support
-    pub fn record_span_type(&mut self, start: usize, end: usize, type_str: String) {
-        self.span_types.insert((start, end), type_str);
+    pub fn record_span_type(&mut self, span: Span, type_str: String) {
+        self.span_types.insert(span, type_str);
     }
     
     /// Record a span -> definition mapping for go-to-definition
-    pub fn record_span_definition(&mut self, start: usize, end: usize, def_id: DefId) {
-        self.span_definitions.insert((start, end), def_id);
+    pub fn record_span_definition(&mut self, span: Span, def_id: DefId) {
+        self.span_definitions.insert(span, def_id);
     }
     
     /// Get the type string for a span (exact match)
-    pub fn get_span_type(&self, start: usize, end: usize) -> Option<&String> {
-        self.span_types.get(&(start, end))
+    pub fn get_span_type(&self, span: Span) -> Option<&String> {
+        self.span_types.get(&span)
     }
     
     /// Get the definition for a span (exact match)
-    pub fn get_span_definition(&self, start: usize, end: usize) -> Option<DefId> {
-        self.span_definitions.get(&(start, end)).copied()
+    pub fn get_span_definition(&self, span: Span) -> Option<DefId> {
+        self.span_definitions.get(&span).copied()
     }
     
-    /// Find the smallest span containing the given offset and return its type
-    pub fn type_at_offset(&self, offset: usize) -> Option<&String> {
-        let mut best: Option<((usize, usize), &String)> = None;
-        for ((start, end), type_str) in &self.span_types {
-            if offset >= *start && offset <= *end {
-                let span_size = end - start;
-                if best.is_none() || span_size < (best.as_ref().unwrap().0.1 - best.as_ref().unwrap().0.0) {
-                    best = Some(((*start, *end), type_str));
-                }
-            }
-        }
-        best.map(|(_, s)| s)
+    /// Find the smallest span of `file` containing the given offset and return its type
+    pub fn type_at_offset(&self, file: FileId, offset: usize) -> Option<&String> {
+        smallest_containing(&self.span_types, file, offset)
     }
     
-    /// Find the smallest span containing the given offset and return its definition
-    pub fn definition_at_offset(&self, offset: usize) -> Option<DefId> {
-        let mut best: Option<((usize, usize), DefId)> = None;
-        for ((start, end), def_id) in &self.span_definitions {
-            if offset >= *start && offset <= *end {
-                let span_size = end - start;
-                if best.is_none() || span_size < (best.as_ref().unwrap().0.1 - best.as_ref().unwrap().0.0) {
-                    best = Some(((*start, *end), *def_id));
-                }
-            }
-        }
-        best.map(|(_, d)| d)
+    /// Find the smallest span of `file` containing the given offset and return its definition
+    pub fn definition_at_offset(&self, file: FileId, offset: usize) -> Option<DefId> {
+        smallest_containing(&self.span_definitions, file, offset).copied()
     }
     
     /// Get all span types (for debugging/iteration)
-    pub fn all_span_types(&self) -> &HashMap<(usize, usize), String> {
+    pub fn all_span_types(&self) -> &HashMap<Span, String> {
         &self.span_types
     }
     
     /// Get all span definitions (for debugging/iteration)
-    pub fn all_span_definitions(&self) -> &HashMap<(usize, usize), DefId> {
+    pub fn all_span_definitions(&self) -> &HashMap<Span, DefId> {
         &self.span_definitions
     }
 
@@ -389,6 +372,14 @@ impl TypeContext {
                 params: params.iter().map(|t| self.apply(t)).collect(),
                 ret: Box::new(self.apply(ret)),
             },
+            Type::Struct { def_id, type_args } => Type::Struct {
+                def_id: *def_id,
+                type_args: type_args.iter().map(|t| self.apply(t)).collect(),
+            },
+            Type::Enum { def_id, type_args } => Type::Enum {
+                def_id: *def_id,
+                type_args: type_args.iter().map(|t| self.apply(t)).collect(),
+            },
             _ => ty.clone(),
         }
     }
@@ -532,3 +523,10 @@ pub fn parse_type_name(name: &str) -> Option<Type> {
     }
 }
 
+/// Value of the smallest span of `file` containing `offset`
+fn smallest_containing<T>(spans: &HashMap<Span, T>, file: FileId, offset: usize) -> Option<&T> {
+    spans.iter()
+        .filter(|(span, _)| span.file == file && span.start <= offset && offset <= span.end)
+        .min_by_key(|(span, _)| span.end - span.start)
+        .map(|(_, value)| value)
+}